@@ -0,0 +1,33 @@
+// Shared by `ssao.comp.glsl` and `ssao_blur.comp.glsl`, needs `scene_data.glsl`.
+
+// See `AO_GROUP_SIZE`.
+layout (local_size_x = 8, local_size_y = 8) in;
+
+// See `AoTargets` in `src/rasterizer/vulkan/ambient_occlusion.rs`.
+layout(set = 1, binding = 0) uniform sampler2D depthMap;
+layout(set = 1, binding = 1) uniform sampler2D normalMap;
+// The occlusion to blur.
+layout(set = 1, binding = 2) uniform sampler2D occlusionMap;
+layout(r32f, set = 1, binding = 3) uniform writeonly image2D image;
+
+// See `AoPushConstants`.
+layout( push_constant ) uniform constants
+{
+    uvec2 size;
+    // In world units.
+    float radius;
+    float intensity;
+    uint samples;
+    // One pixel along the axis of the blur.
+    ivec2 direction;
+} PushConstants;
+
+// View space position of the point at `uv` in [0,1] and `depth`, from the perspective
+// projection.
+vec3 viewPosition(vec2 uv, float depth)
+{
+    mat4 proj = sceneData.proj;
+    float z = -proj[3][2] / (depth + proj[2][2]);
+    vec2 ndc = uv * 2. - 1.;
+    return vec3(ndc.x * -z / proj[0][0], ndc.y * -z / proj[1][1], z);
+}
@@ -0,0 +1,51 @@
+// Sky lit by the sun, the same as `atmosphere` in `src/scene/environment.rs`, which explains the
+// model and has to give the same colours.
+
+#define PI 3.14159265359
+
+// Optical depths at the zenith.
+const vec3 RAYLEIGH = vec3(0.05, 0.12, 0.29);
+const float MIE = 0.02;
+const float MIE_G = 0.76;
+const float SUN_ILLUMINANCE = 20.;
+const float SUN_RADIANCE = 100.;
+const float SUN_COS_RADIUS = 0.99995;
+const vec3 GROUND = vec3(0.25, 0.22, 0.2);
+const vec3 NIGHT = vec3(0.002, 0.003, 0.006);
+
+// Relative to the zenith, from Kasten and Young.
+float airMass(float cosZenith)
+{
+    cosZenith = clamp(cosZenith, 0., 1.);
+    float zenithDegrees = degrees(acos(cosZenith));
+    return 1. / (cosZenith + 0.50572 * pow(96.07995 - zenithDegrees, -1.6364));
+}
+
+float henyeyGreenstein(float cosTheta)
+{
+    float g2 = MIE_G * MIE_G;
+    return (1. - g2) / (4. * PI * pow(1. + g2 - 2. * MIE_G * cosTheta, 1.5));
+}
+
+// `sun` is normalised, towards the sun.
+vec3 atmosphere(vec3 dir, vec3 sun)
+{
+    dir = normalize(dir);
+    vec3 extinction = RAYLEIGH + MIE;
+    vec3 sunlight = SUN_ILLUMINANCE * exp(-extinction * airMass(sun.y)) * smoothstep(-0.1, 0., sun.y);
+
+    float cosTheta = dot(dir, sun);
+    float rayleighPhase = 3. / (16. * PI) * (1. + cosTheta * cosTheta);
+    float miePhase = henyeyGreenstein(cosTheta);
+    vec3 viewExtinction = exp(-extinction * airMass(dir.y));
+    vec3 color = sunlight * (RAYLEIGH * rayleighPhase + MIE * miePhase) / extinction
+        * (1. - viewExtinction);
+
+    if (dir.y < 0.) {
+        vec3 ground = GROUND * sunlight * max(sun.y, 0.) / PI;
+        color = mix(color, ground, smoothstep(0., -0.1, dir.y));
+    } else if (cosTheta > SUN_COS_RADIUS) {
+        color += SUN_RADIANCE * viewExtinction * smoothstep(-0.1, 0., sun.y);
+    }
+    return color + NIGHT;
+}
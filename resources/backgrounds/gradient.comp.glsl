@@ -4,7 +4,9 @@ layout (local_size_x = 16, local_size_y = 16) in;
 
 layout(rgba16f,set = 0, binding = 0) uniform image2D image;
 
-//push constants block
+//push constants block, filled from the parameters
+//@param top_color: color4 = 1 0 0 1
+//@param bottom_color: color4 = 0 0 1 1
 layout( push_constant ) uniform constants
 {
  vec4 data1;
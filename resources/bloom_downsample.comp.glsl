@@ -0,0 +1,91 @@
+#version 450
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+layout(rgba16f, set = 0, binding = 0) uniform writeonly image2D outImage;
+// `draw_img` or the previous mip.
+layout(set = 0, binding = 1) uniform sampler2D inImage;
+
+layout( push_constant ) uniform constants
+{
+    uvec2 srcSize;
+    uvec2 dstSize;
+    float threshold;
+    float radius;
+    uint firstPass;
+} PushConstants;
+
+const vec3 LUMINANCE = vec3(0.2126, 0.7152, 0.0722);
+
+vec2 uv;
+vec2 srcTexel;
+
+vec3 tap(float x, float y)
+{
+    // Outside of the used part, the texels are from a previous frame.
+    vec2 maxUv = (vec2(PushConstants.srcSize) - 0.5) * srcTexel;
+    return texture(inImage, clamp(uv + vec2(x, y) * srcTexel, 0.5 * srcTexel, maxUv)).rgb;
+}
+
+// Against fireflies : the brightest pixels weight less.
+float karisWeight(vec3 color)
+{
+    return 1. / (1. + dot(color, LUMINANCE));
+}
+
+void main()
+{
+    uvec2 texel = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(texel, PushConstants.dstSize))) {
+        return;
+    }
+
+    srcTexel = 1. / vec2(textureSize(inImage, 0));
+    // Between the 4 covered source texels.
+    uv = vec2(texel * 2u + 1u) * srcTexel;
+
+    // 13 taps, from "Next generation post processing in Call of Duty: Advanced Warfare".
+    vec3 a = tap(-2., 2.);
+    vec3 b = tap(0., 2.);
+    vec3 c = tap(2., 2.);
+    vec3 d = tap(-2., 0.);
+    vec3 e = tap(0., 0.);
+    vec3 f = tap(2., 0.);
+    vec3 g = tap(-2., -2.);
+    vec3 h = tap(0., -2.);
+    vec3 i = tap(2., -2.);
+    vec3 j = tap(-1., 1.);
+    vec3 k = tap(1., 1.);
+    vec3 l = tap(-1., -1.);
+    vec3 m = tap(1., -1.);
+
+    vec3 color;
+    if (PushConstants.firstPass != 0u) {
+        vec3 groups[5] = vec3[](
+            (j + k + l + m) * 0.25,
+            (a + b + d + e) * 0.25,
+            (b + c + e + f) * 0.25,
+            (d + e + g + h) * 0.25,
+            (e + f + h + i) * 0.25);
+        float weights[5] = float[](0.5, 0.125, 0.125, 0.125, 0.125);
+        color = vec3(0.);
+        float total = 0.;
+        for (int n = 0; n < 5; n++) {
+            float w = weights[n] * karisWeight(groups[n]);
+            color += groups[n] * w;
+            total += w;
+        }
+        color /= total;
+
+        // Only what is above the threshold blooms.
+        float luminance = dot(color, LUMINANCE);
+        color *= max(luminance - PushConstants.threshold, 0.) / max(luminance, 0.0001);
+    } else {
+        color = e * 0.125
+            + (a + c + g + i) * 0.03125
+            + (b + d + f + h) * 0.0625
+            + (j + k + l + m) * 0.125;
+    }
+
+    imageStore(outImage, ivec2(texel), vec4(color, 1.));
+}
@@ -0,0 +1,42 @@
+#version 450
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+// Accumulates the upsampled smaller mip.
+layout(rgba16f, set = 0, binding = 0) uniform image2D outImage;
+layout(set = 0, binding = 1) uniform sampler2D inImage;
+
+layout( push_constant ) uniform constants
+{
+    uvec2 srcSize;
+    uvec2 dstSize;
+    float threshold;
+    float radius;
+    uint firstPass;
+} PushConstants;
+
+void main()
+{
+    uvec2 texel = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(texel, PushConstants.dstSize))) {
+        return;
+    }
+
+    vec2 srcTexel = 1. / vec2(textureSize(inImage, 0));
+    vec2 uv = (vec2(texel) + 0.5) / vec2(imageSize(outImage));
+    vec2 maxUv = (vec2(PushConstants.srcSize) - 0.5) * srcTexel;
+    vec2 d = PushConstants.radius * srcTexel;
+
+    // 3x3 tent filter.
+    vec3 color = vec3(0.);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            float weight = float((2 - abs(x)) * (2 - abs(y))) / 16.;
+            vec2 tapUv = clamp(uv + vec2(x, y) * d, 0.5 * srcTexel, maxUv);
+            color += texture(inImage, tapUv).rgb * weight;
+        }
+    }
+
+    vec3 current = imageLoad(outImage, ivec2(texel)).rgb;
+    imageStore(outImage, ivec2(texel), vec4(current + color, 1.));
+}
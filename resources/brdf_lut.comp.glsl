@@ -0,0 +1,50 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "ibl_common.glsl"
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+// Scale and bias of F0 in rg, by `NdotV` in x and the roughness in y.
+layout(rgba16f, set = 0, binding = 0) uniform writeonly image2D outImage;
+
+float geometrySchlickGGX(float nDotX, float roughness)
+{
+    // k of the IBL, not the one of the analytic lights.
+    float k = roughness * roughness / 2.;
+    return nDotX / (nDotX * (1. - k) + k);
+}
+
+void main()
+{
+    uvec2 id = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(id, uvec2(PushConstants.size)))) {
+        return;
+    }
+
+    vec2 uv = (vec2(id) + 0.5) / float(PushConstants.size);
+    float nDotV = uv.x;
+    float roughness = uv.y;
+    vec3 v = vec3(sqrt(1. - nDotV * nDotV), 0., nDotV);
+    vec3 n = vec3(0., 0., 1.);
+
+    float scale = 0.;
+    float bias = 0.;
+    for (uint i = 0u; i < PushConstants.sampleCount; i++) {
+        vec3 h = importanceSampleGGX(hammersley(i, PushConstants.sampleCount), n, roughness);
+        vec3 l = 2. * dot(v, h) * h - v;
+        float nDotL = max(l.z, 0.);
+        float nDotH = max(h.z, 0.);
+        float vDotH = max(dot(v, h), 0.);
+        if (nDotL > 0.) {
+            float g = geometrySchlickGGX(nDotV, roughness) * geometrySchlickGGX(nDotL, roughness);
+            float gVis = g * vDotH / (nDotH * nDotV);
+            float fc = pow(1. - vDotH, 5.);
+            scale += (1. - fc) * gVis;
+            bias += fc * gVis;
+        }
+    }
+
+    vec2 lut = vec2(scale, bias) / float(PushConstants.sampleCount);
+    imageStore(outImage, ivec2(id), vec4(lut, 0., 1.));
+}
@@ -0,0 +1,121 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_buffer_reference : require
+
+#include "draw_objects.glsl"
+
+layout (local_size_x = 64) in;
+
+// Farthest depth of the previous frame, see `depth_reduce.comp.glsl`.
+layout(set = 0, binding = 0) uniform sampler2D depthPyramid;
+
+// VkDrawIndexedIndirectCommand
+struct DrawCommand {
+    uint indexCount;
+    uint instanceCount;
+    uint firstIndex;
+    int vertexOffset;
+    uint firstInstance;
+};
+
+layout(buffer_reference, std430) writeonly buffer CommandBuffer {
+    DrawCommand commands[];
+};
+
+// One per batch.
+layout(buffer_reference, std430) buffer CountBuffer {
+    uint counts[];
+};
+
+// See `GpuCullData` in `src/rasterizer/vulkan/gpu_culling.rs`.
+layout(buffer_reference, std430) readonly buffer CullData {
+    mat4 viewProj;
+    vec4 frustumPlanes[6];
+    uvec2 pyramidSize;
+    uint objectCount;
+    uint frustum;
+    uint occlusion;
+};
+
+layout( push_constant ) uniform constants
+{
+    CullData cullData;
+    ObjectBuffer objectBuffer;
+    CommandBuffer commandBuffer;
+    CountBuffer countBuffer;
+} PushConstants;
+
+bool isInFrustum(DrawObject o)
+{
+    vec3 center = (o.worldMatrix * vec4(o.boundsOrigin.xyz, 1.)).xyz;
+    mat3 axes = transpose(mat3(o.worldMatrix));
+    for (int i = 0; i < 6; i++) {
+        vec4 plane = PushConstants.cullData.frustumPlanes[i];
+        // Extent of the transformed box along the plane normal.
+        float radius = dot(o.boundsExtents.xyz, abs(axes * plane.xyz));
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return false;
+        }
+    }
+    return true;
+}
+
+bool isOccluded(DrawObject o)
+{
+    mat4 matrix = PushConstants.cullData.viewProj * o.worldMatrix;
+    vec3 ndcMin = vec3(1.);
+    vec3 ndcMax = vec3(-1.);
+    for (int i = 0; i < 8; i++) {
+        vec3 corner = vec3((i & 1) != 0 ? 1. : -1., (i & 2) != 0 ? 1. : -1., (i & 4) != 0 ? 1. : -1.);
+        vec4 clip = matrix * vec4(o.boundsOrigin.xyz + corner * o.boundsExtents.xyz, 1.);
+        // Crossing the near plane (at z = w with the reversed depth), the projection is wrong.
+        if (clip.w <= 0. || clip.z > clip.w) {
+            return false;
+        }
+        vec3 ndc = clip.xyz / clip.w;
+        ndcMin = min(ndcMin, ndc);
+        ndcMax = max(ndcMax, ndc);
+    }
+
+    vec2 uvMin = clamp(ndcMin.xy * 0.5 + 0.5, 0., 1.);
+    vec2 uvMax = clamp(ndcMax.xy * 0.5 + 0.5, 0., 1.);
+
+    // Level where the rectangle is at most a texel wide, so it covers at most 2x2 texels.
+    vec2 size = (uvMax - uvMin) * vec2(PushConstants.cullData.pyramidSize);
+    int level = int(ceil(log2(max(max(size.x, size.y), 1.))));
+    level = min(level, textureQueryLevels(depthPyramid) - 1);
+
+    ivec2 levelSize = textureSize(depthPyramid, level);
+    ivec2 texelMin = clamp(ivec2(uvMin * vec2(levelSize)), ivec2(0), levelSize - 1);
+    ivec2 texelMax = clamp(ivec2(uvMax * vec2(levelSize)), ivec2(0), levelSize - 1);
+    float farthest = min(
+        min(texelFetch(depthPyramid, texelMin, level).r,
+            texelFetch(depthPyramid, ivec2(texelMax.x, texelMin.y), level).r),
+        min(texelFetch(depthPyramid, ivec2(texelMin.x, texelMax.y), level).r,
+            texelFetch(depthPyramid, texelMax, level).r));
+
+    // Reversed depth : the nearest point of the bounds has the biggest depth.
+    return ndcMax.z < farthest;
+}
+
+void main()
+{
+    uint index = gl_GlobalInvocationID.x;
+    if (index >= PushConstants.cullData.objectCount) {
+        return;
+    }
+
+    DrawObject o = PushConstants.objectBuffer.objects[index];
+    if (PushConstants.cullData.frustum != 0u && !isInFrustum(o)) {
+        return;
+    }
+    if (PushConstants.cullData.occlusion != 0u && isOccluded(o)) {
+        return;
+    }
+
+    uint slot = atomicAdd(PushConstants.countBuffer.counts[o.batch], 1u);
+    // The vertex shader finds the object with gl_InstanceIndex.
+    PushConstants.commandBuffer.commands[o.firstCommand + slot] =
+        DrawCommand(o.indexCount, 1u, o.firstIndex, 0, index);
+}
@@ -0,0 +1,180 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "scene_data.glsl"
+#include "lighting.glsl"
+
+// One tile per workgroup, see `LIGHTING_TILE_SIZE`.
+layout (local_size_x = 16, local_size_y = 16) in;
+
+// See `GBuffer` in `src/rasterizer/vulkan/deferred.rs`.
+layout(set = 1, binding = 0) uniform sampler2D albedoMap;
+layout(set = 1, binding = 1) uniform sampler2D normalMap;
+layout(set = 1, binding = 2) uniform sampler2D metalRoughMap;
+layout(set = 1, binding = 3) uniform sampler2D depthMap;
+layout(rgba16f, set = 1, binding = 4) uniform writeonly image2D image;
+// See `AoTargets` in `src/rasterizer/vulkan/ambient_occlusion.rs`.
+layout(set = 1, binding = 5) uniform sampler2D occlusionMap;
+
+layout( push_constant ) uniform constants
+{
+    mat4 invProj;
+    uvec2 size;
+    // See `GBufferView`, the pixels are shaded with 0.
+    uint gbufferView;
+    // Boolean, `occlusionMap` is ignored otherwise.
+    uint ambientOcclusion;
+} PushConstants;
+
+#define GBUFFER_VIEW_OFF 0
+#define GBUFFER_VIEW_ALBEDO 1
+#define GBUFFER_VIEW_NORMALS 2
+#define GBUFFER_VIEW_METAL_ROUGH 3
+#define GBUFFER_VIEW_DEPTH 4
+#define GBUFFER_VIEW_LIGHT_COUNT 5
+#define GBUFFER_VIEW_OCCLUSION 6
+
+// The other lights of a crowded tile are ignored.
+#define MAX_TILE_LIGHTS 256
+// Red in the light count view, the tiles over MAX_TILE_LIGHTS are white.
+const float LIGHT_COUNT_VIEW_MAX = 32.;
+
+// Bits of the positive depths, which keep their order.
+shared uint tileMinDepth;
+shared uint tileMaxDepth;
+shared uint tileLightCount;
+shared uint tileLights[MAX_TILE_LIGHTS];
+
+// View space position of the point at `uv` in [0,1] and `depth`.
+vec3 viewPosition(vec2 uv, float depth)
+{
+    vec4 point = PushConstants.invProj * vec4(uv * 2. - 1., depth, 1.);
+    return point.xyz / point.w;
+}
+
+// Gathers the lights touching the view space box around the tile, between its closest and
+// farthest depths.
+void cullLights()
+{
+    float minDepth = uintBitsToFloat(tileMinDepth);
+    float maxDepth = uintBitsToFloat(tileMaxDepth);
+    vec2 tileMin = vec2(gl_WorkGroupID.xy * gl_WorkGroupSize.xy) / vec2(PushConstants.size);
+    vec2 tileMax =
+        vec2((gl_WorkGroupID.xy + 1u) * gl_WorkGroupSize.xy) / vec2(PushConstants.size);
+
+    vec3 boxMin = vec3(1e30);
+    vec3 boxMax = vec3(-1e30);
+    for (uint i = 0u; i < 8u; i++) {
+        vec2 uv = vec2((i & 1u) != 0u ? tileMax.x : tileMin.x,
+                       (i & 2u) != 0u ? tileMax.y : tileMin.y);
+        vec3 corner = viewPosition(uv, (i & 4u) != 0u ? maxDepth : minDepth);
+        boxMin = min(boxMin, corner);
+        boxMax = max(boxMax, corner);
+    }
+
+    uint threads = gl_WorkGroupSize.x * gl_WorkGroupSize.y;
+    for (uint i = gl_LocalInvocationIndex; i < sceneData.lightCount; i += threads) {
+        PointLight light = lightBuffer.lights[i];
+        vec3 center = (sceneData.view * vec4(light.position, 1.)).xyz;
+        vec3 toBox = center - clamp(center, boxMin, boxMax);
+        if (dot(toBox, toBox) <= light.radius * light.radius) {
+            uint slot = atomicAdd(tileLightCount, 1u);
+            if (slot < MAX_TILE_LIGHTS) {
+                tileLights[slot] = i;
+            }
+        }
+    }
+}
+
+void main()
+{
+    ivec2 texel = ivec2(gl_GlobalInvocationID.xy);
+    bool inside = all(lessThan(gl_GlobalInvocationID.xy, PushConstants.size));
+
+    if (gl_LocalInvocationIndex == 0u) {
+        tileMinDepth = 0xffffffffu;
+        tileMaxDepth = 0u;
+        tileLightCount = 0u;
+    }
+    barrier();
+
+    // Reversed depth, the sky is at 0 and gets no light.
+    float depth = inside ? texelFetch(depthMap, texel, 0).r : 0.;
+    if (depth > 0.) {
+        atomicMin(tileMinDepth, floatBitsToUint(depth));
+        atomicMax(tileMaxDepth, floatBitsToUint(depth));
+    }
+    barrier();
+
+    if (tileMaxDepth != 0u) {
+        cullLights();
+    }
+    barrier();
+
+    if (!inside) {
+        return;
+    }
+    if (depth == 0.) {
+        // The background, only cleared by the debug views.
+        if (PushConstants.gbufferView != GBUFFER_VIEW_OFF) {
+            imageStore(image, texel, vec4(0., 0., 0., 1.));
+        }
+        return;
+    }
+
+    vec4 albedo = texelFetch(albedoMap, texel, 0);
+    vec3 n = normalize(texelFetch(normalMap, texel, 0).xyz * 2. - 1.);
+    vec2 metalRough = texelFetch(metalRoughMap, texel, 0).rg;
+    vec2 uv = (vec2(texel) + 0.5) / vec2(PushConstants.size);
+    vec3 viewPos = viewPosition(uv, depth);
+    uint lightCount = min(tileLightCount, MAX_TILE_LIGHTS);
+    float occlusion =
+        PushConstants.ambientOcclusion != 0u ? texelFetch(occlusionMap, texel, 0).r : 1.;
+
+    vec3 color;
+    switch (PushConstants.gbufferView) {
+    case GBUFFER_VIEW_ALBEDO:
+        color = albedo.rgb;
+        break;
+    case GBUFFER_VIEW_NORMALS:
+        color = n * 0.5 + 0.5;
+        break;
+    case GBUFFER_VIEW_METAL_ROUGH:
+        color = vec3(metalRough, 0.);
+        break;
+    case GBUFFER_VIEW_DEPTH:
+        color = vec3(1. - clamp(-viewPos.z / DEPTH_VIEW_FAR, 0., 1.));
+        break;
+    case GBUFFER_VIEW_LIGHT_COUNT: {
+        float heat = min(float(lightCount) / LIGHT_COUNT_VIEW_MAX, 1.);
+        color = lightCount == 0u ? vec3(0.) : mix(vec3(0., 0., 1.), vec3(1., 0., 0.), heat);
+        if (tileLightCount > MAX_TILE_LIGHTS) {
+            color = vec3(1.);
+        }
+        break;
+    }
+    case GBUFFER_VIEW_OCCLUSION:
+        color = vec3(occlusion);
+        break;
+    default: {
+        // The view matrix is a rotation and a translation.
+        mat3 rotation = mat3(sceneData.view);
+        vec3 p = transpose(rotation) * (viewPos - sceneData.view[3].xyz);
+        vec3 v = normalize(sceneData.cameraPosition.xyz - p);
+        float metallic = metalRough.r;
+        float roughness = metalRough.g;
+
+        color = sunAndAmbient(albedo.rgb, n, v, metallic, roughness, occlusion);
+        for (uint i = 0u; i < lightCount; i++) {
+            color += pointLight(lightBuffer.lights[tileLights[i]], p, albedo.rgb, n, v, metallic,
+                                roughness);
+        }
+        if (albedo.a > 0.5) {
+            color = mix(color, HIGHLIGHT_COLOR, HIGHLIGHT_STRENGTH);
+        }
+        break;
+    }
+    }
+
+    imageStore(image, texel, vec4(color, 1.));
+}
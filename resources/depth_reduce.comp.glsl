@@ -0,0 +1,37 @@
+#version 450
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+layout(r32f, set = 0, binding = 0) uniform writeonly image2D outImage;
+// The depth image or the previous mip.
+layout(set = 0, binding = 1) uniform sampler2D inImage;
+
+layout( push_constant ) uniform constants
+{
+    uvec2 srcSize;
+    uvec2 dstSize;
+} PushConstants;
+
+void main()
+{
+    uvec2 texel = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(texel, PushConstants.dstSize))) {
+        return;
+    }
+
+    // Every source texel covered is read, even if the sizes aren't multiples.
+    uvec2 srcMin = texel * PushConstants.srcSize / PushConstants.dstSize;
+    uvec2 srcMax = max(
+        ((texel + 1) * PushConstants.srcSize + PushConstants.dstSize - 1) / PushConstants.dstSize,
+        srcMin + 1);
+
+    // Reversed depth : the farthest is the smallest.
+    float depth = 1.;
+    for (uint y = srcMin.y; y < srcMax.y; y++) {
+        for (uint x = srcMin.x; x < srcMax.x; x++) {
+            depth = min(depth, texelFetch(inImage, ivec2(x, y), 0).r);
+        }
+    }
+
+    imageStore(outImage, ivec2(texel), vec4(depth));
+}
@@ -0,0 +1,21 @@
+// See `GpuObject` in `src/rasterizer/vulkan/gpu_culling.rs`.
+struct DrawObject {
+    mat4 worldMatrix;
+    vec4 boundsOrigin;
+    vec4 boundsExtents;
+    // Address of the `VertexBuffer`.
+    uvec2 vertexBuffer;
+    uint meshId;
+    uint surfaceId;
+    uint lod;
+    uint highlighted;
+    uint batch;
+    uint firstCommand;
+    uint firstIndex;
+    uint indexCount;
+    uint materialId;
+};
+
+layout(buffer_reference, std430) readonly buffer ObjectBuffer {
+    DrawObject objects[];
+};
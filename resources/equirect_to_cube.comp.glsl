@@ -0,0 +1,22 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "ibl_common.glsl"
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+// One mip of the environment cube.
+layout(rgba16f, set = 0, binding = 0) uniform writeonly image2DArray outImage;
+layout(set = 0, binding = 1) uniform sampler2D equirect;
+
+void main()
+{
+    uvec3 id = gl_GlobalInvocationID;
+    if (any(greaterThanEqual(id.xy, uvec2(PushConstants.size)))) {
+        return;
+    }
+
+    vec3 dir = cubeDirection(id, PushConstants.size);
+    vec2 uv = vec2(atan(dir.z, dir.x) / (2. * PI) + 0.5, acos(clamp(dir.y, -1., 1.)) / PI);
+    imageStore(outImage, ivec3(id), vec4(textureLod(equirect, uv, 0.).rgb, 1.));
+}
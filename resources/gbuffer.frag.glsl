@@ -0,0 +1,37 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
+#include "input_structures.glsl"
+
+// Same inputs as `mesh.frag.glsl`.
+layout (location = 0) in vec3 inNormal;
+layout (location = 1) in vec3 inColor;
+layout (location = 2) in vec2 inUV;
+layout (location = 3) in float inViewDepth;
+layout (location = 4) flat in uvec3 inDebugIds;
+layout (location = 5) flat in uint inHighlighted;
+layout (location = 6) flat in uint inMaterialId;
+layout (location = 7) in vec3 inWorldPosition;
+
+// See `GBUFFER_FORMATS` in `src/rasterizer/vulkan/deferred.rs`.
+// The alpha is 1 for the selected nodes.
+layout (location = 0) out vec4 outAlbedo;
+// World space, mapped to [0,1].
+layout (location = 1) out vec4 outNormal;
+layout (location = 2) out vec2 outMetalRough;
+
+void main()
+{
+    MaterialData material = materialBuffer.materials[inMaterialId];
+    vec3 color = inColor * texture(textures[nonuniformEXT(material.colorTex)], inUV).xyz;
+
+    // glTF layout : roughness in g, metallic in b.
+    vec4 metalRough = texture(textures[nonuniformEXT(material.metalRoughTex)], inUV);
+    float metallic = clamp(material.metal_rough_factors.x * metalRough.b, 0., 1.);
+    float roughness = clamp(material.metal_rough_factors.y * metalRough.g, 0.04, 1.);
+
+    outAlbedo = vec4(color, inHighlighted != 0u ? 1. : 0.);
+    outNormal = vec4(normalize(inNormal) * 0.5 + 0.5, 0.);
+    outMetalRough = vec2(metallic, roughness);
+}
@@ -0,0 +1,71 @@
+// Shared by the IBL precomputations, see `Ibl` in `src/rasterizer/vulkan/ibl.rs`.
+
+#define PI 3.14159265359
+
+// Same for all of them, see `BakePushConstants`.
+layout( push_constant ) uniform constants
+{
+    // Of the written face or image.
+    uint size;
+    float roughness;
+    // Of the first mip of the environment cube.
+    uint envSize;
+    uint sampleCount;
+} PushConstants;
+
+// Direction of the texel `id.xy` of the face `id.z`, in the Vulkan order : +X, -X, +Y, -Y, +Z, -Z.
+vec3 cubeDirection(uvec3 id, uint size)
+{
+    vec2 uv = (vec2(id.xy) + 0.5) / float(size) * 2. - 1.;
+    vec3 dir;
+    switch (id.z) {
+    case 0: dir = vec3(1., -uv.y, -uv.x); break;
+    case 1: dir = vec3(-1., -uv.y, uv.x); break;
+    case 2: dir = vec3(uv.x, 1., uv.y); break;
+    case 3: dir = vec3(uv.x, -1., -uv.y); break;
+    case 4: dir = vec3(uv.x, -uv.y, 1.); break;
+    default: dir = vec3(-uv.x, -uv.y, -1.); break;
+    }
+    return normalize(dir);
+}
+
+vec2 hammersley(uint i, uint n)
+{
+    uint bits = bitfieldReverse(i);
+    return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+}
+
+// Tangent space to world space, around `n`.
+mat3 tangentFrame(vec3 n)
+{
+    vec3 up = abs(n.z) < 0.999 ? vec3(0., 0., 1.) : vec3(1., 0., 0.);
+    vec3 tangent = normalize(cross(up, n));
+    return mat3(tangent, cross(n, tangent), n);
+}
+
+// Half vector of the GGX distribution, around `n`.
+vec3 importanceSampleGGX(vec2 xi, vec3 n, float roughness)
+{
+    float a = roughness * roughness;
+    float phi = 2. * PI * xi.x;
+    float cosTheta = sqrt((1. - xi.y) / (1. + (a * a - 1.) * xi.y));
+    float sinTheta = sqrt(1. - cosTheta * cosTheta);
+    return tangentFrame(n) * vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+}
+
+float distributionGGX(float nDotH, float roughness)
+{
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = nDotH * nDotH * (a2 - 1.) + 1.;
+    return a2 / (PI * d * d);
+}
+
+// Mip of the environment cube covering the solid angle of a sample of probability `pdf`,
+// against the aliasing of the bright spots.
+float sampleLod(float pdf)
+{
+    float texelSolidAngle = 4. * PI / (6. * float(PushConstants.envSize * PushConstants.envSize));
+    float sampleSolidAngle = 1. / (float(PushConstants.sampleCount) * pdf + 0.0001);
+    return max(0.5 * log2(sampleSolidAngle / texelSolidAngle) + 1., 0.);
+}
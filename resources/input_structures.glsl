@@ -1,16 +1,19 @@
-layout(set = 0, binding = 0) uniform  SceneData{
-    mat4 view;
-    mat4 proj;
-    mat4 viewproj;
-    vec4 ambientColor;
-    vec4 sunlightDirection; //w for sun power
-    vec4 sunlightColor;
-} sceneData;
+#include "scene_data.glsl"
 
-layout(set = 1, binding = 0) uniform GLTFMaterialData{
+// Bindless, see `BindlessTable` in `src/rasterizer/vulkan/bindless.rs`.
+// Needs `GL_EXT_nonuniform_qualifier`.
+
+// See `MaterialConstants` in `src/rasterizer/vulkan/textures.rs`.
+struct MaterialData {
     vec4 colorFactors;
     vec4 metal_rough_factors;
-} materialData;
+    // Indices in `textures`.
+    uint colorTex;
+    uint metalRoughTex;
+};
+
+layout(set = 1, binding = 0, std430) readonly buffer MaterialBuffer {
+    MaterialData materials[];
+} materialBuffer;
 
-layout(set = 1, binding = 1) uniform sampler2D colorTex;
-layout(set = 1, binding = 2) uniform sampler2D metalRoughTex;
+layout(set = 1, binding = 1) uniform sampler2D textures[];
@@ -0,0 +1,33 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "ibl_common.glsl"
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+layout(rgba16f, set = 0, binding = 0) uniform writeonly image2DArray outImage;
+layout(set = 0, binding = 1) uniform samplerCube environment;
+
+void main()
+{
+    uvec3 id = gl_GlobalInvocationID;
+    if (any(greaterThanEqual(id.xy, uvec2(PushConstants.size)))) {
+        return;
+    }
+
+    vec3 n = cubeDirection(id, PushConstants.size);
+    mat3 frame = tangentFrame(n);
+
+    // Cosine weighted, so the sum is the irradiance divided by PI, ready for a Lambertian albedo.
+    vec3 sum = vec3(0.);
+    for (uint i = 0u; i < PushConstants.sampleCount; i++) {
+        vec2 xi = hammersley(i, PushConstants.sampleCount);
+        float phi = 2. * PI * xi.x;
+        float cosTheta = sqrt(1. - xi.y);
+        float sinTheta = sqrt(xi.y);
+        vec3 l = frame * vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+        sum += textureLod(environment, l, sampleLod(cosTheta / PI)).rgb;
+    }
+
+    imageStore(outImage, ivec3(id), vec4(sum / float(PushConstants.sampleCount), 1.));
+}
@@ -0,0 +1,95 @@
+// Shading shared by `mesh.frag.glsl` and `deferred_lighting.comp.glsl`, needs `scene_data.glsl`.
+
+#define PI 3.14159265359
+
+// Image-based lighting, see `Ibl` in `src/rasterizer/vulkan/ibl.rs`.
+
+// Irradiance divided by PI.
+layout(set = 2, binding = 0) uniform samplerCube irradianceMap;
+// One mip per roughness step.
+layout(set = 2, binding = 1) uniform samplerCube prefilteredMap;
+layout(set = 2, binding = 2) uniform sampler2D brdfLut;
+
+// See `PREFILTERED_MIPS`.
+#define PREFILTERED_MIPS 5
+
+// Tint of the selected nodes.
+const vec3 HIGHLIGHT_COLOR = vec3(1., 0.6, 0.1);
+const float HIGHLIGHT_STRENGTH = 0.5;
+
+vec3 fresnelSchlick(float cosTheta, vec3 f0)
+{
+    return f0 + (1. - f0) * pow(clamp(1. - cosTheta, 0., 1.), 5.);
+}
+
+// Schlick, attenuated on the rough surfaces as they reflect less of the environment.
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 f0, float roughness)
+{
+    return f0 + (max(vec3(1. - roughness), f0) - f0) * pow(clamp(1. - cosTheta, 0., 1.), 5.);
+}
+
+// Split-sum approximation, see "Real Shading in Unreal Engine 4".
+vec3 imageBasedLighting(vec3 albedo, vec3 n, vec3 v, float metallic, float roughness)
+{
+    float nDotV = max(dot(n, v), 0.);
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 f = fresnelSchlickRoughness(nDotV, f0, roughness);
+
+    vec3 diffuse = texture(irradianceMap, n).rgb * albedo * (1. - f) * (1. - metallic);
+
+    vec3 r = reflect(-v, n);
+    vec3 prefiltered = textureLod(prefilteredMap, r, roughness * float(PREFILTERED_MIPS - 1)).rgb;
+    vec2 brdf = texture(brdfLut, vec2(nDotV, roughness)).rg;
+    vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+    return (diffuse + specular) * sceneData.iblIntensity;
+}
+
+// The sun, and the environment or the flat ambient colour darkened by the `occlusion`.
+vec3 sunAndAmbient(vec3 albedo, vec3 n, vec3 v, float metallic, float roughness, float occlusion)
+{
+    float lightValue = max(dot(n, sceneData.sunlightDirection.xyz), 0.1f);
+
+    vec3 ambient;
+    if (sceneData.ibl != 0u) {
+        ambient = imageBasedLighting(albedo, n, v, metallic, roughness);
+    } else {
+        ambient = albedo * sceneData.ambientColor.xyz;
+    }
+
+    return albedo * lightValue * sceneData.sunlightColor.w + ambient * occlusion;
+}
+
+// Cook-Torrance with GGX, at the world position `p`. The falloff is windowed so that the light
+// has no effect past its radius, see "Real Shading in Unreal Engine 4".
+vec3 pointLight(PointLight light, vec3 p, vec3 albedo, vec3 n, vec3 v, float metallic,
+                float roughness)
+{
+    vec3 toLight = light.position - p;
+    float dist2 = dot(toLight, toLight);
+    float ratio2 = dist2 / (light.radius * light.radius);
+    float window = clamp(1. - ratio2 * ratio2, 0., 1.);
+    if (window == 0.) {
+        return vec3(0.);
+    }
+    float falloff = window * window / (dist2 + 1.);
+
+    vec3 l = toLight * inversesqrt(dist2);
+    vec3 h = normalize(l + v);
+    float nDotL = max(dot(n, l), 0.);
+    float nDotV = max(dot(n, v), 1e-4);
+    float nDotH = max(dot(n, h), 0.);
+
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = nDotH * nDotH * (a2 - 1.) + 1.;
+    float distribution = a2 / (PI * d * d);
+    float k = (roughness + 1.) * (roughness + 1.) / 8.;
+    float geometry = nDotL / (nDotL * (1. - k) + k) * nDotV / (nDotV * (1. - k) + k);
+    vec3 f = fresnelSchlick(max(dot(h, v), 0.), mix(vec3(0.04), albedo, metallic));
+
+    vec3 specular = distribution * geometry * f / max(4. * nDotL * nDotV, 1e-4);
+    vec3 diffuse = (1. - f) * (1. - metallic) * albedo / PI;
+
+    return (diffuse + specular) * light.color * light.intensity * falloff * nDotL;
+}
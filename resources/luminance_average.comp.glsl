@@ -0,0 +1,74 @@
+#version 450
+
+#extension GL_EXT_buffer_reference : require
+
+// One thread per bin, in a single group.
+layout (local_size_x = 256) in;
+
+#define BINS 256
+
+layout(buffer_reference, std430) readonly buffer HistogramBuffer {
+    uint bins[BINS];
+};
+
+// Kept between frames, see `PostProcess`.
+layout(buffer_reference, std430) buffer ExposureBuffer {
+    float luminance;
+    float exposure;
+};
+
+layout( push_constant ) uniform constants
+{
+    HistogramBuffer histogram;
+    ExposureBuffer exposure;
+    uint pixelCount;
+    float minLogLuminance;
+    float logLuminanceRange;
+    // Part of the new luminance kept this frame.
+    float adaptation;
+    float exposureEv;
+    uint autoExposure;
+    uint reset;
+} PushConstants;
+
+// Middle grey.
+const float KEY = 0.18;
+
+shared float weighted[BINS];
+
+void main()
+{
+    uint i = gl_LocalInvocationIndex;
+    uint count = PushConstants.histogram.bins[i];
+    weighted[i] = float(count) * float(i);
+    barrier();
+
+    for (uint stride = BINS / 2; stride > 0; stride >>= 1) {
+        if (i < stride) {
+            weighted[i] += weighted[i + stride];
+        }
+        barrier();
+    }
+
+    if (i != 0) {
+        return;
+    }
+
+    float compensation = exp2(PushConstants.exposureEv);
+    if (PushConstants.autoExposure == 0u) {
+        PushConstants.exposure.exposure = compensation;
+        return;
+    }
+
+    // `count` is the one of the black bin here.
+    float lit = max(float(PushConstants.pixelCount) - float(count), 1.);
+    float logAverage = weighted[0] / lit - 1.;
+    float luminance =
+        exp2(logAverage / 254. * PushConstants.logLuminanceRange + PushConstants.minLogLuminance);
+
+    float adapted = PushConstants.reset != 0u
+        ? luminance
+        : mix(PushConstants.exposure.luminance, luminance, PushConstants.adaptation);
+    PushConstants.exposure.luminance = adapted;
+    PushConstants.exposure.exposure = compensation * KEY / max(adapted, 0.0001);
+}
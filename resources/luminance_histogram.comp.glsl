@@ -0,0 +1,55 @@
+#version 450
+
+#extension GL_EXT_buffer_reference : require
+
+// One thread per bin.
+layout (local_size_x = 16, local_size_y = 16) in;
+
+#define BINS 256
+
+layout(rgba16f, set = 0, binding = 0) uniform readonly image2D image;
+
+layout(buffer_reference, std430) buffer HistogramBuffer {
+    uint bins[BINS];
+};
+
+layout( push_constant ) uniform constants
+{
+    HistogramBuffer histogram;
+    uvec2 size;
+    float minLogLuminance;
+    float invLogLuminanceRange;
+} PushConstants;
+
+const vec3 LUMINANCE = vec3(0.2126, 0.7152, 0.0722);
+
+shared uint localBins[BINS];
+
+uint binIndex(vec3 color)
+{
+    float luminance = dot(color, LUMINANCE);
+    // The black pixels have their own bin, ignored by the average.
+    if (luminance < 0.0001) {
+        return 0u;
+    }
+    float logLuminance = clamp(
+        (log2(luminance) - PushConstants.minLogLuminance) * PushConstants.invLogLuminanceRange,
+        0., 1.);
+    return uint(logLuminance * 254. + 1.);
+}
+
+void main()
+{
+    localBins[gl_LocalInvocationIndex] = 0u;
+    barrier();
+
+    uvec2 texel = gl_GlobalInvocationID.xy;
+    if (all(lessThan(texel, PushConstants.size))) {
+        vec3 color = imageLoad(image, ivec2(texel)).rgb;
+        atomicAdd(localBins[binIndex(color)], 1u);
+    }
+    barrier();
+
+    atomicAdd(PushConstants.histogram.bins[gl_LocalInvocationIndex],
+        localBins[gl_LocalInvocationIndex]);
+}
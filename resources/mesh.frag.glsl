@@ -1,20 +1,93 @@
 #version 450
 
 #extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
 #include "input_structures.glsl"
+#include "lighting.glsl"
 
 layout (location = 0) in vec3 inNormal;
 layout (location = 1) in vec3 inColor;
 layout (location = 2) in vec2 inUV;
+layout (location = 3) in float inViewDepth;
+layout (location = 4) flat in uvec3 inDebugIds;
+layout (location = 5) flat in uint inHighlighted;
+layout (location = 6) flat in uint inMaterialId;
+layout (location = 7) in vec3 inWorldPosition;
 
 layout (location = 0) out vec4 outFragColor;
 
+// Each fragment adds this, so a pixel goes red, then yellow, then white.
+const vec3 OVERDRAW_STEP = vec3(0.25, 0.125, 0.0625);
+// From the original surfaces to the coarsest levels of detail.
+const vec3 LOD_COLORS[5] = vec3[](
+    vec3(0., 0., 1.), vec3(0., 1., 0.), vec3(1., 1., 0.), vec3(1., 0., 0.), vec3(1.));
+
+vec3 falseColor(uint id)
+{
+    // Integer hash to spread close ids.
+    id ^= id >> 16;
+    id *= 0x7feb352du;
+    id ^= id >> 15;
+    id *= 0x846ca68bu;
+    id ^= id >> 16;
+    return vec3((id >> 8) & 0xffu, (id >> 16) & 0xffu, (id >> 24) & 0xffu) / 255. * 0.8 + 0.2;
+}
+
 void main()
 {
-    float lightValue = max(dot(inNormal, sceneData.sunlightDirection.xyz), 0.1f);
+    switch (sceneData.viewMode) {
+    case VIEW_MODE_DEPTH: {
+        float grey = 1. - clamp(inViewDepth / DEPTH_VIEW_FAR, 0., 1.);
+        outFragColor = vec4(vec3(grey), 1.);
+        break;
+    }
+    case VIEW_MODE_OVERDRAW:
+        // Additive, a tint would break the count.
+        outFragColor = vec4(OVERDRAW_STEP, 1.);
+        return;
+    case VIEW_MODE_NORMALS:
+        outFragColor = vec4(normalize(inNormal) * 0.5 + 0.5, 1.);
+        break;
+    case VIEW_MODE_UVS:
+        outFragColor = vec4(fract(inUV), 0., 1.);
+        break;
+    case VIEW_MODE_MESH_COLOR:
+        outFragColor = vec4(falseColor(inDebugIds.x), 1.);
+        break;
+    case VIEW_MODE_SURFACE_COLOR:
+        outFragColor = vec4(falseColor(inDebugIds.y), 1.);
+        break;
+    case VIEW_MODE_LOD:
+        // Like `heatmap` in `src/rasterizer/cpu/debug_view.rs`.
+        outFragColor = vec4(LOD_COLORS[min(inDebugIds.z, 4u)], 1.);
+        break;
+    default: {
+        // Several draws can share a subgroup with the indirect ones.
+        MaterialData material = materialBuffer.materials[inMaterialId];
+        vec3 color = inColor * texture(textures[nonuniformEXT(material.colorTex)], inUV).xyz;
+
+        // glTF layout : roughness in g, metallic in b.
+        vec4 metalRough = texture(textures[nonuniformEXT(material.metalRoughTex)], inUV);
+        float metallic = clamp(material.metal_rough_factors.x * metalRough.b, 0., 1.);
+        float roughness = clamp(material.metal_rough_factors.y * metalRough.g, 0.04, 1.);
+
+        vec3 n = normalize(inNormal);
+        vec3 v = normalize(sceneData.cameraPosition.xyz - inWorldPosition);
+        // The ambient occlusion needs the depth of the whole frame, only the deferred shading
+        // has it before the lighting.
+        vec3 lit = sunAndAmbient(color, n, v, metallic, roughness, 1.);
+        // Every light, the deferred shading only goes through the ones of the tile.
+        for (uint i = 0u; i < sceneData.lightCount; i++) {
+            lit += pointLight(lightBuffer.lights[i], inWorldPosition, color, n, v, metallic,
+                              roughness);
+        }
 
-    vec3 color = inColor * texture(colorTex,inUV).xyz;
-    vec3 ambient = color *  sceneData.ambientColor.xyz;
+        outFragColor = vec4(lit, 1.0f);
+        break;
+    }
+    }
 
-    outFragColor = vec4(color * lightValue *  sceneData.sunlightColor.w + ambient ,1.0f);
+    if (inHighlighted != 0u) {
+        outFragColor.rgb = mix(outFragColor.rgb, HIGHLIGHT_COLOR, HIGHLIGHT_STRENGTH);
+    }
 }
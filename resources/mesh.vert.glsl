@@ -2,12 +2,21 @@
 
 #extension GL_GOOGLE_include_directive : require
 #extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference_uvec2 : require
+#extension GL_EXT_nonuniform_qualifier : require
 
 #include "input_structures.glsl"
+#include "draw_objects.glsl"
 
 layout (location = 0) out vec3 outNormal;
 layout (location = 1) out vec3 outColor;
 layout (location = 2) out vec2 outUV;
+layout (location = 3) out float outViewDepth;
+// Mesh, surface and level of detail.
+layout (location = 4) flat out uvec3 outDebugIds;
+layout (location = 5) flat out uint outHighlighted;
+layout (location = 6) flat out uint outMaterialId;
+layout (location = 7) out vec3 outWorldPosition;
 
 struct Vertex {
 
@@ -16,6 +25,7 @@ struct Vertex {
     vec3 normal;
     float uv_y;
     vec4 color;
+    vec4 tangent;
 };
 
 layout(buffer_reference, std430) readonly buffer VertexBuffer{
@@ -27,18 +37,48 @@ layout( push_constant ) uniform constants
 {
     mat4 render_matrix;
     VertexBuffer vertexBuffer;
+    // Used instead of the other fields with `drawIndirect`.
+    ObjectBuffer objectBuffer;
+    uint meshId;
+    uint surfaceId;
+    uint lod;
+    uint highlighted;
+    uint materialId;
+    uint drawIndirect;
 } PushConstants;
 
 void main()
 {
-    Vertex v = PushConstants.vertexBuffer.vertices[gl_VertexIndex];
+    mat4 renderMatrix = PushConstants.render_matrix;
+    VertexBuffer vertexBuffer = PushConstants.vertexBuffer;
+    uvec3 debugIds = uvec3(PushConstants.meshId, PushConstants.surfaceId, PushConstants.lod);
+    uint highlighted = PushConstants.highlighted;
+    uint materialId = PushConstants.materialId;
+    if (PushConstants.drawIndirect != 0u) {
+        // `firstInstance` is the object index, see `cull_objects.comp.glsl`, or the first of
+        // the instances, see `src/rasterizer/vulkan/instancing.rs`.
+        DrawObject o = PushConstants.objectBuffer.objects[gl_InstanceIndex];
+        renderMatrix = o.worldMatrix;
+        vertexBuffer = VertexBuffer(o.vertexBuffer);
+        debugIds = uvec3(o.meshId, o.surfaceId, o.lod);
+        highlighted = o.highlighted;
+        materialId = o.materialId;
+    }
+
+    Vertex v = vertexBuffer.vertices[gl_VertexIndex];
 
     vec4 position = vec4(v.position, 1.0f);
 
-    gl_Position =  sceneData.viewproj * PushConstants.render_matrix *position;
+    vec4 worldPosition = renderMatrix * position;
+    gl_Position = sceneData.viewproj * worldPosition;
 
-    outNormal = (PushConstants.render_matrix * vec4(v.normal, 0.f)).xyz;
-    outColor = v.color.xyz * materialData.colorFactors.xyz;
+    outNormal = (renderMatrix * vec4(v.normal, 0.f)).xyz;
+    outColor = v.color.xyz * materialBuffer.materials[materialId].colorFactors.xyz;
     outUV.x = v.uv_x;
     outUV.y = v.uv_y;
+    outViewDepth = -(sceneData.view * worldPosition).z;
+    outDebugIds = debugIds;
+    outHighlighted = highlighted;
+    outMaterialId = materialId;
+    outWorldPosition = worldPosition.xyz;
 }
@@ -0,0 +1,10 @@
+#version 450
+
+// Used with `vk::PolygonMode::LINE` to draw the edges of the meshes.
+
+layout (location = 0) out vec4 outFragColor;
+
+void main()
+{
+    outFragColor = vec4(0.88, 0.88, 0.88, 1.);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// See `MsaaTargets` in `src/rasterizer/vulkan/msaa.rs`.
+layout(set = 0, binding = 0) uniform sampler2D drawImage;
+
+layout (location = 0) out vec4 outFragColor;
+
+// Copies the background of `draw_img` to every sample of the multisampled attachment.
+void main()
+{
+    outFragColor = texelFetch(drawImage, ivec2(gl_FragCoord.xy), 0);
+}
@@ -0,0 +1,45 @@
+// Set 0, shared by the geometry pipelines and the deferred lighting, see `Scene` in
+// `src/rasterizer/vulkan/scene.rs`.
+
+layout(set = 0, binding = 0) uniform  SceneData{
+    mat4 view;
+    mat4 proj;
+    mat4 viewproj;
+    vec4 ambientColor;
+    vec4 sunlightDirection; //w for sun power
+    vec4 sunlightColor;
+    vec4 cameraPosition;
+    // See `ViewMode` in `src/rasterizer/settings.rs`
+    uint viewMode;
+    // Boolean, `ambientColor` is used otherwise.
+    uint ibl;
+    float iblIntensity;
+    // In `lightBuffer`.
+    uint lightCount;
+} sceneData;
+
+// See `GpuPointLight` in `src/rasterizer/vulkan/lights.rs`.
+struct PointLight {
+    vec3 position;
+    // Distance at which it stops lighting.
+    float radius;
+    vec3 color;
+    float intensity;
+};
+
+layout(set = 0, binding = 1, std430) readonly buffer LightBuffer {
+    PointLight lights[];
+} lightBuffer;
+
+#define VIEW_MODE_SHADED 0
+#define VIEW_MODE_DEPTH 1
+#define VIEW_MODE_OVERDRAW 2
+#define VIEW_MODE_NORMALS 3
+#define VIEW_MODE_UVS 4
+#define VIEW_MODE_MESH_COLOR 5
+#define VIEW_MODE_SURFACE_COLOR 6
+#define VIEW_MODE_LOD 7
+
+// Depth mapped to black in the depth views, see `DEPTH_VIEW_FAR` in
+// `src/rasterizer/settings.rs`.
+const float DEPTH_VIEW_FAR = 100.;
@@ -0,0 +1,51 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "atmosphere.glsl"
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+layout(rgba16f, set = 0, binding = 0) uniform writeonly image2D image;
+
+// See `Ibl::layout` in `src/rasterizer/vulkan/ibl.rs`, set 2 of the meshes.
+layout(set = 1, binding = 1) uniform samplerCube prefilteredMap;
+layout(set = 1, binding = 3) uniform samplerCube environmentMap;
+
+// See `PREFILTERED_MIPS`.
+#define PREFILTERED_MIPS 5
+
+layout( push_constant ) uniform constants
+{
+    // Without the camera translation.
+    mat4 invViewProj;
+    uvec2 size;
+    float intensity;
+    // Roughness of the prefiltered map shown instead, if not 0.
+    float blur;
+    // Towards the sun, `w` is 1 if the atmosphere is computed instead of sampled, which keeps the
+    // sun disc sharp.
+    vec4 sunDirection;
+} PushConstants;
+
+void main()
+{
+    uvec2 texel = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(texel, PushConstants.size))) {
+        return;
+    }
+
+    vec2 ndc = (vec2(texel) + 0.5) / vec2(PushConstants.size) * 2. - 1.;
+    // Reversed depth, 1 is the near plane.
+    vec4 point = PushConstants.invViewProj * vec4(ndc, 1., 1.);
+    vec3 dir = normalize(point.xyz / point.w);
+
+    vec3 color;
+    if (PushConstants.blur > 0.) {
+        color = textureLod(prefilteredMap, dir, PushConstants.blur * float(PREFILTERED_MIPS - 1)).rgb;
+    } else if (PushConstants.sunDirection.w > 0.) {
+        color = atmosphere(dir, normalize(PushConstants.sunDirection.xyz));
+    } else {
+        color = textureLod(environmentMap, dir, 0.).rgb;
+    }
+    imageStore(image, ivec2(texel), vec4(color * PushConstants.intensity, 1.));
+}
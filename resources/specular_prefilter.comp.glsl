@@ -0,0 +1,44 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "ibl_common.glsl"
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+// One mip of the prefiltered cube, of `PushConstants.roughness`.
+layout(rgba16f, set = 0, binding = 0) uniform writeonly image2DArray outImage;
+layout(set = 0, binding = 1) uniform samplerCube environment;
+
+void main()
+{
+    uvec3 id = gl_GlobalInvocationID;
+    if (any(greaterThanEqual(id.xy, uvec2(PushConstants.size)))) {
+        return;
+    }
+
+    // The view is assumed along the normal, see "Real Shading in Unreal Engine 4".
+    vec3 n = cubeDirection(id, PushConstants.size);
+    float roughness = PushConstants.roughness;
+
+    if (roughness == 0.) {
+        imageStore(outImage, ivec3(id), vec4(textureLod(environment, n, 0.).rgb, 1.));
+        return;
+    }
+
+    vec3 sum = vec3(0.);
+    float weight = 0.;
+    for (uint i = 0u; i < PushConstants.sampleCount; i++) {
+        vec3 h = importanceSampleGGX(hammersley(i, PushConstants.sampleCount), n, roughness);
+        float nDotH = max(dot(n, h), 0.);
+        vec3 l = 2. * nDotH * h - n;
+        float nDotL = dot(n, l);
+        if (nDotL > 0.) {
+            // With n = v, the pdf is D * nDotH / (4 * vDotH) = D / 4.
+            float pdf = distributionGGX(nDotH, roughness) / 4.;
+            sum += textureLod(environment, l, sampleLod(pdf)).rgb * nDotL;
+            weight += nDotL;
+        }
+    }
+
+    imageStore(outImage, ivec3(id), vec4(sum / max(weight, 0.0001), 1.));
+}
@@ -0,0 +1,94 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "scene_data.glsl"
+#include "ambient_occlusion.glsl"
+
+#define PI 3.14159265359
+
+// Of the radius, keeps the flat surfaces from occluding themselves.
+const float DEPTH_BIAS = 0.05;
+
+// In [0,1), a different sequence per `seed`. Like `hash` in
+// `src/rasterizer/cpu/ambient_occlusion.rs`.
+float hash(uint i, uint seed)
+{
+    uint x = (i * 0x9e3779b9u) ^ (seed * 0x85ebca6bu);
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return float(x >> 8) / 16777216.;
+}
+
+// `i`-th of `count` points in the hemisphere around z, closer to the centre for the first ones.
+vec3 kernel(uint i, uint count)
+{
+    float phi = hash(i, 0u) * 2. * PI;
+    float cosTheta = hash(i, 1u);
+    float sinTheta = sqrt(1. - cosTheta * cosTheta);
+    float t = (float(i) + 0.5) / float(count);
+    return vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta) * (0.1 + 0.9 * t * t);
+}
+
+// Rotation of the kernel per pixel, in [0,1), smoothed out by the blur.
+float interleavedGradientNoise(vec2 pixel)
+{
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+void main()
+{
+    ivec2 texel = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(gl_GlobalInvocationID.xy, PushConstants.size))) {
+        return;
+    }
+
+    // Reversed depth, nothing occludes the sky.
+    float depth = texelFetch(depthMap, texel, 0).r;
+    if (depth == 0.) {
+        imageStore(image, texel, vec4(1.));
+        return;
+    }
+
+    vec2 uv = (vec2(texel) + 0.5) / vec2(PushConstants.size);
+    vec3 p = viewPosition(uv, depth);
+    vec3 n = normalize(mat3(sceneData.view) * (texelFetch(normalMap, texel, 0).xyz * 2. - 1.));
+
+    // Any tangent, turned around the normal by the noise.
+    vec3 helper = abs(n.x) < 0.9 ? vec3(1., 0., 0.) : vec3(0., 1., 0.);
+    vec3 t = normalize(cross(helper, n));
+    vec3 b = cross(n, t);
+    float angle = interleavedGradientNoise(vec2(texel)) * 2. * PI;
+    mat3 tbn = mat3(t * cos(angle) + b * sin(angle), b * cos(angle) - t * sin(angle), n);
+
+    float radius = PushConstants.radius;
+    float bias = radius * DEPTH_BIAS;
+    float occlusion = 0.;
+    for (uint i = 0u; i < PushConstants.samples; i++) {
+        vec3 s = p + tbn * kernel(i, PushConstants.samples) * radius;
+        vec4 clip = sceneData.proj * vec4(s, 1.);
+        if (clip.w <= 0.) {
+            continue;
+        }
+        vec2 sampleUv = clip.xy / clip.w * 0.5 + 0.5;
+        if (any(lessThan(sampleUv, vec2(0.))) || any(greaterThanEqual(sampleUv, vec2(1.)))) {
+            continue;
+        }
+        ivec2 sampleTexel = ivec2(sampleUv * vec2(PushConstants.size));
+        float sceneDepth = texelFetch(depthMap, sampleTexel, 0).r;
+        if (sceneDepth == 0.) {
+            continue;
+        }
+        float sceneZ = viewPosition(sampleUv, sceneDepth).z;
+        // The view looks along -z, the surface is in front of the sample.
+        if (sceneZ >= s.z + bias) {
+            // Far in front, the surface doesn't hide this pixel.
+            occlusion += smoothstep(0., 1., radius / abs(p.z - sceneZ));
+        }
+    }
+
+    float visibility = 1. - occlusion / float(max(PushConstants.samples, 1u));
+    imageStore(image, texel, vec4(pow(visibility, PushConstants.intensity)));
+}
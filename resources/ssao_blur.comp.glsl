@@ -0,0 +1,52 @@
+#version 450
+
+#extension GL_GOOGLE_include_directive : require
+#include "scene_data.glsl"
+#include "ambient_occlusion.glsl"
+
+// Taps on each side, along `direction`.
+#define BLUR_RADIUS 4
+// Difference of depth, relative to the depth of the pixel, past which a tap is ignored.
+const float BLUR_DEPTH_TOLERANCE = 0.05;
+
+float viewDepth(ivec2 texel)
+{
+    vec2 uv = (vec2(texel) + 0.5) / vec2(PushConstants.size);
+    return -viewPosition(uv, texelFetch(depthMap, texel, 0).r).z;
+}
+
+// Separable, weighted by the distance and by the difference of depth so that the occlusion
+// doesn't leak over the edges.
+void main()
+{
+    ivec2 texel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = ivec2(PushConstants.size);
+    if (any(greaterThanEqual(texel, size))) {
+        return;
+    }
+
+    float center = texelFetch(occlusionMap, texel, 0).r;
+    if (texelFetch(depthMap, texel, 0).r == 0.) {
+        imageStore(image, texel, vec4(center));
+        return;
+    }
+
+    float depth = viewDepth(texel);
+    float sum = 0.;
+    float weights = 0.;
+    for (int i = -BLUR_RADIUS; i <= BLUR_RADIUS; i++) {
+        ivec2 tap = texel + PushConstants.direction * i;
+        if (any(lessThan(tap, ivec2(0))) || any(greaterThanEqual(tap, size))) {
+            continue;
+        }
+        float depthWeight =
+            max(1. - abs(viewDepth(tap) - depth) / (depth * BLUR_DEPTH_TOLERANCE), 0.);
+        float distance = float(i) / float(BLUR_RADIUS);
+        float weight = depthWeight * exp(-2. * distance * distance);
+        sum += texelFetch(occlusionMap, tap, 0).r * weight;
+        weights += weight;
+    }
+
+    // The centre always has a weight of 1.
+    imageStore(image, texel, vec4(sum / weights));
+}
@@ -0,0 +1,165 @@
+#version 450
+
+#extension GL_EXT_buffer_reference : require
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+// `draw_img`, tone mapped in place.
+layout(rgba16f, set = 0, binding = 0) uniform image2D image;
+// First mip of the bloom chain, half the size of `image`.
+layout(set = 0, binding = 1) uniform sampler2D bloom;
+
+layout(buffer_reference, std430) readonly buffer ExposureBuffer {
+    float luminance;
+    float exposure;
+};
+
+layout( push_constant ) uniform constants
+{
+    ExposureBuffer exposure;
+    uvec2 size;
+    vec4 tint;
+    uint toneMapper;
+    uint bloomEnabled;
+    float bloomIntensity;
+    float contrast;
+    float saturation;
+    float gamma;
+    uint outputTransfer;
+    // In nits, for the HDR outputs.
+    float paperWhite;
+} PushConstants;
+
+// See `ToneMapper` in `src/rasterizer/vulkan/post_process.rs`.
+#define TONE_MAPPER_OFF 0
+#define TONE_MAPPER_REINHARD 1
+#define TONE_MAPPER_ACES 2
+#define TONE_MAPPER_AGX 3
+
+// See `OutputTransfer` in `src/rasterizer/vulkan/present.rs`.
+#define OUTPUT_LINEAR 0
+#define OUTPUT_SRGB 1
+#define OUTPUT_PQ 2
+#define OUTPUT_SCRGB 3
+
+const vec3 LUMINANCE = vec3(0.2126, 0.7152, 0.0722);
+
+vec3 reinhard(vec3 color)
+{
+    return color / (1. + color);
+}
+
+// Krzysztof Narkowicz's fit.
+vec3 aces(vec3 color)
+{
+    return clamp(
+        (color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0., 1.);
+}
+
+// Minimal AgX, from https://iolite-engine.com/blog_posts/minimal_agx_implementation
+vec3 agxContrast(vec3 x)
+{
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+vec3 agx(vec3 color)
+{
+    const mat3 inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+    const mat3 outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+    const float minEv = -12.47393;
+    const float maxEv = 4.026069;
+
+    color = inset * color;
+    color = clamp(log2(max(color, 1e-10)), minEv, maxEv);
+    color = (color - minEv) / (maxEv - minEv);
+    color = agxContrast(color);
+    // Back to linear, the gamma is applied at the end.
+    return pow(max(outset * color, 0.), vec3(2.2));
+}
+
+vec3 srgbEncode(vec3 color)
+{
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1. / 2.4)) - 0.055,
+        step(0.0031308, color));
+}
+
+// SMPTE ST 2084, from a luminance normalized to 10000 nits.
+vec3 pqEncode(vec3 color)
+{
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 p = pow(color, vec3(m1));
+    return pow((c1 + c2 * p) / (1. + c3 * p), vec3(m2));
+}
+
+vec3 encodeOutput(vec3 color)
+{
+    // Rec.709 to Rec.2020 primaries, column-major.
+    const mat3 toRec2020 = mat3(
+        0.627404, 0.069097, 0.016391,
+        0.329283, 0.919540, 0.088013,
+        0.043313, 0.011362, 0.895595);
+
+    switch (PushConstants.outputTransfer) {
+    case OUTPUT_SRGB:
+        return srgbEncode(color);
+    case OUTPUT_PQ:
+        return pqEncode(toRec2020 * color * PushConstants.paperWhite / 10000.);
+    case OUTPUT_SCRGB:
+        // 1 is 80 nits.
+        return color * PushConstants.paperWhite / 80.;
+    default:
+        return color;
+    }
+}
+
+void main()
+{
+    uvec2 texel = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(texel, PushConstants.size))) {
+        return;
+    }
+
+    vec3 color = imageLoad(image, ivec2(texel)).rgb;
+
+    if (PushConstants.bloomEnabled != 0u) {
+        vec2 uv = (vec2(texel) + 0.5) * 0.5 / vec2(textureSize(bloom, 0));
+        color = mix(color, texture(bloom, uv).rgb, PushConstants.bloomIntensity);
+    }
+
+    color *= PushConstants.exposure.exposure;
+
+    switch (PushConstants.toneMapper) {
+    case TONE_MAPPER_REINHARD:
+        color = reinhard(color);
+        break;
+    case TONE_MAPPER_ACES:
+        color = aces(color);
+        break;
+    case TONE_MAPPER_AGX:
+        color = agx(color);
+        break;
+    default:
+        break;
+    }
+
+    // Colour grading, neutral when disabled.
+    color *= PushConstants.tint.rgb;
+    color = mix(vec3(dot(color, LUMINANCE)), color, PushConstants.saturation);
+    color = (color - 0.5) * PushConstants.contrast + 0.5;
+
+    color = pow(clamp(color, 0., 1.), vec3(1. / PushConstants.gamma));
+    imageStore(image, ivec2(texel), vec4(encodeOutput(color), 1.));
+}
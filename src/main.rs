@@ -8,6 +8,59 @@ mod window;
 
 use window::App;
 
+#[cfg(feature = "vulkan")]
+const USAGE: &str = "Usage : graphics [--headless <output.png> [<width>x<height>]] \
+[--compare <output folder> [<width>x<height>]] [--gpu <index|name>]";
+
 fn main() {
+    // `--headless <output.png> [<width>x<height>]` : one Vulkan frame without window.
+    // `--compare <output folder> [<width>x<height>]` : the Vulkan frame and the CPU reference
+    // without window, fails if they differ.
+    // `--gpu <index|name>` : the Vulkan GPU, read when it is chosen.
+    #[cfg(feature = "vulkan")]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        let flag_args = |flag: &str| {
+            let i = args.iter().position(|a| a == flag)?;
+            let path = args
+                .get(i + 1)
+                .filter(|a| !a.starts_with("--"))
+                .unwrap_or_else(|| usage_error(&format!("Missing path after {flag}")));
+            Some((std::path::Path::new(path), parse_size(args.get(i + 2))))
+        };
+        if let Some((output, size)) = flag_args("--headless") {
+            rasterizer::render_headless(size, output);
+            return;
+        }
+        #[cfg(feature = "cpu")]
+        if let Some((output, size)) = flag_args("--compare") {
+            let mismatch = rasterizer::compare_headless(size, output);
+            println!("{:.2}% of the pixels differ", mismatch * 100.);
+            if mismatch > rasterizer::MAX_MISMATCH {
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+
     App::run();
 }
+
+/// `<width>x<height>`, 1280x720 without it.
+#[cfg(feature = "vulkan")]
+fn parse_size(arg: Option<&String>) -> winit::dpi::PhysicalSize<u32> {
+    let Some(arg) = arg.filter(|a| !a.starts_with("--")) else {
+        return winit::dpi::PhysicalSize::new(1280, 720);
+    };
+    arg.split_once('x')
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        .filter(|(w, h)| *w > 0 && *h > 0)
+        .map(|(w, h)| winit::dpi::PhysicalSize::new(w, h))
+        .unwrap_or_else(|| usage_error(&format!("Invalid size `{arg}`")))
+}
+
+#[cfg(feature = "vulkan")]
+fn usage_error(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    std::process::exit(2)
+}
@@ -0,0 +1,145 @@
+//! Compares the Vulkan engine with the CPU reference, both rendering without window.
+//!
+//! Their shading models differ, so the frames compared are the depth views of the default Vulkan
+//! scene : both engines map the camera depth to the same grey, see `DEPTH_VIEW_FAR`.
+
+use std::{fs, path::Path};
+
+use ash::vk;
+use winit::dpi::PhysicalSize;
+
+use super::{
+    Settings,
+    cpu::Steps2Engine,
+    settings::ViewMode,
+    vulkan::{DEFAULT_SCENE, FOV_Y, VulkanEngine},
+};
+use crate::scene::{Camera, World};
+
+/// Channel difference under which two pixels are the same, the rasterisation rules and the MSAA
+/// differ along the edges.
+const PIXEL_TOLERANCE: u8 = 8;
+/// Fraction of different pixels over which the engines disagree.
+pub const MAX_MISMATCH: f32 = 0.02;
+
+/// Renders the depth view with both engines, saves them as `vulkan.png` and `cpu.png` in
+/// `output_folder`, and returns the fraction of the pixels which differ.
+///
+/// Panics if the frame is higher than wide, the CPU projection scales the other axis then.
+pub fn compare_headless(size: PhysicalSize<u32>, output_folder: &Path) -> f32 {
+    assert!(
+        size.width >= size.height,
+        "The frame must be wider than high"
+    );
+    let settings = Settings {
+        view_mode: ViewMode::Depth,
+        ..Default::default()
+    };
+    let camera = Camera::default();
+
+    let vulkan = render_vulkan(&settings, &camera, size);
+    let cpu = render_cpu(&settings, &camera, size);
+
+    fs::create_dir_all(output_folder).unwrap();
+    [("vulkan.png", &vulkan), ("cpu.png", &cpu)]
+        .into_iter()
+        .for_each(|(name, pixels)| {
+            ::image::RgbaImage::from_raw(size.width, size.height, pixels.clone())
+                .unwrap()
+                .save(output_folder.join(name))
+                .unwrap()
+        });
+
+    mismatch(&vulkan, &cpu)
+}
+
+/// RGBA8, row by row.
+fn render_vulkan(settings: &Settings, camera: &Camera, size: PhysicalSize<u32>) -> Vec<u8> {
+    let mut engine = VulkanEngine::new_headless(vk::Extent2D {
+        width: size.width,
+        height: size.height,
+    });
+    engine.render_offscreen(settings, camera)
+}
+
+/// RGBA8, row by row, by the Steps2 engine with the projection of the Vulkan one.
+fn render_cpu(settings: &Settings, camera: &Camera, size: PhysicalSize<u32>) -> Vec<u8> {
+    let camera = Camera {
+        canvas_side: camera.z_near * (FOV_Y / 2.).tan(),
+        ..*camera
+    };
+    let world = World::new_loaded(DEFAULT_SCENE, camera);
+    Steps2Engine::default()
+        .render(settings, &world, size)
+        .iter()
+        .flat_map(|argb| {
+            let [b, g, r, a] = argb.to_le_bytes();
+            [r, g, b, a]
+        })
+        .collect()
+}
+
+/// Fraction of the RGBA8 pixels differing by more than [`PIXEL_TOLERANCE`] on a channel.
+fn mismatch(a: &[u8], b: &[u8]) -> f32 {
+    assert_eq!(a.len(), b.len());
+    let different = a
+        .chunks_exact(4)
+        .zip(b.chunks_exact(4))
+        .filter(|(a, b)| {
+            a.iter()
+                .zip(b.iter())
+                .any(|(a, b)| a.abs_diff(*b) > PIXEL_TOLERANCE)
+        })
+        .count();
+    different as f32 / (a.len() / 4).max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(320, 180);
+
+    #[test]
+    fn mismatch_counts_pixels_over_the_tolerance() {
+        let a = [
+            10, 10, 10, 255, 0, 0, 0, 255, 100, 100, 100, 255, 7, 7, 7, 255,
+        ];
+        let b = [
+            10, 10, 10, 255, 0, 0, 9, 255, 100, 92, 100, 255, 0, 0, 0, 255,
+        ];
+        assert_eq!(mismatch(&a, &a), 0.);
+        assert_eq!(mismatch(&a, &b), 0.25);
+    }
+
+    #[test]
+    fn cpu_reference_draws_the_scene() {
+        let settings = Settings {
+            view_mode: ViewMode::Depth,
+            ..Default::default()
+        };
+        let pixels = render_cpu(&settings, &Camera::default(), SIZE);
+        assert_eq!(pixels.len(), (SIZE.width * SIZE.height * 4) as usize);
+        // The background is black, the meshes grey.
+        let drawn = pixels.chunks_exact(4).filter(|p| p[0] > 0).count();
+        assert!(
+            drawn > 0 && drawn < pixels.len() / 4,
+            "{drawn} pixels drawn"
+        );
+        assert!(pixels.chunks_exact(4).all(|p| p[0] == p[1] && p[1] == p[2]));
+    }
+
+    /// Run with `cargo test -- --ignored`, with a software driver such as lavapipe on CI.
+    #[test]
+    #[ignore = "needs a Vulkan driver"]
+    fn vulkan_matches_cpu_reference() {
+        let output = std::env::temp_dir().join("graphics_compare");
+        let mismatch = compare_headless(SIZE, &output);
+        assert!(
+            mismatch <= MAX_MISMATCH,
+            "{:.1}% of the pixels differ, see {}",
+            mismatch * 100.,
+            output.to_string_lossy()
+        );
+    }
+}
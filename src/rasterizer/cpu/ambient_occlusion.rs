@@ -0,0 +1,263 @@
+//! Screen-space ambient occlusion of the CPU engines, a post-process on their depth buffers.
+//!
+//! Same sampling and bilateral blur as `resources/ssao.comp.glsl`. The engines don't keep the
+//! ambient term apart, so the occlusion darkens the whole colour.
+
+use glam::{Vec3, vec3};
+use rayon::prelude::*;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    maths::ColorF32,
+    rasterizer::settings::{AoSettings, Settings, ViewMode, Wireframe},
+    scene::Camera,
+};
+
+/// Taps on each side of the blur, along each axis.
+const BLUR_RADIUS: i32 = 4;
+/// Difference of depth, relative to the depth of the pixel, past which a tap is ignored by the
+/// blur.
+const BLUR_DEPTH_TOLERANCE: f32 = 0.05;
+/// Of the radius, keeps the flat surfaces from occluding themselves.
+const DEPTH_BIAS: f32 = 0.05;
+
+/// Visibility per pixel, reused between the frames.
+#[derive(Default, Clone)]
+pub struct AmbientOcclusion {
+    /// Distance along the view direction, infinite for the background.
+    depth: Vec<f32>,
+    visibility: Vec<f32>,
+    blurred: Vec<f32>,
+}
+
+impl AmbientOcclusion {
+    /// The visibility of every pixel in [0,1], if the occlusion applies to the frame.
+    ///
+    /// `depth` gives the distance of the pixel at an index along the view direction, infinite
+    /// for the background, like the engine depth buffers.
+    pub fn compute(
+        &mut self,
+        settings: &Settings,
+        camera: &Camera,
+        size: PhysicalSize<u32>,
+        depth: impl Fn(usize) -> f32 + Sync,
+    ) -> Option<&[f32]> {
+        // The debug views show the raw material.
+        if !settings.ao.enabled
+            || settings.view_mode != ViewMode::Shaded
+            || settings.wireframe == Wireframe::Only
+        {
+            return None;
+        }
+
+        let len = size.width as usize * size.height as usize;
+        self.depth.resize(len, 0.);
+        self.visibility.resize(len, 1.);
+        self.blurred.resize(len, 1.);
+        self.depth
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, d)| *d = depth(i));
+
+        let view = ViewSpace::new(camera, size);
+        let width = size.width as usize;
+        let ao = &settings.ao;
+        let samples = ao.samples.clamp(1, AoSettings::MAX_SAMPLES);
+        let bias = ao.radius * DEPTH_BIAS;
+        let depth = &self.depth[..];
+        self.visibility
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                row.iter_mut().enumerate().for_each(|(x, visibility)| {
+                    *visibility = 1.;
+                    let d = depth[x + y * width];
+                    if !d.is_finite() {
+                        return;
+                    }
+                    let p = view.position(x, y, d);
+                    let n = view.normal(depth, x, y, p);
+                    let (t, b) = n.any_orthonormal_pair();
+                    let angle = interleaved_gradient_noise(x, y) * std::f32::consts::TAU;
+                    let (sin, cos) = angle.sin_cos();
+                    let (t, b) = (t * cos + b * sin, b * cos - t * sin);
+
+                    let occlusion: f32 = (0..samples)
+                        .map(|i| {
+                            let k = kernel(i, samples);
+                            let s = p + (t * k.x + b * k.y + n * k.z) * ao.radius;
+                            let Some(index) = view.pixel(s) else {
+                                return 0.;
+                            };
+                            let scene_d = depth[index];
+                            if scene_d >= s.z - bias {
+                                return 0.;
+                            }
+                            // Far in front, the surface doesn't hide this pixel.
+                            smoothstep(ao.radius / (d - scene_d).abs())
+                        })
+                        .sum();
+                    *visibility = (1. - occlusion / samples as f32).powf(ao.intensity);
+                });
+            });
+
+        blur(depth, &self.visibility, &mut self.blurred, size, (1, 0));
+        blur(depth, &self.blurred, &mut self.visibility, size, (0, 1));
+        Some(&self.visibility[..])
+    }
+}
+
+/// Multiplies the colour, keeping its alpha.
+pub fn darken(color: u32, visibility: f32) -> u32 {
+    let c = ColorF32::from_argb_u32(color);
+    ColorF32 {
+        a: c.a,
+        ..c * visibility
+    }
+    .as_color_u32()
+}
+
+/// Inverse of [`crate::scene::local_to_clipspace`], with the depth along the view direction as
+/// the `z`.
+struct ViewSpace {
+    size: PhysicalSize<u32>,
+    /// From the view space, divided by the depth, to [-1,1].
+    scale_x: f32,
+    scale_y: f32,
+}
+
+impl ViewSpace {
+    fn new(camera: &Camera, size: PhysicalSize<u32>) -> Self {
+        let ratio_w_h = size.width as f32 / size.height as f32;
+        let scale = camera.z_near / camera.canvas_side;
+        let (scale_x, scale_y) = if size.width > size.height {
+            (scale / ratio_w_h, scale)
+        } else {
+            (scale, scale * ratio_w_h)
+        };
+        Self {
+            size,
+            scale_x,
+            scale_y,
+        }
+    }
+
+    /// At the centre of the pixel.
+    fn position(&self, x: usize, y: usize, depth: f32) -> Vec3 {
+        let ndc_x = (x as f32 + 0.5) / self.size.width as f32 * 2. - 1.;
+        let ndc_y = 1. - (y as f32 + 0.5) / self.size.height as f32 * 2.;
+        vec3(
+            ndc_x * depth / self.scale_x,
+            ndc_y * depth / self.scale_y,
+            depth,
+        )
+    }
+
+    /// Index of the pixel covering `p`, if on the screen.
+    fn pixel(&self, p: Vec3) -> Option<usize> {
+        if p.z <= 0. {
+            return None;
+        }
+        let x = (p.x * self.scale_x / p.z + 1.) / 2. * self.size.width as f32;
+        let y = (1. - p.y * self.scale_y / p.z) / 2. * self.size.height as f32;
+        super::buffer_index(vec3(x, y, 0.), self.size)
+    }
+
+    /// Facing the camera, from the closest neighbours on each axis so that the edges don't bend
+    /// it.
+    fn normal(&self, depth: &[f32], x: usize, y: usize, p: Vec3) -> Vec3 {
+        let width = self.size.width as usize;
+        let neighbour = |nx: Option<usize>, ny: Option<usize>| {
+            let (nx, ny) = (nx?, ny?);
+            if nx >= width || ny >= self.size.height as usize {
+                return None;
+            }
+            let d = depth[nx + ny * width];
+            d.is_finite().then(|| self.position(nx, ny, d) - p)
+        };
+        let closest = |a: Option<Vec3>, b: Option<Vec3>| match (a, b) {
+            (Some(a), Some(b)) if a.z.abs() <= b.z.abs() => Some(a),
+            (Some(a), None) => Some(a),
+            (_, b) => b.map(|b| -b),
+        };
+        let dx = closest(
+            neighbour(Some(x + 1), Some(y)),
+            neighbour(x.checked_sub(1), Some(y)),
+        );
+        let dy = closest(
+            neighbour(Some(x), Some(y + 1)),
+            neighbour(Some(x), y.checked_sub(1)),
+        );
+        let n = match (dx, dy) {
+            (Some(dx), Some(dy)) => dx.cross(dy).normalize_or_zero(),
+            _ => Vec3::ZERO,
+        };
+        let n = if n == Vec3::ZERO { -p.normalize() } else { n };
+        if n.dot(p) > 0. { -n } else { n }
+    }
+}
+
+/// Separable pass, weighted by the distance and by the difference of depth.
+fn blur(depth: &[f32], src: &[f32], dst: &mut [f32], size: PhysicalSize<u32>, step: (i32, i32)) {
+    let (width, height) = (size.width as i32, size.height as i32);
+    dst.par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            row.iter_mut().enumerate().for_each(|(x, out)| {
+                let index = x + y * width as usize;
+                let d = depth[index];
+                if !d.is_finite() {
+                    *out = src[index];
+                    return;
+                }
+                let (sum, weights) = (-BLUR_RADIUS..=BLUR_RADIUS)
+                    .filter_map(|i| {
+                        let (tx, ty) = (x as i32 + i * step.0, y as i32 + i * step.1);
+                        if tx < 0 || ty < 0 || tx >= width || ty >= height {
+                            return None;
+                        }
+                        let tap = (tx + ty * width) as usize;
+                        let depth_weight =
+                            (1. - (depth[tap] - d).abs() / (d * BLUR_DEPTH_TOLERANCE)).max(0.);
+                        let distance = i as f32 / BLUR_RADIUS as f32;
+                        let weight = depth_weight * (-2. * distance * distance).exp();
+                        Some((src[tap] * weight, weight))
+                    })
+                    .fold((0., 0.), |(s, w), (ts, tw)| (s + ts, w + tw));
+                // The centre always has a weight of 1.
+                *out = sum / weights;
+            });
+        });
+}
+
+/// `i`-th of `count` points in the hemisphere around z, closer to the centre for the first ones.
+fn kernel(i: u32, count: u32) -> Vec3 {
+    let (u, v) = (hash(i, 0), hash(i, 1));
+    let phi = u * std::f32::consts::TAU;
+    let cos_theta = v;
+    let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+    let t = (i as f32 + 0.5) / count as f32;
+    let scale = 0.1 + 0.9 * t * t;
+    vec3(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta) * scale
+}
+
+/// In [0,1), a different sequence per `seed`. See `hash` in `resources/ssao.comp.glsl`.
+fn hash(i: u32, seed: u32) -> f32 {
+    let mut x = i.wrapping_mul(0x9e37_79b9) ^ seed.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Rotation of the kernel per pixel, in [0,1), smoothed out by the blur.
+fn interleaved_gradient_noise(x: usize, y: usize) -> f32 {
+    (52.982_918 * (0.067_110_56 * x as f32 + 0.005_837_15 * y as f32).fract()).fract()
+}
+
+fn smoothstep(x: f32) -> f32 {
+    let x = x.clamp(0., 1.);
+    x * x * (3. - 2. * x)
+}
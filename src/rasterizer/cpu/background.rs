@@ -0,0 +1,104 @@
+//! Environment of the CPU engines, sampled by view direction where nothing was drawn.
+//!
+//! The atmosphere is computed once in an equirectangular image like the other environments,
+//! since the sun of the CPU engines doesn't move.
+
+use glam::{Mat3, Vec3, vec3};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    maths::ColorF32,
+    rasterizer::settings::{Background, Settings, ViewMode, Wireframe},
+    scene::{
+        Camera,
+        environment::{EnvironmentImage, EnvironmentSource},
+    },
+};
+
+/// The environment of [`Settings::background`], loaded again when another one is selected.
+#[derive(Default, Clone)]
+pub struct Environment {
+    /// Listed on the first use.
+    sources: Vec<EnvironmentSource>,
+    /// With its index in `sources`.
+    loaded: Option<(usize, EnvironmentImage)>,
+}
+
+impl Environment {
+    /// The background of the frame, if it isn't the flat colour.
+    ///
+    /// `sun_direction` is the one of the world, from the sun.
+    pub fn sky(
+        &mut self,
+        settings: &Settings,
+        camera: &Camera,
+        sun_direction: Vec3,
+        size: PhysicalSize<u32>,
+    ) -> Option<Sky<'_>> {
+        // The debug views show the raw material.
+        if settings.view_mode != ViewMode::Shaded || settings.wireframe == Wireframe::Only {
+            return None;
+        }
+        let Background::Environment(index) = settings.background else {
+            return None;
+        };
+        if self.sources.is_empty() {
+            self.sources = EnvironmentSource::list();
+        }
+        let source = self.sources.get(index)?;
+        if self.loaded.as_ref().is_none_or(|(i, _)| *i != index) {
+            // Keeps the previous one, or the atmosphere, under the new index not to retry.
+            let image = EnvironmentImage::load(source, -sun_direction).unwrap_or_else(|e| {
+                eprintln!("Can't load the environment, keeping the previous one : {e}");
+                self.loaded.take().map_or_else(
+                    || {
+                        EnvironmentImage::load(&EnvironmentSource::Atmosphere, -sun_direction)
+                            .unwrap()
+                    },
+                    |(_, image)| image,
+                )
+            });
+            self.loaded = Some((index, image));
+        }
+
+        let ratio_w_h = size.width as f32 / size.height as f32;
+        let scale = camera.z_near / camera.canvas_side;
+        let (scale_x, scale_y) = if size.width > size.height {
+            (scale / ratio_w_h, scale)
+        } else {
+            (scale, scale * ratio_w_h)
+        };
+        Some(Sky {
+            image: &self.loaded.as_ref().unwrap().1,
+            rotation: Mat3::from_mat4(camera.rot_mat()),
+            size,
+            scale_x,
+            scale_y,
+        })
+    }
+}
+
+/// Colour of the background by pixel, for one frame.
+pub struct Sky<'a> {
+    image: &'a EnvironmentImage,
+    /// From the view space to the world space.
+    rotation: Mat3,
+    size: PhysicalSize<u32>,
+    /// From the view space, divided by the depth, to [-1,1].
+    scale_x: f32,
+    scale_y: f32,
+}
+
+impl Sky<'_> {
+    /// Of the pixel at `index` in the buffer, opaque. The radiance is clamped to [0,1].
+    pub fn color(&self, index: usize) -> u32 {
+        let width = self.size.width as usize;
+        let (x, y) = (index % width, index / width);
+        let ndc_x = (x as f32 + 0.5) / self.size.width as f32 * 2. - 1.;
+        let ndc_y = 1. - (y as f32 + 0.5) / self.size.height as f32 * 2.;
+        // The camera looks along -Z.
+        let dir = self.rotation * vec3(ndc_x / self.scale_x, ndc_y / self.scale_y, -1.);
+        let c = self.image.sample(dir).clamp(Vec3::ZERO, Vec3::ONE);
+        ColorF32::from_rgba([c.x, c.y, c.z, 1.]).as_color_u32()
+    }
+}
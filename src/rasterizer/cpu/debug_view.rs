@@ -0,0 +1,187 @@
+//! Debug visualisations shared by the CPU engines, see [`ViewMode`] and [`Wireframe`].
+
+use glam::{Mat3, Mat4, Vec3};
+use std::ops::DerefMut;
+use winit::dpi::PhysicalSize;
+
+use super::buffer_index;
+use crate::{
+    maths::ColorF32,
+    rasterizer::settings::{DEPTH_VIEW_FAR, ViewMode},
+    scene::{MeshAsset, Texture},
+};
+
+pub const WIREFRAME_COLOR: u32 = 0xffe0e0e0;
+
+/// Tint of the selected nodes, see [`highlight`].
+const HIGHLIGHT_COLOR: [f32; 4] = [1., 0.6, 0.1, 1.];
+const HIGHLIGHT_STRENGTH: f32 = 0.5;
+
+/// Relative tolerance when testing wireframe edges against the depth buffer.
+const WIREFRAME_DEPTH_BIAS: f32 = 1.01;
+
+/// Material to use instead of the surface one, `None` if the view mode doesn't override it.
+///
+/// `level` is the level of detail drawn, see [`crate::scene::lod`].
+pub fn debug_material(
+    view_mode: ViewMode,
+    mesh: &MeshAsset,
+    surface_index: usize,
+    level: usize,
+    is: &[usize],
+    world_transform: &Mat4,
+) -> Option<Texture> {
+    let vertex_colors = |f: &dyn Fn(usize) -> [f32; 4]| {
+        let (c0, c1, c2) = (f(is[0]), f(is[1]), f(is[2]));
+        if c0 == c1 && c0 == c2 {
+            Texture::Color(ColorF32::from_rgba(c0).as_color_u32())
+        } else {
+            Texture::VertexColor(
+                ColorF32::from_rgba(c0).as_color_u32(),
+                ColorF32::from_rgba(c1).as_color_u32(),
+                ColorF32::from_rgba(c2).as_color_u32(),
+            )
+        }
+    };
+
+    match view_mode {
+        ViewMode::Shaded | ViewMode::Depth | ViewMode::Overdraw => None,
+        ViewMode::Normals => {
+            let normal_mat = Mat3::from_mat4(*world_transform).inverse().transpose();
+            Some(vertex_colors(&|i| {
+                ((normal_mat * mesh.vertices[i].normal).normalize_or_zero() * 0.5 + 0.5)
+                    .extend(1.)
+                    .to_array()
+            }))
+        }
+        ViewMode::Uvs => Some(vertex_colors(&|i| {
+            let v = &mesh.vertices[i];
+            [v.uv_x.rem_euclid(1.), v.uv_y.rem_euclid(1.), 0., 1.]
+        })),
+        ViewMode::MeshColor => Some(Texture::Color(
+            ColorF32::from_rgba(false_color(mesh.id)).as_color_u32(),
+        )),
+        ViewMode::SurfaceColor => Some(Texture::Color(
+            ColorF32::from_rgba(false_color(
+                mesh.id ^ (surface_index as u64).wrapping_mul(31),
+            ))
+            .as_color_u32(),
+        )),
+        ViewMode::Lod => Some(Texture::Color(heatmap(level as u32 + 1))),
+    }
+}
+
+/// Mixes the material with the selection color.
+pub fn highlight(material: Texture) -> Texture {
+    let tint = |c: u32| {
+        (ColorF32::from_argb_u32(c) * (1. - HIGHLIGHT_STRENGTH)
+            + ColorF32::from_rgba(HIGHLIGHT_COLOR) * HIGHLIGHT_STRENGTH)
+            .as_color_u32()
+    };
+    match material {
+        Texture::Color(c) => Texture::Color(tint(c)),
+        Texture::VertexColor(c0, c1, c2) => Texture::VertexColor(tint(c0), tint(c1), tint(c2)),
+    }
+}
+
+/// Replaces the `buffer` with the depth, white at the camera and black from
+/// [`DEPTH_VIEW_FAR`] like the Vulkan engine.
+pub fn depth_view<B: DerefMut<Target = [u32]>>(buffer: &mut B, depth_buffer: &[f32]) {
+    buffer
+        .iter_mut()
+        .zip(depth_buffer.iter())
+        .for_each(|(col, depth)| {
+            *col = if depth.is_finite() {
+                let grey = 1. - (depth / DEPTH_VIEW_FAR).clamp(0., 1.);
+                ColorF32::from_rgba([grey, grey, grey, 1.]).as_color_u32()
+            } else {
+                0xff000000
+            };
+        });
+}
+
+/// Replaces the `buffer` with a heatmap of the number of writes per pixel.
+pub fn overdraw_view<B: DerefMut<Target = [u32]>>(buffer: &mut B, overdraw_buffer: &[u32]) {
+    buffer
+        .iter_mut()
+        .zip(overdraw_buffer.iter())
+        .for_each(|(col, count)| *col = heatmap(*count));
+}
+
+/// Deterministic false colour for debug views from any id (hash, index, ...).
+fn false_color(id: u64) -> [f32; 4] {
+    // Fibonacci hashing to spread close ids.
+    let h = id.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let c = |shift: u32| ((h >> shift) & 0xff) as f32 / 255. * 0.8 + 0.2;
+    [c(40), c(48), c(56), 1.]
+}
+
+/// Black → blue → green → yellow → red → white.
+pub fn heatmap(count: u32) -> u32 {
+    match count {
+        0 => 0xff000000,
+        1 => 0xff0000ff,
+        2 => 0xff00ff00,
+        3 => 0xffffff00,
+        4 => 0xffff0000,
+        _ => 0xffffffff,
+    }
+}
+
+/// Draws the edges of a raster triangle (x,y in pixels, z as camera depth).
+///
+/// Edges hidden behind the `depth_buffer` aren't drawn.
+pub fn draw_wireframe<B: DerefMut<Target = [u32]>>(
+    buffer: &mut B,
+    depth_buffer: &[f32],
+    z_near: f32,
+    size: PhysicalSize<u32>,
+    (p0, p1, p2): (Vec3, Vec3, Vec3),
+) {
+    draw_line(buffer, depth_buffer, z_near, size, p0, p1);
+    draw_line(buffer, depth_buffer, z_near, size, p1, p2);
+    draw_line(buffer, depth_buffer, z_near, size, p2, p0);
+}
+
+fn draw_line<B: DerefMut<Target = [u32]>>(
+    buffer: &mut B,
+    depth_buffer: &[f32],
+    z_near: f32,
+    size: PhysicalSize<u32>,
+    a: Vec3,
+    b: Vec3,
+) {
+    if a.z <= z_near || b.z <= z_near {
+        return;
+    }
+
+    let (min, max) = (a.min(b), a.max(b));
+    if max.x < 0. || max.y < 0. || min.x >= size.width as f32 || min.y >= size.height as f32 {
+        return;
+    }
+
+    // Clamped so a huge edge doesn't stall the frame.
+    let steps = (b.x - a.x)
+        .abs()
+        .max((b.y - a.y).abs())
+        .ceil()
+        .min(((size.width + size.height) * 2) as f32) as usize;
+    let (a_z_inv, b_z_inv) = (1. / a.z, 1. / b.z);
+
+    (0..=steps).for_each(|i| {
+        let t = if steps == 0 {
+            0.
+        } else {
+            i as f32 / steps as f32
+        };
+        let p = a.lerp(b, t);
+        // Depth doesn't evolve linearly (its inverse does).
+        let depth = 1. / (a_z_inv + (b_z_inv - a_z_inv) * t);
+
+        if let Some(index) = buffer_index(p, size)
+            && depth <= depth_buffer[index] * WIREFRAME_DEPTH_BIAS
+        {
+            buffer[index] = WIREFRAME_COLOR;
+        }
+    });
+}
@@ -0,0 +1,78 @@
+//! Sharing of the transformed vertices of a mesh instance.
+//!
+//! A mesh referenced by many nodes is drawn once per node, each node being an instance with its
+//! own world positions. The triangles of an instance share most of their vertices, so they are
+//! transformed to world space once per instance and per frame, instead of once per triangle
+//! corner, even when the BVH traversal interleaves the surfaces of different nodes.
+
+use std::collections::HashMap;
+
+use glam::{Vec3, Vec4Swizzles};
+
+use crate::scene::Node;
+
+/// The transformed vertices of one node.
+#[derive(Default, Clone)]
+struct NodeVertices {
+    /// World positions of the vertices of the node mesh.
+    positions: Vec<Vec3>,
+    /// `generation` when each position was transformed.
+    stamps: Vec<u32>,
+    /// `generation` of the last frame using the node.
+    used: u32,
+}
+
+#[derive(Clone)]
+pub struct InstanceVertices {
+    /// By address of the node, only compared.
+    nodes: HashMap<usize, NodeVertices>,
+    /// Incremented every frame, never 0 which is the stamp of the new positions.
+    generation: u32,
+}
+
+impl Default for InstanceVertices {
+    fn default() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            generation: 1,
+        }
+    }
+}
+
+impl InstanceVertices {
+    /// Forgets the transformed vertices, to be done every frame since the nodes could have moved.
+    ///
+    /// The nodes not drawn in the last frame are dropped, the others keep their allocations.
+    pub fn clear(&mut self) {
+        let generation = self.generation;
+        self.nodes.retain(|_, n| n.used == generation);
+        self.generation = self.generation.wrapping_add(1);
+        // Stamps of the previous cycle could match again.
+        if self.generation == 0 {
+            self.nodes.values_mut().for_each(|n| n.stamps.fill(0));
+            self.generation = 1;
+        }
+    }
+
+    /// World positions of the triangle `is` of the mesh of `node`.
+    pub fn triangle(&mut self, node: &Node, is: &[usize]) -> (Vec3, Vec3, Vec3) {
+        let mesh = node.mesh.as_ref().unwrap();
+        let generation = self.generation;
+        let vertices = self.nodes.entry(node as *const Node as usize).or_default();
+        vertices.used = generation;
+        if vertices.positions.len() < mesh.vertices.len() {
+            vertices.positions.resize(mesh.vertices.len(), Vec3::ZERO);
+            vertices.stamps.resize(mesh.vertices.len(), 0);
+        }
+
+        let mut position = |i: usize| {
+            if vertices.stamps[i] != generation {
+                vertices.stamps[i] = generation;
+                vertices.positions[i] =
+                    (node.world_transform * mesh.vertices[i].position.extend(1.)).xyz();
+            }
+            vertices.positions[i]
+        };
+        (position(is[0]), position(is[1]), position(is[2]))
+    }
+}
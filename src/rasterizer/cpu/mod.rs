@@ -1,4 +1,9 @@
+mod ambient_occlusion;
+mod background;
 mod cpu_engine;
+mod debug_view;
+mod instancing;
+mod occlusion;
 mod parallel;
 mod single_threaded;
 
@@ -11,10 +16,14 @@ use crate::{
     window::AppObserver,
 };
 pub use cpu_engine::CPUEngine;
+#[cfg(feature = "vulkan")]
+pub use single_threaded::Steps2Engine;
 
 #[cfg(feature = "stats")]
 #[derive(Default, Debug, Clone)]
 pub struct Stats {
+    pub nb_surfaces_occlusion_tested: usize,
+    pub nb_surfaces_occluded: usize,
     pub nb_triangles_tot: usize,
     pub nb_triangles_sight: usize,
     pub nb_triangles_facing: usize,
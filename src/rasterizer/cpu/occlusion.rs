@@ -0,0 +1,343 @@
+//! Hierarchical-Z occlusion culling.
+//!
+//! The large surfaces of the frame are rasterized first in a low resolution depth buffer, keeping
+//! for each tile a depth that nothing visible can be behind. It is reduced into a pyramid of the
+//! farthest depths, then the bounds of the surfaces are tested against it before emitting their
+//! triangles.
+
+use glam::{Mat4, Vec3, vec3};
+use winit::dpi::PhysicalSize;
+
+use super::{edge_function, vec_cross_z};
+use crate::{
+    rasterizer::settings::Settings,
+    scene::{Bounds, Camera, Node, Scene, to_cam_tr, to_raster},
+};
+
+/// Side of a tile of the finest level, in pixels.
+const OCCLUSION_TILE: u32 = 8;
+/// Minimal part of the screen covered by the bounds of a surface to use it as an occluder.
+const OCCLUDER_MIN_SCREEN_RATIO: f32 = 0.02;
+/// Maximum number of occluder triangles rasterized per frame.
+const OCCLUDER_TRIANGLE_BUDGET: usize = 8192;
+/// Maximum number of tiles read on a side when testing bounds.
+const OCCLUSION_TEST_TILES: u32 = 4;
+
+#[derive(Default, Clone)]
+struct DepthLevel {
+    width: u32,
+    height: u32,
+    /// Farthest depth visible in each tile, `f32::INFINITY` if not covered.
+    depth: Vec<f32>,
+}
+
+impl DepthLevel {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            depth: vec![f32::INFINITY; (width * height) as usize],
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> f32 {
+        self.depth[(x + y * self.width) as usize]
+    }
+}
+
+/// Screen rectangle (in pixels) and nearest depth of projected bounds.
+struct ScreenRect {
+    min: Vec3,
+    max: Vec3,
+}
+
+#[derive(Default, Clone)]
+pub struct OcclusionCuller {
+    enabled: bool,
+    size: PhysicalSize<u32>,
+    /// From the finest level to a single tile.
+    levels: Vec<DepthLevel>,
+    nb_occluder_triangles: usize,
+
+    pub nb_surfaces_tested: usize,
+    pub nb_surfaces_occluded: usize,
+}
+
+impl OcclusionCuller {
+    /// Rasterizes the occluders of the `scene` and builds the depth pyramid.
+    ///
+    /// Disabled if [`Settings::culling_occlusion`] isn't set, nothing is occluded then.
+    pub fn prepare(
+        &mut self,
+        settings: &Settings,
+        camera: &Camera,
+        size: PhysicalSize<u32>,
+        ratio_w_h: f32,
+        scene: &Scene,
+    ) {
+        self.enabled = settings.culling_occlusion;
+        self.nb_occluder_triangles = 0;
+        self.nb_surfaces_tested = 0;
+        self.nb_surfaces_occluded = 0;
+        if !self.enabled {
+            return;
+        }
+
+        self.size = size;
+        self.levels.clear();
+        let (mut width, mut height) = (
+            size.width.div_ceil(OCCLUSION_TILE).max(1),
+            size.height.div_ceil(OCCLUSION_TILE).max(1),
+        );
+        self.levels.push(DepthLevel::new(width, height));
+        while width > 1 || height > 1 {
+            width = width.div_ceil(2);
+            height = height.div_ceil(2);
+            self.levels.push(DepthLevel::new(width, height));
+        }
+
+        if settings.culling_bvh {
+            scene.for_each_visible_surface(&camera.frustum(size, ratio_w_h), |node, s_index| {
+                self.add_occluder(camera, ratio_w_h, node, s_index);
+            });
+        } else {
+            scene
+                .top_nodes()
+                .iter()
+                .for_each(|n| self.add_occluders(camera, ratio_w_h, &n.read().unwrap()));
+        }
+
+        self.build_pyramid();
+    }
+
+    fn add_occluders(&mut self, camera: &Camera, ratio_w_h: f32, node: &Node) {
+        if let Some(mesh) = node.mesh.as_ref() {
+            (0..mesh.surfaces.len()).for_each(|s_index| {
+                self.add_occluder(camera, ratio_w_h, node, s_index);
+            });
+        }
+        node.children
+            .iter()
+            .for_each(|c| self.add_occluders(camera, ratio_w_h, &c.read().unwrap()));
+    }
+
+    /// Rasterizes the surface if it covers enough of the screen.
+    fn add_occluder(&mut self, camera: &Camera, ratio_w_h: f32, node: &Node, s_index: usize) {
+        let Some(mesh) = node.mesh.as_ref() else {
+            return;
+        };
+        let s = &mesh.surfaces[s_index];
+        if self.nb_occluder_triangles + s.count / 3 > OCCLUDER_TRIANGLE_BUDGET {
+            return;
+        }
+
+        let to_cam_tr = to_cam_tr(camera, &node.world_transform);
+        let Some(rect) = self.screen_rect(camera, &to_cam_tr, ratio_w_h, &s.bounds) else {
+            return;
+        };
+        let covered = (rect.max.x - rect.min.x) * (rect.max.y - rect.min.y);
+        if covered < OCCLUDER_MIN_SCREEN_RATIO * (self.size.width * self.size.height) as f32 {
+            return;
+        }
+
+        self.nb_occluder_triangles += s.count / 3;
+        mesh.indices[s.start_index..s.start_index + s.count]
+            .chunks_exact(3)
+            .for_each(|is| {
+                let p0 = to_raster(
+                    mesh.vertices[is[0]].position,
+                    camera,
+                    &to_cam_tr,
+                    self.size,
+                    ratio_w_h,
+                );
+                let p1 = to_raster(
+                    mesh.vertices[is[1]].position,
+                    camera,
+                    &to_cam_tr,
+                    self.size,
+                    ratio_w_h,
+                );
+                let p2 = to_raster(
+                    mesh.vertices[is[2]].position,
+                    camera,
+                    &to_cam_tr,
+                    self.size,
+                    ratio_w_h,
+                );
+                self.rasterize_occluder(camera.z_near, p0, p1, p2);
+            });
+    }
+
+    /// Conservative rasterization : a tile is only written if the triangle covers all its pixels,
+    /// with the farthest depth of the triangle over the tile.
+    fn rasterize_occluder(&mut self, z_near: f32, p0: Vec3, p1: Vec3, p2: Vec3) {
+        // Clipping isn't done, triangles crossing the near plane are skipped.
+        if p0.z <= z_near || p1.z <= z_near || p2.z <= z_near {
+            return;
+        }
+        let (p01, p12, p20) = (p1 - p0, p2 - p1, p0 - p2);
+        // Back faces are culled when drawing, they can't hide anything.
+        if vec_cross_z(p01, p20) < 0. {
+            return;
+        }
+        let tri_area = edge_function(p20, p01);
+        if tri_area == 0. {
+            return;
+        }
+
+        let level = &mut self.levels[0];
+        let min = p0.min(p1).min(p2);
+        let max = p0.max(p1).max(p2);
+        let tile_range = |min: f32, max: f32, tiles: u32| {
+            let min = (min.max(0.) as u32).div_ceil(OCCLUSION_TILE);
+            let max = ((max.max(0.) as u32 + 1) / OCCLUSION_TILE).min(tiles);
+            min..max
+        };
+        let (size_x, size_y) = (self.size.width as f32 - 1., self.size.height as f32 - 1.);
+
+        tile_range(min.y, max.y, level.height).for_each(|ty| {
+            tile_range(min.x, max.x, level.width).for_each(|tx| {
+                let (x0, y0) = ((tx * OCCLUSION_TILE) as f32, (ty * OCCLUSION_TILE) as f32);
+                let (x1, y1) = (
+                    (x0 + (OCCLUSION_TILE - 1) as f32).min(size_x),
+                    (y0 + (OCCLUSION_TILE - 1) as f32).min(size_y),
+                );
+
+                // The triangle is convex : the tile is covered if its corner pixels are.
+                let mut farthest = 0_f32;
+                for corner in [
+                    vec3(x0, y0, 0.),
+                    vec3(x1, y0, 0.),
+                    vec3(x0, y1, 0.),
+                    vec3(x1, y1, 0.),
+                ] {
+                    let e01 = edge_function(p01, corner - p0);
+                    let e12 = edge_function(p12, corner - p1);
+                    let e20 = edge_function(p20, corner - p2);
+                    if e01 < 0. || e12 < 0. || e20 < 0. {
+                        return;
+                    }
+                    // Same interpolation as the rasterization : the inverse of the depth is
+                    // linear, so the farthest depth of the tile is on a corner.
+                    let p2_z_inv = 1. / p2.z;
+                    let depth = 1.
+                        / (p2_z_inv
+                            + (1. / p0.z - p2_z_inv) * (e12 / tri_area)
+                            + (1. / p1.z - p2_z_inv) * (e20 / tri_area));
+                    farthest = farthest.max(depth);
+                }
+
+                let tile = &mut level.depth[(tx + ty * level.width) as usize];
+                *tile = tile.min(farthest);
+            });
+        });
+    }
+
+    fn build_pyramid(&mut self) {
+        (1..self.levels.len()).for_each(|l| {
+            let (finer, coarser) = self.levels.split_at_mut(l);
+            let (finer, coarser) = (&finer[l - 1], &mut coarser[0]);
+            (0..coarser.height).for_each(|y| {
+                (0..coarser.width).for_each(|x| {
+                    let (x0, y0) = (x * 2, y * 2);
+                    let (x1, y1) = (
+                        (x0 + 1).min(finer.width - 1),
+                        (y0 + 1).min(finer.height - 1),
+                    );
+                    coarser.depth[(x + y * coarser.width) as usize] = finer
+                        .get(x0, y0)
+                        .max(finer.get(x1, y0))
+                        .max(finer.get(x0, y1))
+                        .max(finer.get(x1, y1));
+                });
+            });
+        });
+    }
+
+    /// Projected `bounds`, `None` if they cross the near plane or are out of the screen.
+    fn screen_rect(
+        &self,
+        camera: &Camera,
+        to_cam_tr: &Mat4,
+        ratio_w_h: f32,
+        bounds: &Bounds,
+    ) -> Option<ScreenRect> {
+        let mut min = Vec3::INFINITY;
+        let mut max = Vec3::NEG_INFINITY;
+        for corner in [
+            vec3(1., 1., 1.),
+            vec3(1., 1., -1.),
+            vec3(1., -1., 1.),
+            vec3(1., -1., -1.),
+            vec3(-1., 1., 1.),
+            vec3(-1., 1., -1.),
+            vec3(-1., -1., 1.),
+            vec3(-1., -1., -1.),
+        ] {
+            let p = to_raster(
+                bounds.origin + corner * bounds.extents,
+                camera,
+                to_cam_tr,
+                self.size,
+                ratio_w_h,
+            );
+            if p.z <= camera.z_near {
+                return None;
+            }
+            min = min.min(p);
+            max = max.max(p);
+        }
+
+        let screen_max = vec3(
+            self.size.width as f32 - 1.,
+            self.size.height as f32 - 1.,
+            max.z,
+        );
+        let (min, max) = (min.max(vec3(0., 0., min.z)), max.min(screen_max));
+        (min.x <= max.x && min.y <= max.y).then_some(ScreenRect { min, max })
+    }
+
+    /// Whether the `bounds` are hidden behind the occluders, counted in the statistics.
+    pub fn is_occluded(
+        &mut self,
+        camera: &Camera,
+        to_cam_tr: &Mat4,
+        ratio_w_h: f32,
+        bounds: &Bounds,
+    ) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.nb_surfaces_tested += 1;
+        let Some(rect) = self.screen_rect(camera, to_cam_tr, ratio_w_h, bounds) else {
+            return false;
+        };
+
+        // Coarsest level where the rectangle spans a few tiles.
+        let (min_x, min_y) = (
+            rect.min.x as u32 / OCCLUSION_TILE,
+            rect.min.y as u32 / OCCLUSION_TILE,
+        );
+        let (max_x, max_y) = (
+            rect.max.x as u32 / OCCLUSION_TILE,
+            rect.max.y as u32 / OCCLUSION_TILE,
+        );
+        let mut l = 0;
+        while l + 1 < self.levels.len()
+            && ((max_x >> l) - (min_x >> l)).max((max_y >> l) - (min_y >> l))
+                >= OCCLUSION_TEST_TILES
+        {
+            l += 1;
+        }
+
+        let level = &self.levels[l];
+        let occluded = ((min_y >> l)..=(max_y >> l).min(level.height - 1)).all(|y| {
+            ((min_x >> l)..=(max_x >> l).min(level.width - 1)).all(|x| rect.min.z > level.get(x, y))
+        });
+        if occluded {
+            self.nb_surfaces_occluded += 1;
+        }
+        occluded
+    }
+}
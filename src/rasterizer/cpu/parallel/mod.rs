@@ -365,9 +365,19 @@ const fn u64_to_color(depth_color: u64) -> u32 {
     (0xffffffff & depth_color) as u32
 }
 
+/// Infinite where nothing was drawn, like the depth buffers of the single-threaded engines.
+fn u64_to_depth(depth_color: u64) -> f32 {
+    match (depth_color >> 32) as u32 {
+        DEFAULT_DEPTH => f32::INFINITY,
+        depth => depth as f32 / DEPTH_PRECISION,
+    }
+}
+
 #[cfg(feature = "stats")]
 #[derive(Default, Debug)]
 pub struct ParStats {
+    pub nb_surfaces_occlusion_tested: AtomicUsize,
+    pub nb_surfaces_occluded: AtomicUsize,
     pub nb_triangles_tot: AtomicUsize,
     pub nb_triangles_sight: AtomicUsize,
     pub nb_triangles_facing: AtomicUsize,
@@ -382,6 +392,8 @@ pub struct ParStats {
 #[cfg(feature = "stats")]
 impl ParStats {
     fn update_stats(self, stats: &mut Stats) {
+        stats.nb_surfaces_occlusion_tested = self.nb_surfaces_occlusion_tested.into_inner();
+        stats.nb_surfaces_occluded = self.nb_surfaces_occluded.into_inner();
         stats.nb_triangles_tot = self.nb_triangles_tot.into_inner();
         stats.nb_triangles_sight = self.nb_triangles_sight.into_inner();
         stats.nb_triangles_facing = self.nb_triangles_facing.into_inner();
@@ -397,6 +409,8 @@ impl ParStats {
 impl From<&Stats> for ParStats {
     fn from(value: &Stats) -> Self {
         Self {
+            nb_surfaces_occlusion_tested: value.nb_surfaces_occlusion_tested.into(),
+            nb_surfaces_occluded: value.nb_surfaces_occluded.into(),
             nb_triangles_tot: value.nb_triangles_tot.into(),
             nb_triangles_sight: value.nb_triangles_sight.into(),
             nb_triangles_facing: value.nb_triangles_facing.into(),
@@ -1,6 +1,6 @@
 //! Like steps2 but parallel : par_bridge
 //! Can't create t_raster with par_bridge as it doesn't keep order -> textures don't match
-use glam::{Mat4, Vec3, Vec4Swizzles};
+use glam::Vec3;
 use rayon::prelude::*;
 use std::{
     ops::DerefMut,
@@ -18,6 +18,8 @@ use crate::{
     rasterizer::{
         cpu::{
             MINIMAL_AMBIANT_LIGHT, cursor_buffer_index, format_debug,
+            instancing::InstanceVertices,
+            occlusion::OcclusionCuller,
             parallel::{clean_resize_buffer, u64_to_color},
             single_threaded::populate_nodes_split,
             vec_cross_z,
@@ -37,15 +39,16 @@ use crate::rasterizer::Stats;
 
 #[derive(Default, Clone)]
 pub struct ParIterEngine0 {
+    /// In world space.
     triangles: Vec<(Vec3, Vec3, Vec3)>,
-    world_trs: Vec<Mat4>,
-    to_cam_trs: Vec<Mat4>,
     textures: Vec<Texture>,
+    instance_vertices: InstanceVertices,
 
     t_raster: Vec<(Vec3, Vec3, Vec3)>,
     bounding_boxes: Vec<BoundingBox<u32>>,
     p01p20: Vec<(Vec3, Vec3)>,
     depth_color_buffer: Arc<[AtomicU64]>,
+    occlusion: OcclusionCuller,
 }
 
 impl ParIterEngine0 {
@@ -58,10 +61,12 @@ impl ParIterEngine0 {
         #[cfg(feature = "stats")] stats: &ParStats,
     ) {
         // self.triangles.clear();
-        // self.world_trs.clear();
-        // self.to_cam_trs.clear();
         // self.textures.clear();
         world.scene.if_present(|s| {
+            self.occlusion
+                .prepare(settings, &world.camera, size, ratio_w_h, s);
+            self.instance_vertices.clear();
+            let lods = world.camera.lod_selector(settings.lod, size, ratio_w_h);
             s.top_nodes().iter().for_each(|n| {
                 populate_nodes_split(
                     settings,
@@ -69,9 +74,10 @@ impl ParIterEngine0 {
                     size,
                     ratio_w_h,
                     &mut self.triangles,
-                    &mut self.world_trs,
-                    &mut self.to_cam_trs,
                     &mut self.textures,
+                    &mut self.instance_vertices,
+                    &mut self.occlusion,
+                    &lods,
                     &n.read().unwrap(),
                 )
             })
@@ -79,6 +85,12 @@ impl ParIterEngine0 {
 
         #[cfg(feature = "stats")]
         {
+            stats
+                .nb_surfaces_occlusion_tested
+                .store(self.occlusion.nb_surfaces_tested, Ordering::Relaxed);
+            stats
+                .nb_surfaces_occluded
+                .store(self.occlusion.nb_surfaces_occluded, Ordering::Relaxed);
             stats
                 .nb_triangles_tot
                 .store(self.triangles.len(), Ordering::Relaxed);
@@ -86,21 +98,17 @@ impl ParIterEngine0 {
 
         // par_bridge doesn't preserve order, so textures won't match
         let camera = &world.camera;
+        let view = camera.view_mat();
         // self.t_raster.clear();
         // self.t_raster.reserve(self.triangles.len());
-        self.t_raster.par_extend(
-            self.triangles
-                .par_iter()
-                .zip(self.to_cam_trs.par_drain(..))
-                .map(|((p0, p1, p2), tr)| {
-                    (
-                        to_raster(*p0, camera, &tr, size, ratio_w_h),
-                        to_raster(*p1, camera, &tr, size, ratio_w_h),
-                        to_raster(*p2, camera, &tr, size, ratio_w_h),
-                    )
-                }),
-        );
-        // No need for self.to_cam_trs anymore.
+        self.t_raster
+            .par_extend(self.triangles.par_iter().map(|(p0, p1, p2)| {
+                (
+                    to_raster(*p0, camera, &view, size, ratio_w_h),
+                    to_raster(*p1, camera, &view, size, ratio_w_h),
+                    to_raster(*p2, camera, &view, size, ratio_w_h),
+                )
+            }));
 
         // self.bounding_boxes.clear();
         // self.bounding_boxes.reserve(self.triangles.len());
@@ -111,7 +119,6 @@ impl ParIterEngine0 {
                 self.bounding_boxes.push(bb);
             } else {
                 self.triangles.swap_remove(i);
-                self.world_trs.swap_remove(i);
                 self.textures.swap_remove(i);
                 self.t_raster.swap_remove(i);
             }
@@ -138,7 +145,6 @@ impl ParIterEngine0 {
                 self.p01p20.push((p01, p20));
             } else {
                 self.triangles.swap_remove(i);
-                self.world_trs.swap_remove(i);
                 self.textures.swap_remove(i);
                 self.t_raster.swap_remove(i);
                 self.bounding_boxes.swap_remove(i);
@@ -152,17 +158,6 @@ impl ParIterEngine0 {
                 .store(self.triangles.len(), Ordering::Relaxed);
         }
 
-        self.triangles
-            .iter_mut()
-            .zip(self.world_trs.drain(..))
-            .par_bridge()
-            .for_each(|((p0, p1, p2), tr)| {
-                *p0 = (tr * p0.extend(1.)).xyz();
-                *p1 = (tr * p1.extend(1.)).xyz();
-                *p2 = (tr * p2.extend(1.)).xyz();
-            });
-        // No need for self.world_trs anymore.
-
         ////////////////////////////////
         // Sunlight
         // Dot product gives negative if two vectors are opposed, so we compare light
@@ -1,5 +1,5 @@
 //! Like steps2 but parallel : par_drain
-use glam::{Mat4, Vec3, Vec4Swizzles};
+use glam::Vec3;
 use rayon::prelude::*;
 use std::{
     ops::DerefMut,
@@ -16,12 +16,17 @@ use crate::{
     maths::ColorF32,
     rasterizer::{
         cpu::{
-            MINIMAL_AMBIANT_LIGHT, cursor_buffer_index, format_debug,
-            parallel::{clean_resize_buffer, u64_to_color},
-            single_threaded::populate_nodes_split,
+            MINIMAL_AMBIANT_LIGHT,
+            ambient_occlusion::{AmbientOcclusion, darken},
+            background::Environment,
+            cursor_buffer_index, format_debug,
+            instancing::InstanceVertices,
+            occlusion::OcclusionCuller,
+            parallel::{clean_resize_buffer, u64_to_color, u64_to_depth},
+            single_threaded::populate_scene,
             vec_cross_z,
         },
-        settings::Settings,
+        settings::{Settings, ViewMode},
     },
     scene::{BoundingBox, Texture, Triangle, World, to_raster},
     window::AppObserver,
@@ -36,15 +41,18 @@ use crate::rasterizer::Stats;
 
 #[derive(Default, Clone)]
 pub struct ParIterEngine1 {
+    /// In world space.
     triangles: Vec<(Vec3, Vec3, Vec3)>,
-    world_trs: Vec<Mat4>,
-    to_cam_trs: Vec<Mat4>,
     textures: Vec<Texture>,
+    instance_vertices: InstanceVertices,
 
     t_raster: Vec<(Vec3, Vec3, Vec3)>,
     bounding_boxes: Vec<BoundingBox<u32>>,
     p01p20: Vec<(Vec3, Vec3)>,
     depth_color_buffer: Arc<[AtomicU64]>,
+    occlusion: OcclusionCuller,
+    ambient_occlusion: AmbientOcclusion,
+    environment: Environment,
 }
 
 impl ParIterEngine1 {
@@ -57,25 +65,21 @@ impl ParIterEngine1 {
         #[cfg(feature = "stats")] stats: &ParStats,
     ) {
         // self.triangles.clear();
-        // self.world_trs.clear();
-        // self.to_cam_trs.clear();
         // self.textures.clear();
         // let t = Instant::now();
         world.scene.if_present(|s| {
             // let t = Instant::now();
-            s.top_nodes().iter().for_each(|n| {
-                populate_nodes_split(
-                    settings,
-                    &world.camera,
-                    size,
-                    ratio_w_h,
-                    &mut self.triangles,
-                    &mut self.world_trs,
-                    &mut self.to_cam_trs,
-                    &mut self.textures,
-                    &n.read().unwrap(),
-                )
-            });
+            populate_scene(
+                settings,
+                &world.camera,
+                size,
+                ratio_w_h,
+                &mut self.triangles,
+                &mut self.textures,
+                &mut self.instance_vertices,
+                &mut self.occlusion,
+                s,
+            );
             // println!("Populated nodes in : {}μs", t.elapsed().as_micros());
         });
         // if !self.triangles.is_empty() {
@@ -84,27 +88,29 @@ impl ParIterEngine1 {
 
         #[cfg(feature = "stats")]
         {
+            stats
+                .nb_surfaces_occlusion_tested
+                .store(self.occlusion.nb_surfaces_tested, Ordering::Relaxed);
+            stats
+                .nb_surfaces_occluded
+                .store(self.occlusion.nb_surfaces_occluded, Ordering::Relaxed);
             stats
                 .nb_triangles_tot
                 .store(self.triangles.len(), Ordering::Relaxed);
         }
 
         let camera = &world.camera;
+        let view = camera.view_mat();
         // self.t_raster.clear();
         // self.t_raster.reserve(self.triangles.len());
-        self.t_raster.par_extend(
-            self.triangles
-                .par_iter()
-                .zip(self.to_cam_trs.par_drain(..))
-                .map(|((p0, p1, p2), tr)| {
-                    (
-                        to_raster(*p0, camera, &tr, size, ratio_w_h),
-                        to_raster(*p1, camera, &tr, size, ratio_w_h),
-                        to_raster(*p2, camera, &tr, size, ratio_w_h),
-                    )
-                }),
-        );
-        // No need for self.to_cam_trs anymore.
+        self.t_raster
+            .par_extend(self.triangles.par_iter().map(|(p0, p1, p2)| {
+                (
+                    to_raster(*p0, camera, &view, size, ratio_w_h),
+                    to_raster(*p1, camera, &view, size, ratio_w_h),
+                    to_raster(*p2, camera, &view, size, ratio_w_h),
+                )
+            }));
 
         // self.bounding_boxes.clear();
         // self.bounding_boxes.reserve(self.triangles.len());
@@ -115,7 +121,6 @@ impl ParIterEngine1 {
                 self.bounding_boxes.push(bb);
             } else {
                 self.triangles.swap_remove(i);
-                self.world_trs.swap_remove(i);
                 self.textures.swap_remove(i);
                 self.t_raster.swap_remove(i);
             }
@@ -142,7 +147,6 @@ impl ParIterEngine1 {
                 self.p01p20.push((p01, p20));
             } else {
                 self.triangles.swap_remove(i);
-                self.world_trs.swap_remove(i);
                 self.textures.swap_remove(i);
                 self.t_raster.swap_remove(i);
                 self.bounding_boxes.swap_remove(i);
@@ -156,16 +160,6 @@ impl ParIterEngine1 {
                 .store(self.triangles.len(), Ordering::Relaxed);
         }
 
-        self.triangles
-            .par_iter_mut()
-            .zip(self.world_trs.par_drain(..))
-            .for_each(|((p0, p1, p2), tr)| {
-                *p0 = (tr * p0.extend(1.)).xyz();
-                *p1 = (tr * p1.extend(1.)).xyz();
-                *p2 = (tr * p2.extend(1.)).xyz();
-            });
-        // No need for self.world_trs anymore.
-
         ////////////////////////////////
         // Sunlight
         // Dot product gives negative if two vectors are opposed, so we compare light
@@ -177,10 +171,15 @@ impl ParIterEngine1 {
             .par_iter_mut()
             .zip(self.triangles.par_drain(..))
             .for_each(|(texture, (p0, p1, p2))| {
-                let triangle_normal = (p1 - p0).cross(p0 - p2).normalize();
-                let light = sun_direction
-                    .dot(triangle_normal)
-                    .clamp(MINIMAL_AMBIANT_LIGHT, 1.);
+                // Debug views show the raw material.
+                let light = if settings.view_mode == ViewMode::Shaded {
+                    let triangle_normal = (p1 - p0).cross(p0 - p2).normalize();
+                    sun_direction
+                        .dot(triangle_normal)
+                        .clamp(MINIMAL_AMBIANT_LIGHT, 1.)
+                } else {
+                    1.
+                };
 
                 // TODO: remove this test, just load correctly ?
                 // If a `Texture::VertexColor` has the same color for all triangles, then we can
@@ -270,6 +269,39 @@ impl ParIterEngine1 {
             );
             #[cfg(feature = "stats")]
             par_stats.update_stats(stats);
+
+            // Over the oversampled pixels, before the text is written in the same buffer.
+            let depth_color_buffer = &self.depth_color_buffer[..];
+            if let Some(visibility) =
+                self.ambient_occlusion
+                    .compute(settings, &world.camera, size, |i| {
+                        u64_to_depth(depth_color_buffer[i].load(Ordering::Relaxed))
+                    })
+            {
+                depth_color_buffer
+                    .par_iter()
+                    .zip(visibility.par_iter())
+                    .for_each(|(depth_color, v)| {
+                        let dc = depth_color.load(Ordering::Relaxed);
+                        let color = darken(u64_to_color(dc), *v);
+                        depth_color.store((dc & !0xffffffff) | color as u64, Ordering::Relaxed);
+                    });
+            }
+            if let Some(sky) =
+                self.environment
+                    .sky(settings, &world.camera, world.sun_direction, size)
+            {
+                depth_color_buffer
+                    .par_iter()
+                    .enumerate()
+                    .for_each(|(i, depth_color)| {
+                        let dc = depth_color.load(Ordering::Relaxed);
+                        if u64_to_depth(dc).is_infinite() {
+                            let color = sky.color(i);
+                            depth_color.store((dc & !0xffffffff) | color as u64, Ordering::Relaxed);
+                        }
+                    });
+            }
             app.last_rendering_micros = t.elapsed().as_micros();
         }
 
@@ -1,6 +1,6 @@
 //! Like thread_pool, but we use an atomic array, so each thread directly writes into
 //! it.
-use glam::{Mat4, Vec3, Vec4Swizzles, vec3};
+use glam::{Vec3, vec3};
 use std::{
     ops::DerefMut,
     sync::{
@@ -20,16 +20,24 @@ use crate::{
     maths::ColorF32,
     rasterizer::{
         cpu::{
-            MINIMAL_AMBIANT_LIGHT, cursor_buffer_index, edge_function, format_debug,
+            MINIMAL_AMBIANT_LIGHT,
+            ambient_occlusion::{AmbientOcclusion, darken},
+            background::Environment,
+            cursor_buffer_index, edge_function, format_debug,
+            instancing::InstanceVertices,
+            occlusion::OcclusionCuller,
             parallel::{
                 clean_resize_buffer, depth_to_u64, draw_vertice_basic, thread_pool::NB_THREADS,
-                u64_to_color,
+                u64_to_color, u64_to_depth,
             },
+            single_threaded::populate_surface,
             vec_cross_z,
         },
-        settings::Settings,
+        settings::{Settings, ViewMode},
+    },
+    scene::{
+        BoundingBox, Camera, Node, Texture, Triangle, World, lod::LodSelector, to_cam_tr, to_raster,
     },
-    scene::{BoundingBox, Camera, Node, Texture, Triangle, World, to_cam_tr, to_raster},
     window::AppObserver,
 };
 
@@ -132,24 +140,22 @@ impl ThreadLocalData {
         // We will work on every NB_THREADS of triangle collection : 0, 3, 6, ...
         self.indices
             .extend((self.thread_i..shared.triangles.len()).step_by(NB_THREADS));
+        let view = shared.camera.view_mat();
 
         // self.triangles.clear();
         // self.textures.clear();
 
         // self.t_raster.clear();
         // self.t_raster.reserve(self.triangles.len());
-        self.t_raster.extend(
-            self.indices
-                .iter()
-                .map(|i| (&shared.triangles[*i], &shared.to_cam_trs[*i]))
-                .map(|((p0, p1, p2), tr)| {
-                    (
-                        to_raster(*p0, &shared.camera, tr, shared.size, shared.ratio_w_h),
-                        to_raster(*p1, &shared.camera, tr, shared.size, shared.ratio_w_h),
-                        to_raster(*p2, &shared.camera, tr, shared.size, shared.ratio_w_h),
-                    )
-                }),
-        );
+        let (camera, size, ratio_w_h) = (&shared.camera, shared.size, shared.ratio_w_h);
+        self.t_raster.extend(self.indices.iter().map(|i| {
+            let (p0, p1, p2) = shared.triangles[*i];
+            (
+                to_raster(p0, camera, &view, size, ratio_w_h),
+                to_raster(p1, camera, &view, size, ratio_w_h),
+                to_raster(p2, camera, &view, size, ratio_w_h),
+            )
+        }));
 
         // self.bounding_boxes.clear();
         // self.bounding_boxes.reserve(self.triangles.len());
@@ -193,19 +199,8 @@ impl ThreadLocalData {
             stats.nb_triangles_facing = self.indices.len();
         }
 
-        self.triangles.extend(
-            self.indices
-                .iter()
-                .map(|i| (&shared.triangles[*i], &shared.world_trs[*i]))
-                .map(|((p0, p1, p2), tr)| {
-                    (
-                        (tr * p0.extend(1.)).xyz(),
-                        (tr * p1.extend(1.)).xyz(),
-                        (tr * p2.extend(1.)).xyz(),
-                    )
-                }),
-        );
-        // No need for self.world_trs anymore.
+        self.triangles
+            .extend(self.indices.iter().map(|i| shared.triangles[*i]));
 
         ////////////////////////////////
         // Sunlight
@@ -221,11 +216,16 @@ impl ThreadLocalData {
                     .map(|(i, (p0, p1, p2))| {
                         let mut texture = shared.textures[*i];
 
-                        let triangle_normal = (p1 - p0).cross(p0 - p2).normalize();
-                        let light = shared
-                            .sun_direction
-                            .dot(triangle_normal)
-                            .clamp(MINIMAL_AMBIANT_LIGHT, 1.);
+                        // Debug views show the raw material.
+                        let light = if shared.settings.view_mode == ViewMode::Shaded {
+                            let triangle_normal = (p1 - p0).cross(p0 - p2).normalize();
+                            shared
+                                .sun_direction
+                                .dot(triangle_normal)
+                                .clamp(MINIMAL_AMBIANT_LIGHT, 1.)
+                        } else {
+                            1.
+                        };
 
                         // TODO: remove this test, just load correctly ?
                         // If a `Texture::VertexColor` has the same color for all triangles, then we can
@@ -326,10 +326,12 @@ impl WorkerThread {
 
 #[derive(Default)]
 struct SharedData {
+    /// In world space.
     pub triangles: Vec<(Vec3, Vec3, Vec3)>,
-    pub world_trs: Vec<Mat4>,
-    pub to_cam_trs: Vec<Mat4>,
     pub textures: Vec<Texture>,
+    /// Only used while populating.
+    pub instance_vertices: InstanceVertices,
+    pub lods: LodSelector,
 
     pub settings: Settings,
     pub size: PhysicalSize<u32>,
@@ -341,8 +343,6 @@ struct SharedData {
 impl SharedData {
     pub fn clear(&mut self) {
         self.triangles.clear();
-        self.world_trs.clear();
-        self.to_cam_trs.clear();
         self.textures.clear();
     }
 }
@@ -355,6 +355,7 @@ fn populate_nodes_split(
     size: PhysicalSize<u32>,
     ratio_w_h: f32,
     shared: &mut impl DerefMut<Target = SharedData>,
+    occlusion: &mut OcclusionCuller,
     node: &Node,
 ) {
     {
@@ -367,57 +368,28 @@ fn populate_nodes_split(
         {
             // let vert_count = mesh.surfaces.iter().map(|s| s.count).sum::<usize>() / 3;
             // triangles.reserve(vert_count);
-            // world_trs.reserve(vert_count);
-            // to_cam_trs.reserve(vert_count);
             // textures.reserve(vert_count);
             mesh.surfaces
                 .iter()
-                .filter(|s| {
+                .enumerate()
+                .filter(|(_, s)| {
                     !settings.culling_surfaces
                         || s.bounds.is_visible_cpu(camera, &to_cam_tr, size, ratio_w_h)
                 })
-                .for_each(|s| {
-                    mesh.indices[s.start_index..s.start_index + s.count]
-                        .chunks_exact(3)
-                        .for_each(|is| {
-                            shared.triangles.push((
-                                mesh.vertices[is[0]].position,
-                                mesh.vertices[is[1]].position,
-                                mesh.vertices[is[2]].position,
-                            ));
-                            shared.world_trs.push(node.world_transform);
-                            shared.to_cam_trs.push(to_cam_tr);
-
-                            let material = if settings.vertex_color {
-                                let (c0, c1, c2) = if settings.vertex_color_normal {
-                                    (
-                                        mesh.vertices[is[0]].normal.extend(1.),
-                                        mesh.vertices[is[1]].normal.extend(1.),
-                                        mesh.vertices[is[2]].normal.extend(1.),
-                                    )
-                                } else {
-                                    (
-                                        mesh.vertices[is[0]].color,
-                                        mesh.vertices[is[1]].color,
-                                        mesh.vertices[is[2]].color,
-                                    )
-                                };
-                                if c0 == c1 && c0 == c2 {
-                                    Texture::Color(
-                                        ColorF32::from_rgba(c0.to_array()).as_color_u32(),
-                                    )
-                                } else {
-                                    Texture::VertexColor(
-                                        ColorF32::from_rgba(c0.to_array()).as_color_u32(),
-                                        ColorF32::from_rgba(c1.to_array()).as_color_u32(),
-                                        ColorF32::from_rgba(c2.to_array()).as_color_u32(),
-                                    )
-                                }
-                            } else {
-                                s.material
-                            };
-                            shared.textures.push(material);
-                        });
+                .filter(|(_, s)| !occlusion.is_occluded(camera, &to_cam_tr, ratio_w_h, &s.bounds))
+                .for_each(|(s_index, s)| {
+                    let shared = &mut **shared;
+                    populate_surface(
+                        settings,
+                        &mut shared.triangles,
+                        &mut shared.textures,
+                        &mut shared.instance_vertices,
+                        node,
+                        s_index,
+                        shared
+                            .lods
+                            .level(mesh.lods(s_index), &s.bounds, &node.world_transform),
+                    )
                 });
         }
     }
@@ -429,6 +401,7 @@ fn populate_nodes_split(
             size,
             ratio_w_h,
             shared,
+            occlusion,
             &c.read().unwrap(),
         )
     });
@@ -570,6 +543,9 @@ pub struct ThreadPoolEngine2 {
     all_stats: Vec<Arc<RwLock<ThreadStats>>>,
     shared: Arc<RwLock<SharedData>>,
     depth_color_buffer: Arc<[AtomicU64]>,
+    occlusion: OcclusionCuller,
+    ambient_occlusion: AmbientOcclusion,
+    environment: Environment,
 }
 
 impl Default for ThreadPoolEngine2 {
@@ -598,6 +574,9 @@ impl Default for ThreadPoolEngine2 {
             all_stats,
             shared,
             depth_color_buffer: Default::default(),
+            occlusion: Default::default(),
+            ambient_occlusion: Default::default(),
+            environment: Default::default(),
         }
     }
 }
@@ -630,16 +609,48 @@ impl ThreadPoolEngine2 {
             shared.clear();
             world.scene.if_present(|s| {
                 // let t = Instant::now();
-                s.top_nodes().iter().for_each(|n| {
-                    populate_nodes_split(
-                        settings,
-                        &world.camera,
-                        size,
-                        ratio_w_h,
-                        &mut shared,
-                        &n.read().unwrap(),
-                    )
-                });
+                let occlusion = &mut self.occlusion;
+                occlusion.prepare(settings, &world.camera, size, ratio_w_h, s);
+                shared.instance_vertices.clear();
+                shared.lods = world.camera.lod_selector(settings.lod, size, ratio_w_h);
+                if settings.lod.enabled {
+                    s.request_lods();
+                }
+                if settings.culling_bvh {
+                    let shared = &mut *shared;
+                    let frustum = world.camera.frustum(size, ratio_w_h);
+                    s.for_each_visible_surface(&frustum, |node, s_index| {
+                        let to_cam_tr = to_cam_tr(&world.camera, &node.world_transform);
+                        let mesh = node.mesh.as_ref().unwrap();
+                        let s = &mesh.surfaces[s_index];
+                        if occlusion.is_occluded(&world.camera, &to_cam_tr, ratio_w_h, &s.bounds) {
+                            return;
+                        }
+                        populate_surface(
+                            settings,
+                            &mut shared.triangles,
+                            &mut shared.textures,
+                            &mut shared.instance_vertices,
+                            node,
+                            s_index,
+                            shared
+                                .lods
+                                .level(mesh.lods(s_index), &s.bounds, &node.world_transform),
+                        )
+                    });
+                } else {
+                    s.top_nodes().iter().for_each(|n| {
+                        populate_nodes_split(
+                            settings,
+                            &world.camera,
+                            size,
+                            ratio_w_h,
+                            &mut shared,
+                            occlusion,
+                            &n.read().unwrap(),
+                        )
+                    });
+                }
                 // println!("Populated nodes in : {}μs", t.elapsed().as_micros());
             });
             // if !shared.triangles.is_empty() {
@@ -654,6 +665,8 @@ impl ThreadPoolEngine2 {
 
             #[cfg(feature = "stats")]
             {
+                stats.nb_surfaces_occlusion_tested = self.occlusion.nb_surfaces_tested;
+                stats.nb_surfaces_occluded = self.occlusion.nb_surfaces_occluded;
                 stats.nb_triangles_tot = shared.triangles.len();
             }
         };
@@ -712,6 +725,29 @@ impl ThreadPoolEngine2 {
         (0..(size.width * size.height) as usize).for_each(|i| {
             buffer[i] = u64_to_color(self.depth_color_buffer[i].load(Ordering::Relaxed));
         });
+        let depth_color_buffer = &self.depth_color_buffer[..];
+        if let Some(visibility) =
+            self.ambient_occlusion
+                .compute(settings, &world.camera, size, |i| {
+                    u64_to_depth(depth_color_buffer[i].load(Ordering::Relaxed))
+                })
+        {
+            buffer
+                .iter_mut()
+                .zip(visibility.iter())
+                .for_each(|(col, v)| *col = darken(*col, *v));
+        }
+        if let Some(sky) = self
+            .environment
+            .sky(settings, &world.camera, world.sun_direction, size)
+        {
+            buffer
+                .iter_mut()
+                .zip(depth_color_buffer.iter())
+                .enumerate()
+                .filter(|(_, (_, dc))| u64_to_depth(dc.load(Ordering::Relaxed)).is_infinite())
+                .for_each(|(i, (col, _))| *col = sky.color(i));
+        }
         app.last_buffer_copy_micros = t.elapsed().as_micros();
 
         {
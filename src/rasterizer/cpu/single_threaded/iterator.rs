@@ -126,6 +126,7 @@ impl SingleThreadedEngine for IteratorEngine {
                     &t_raster,
                     buffer,
                     &mut self.depth_buffer[..],
+                    None,
                     world.camera.z_near,
                     size,
                     #[cfg(feature = "stats")]
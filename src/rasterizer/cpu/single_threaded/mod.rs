@@ -7,7 +7,7 @@ use glam::{Vec3, vec3};
 pub use iterator::IteratorEngine;
 pub use original::OriginalEngine;
 pub use steps::StepsEngine;
-pub use steps2::{Steps2Engine, populate_nodes_split};
+pub use steps2::{Steps2Engine, populate_nodes_split, populate_scene, populate_surface};
 
 use std::{ops::DerefMut, time::Instant};
 use winit::dpi::PhysicalSize;
@@ -158,6 +158,7 @@ pub fn rasterize_triangle<B: DerefMut<Target = [u32]>>(
     tri_raster: &Triangle,
     buffer: &mut B,
     depth_buffer: &mut [f32],
+    mut overdraw_buffer: Option<&mut [u32]>,
     z_near: f32,
     size: PhysicalSize<u32>,
     #[cfg(feature = "stats")] stats: &mut Stats,
@@ -251,6 +252,9 @@ pub fn rasterize_triangle<B: DerefMut<Target = [u32]>>(
 
             buffer[index] = col;
             depth_buffer[index] = depth;
+            if let Some(overdraw_buffer) = overdraw_buffer.as_deref_mut() {
+                overdraw_buffer[index] += 1;
+            }
         });
 
     #[cfg(feature = "stats")]
@@ -149,6 +149,7 @@ impl SingleThreadedEngine for StepsEngine {
                     &mut t_raster,
                     buffer,
                     &mut self.depth_buffer[..],
+                    None,
                     world.camera.z_near,
                     size,
                     #[cfg(feature = "stats")]
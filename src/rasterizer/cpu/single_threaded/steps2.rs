@@ -1,6 +1,6 @@
 //! Copy from steps : Trying to cull early
 
-use glam::{Mat4, Vec3, Vec4Swizzles};
+use glam::Vec3;
 use std::{ops::DerefMut, time::Instant};
 use winit::dpi::PhysicalSize;
 
@@ -10,12 +10,23 @@ use crate::{
     rasterizer::{
         Settings,
         cpu::{
-            MINIMAL_AMBIANT_LIGHT, cursor_buffer_index, format_debug,
+            MINIMAL_AMBIANT_LIGHT,
+            ambient_occlusion::{AmbientOcclusion, darken},
+            background::Environment,
+            cursor_buffer_index,
+            debug_view::{debug_material, depth_view, draw_wireframe, highlight, overdraw_view},
+            format_debug,
+            instancing::InstanceVertices,
+            occlusion::OcclusionCuller,
             single_threaded::{clean_resize_buffers, rasterize_triangle},
             vec_cross_z,
         },
+        settings::{ViewMode, Wireframe},
+    },
+    scene::{
+        BoundingBox, Camera, Node, Scene, Texture, Triangle, World, lod::LodSelector, to_cam_tr,
+        to_raster,
     },
-    scene::{BoundingBox, Camera, Node, Texture, Triangle, World, to_cam_tr, to_raster},
     window::AppObserver,
 };
 
@@ -24,15 +35,20 @@ use crate::rasterizer::cpu::Stats;
 
 #[derive(Default)]
 pub struct Steps2Engine {
+    /// In world space.
     triangles: Vec<(Vec3, Vec3, Vec3)>,
-    world_trs: Vec<Mat4>,
-    to_cam_trs: Vec<Mat4>,
     textures: Vec<Texture>,
+    instance_vertices: InstanceVertices,
 
     t_raster: Vec<(Vec3, Vec3, Vec3)>,
     bounding_boxes: Vec<BoundingBox<u32>>,
     p01p20: Vec<(Vec3, Vec3)>,
     depth_buffer: Vec<f32>,
+    overdraw_buffer: Vec<u32>,
+    wireframe_triangles: Vec<(Vec3, Vec3, Vec3)>,
+    occlusion: OcclusionCuller,
+    ambient_occlusion: AmbientOcclusion,
+    environment: Environment,
 }
 
 impl Steps2Engine {
@@ -46,25 +62,21 @@ impl Steps2Engine {
         #[cfg(feature = "stats")] stats: &mut Stats,
     ) {
         // self.triangles.clear();
-        // self.world_trs.clear();
-        // self.to_cam_trs.clear();
         // self.textures.clear();
         // let t = Instant::now();
         world.scene.if_present(|s| {
             // let t = Instant::now();
-            s.top_nodes().iter().for_each(|n| {
-                populate_nodes_split(
-                    settings,
-                    &world.camera,
-                    size,
-                    ratio_w_h,
-                    &mut self.triangles,
-                    &mut self.world_trs,
-                    &mut self.to_cam_trs,
-                    &mut self.textures,
-                    &n.read().unwrap(),
-                )
-            });
+            populate_scene(
+                settings,
+                &world.camera,
+                size,
+                ratio_w_h,
+                &mut self.triangles,
+                &mut self.textures,
+                &mut self.instance_vertices,
+                &mut self.occlusion,
+                s,
+            );
             // println!("Populated nodes in : {}μs", t.elapsed().as_micros());
         });
         // if !self.triangles.is_empty() {
@@ -73,6 +85,8 @@ impl Steps2Engine {
 
         #[cfg(feature = "stats")]
         {
+            stats.nb_surfaces_occlusion_tested = self.occlusion.nb_surfaces_tested;
+            stats.nb_surfaces_occluded = self.occlusion.nb_surfaces_occluded;
             stats.nb_triangles_tot = self.triangles.len();
         }
 
@@ -80,17 +94,15 @@ impl Steps2Engine {
 
         // self.t_raster.clear();
         // self.t_raster.reserve(self.triangles.len());
+        let view = world.camera.view_mat();
         self.t_raster
-            .extend(self.triangles.iter().zip(self.to_cam_trs.drain(..)).map(
-                |((p0, p1, p2), tr)| {
-                    (
-                        to_raster(*p0, &world.camera, &tr, size, ratio_w_h),
-                        to_raster(*p1, &world.camera, &tr, size, ratio_w_h),
-                        to_raster(*p2, &world.camera, &tr, size, ratio_w_h),
-                    )
-                },
-            ));
-        // No need for self.to_cam_trs anymore.
+            .extend(self.triangles.iter().map(|(p0, p1, p2)| {
+                (
+                    to_raster(*p0, &world.camera, &view, size, ratio_w_h),
+                    to_raster(*p1, &world.camera, &view, size, ratio_w_h),
+                    to_raster(*p2, &world.camera, &view, size, ratio_w_h),
+                )
+            }));
 
         // self.bounding_boxes.clear();
         // self.bounding_boxes.reserve(self.triangles.len());
@@ -101,7 +113,6 @@ impl Steps2Engine {
                 self.bounding_boxes.push(bb);
             } else {
                 self.triangles.swap_remove(i);
-                self.world_trs.swap_remove(i);
                 self.textures.swap_remove(i);
                 self.t_raster.swap_remove(i);
             }
@@ -126,7 +137,6 @@ impl Steps2Engine {
                 self.p01p20.push((p01, p20));
             } else {
                 self.triangles.swap_remove(i);
-                self.world_trs.swap_remove(i);
                 self.textures.swap_remove(i);
                 self.t_raster.swap_remove(i);
                 self.bounding_boxes.swap_remove(i);
@@ -138,16 +148,6 @@ impl Steps2Engine {
             stats.nb_triangles_facing = self.triangles.len();
         }
 
-        self.triangles
-            .iter_mut()
-            .zip(self.world_trs.drain(..))
-            .for_each(|((p0, p1, p2), tr)| {
-                *p0 = (tr * p0.extend(1.)).xyz();
-                *p1 = (tr * p1.extend(1.)).xyz();
-                *p2 = (tr * p2.extend(1.)).xyz();
-            });
-        // No need for self.world_trs anymore.
-
         ////////////////////////////////
         // Sunlight
         // Dot product gives negative if two vectors are opposed, so we compare light
@@ -158,6 +158,11 @@ impl Steps2Engine {
             .iter_mut()
             .zip(self.triangles.drain(..))
             .for_each(|(texture, (p0, p1, p2))| {
+                // Debug views show the raw material.
+                if settings.view_mode != ViewMode::Shaded {
+                    return;
+                }
+
                 let triangle_normal = (p1 - p0).cross(p0 - p2).normalize();
                 let light = world
                     .sun_direction
@@ -188,6 +193,15 @@ impl Steps2Engine {
             });
         // No need for self.triangles anymore.
 
+        let mut overdraw_buffer = if settings.view_mode == ViewMode::Overdraw {
+            self.overdraw_buffer.clear();
+            self.overdraw_buffer
+                .resize(size.width as usize * size.height as usize, 0);
+            Some(&mut self.overdraw_buffer[..])
+        } else {
+            None
+        };
+
         // let nb_triangles_drawn = self.t_raster.len();
         self.t_raster
             .drain(..)
@@ -195,6 +209,13 @@ impl Steps2Engine {
             .zip(self.bounding_boxes.drain(..))
             .zip(self.p01p20.drain(..))
             .for_each(|((((p0, p1, p2), material), bb), (p01, p20))| {
+                if settings.wireframe != Wireframe::Off {
+                    self.wireframe_triangles.push((p0, p1, p2));
+                    if settings.wireframe == Wireframe::Only {
+                        return;
+                    }
+                }
+
                 rasterize_triangle(
                     settings,
                     &Triangle {
@@ -205,6 +226,7 @@ impl Steps2Engine {
                     },
                     buffer,
                     &mut self.depth_buffer[..],
+                    overdraw_buffer.as_deref_mut(),
                     world.camera.z_near,
                     size,
                     #[cfg(feature = "stats")]
@@ -220,6 +242,61 @@ impl Steps2Engine {
         //     nb_triangles_drawn,
         //     t_start_comp.elapsed().as_micros()
         // );
+
+        if let Some(visibility) =
+            self.ambient_occlusion
+                .compute(settings, &world.camera, size, |i| self.depth_buffer[i])
+        {
+            buffer
+                .iter_mut()
+                .zip(visibility.iter())
+                .for_each(|(col, v)| *col = darken(*col, *v));
+        }
+
+        if let Some(sky) = self
+            .environment
+            .sky(settings, &world.camera, world.sun_direction, size)
+        {
+            buffer
+                .iter_mut()
+                .zip(self.depth_buffer.iter())
+                .enumerate()
+                .filter(|(_, (_, depth))| depth.is_infinite())
+                .for_each(|(i, (col, _))| *col = sky.color(i));
+        }
+
+        match settings.view_mode {
+            ViewMode::Depth => depth_view(buffer, &self.depth_buffer),
+            ViewMode::Overdraw => overdraw_view(buffer, &self.overdraw_buffer),
+            _ => (),
+        }
+
+        self.wireframe_triangles
+            .drain(..)
+            .for_each(|t| draw_wireframe(buffer, &self.depth_buffer, world.camera.z_near, size, t));
+    }
+
+    /// The frame of the `world` without the debug text, as a reference for the other engines.
+    /// ARGB, row by row.
+    #[cfg(feature = "vulkan")]
+    pub fn render(
+        &mut self,
+        settings: &Settings,
+        world: &World,
+        size: PhysicalSize<u32>,
+    ) -> Vec<u32> {
+        let mut buffer = vec![0; size.width as usize * size.height as usize];
+        clean_resize_buffers(&mut self.depth_buffer, &mut buffer, size);
+        self.rasterize_world(
+            settings,
+            world,
+            &mut buffer,
+            size,
+            size.width as f32 / size.height as f32,
+            #[cfg(feature = "stats")]
+            &mut Default::default(),
+        );
+        buffer
     }
 
     pub fn rasterize<B: DerefMut<Target = [u32]>>(
@@ -264,15 +341,72 @@ impl Steps2Engine {
     }
 }
 
+/// Fills the triangles of the visible surfaces of the `scene`, not hidden behind its occluders.
+pub fn populate_scene(
+    settings: &Settings,
+    camera: &Camera,
+    size: PhysicalSize<u32>,
+    ratio_w_h: f32,
+    triangles: &mut Vec<(Vec3, Vec3, Vec3)>,
+    textures: &mut Vec<Texture>,
+    instance_vertices: &mut InstanceVertices,
+    occlusion: &mut OcclusionCuller,
+    scene: &Scene,
+) {
+    occlusion.prepare(settings, camera, size, ratio_w_h, scene);
+    instance_vertices.clear();
+    let lods = camera.lod_selector(settings.lod, size, ratio_w_h);
+    if settings.lod.enabled {
+        scene.request_lods();
+    }
+
+    if settings.culling_bvh {
+        scene.for_each_visible_surface(&camera.frustum(size, ratio_w_h), |node, s_index| {
+            let to_cam_tr = to_cam_tr(camera, &node.world_transform);
+            let mesh = node.mesh.as_ref().unwrap();
+            let s = &mesh.surfaces[s_index];
+            if occlusion.is_occluded(camera, &to_cam_tr, ratio_w_h, &s.bounds) {
+                return;
+            }
+            populate_surface(
+                settings,
+                triangles,
+                textures,
+                instance_vertices,
+                node,
+                s_index,
+                lods.level(mesh.lods(s_index), &s.bounds, &node.world_transform),
+            )
+        });
+    } else {
+        scene.top_nodes().iter().for_each(|n| {
+            populate_nodes_split(
+                settings,
+                camera,
+                size,
+                ratio_w_h,
+                triangles,
+                textures,
+                instance_vertices,
+                occlusion,
+                &lods,
+                &n.read().unwrap(),
+            )
+        });
+    }
+}
+
+/// `occlusion` must be prepared for the frame, the levels of detail are selected by `lods`.
 pub fn populate_nodes_split(
     settings: &Settings,
     camera: &Camera,
     size: PhysicalSize<u32>,
     ratio_w_h: f32,
     triangles: &mut Vec<(Vec3, Vec3, Vec3)>,
-    world_trs: &mut Vec<Mat4>,
-    to_cam_trs: &mut Vec<Mat4>,
     textures: &mut Vec<Texture>,
+    instance_vertices: &mut InstanceVertices,
+    occlusion: &mut OcclusionCuller,
+    lods: &LodSelector,
     node: &Node,
 ) {
     {
@@ -285,57 +419,25 @@ pub fn populate_nodes_split(
         {
             // let vert_count = mesh.surfaces.iter().map(|s| s.count).sum::<usize>() / 3;
             // triangles.reserve(vert_count);
-            // world_trs.reserve(vert_count);
-            // to_cam_trs.reserve(vert_count);
             // textures.reserve(vert_count);
             mesh.surfaces
                 .iter()
-                .filter(|s| {
+                .enumerate()
+                .filter(|(_, s)| {
                     !settings.culling_surfaces
                         || s.bounds.is_visible_cpu(camera, &to_cam_tr, size, ratio_w_h)
                 })
-                .for_each(|s| {
-                    mesh.indices[s.start_index..s.start_index + s.count]
-                        .chunks_exact(3)
-                        .for_each(|is| {
-                            triangles.push((
-                                mesh.vertices[is[0]].position,
-                                mesh.vertices[is[1]].position,
-                                mesh.vertices[is[2]].position,
-                            ));
-                            world_trs.push(node.world_transform);
-                            to_cam_trs.push(to_cam_tr);
-
-                            let material = if settings.vertex_color {
-                                let (c0, c1, c2) = if settings.vertex_color_normal {
-                                    (
-                                        mesh.vertices[is[0]].normal.extend(1.),
-                                        mesh.vertices[is[1]].normal.extend(1.),
-                                        mesh.vertices[is[2]].normal.extend(1.),
-                                    )
-                                } else {
-                                    (
-                                        mesh.vertices[is[0]].color,
-                                        mesh.vertices[is[1]].color,
-                                        mesh.vertices[is[2]].color,
-                                    )
-                                };
-                                if c0 == c1 && c0 == c2 {
-                                    Texture::Color(
-                                        ColorF32::from_rgba(c0.to_array()).as_color_u32(),
-                                    )
-                                } else {
-                                    Texture::VertexColor(
-                                        ColorF32::from_rgba(c0.to_array()).as_color_u32(),
-                                        ColorF32::from_rgba(c1.to_array()).as_color_u32(),
-                                        ColorF32::from_rgba(c2.to_array()).as_color_u32(),
-                                    )
-                                }
-                            } else {
-                                s.material
-                            };
-                            textures.push(material);
-                        });
+                .filter(|(_, s)| !occlusion.is_occluded(camera, &to_cam_tr, ratio_w_h, &s.bounds))
+                .for_each(|(s_index, s)| {
+                    populate_surface(
+                        settings,
+                        triangles,
+                        textures,
+                        instance_vertices,
+                        node,
+                        s_index,
+                        lods.level(mesh.lods(s_index), &s.bounds, &node.world_transform),
+                    )
                 });
         }
     }
@@ -347,10 +449,75 @@ pub fn populate_nodes_split(
             size,
             ratio_w_h,
             triangles,
-            world_trs,
-            to_cam_trs,
             textures,
+            instance_vertices,
+            occlusion,
+            lods,
             &c.read().unwrap(),
         )
     });
 }
+
+/// The triangles are in world space, the vertices being shared with the other surfaces of the
+/// `node`. `level` is the level of detail of the surface to draw.
+pub fn populate_surface(
+    settings: &Settings,
+    triangles: &mut Vec<(Vec3, Vec3, Vec3)>,
+    textures: &mut Vec<Texture>,
+    instance_vertices: &mut InstanceVertices,
+    node: &Node,
+    s_index: usize,
+    level: usize,
+) {
+    let Some(mesh) = node.mesh.as_ref() else {
+        return;
+    };
+    let s = &mesh.surfaces[s_index];
+
+    mesh.level_indices(s_index, level)
+        .chunks_exact(3)
+        .for_each(|is| {
+            triangles.push(instance_vertices.triangle(node, is));
+
+            let material = if let Some(material) = debug_material(
+                settings.view_mode,
+                mesh,
+                s_index,
+                level,
+                is,
+                &node.world_transform,
+            ) {
+                material
+            } else if settings.vertex_color {
+                let (c0, c1, c2) = if settings.vertex_color_normal {
+                    (
+                        mesh.vertices[is[0]].normal.extend(1.),
+                        mesh.vertices[is[1]].normal.extend(1.),
+                        mesh.vertices[is[2]].normal.extend(1.),
+                    )
+                } else {
+                    (
+                        mesh.vertices[is[0]].color,
+                        mesh.vertices[is[1]].color,
+                        mesh.vertices[is[2]].color,
+                    )
+                };
+                if c0 == c1 && c0 == c2 {
+                    Texture::Color(ColorF32::from_rgba(c0.to_array()).as_color_u32())
+                } else {
+                    Texture::VertexColor(
+                        ColorF32::from_rgba(c0.to_array()).as_color_u32(),
+                        ColorF32::from_rgba(c1.to_array()).as_color_u32(),
+                        ColorF32::from_rgba(c2.to_array()).as_color_u32(),
+                    )
+                }
+            } else {
+                s.material
+            };
+            textures.push(if node.highlighted {
+                highlight(material)
+            } else {
+                material
+            });
+        });
+}
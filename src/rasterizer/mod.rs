@@ -1,3 +1,5 @@
+#[cfg(all(feature = "cpu", feature = "vulkan"))]
+mod compare;
 #[cfg(feature = "cpu")]
 mod cpu;
 mod settings;
@@ -15,8 +17,12 @@ pub use cpu::Stats;
 use settings::EngineType;
 pub use settings::Settings;
 
+#[cfg(all(feature = "cpu", feature = "vulkan"))]
+pub use compare::{MAX_MISMATCH, compare_headless};
 #[cfg(feature = "vulkan")]
 use vulkan::VulkanEngine;
+#[cfg(feature = "vulkan")]
+pub use vulkan::render_headless;
 
 #[cfg(feature = "cpu")]
 use crate::scene::World;
@@ -80,7 +86,7 @@ impl Engine<'_> {
 
     pub fn rasterize(
         &mut self,
-        settings: &Settings,
+        settings: &mut Settings,
         #[cfg(feature = "cpu")] world: &World,
         #[cfg(all(not(feature = "cpu"), feature = "vulkan"))] camera: &Camera,
         app: &mut AppObserver,
@@ -109,6 +115,16 @@ impl Engine<'_> {
         }
     }
 
+    /// Like [`EngineType::supports_ambient_occlusion`], with the current shading of Vulkan.
+    pub fn supports_ambient_occlusion(&self) -> bool {
+        match self {
+            #[cfg(feature = "cpu")]
+            Self::Cpu(e, _) => e.as_engine_type().supports_ambient_occlusion(),
+            #[cfg(feature = "vulkan")]
+            Self::Vulkan(e) => e.supports_ambient_occlusion(),
+        }
+    }
+
     pub fn on_window_event(&mut self, event: &WindowEvent) {
         #[cfg(feature = "cpu")]
         let _ = event;
@@ -1,3 +1,5 @@
+use crate::scene::lod::LodSettings;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Settings {
     /// Over-print all vertices
@@ -10,11 +12,24 @@ pub struct Settings {
     // pub sort_triangles: TriangleSorting,
     pub parallel_text: bool,
     pub oversampling: usize,
+    /// Cull surfaces against the frustum with the scene BVH, instead of walking all the nodes.
+    pub culling_bvh: bool,
     pub culling_meshes: bool,
     pub culling_surfaces: bool,
     pub culling_triangles: bool,
+    /// Skip the surfaces hidden behind the large ones, see `cpu::occlusion`.
+    pub culling_occlusion: bool,
+    /// Simplified surfaces far from the camera, shared with the Vulkan engine.
+    pub lod: LodSettings,
+    /// Screen-space ambient occlusion, shared with the Vulkan engine.
+    pub ao: AoSettings,
+    /// Only the CPU engines, the Vulkan one selects its environment in its own window.
+    pub background: Background,
     pub vertex_color: bool,
     pub vertex_color_normal: bool,
+    /// Debug visualisation replacing the shaded output
+    pub view_mode: ViewMode,
+    pub wireframe: Wireframe,
 }
 
 impl Default for Settings {
@@ -25,16 +40,39 @@ impl Default for Settings {
             // sort_triangles: Default::default(),
             parallel_text: true,
             oversampling: 1,
+            culling_bvh: true,
             culling_meshes: true,
             culling_surfaces: true,
             culling_triangles: true,
+            culling_occlusion: true,
+            lod: Default::default(),
+            ao: Default::default(),
+            background: Default::default(),
             vertex_color: false,
             vertex_color_normal: false,
+            view_mode: Default::default(),
+            wireframe: Default::default(),
         }
     }
 }
 
 impl Settings {
+    /// Goes back to the shaded view if the engine doesn't implement the debug views, and turns
+    /// off the ambient occlusion and the background it doesn't implement.
+    pub fn restrict_to_engine(&mut self) {
+        if !self.engine_type.supports_debug_views() {
+            self.view_mode = ViewMode::Shaded;
+            self.wireframe = Wireframe::Off;
+        }
+        if !self.engine_type.supports_ambient_occlusion() {
+            self.ao.enabled = false;
+        }
+        // The Vulkan engine ignores it, it is kept for the CPU engines.
+        if !self.engine_type.supports_background() && !self.engine_type.is_vulkan() {
+            self.background = Background::Flat;
+        }
+    }
+
     pub fn next_oversampling(&mut self) {
         self.oversampling = match self.oversampling {
             1 => 2,
@@ -45,6 +83,142 @@ impl Settings {
     }
 }
 
+/// Darkens the creases and the corners, from the depth of the frame and its normals.
+///
+/// Multiplied into the ambient term by the Vulkan deferred shading, and into the whole colour by
+/// the CPU engines.
+#[derive(Debug, Clone, Copy)]
+pub struct AoSettings {
+    pub enabled: bool,
+    /// Of the hemisphere sampled around each pixel, in world units.
+    pub radius: f32,
+    /// Exponent of the visibility, higher darkens the occluded pixels more.
+    pub intensity: f32,
+    /// Per pixel, before the blur.
+    pub samples: u32,
+}
+
+impl Default for AoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.5,
+            intensity: 1.5,
+            samples: 16,
+        }
+    }
+}
+
+impl AoSettings {
+    pub const MAX_SAMPLES: u32 = 64;
+
+    pub fn next_samples(&mut self) {
+        self.samples = match self.samples {
+            ..8 => 8,
+            8..16 => 16,
+            16..32 => 32,
+            32..64 => 64,
+            _ => 4,
+        };
+    }
+
+    pub fn larger(&mut self) {
+        self.radius = (self.radius * 2.).min(8.);
+    }
+
+    pub fn smaller(&mut self) {
+        self.radius = (self.radius / 2.).max(0.0625);
+    }
+}
+
+/// Drawn where there is no surface, in the shaded view. The debug views keep the flat colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    /// `DEFAULT_BACKGROUND_COLOR`.
+    Flat,
+    /// Index in `EnvironmentSource::list`, the first one is the atmosphere.
+    Environment(usize),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::Environment(0)
+    }
+}
+
+impl Background {
+    /// The flat colour, then each of the `environments` in turn.
+    pub fn next(&mut self, environments: usize) {
+        *self = match *self {
+            Background::Flat => Background::Environment(0),
+            Background::Environment(i) if i + 1 < environments => Background::Environment(i + 1),
+            Background::Environment(_) => Background::Flat,
+        };
+    }
+}
+
+/// Depth mapped to black in [`ViewMode::Depth`], see `DEPTH_VIEW_FAR` in
+/// `resources/scene_data.glsl`.
+#[cfg(feature = "cpu")]
+pub const DEPTH_VIEW_FAR: f32 = 100.;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewMode {
+    #[default]
+    Shaded,
+    /// Camera depth, white at the camera and black from `DEPTH_VIEW_FAR`.
+    Depth,
+    /// Heatmap of the number of writes per pixel.
+    Overdraw,
+    /// World-space normals mapped to `[0,1]`.
+    Normals,
+    Uvs,
+    /// One colour per mesh.
+    MeshColor,
+    /// One colour per surface.
+    SurfaceColor,
+    /// One colour per level of detail, blue for the original surfaces.
+    Lod,
+}
+
+impl ViewMode {
+    pub const ALL: [ViewMode; 8] = [
+        ViewMode::Shaded,
+        ViewMode::Depth,
+        ViewMode::Overdraw,
+        ViewMode::Normals,
+        ViewMode::Uvs,
+        ViewMode::MeshColor,
+        ViewMode::SurfaceColor,
+        ViewMode::Lod,
+    ];
+
+    pub fn next(&mut self) {
+        let i = Self::ALL.iter().position(|m| m == self).unwrap_or_default();
+        *self = Self::ALL[(i + 1) % Self::ALL.len()];
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wireframe {
+    #[default]
+    Off,
+    /// Edges drawn over the view mode.
+    Overlay,
+    /// Only the edges are drawn.
+    Only,
+}
+
+impl Wireframe {
+    pub fn next(&mut self) {
+        match self {
+            Wireframe::Off => *self = Wireframe::Overlay,
+            Wireframe::Overlay => *self = Wireframe::Only,
+            Wireframe::Only => *self = Wireframe::Off,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy)]
 pub enum EngineType {
     #[default]
@@ -66,6 +240,44 @@ pub enum EngineType {
     Vulkan,
 }
 
+impl EngineType {
+    /// Implements the [`ViewMode`]s other than `Shaded` and the [`Wireframe`].
+    pub fn supports_debug_views(self) -> bool {
+        match self {
+            EngineType::Steps2 => true,
+            #[cfg(feature = "vulkan")]
+            EngineType::Vulkan => true,
+            _ => false,
+        }
+    }
+
+    /// Implements the [`AoSettings`], only with the deferred shading for Vulkan.
+    pub fn supports_ambient_occlusion(self) -> bool {
+        match self {
+            EngineType::Steps2 | EngineType::ParIter1 | EngineType::ThreadPool2 => true,
+            #[cfg(feature = "vulkan")]
+            EngineType::Vulkan => true,
+            _ => false,
+        }
+    }
+
+    /// Draws the [`Background`], the Vulkan engine has its own environments.
+    pub fn supports_background(self) -> bool {
+        matches!(
+            self,
+            EngineType::Steps2 | EngineType::ParIter1 | EngineType::ThreadPool2
+        )
+    }
+
+    fn is_vulkan(self) -> bool {
+        match self {
+            #[cfg(feature = "vulkan")]
+            EngineType::Vulkan => true,
+            _ => false,
+        }
+    }
+}
+
 /*
 #[derive(Default, Debug, Clone, Copy)]
 pub enum TriangleSorting {
@@ -8,7 +8,10 @@ use std::{
 use vk_mem::Alloc;
 use winit::dpi::PhysicalSize;
 
-use super::commands::{VulkanCommands, image_subresource_range, transition_image};
+use super::{
+    commands::{VulkanCommands, image_subresource_range, transition_image},
+    texture_data::TextureData,
+};
 
 pub struct AllocatedBuffer {
     allocator_copy: Arc<Mutex<vk_mem::Allocator>>,
@@ -21,6 +24,8 @@ pub enum MyMemoryUsage {
     GpuOnly,
     StagingUpload,
     CpuToGpu,
+    /// Written by the GPU, read on the host, see [`AllocatedBuffer::read`].
+    Readback,
 }
 
 impl AllocatedBuffer {
@@ -54,6 +59,10 @@ impl AllocatedBuffer {
                 alloc_info.flags = vk_mem::AllocationCreateFlags::MAPPED
                     | vk_mem::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE;
             }
+            MyMemoryUsage::Readback => {
+                alloc_info.flags = vk_mem::AllocationCreateFlags::MAPPED
+                    | vk_mem::AllocationCreateFlags::HOST_ACCESS_RANDOM;
+            }
         }
 
         let (buffer, allocation, info) = {
@@ -79,6 +88,24 @@ impl AllocatedBuffer {
     pub fn mapped_data(&self) -> *mut c_void {
         self.info.mapped_data
     }
+
+    /// Copies the first `size` bytes, once the GPU is done writing them.
+    ///
+    /// Cached memory may not be coherent, so it's invalidated first.
+    pub fn read(&self, size: usize) -> Vec<u8> {
+        self.allocator_copy
+            .lock()
+            .unwrap()
+            .invalidate_allocation(&self.allocation, 0, vk::WHOLE_SIZE)
+            .unwrap();
+        unsafe { slice::from_raw_parts(self.mapped_data() as *const u8, size) }.to_vec()
+    }
+
+    /// Needs [`vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS`].
+    pub fn device_address(&self, device: &Device) -> vk::DeviceAddress {
+        let info = vk::BufferDeviceAddressInfo::default().buffer(self.buffer);
+        unsafe { device.get_buffer_device_address(&info) }
+    }
 }
 
 impl Drop for AllocatedBuffer {
@@ -111,9 +138,11 @@ impl AllocatedImage {
         allocator: Arc<Mutex<vk_mem::Allocator>>,
         window_size: PhysicalSize<u32>,
     ) -> Self {
+        // Sampled by the bloom, see `PostProcess`.
         let usages = vk::ImageUsageFlags::TRANSFER_SRC
             | vk::ImageUsageFlags::TRANSFER_DST
             | vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::SAMPLED
             | vk::ImageUsageFlags::COLOR_ATTACHMENT;
 
         Self::new_img_with_window_size(
@@ -125,6 +154,28 @@ impl AllocatedImage {
         )
     }
 
+    /// Only used for one frame, see `TransientImages`.
+    pub fn new_transient(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        usages: vk::ImageUsageFlags,
+    ) -> Self {
+        Self::new(
+            device,
+            allocator,
+            vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            format,
+            usages,
+            false,
+        )
+    }
+
     pub fn new_draw_depth(
         device: Rc<Device>,
         allocator: Arc<Mutex<vk_mem::Allocator>>,
@@ -135,7 +186,156 @@ impl AllocatedImage {
             allocator,
             window_size,
             vk::Format::D32_SFLOAT,
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            // Sampled to build the depth pyramid.
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    /// Attachment of the geometry with `samples` per pixel, resolved at the end of the pass, see
+    /// `MsaaTargets`.
+    pub fn new_multisampled(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        extent: vk::Extent3D,
+        format: vk::Format,
+        usages: vk::ImageUsageFlags,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        let aspect = if format == vk::Format::D32_SFLOAT {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        };
+        let create_info = image_create_info(format, usages, extent, false).samples(samples);
+        Self::from_create_info(
+            device,
+            allocator,
+            &create_info,
+            aspect,
+            vk::ImageViewType::TYPE_2D,
+            vk::ComponentMapping::default(),
+        )
+    }
+
+    /// Written by the geometry and read by the deferred lighting, see `GBuffer`.
+    pub fn new_gbuffer(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        extent: vk::Extent3D,
+        format: vk::Format,
+    ) -> Self {
+        let usages = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+        Self::new(device, allocator, extent, format, usages, false)
+    }
+
+    /// Written and blurred by compute shaders, see `AoTargets`.
+    pub fn new_ambient_occlusion(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        extent: vk::Extent3D,
+    ) -> Self {
+        let usages = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED;
+        Self::new(
+            device,
+            allocator,
+            extent,
+            vk::Format::R32_SFLOAT,
+            usages,
+            false,
+        )
+    }
+
+    /// Every mip is written by a compute shader, see `GpuCulling`.
+    pub fn new_depth_pyramid(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        extent: vk::Extent2D,
+    ) -> Self {
+        Self::new(
+            device,
+            allocator,
+            vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            vk::Format::R32_SFLOAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            true,
+        )
+    }
+
+    /// Every mip is written by a compute shader, see `PostProcess`.
+    pub fn new_bloom_chain(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        extent: vk::Extent2D,
+    ) -> Self {
+        Self::new(
+            device,
+            allocator,
+            vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            true,
+        )
+    }
+
+    /// Written once by a compute shader, see `Ibl`.
+    pub fn new_lut(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        size: u32,
+    ) -> Self {
+        Self::new(
+            device,
+            allocator,
+            vk::Extent3D {
+                width: size,
+                height: size,
+                depth: 1,
+            },
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            false,
+        )
+    }
+
+    /// Every face and mip is written by a compute shader, see `Ibl`.
+    ///
+    /// `img_view` is a cube view, the faces are its layers.
+    pub fn new_cubemap(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        size: u32,
+        mip_levels: u32,
+    ) -> Self {
+        let extent = vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        };
+        let create_info = image_create_info(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            extent,
+            false,
+        )
+        .mip_levels(mip_levels)
+        .array_layers(6)
+        .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE);
+
+        Self::from_create_info(
+            device,
+            allocator,
+            &create_info,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageViewType::CUBE,
+            vk::ComponentMapping::default(),
         )
     }
 
@@ -189,6 +389,24 @@ impl AllocatedImage {
         mipmapped: bool,
     ) -> Self {
         let rimg_info = image_create_info(format, usages, extent, mipmapped);
+        Self::from_create_info(
+            device,
+            allocator,
+            &rimg_info,
+            aspect,
+            vk::ImageViewType::TYPE_2D,
+            vk::ComponentMapping::default(),
+        )
+    }
+
+    fn from_create_info(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        rimg_info: &vk::ImageCreateInfo,
+        aspect: vk::ImageAspectFlags,
+        view_type: vk::ImageViewType,
+        components: vk::ComponentMapping,
+    ) -> Self {
         let mut rimg_allocinfo = vk_mem::AllocationCreateInfo::default();
         {
             // Example : https://gpuopen-librariesandsdks.github.io/VulkanMemoryAllocator/html/usage_patterns.html
@@ -203,12 +421,15 @@ impl AllocatedImage {
             allocator
                 .lock()
                 .unwrap()
-                .create_image(&rimg_info, &rimg_allocinfo)
+                .create_image(rimg_info, &rimg_allocinfo)
                 .unwrap()
         };
 
-        let view_create_info =
-            image_view_create_info(format, img, aspect, Some(rimg_info.mip_levels));
+        let mut view_create_info =
+            image_view_create_info(rimg_info.format, img, aspect, Some(rimg_info.mip_levels))
+                .view_type(view_type)
+                .components(components);
+        view_create_info.subresource_range.layer_count = rimg_info.array_layers;
         let img_view = unsafe { device.create_image_view(&view_create_info, None).unwrap() };
 
         Self {
@@ -218,8 +439,8 @@ impl AllocatedImage {
             img,
             img_view,
             allocation,
-            extent,
-            format,
+            extent: rimg_info.extent,
+            format: rimg_info.format,
         }
     }
 
@@ -227,7 +448,7 @@ impl AllocatedImage {
     ///
     /// The image ends in vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
     ///
-    /// Example usecase : textures.
+    /// Example usecase : textures. To upload many of them, prefer an [`UploadBatch`].
     /// `data` must be at least `extent.depth * extent.width * extent.height * size_of::<u32>()`
     pub fn new_and_upload(
         commands: &VulkanCommands,
@@ -239,8 +460,90 @@ impl AllocatedImage {
         mipmapped: bool,
         data: &[u8],
     ) -> Self {
+        let mut batch = UploadBatch::new(allocator);
+        let new_image = batch.image(device, extent, format, usages, mipmapped, data);
+        batch.submit(commands);
+        new_image
+    }
+}
+
+/// Staging copies recorded together and submitted at once, instead of one `immediate_submit`
+/// each.
+pub struct UploadBatch {
+    allocator_copy: Arc<Mutex<vk_mem::Allocator>>,
+    /// Kept until the copies are done.
+    staging: Vec<AllocatedBuffer>,
+    copies: Vec<StagedCopy>,
+}
+
+enum StagedCopy {
+    Buffer {
+        src: vk::Buffer,
+        dst: vk::Buffer,
+        size: u64,
+    },
+    Image {
+        src: vk::Buffer,
+        dst: vk::Image,
+        extent: vk::Extent3D,
+        /// Offset in `src` of each mip level given.
+        level_offsets: Vec<u64>,
+        /// From the first level, the others aren't given.
+        generate_mips: bool,
+    },
+}
+
+impl UploadBatch {
+    pub fn new(allocator: Arc<Mutex<vk_mem::Allocator>>) -> Self {
+        Self {
+            allocator_copy: allocator,
+            staging: Default::default(),
+            copies: Default::default(),
+        }
+    }
+
+    pub fn allocator(&self) -> Arc<Mutex<vk_mem::Allocator>> {
+        self.allocator_copy.clone()
+    }
+
+    fn stage(&mut self, data: &[u8]) -> vk::Buffer {
+        let staging = AllocatedBuffer::new(
+            self.allocator_copy.clone(),
+            data.len() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MyMemoryUsage::StagingUpload,
+        );
+        unsafe { slice::from_raw_parts_mut(staging.mapped_data() as *mut u8, data.len()) }
+            .copy_from_slice(data);
+
+        let buffer = staging.buffer;
+        self.staging.push(staging);
+        buffer
+    }
+
+    /// `dst` needs the `TRANSFER_DST` usage.
+    pub fn buffer(&mut self, dst: vk::Buffer, data: &[u8]) {
+        let src = self.stage(data);
+        self.copies.push(StagedCopy::Buffer {
+            src,
+            dst,
+            size: data.len() as u64,
+        });
+    }
+
+    /// Allocates the image, filled once submitted, like [`AllocatedImage::new_and_upload`].
+    pub fn image(
+        &mut self,
+        device: Rc<Device>,
+        extent: vk::Extent3D,
+        format: vk::Format,
+        usages: vk::ImageUsageFlags,
+        mipmapped: bool,
+        data: &[u8],
+    ) -> AllocatedImage {
         let pixel = match format {
             vk::Format::R8G8B8A8_UNORM => size_of::<u8>() * 4,
+            vk::Format::R32G32B32A32_SFLOAT => size_of::<f32>() * 4,
             // vk::Format::R8_UNORM => size_of::<u8>() * 1,
             // vk::Format::R8G8_UNORM => size_of::<u8>() * 2,
             // vk::Format::R8G8B8_UNORM => size_of::<u8>() * 3,
@@ -250,88 +553,185 @@ impl AllocatedImage {
             // vk::Format::R16G16B16_UNORM => size_of::<u16>() * 3,
             // vk::Format::R16G16B16A16_UNORM => size_of::<u16>() * 4,
             // vk::Format::R32G32B32_SFLOAT => size_of::<f32>() * 3,
-            // vk::Format::R32G32B32A32_SFLOAT => size_of::<f32>() * 4,
             _ => unimplemented!("Unsupported image format : {format:?} !"),
         };
 
         let data_size = (extent.depth * extent.width * extent.height * pixel as u32) as usize;
         assert!(data_size <= data.len());
 
-        let buffer = AllocatedBuffer::new(
-            allocator.clone(),
-            data_size as u64,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            MyMemoryUsage::CpuToGpu,
-        );
-
-        let data_buffer =
-            unsafe { slice::from_raw_parts_mut(buffer.mapped_data() as *mut u8, data_size) };
-
-        data_buffer.copy_from_slice(&data[0..data_size]);
-
-        let new_image = Self::new(
+        let new_image = AllocatedImage::new(
             device,
-            allocator,
+            self.allocator_copy.clone(),
             extent,
             format,
             usages | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC,
             mipmapped,
         );
 
+        let src = self.stage(&data[0..data_size]);
+        self.copies.push(StagedCopy::Image {
+            src,
+            dst: new_image.img,
+            extent,
+            level_offsets: vec![0],
+            generate_mips: mipmapped,
+        });
+
+        new_image
+    }
+
+    /// Allocates the image with the mip levels of `texture`, or the ones generated from its
+    /// first level.
+    pub fn texture(
+        &mut self,
+        device: Rc<Device>,
+        texture: &TextureData,
+        usages: vk::ImageUsageFlags,
+    ) -> AllocatedImage {
+        let usages = if texture.generate_mips {
+            usages | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC
+        } else {
+            usages | vk::ImageUsageFlags::TRANSFER_DST
+        };
+        let mut create_info = image_create_info(
+            texture.format,
+            usages,
+            texture.extent,
+            texture.generate_mips,
+        );
+        if !texture.generate_mips {
+            create_info = create_info.mip_levels(texture.levels.len() as u32);
+        }
+        let new_image = AllocatedImage::from_create_info(
+            device,
+            self.allocator_copy.clone(),
+            &create_info,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageViewType::TYPE_2D,
+            texture.components,
+        );
+
+        let level_offsets = texture
+            .levels
+            .iter()
+            .scan(0, |offset, level| {
+                let level_offset = *offset;
+                *offset += level.len() as u64;
+                Some(level_offset)
+            })
+            .collect();
+        let src = self.stage(&texture.levels.concat());
+        self.copies.push(StagedCopy::Image {
+            src,
+            dst: new_image.img,
+            extent: texture.extent,
+            level_offsets,
+            generate_mips: texture.generate_mips,
+        });
+
+        new_image
+    }
+
+    /// Records every copy in a single command buffer, and waits for it.
+    pub fn submit(self, commands: &VulkanCommands) {
+        if self.copies.is_empty() {
+            return;
+        }
+
         commands.immediate_submit(|device, cmd_buf| {
-            transition_image(
-                device,
-                cmd_buf,
-                new_image.img,
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
-
-            let copy_region = vk::BufferImageCopy::default()
-                .buffer_offset(0)
+            self.copies.iter().for_each(|copy| match copy {
+                &StagedCopy::Buffer { src, dst, size } => {
+                    let copies = [vk::BufferCopy::default().size(size)];
+                    unsafe { device.cmd_copy_buffer(cmd_buf, src, dst, &copies[..]) };
+                }
+                StagedCopy::Image {
+                    src,
+                    dst,
+                    extent,
+                    level_offsets,
+                    generate_mips,
+                } => record_image_copy(
+                    device,
+                    cmd_buf,
+                    *src,
+                    *dst,
+                    *extent,
+                    level_offsets,
+                    *generate_mips,
+                ),
+            });
+        });
+    }
+}
+
+/// The image ends in vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL.
+fn record_image_copy(
+    device: &Device,
+    cmd_buf: vk::CommandBuffer,
+    src: vk::Buffer,
+    dst: vk::Image,
+    extent: vk::Extent3D,
+    level_offsets: &[u64],
+    generate_mips: bool,
+) {
+    transition_image(
+        device,
+        cmd_buf,
+        dst,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    );
+
+    let copy_regions: Vec<vk::BufferImageCopy> = level_offsets
+        .iter()
+        .zip(0..)
+        .map(|(&offset, mip)| {
+            vk::BufferImageCopy::default()
+                .buffer_offset(offset)
                 .buffer_row_length(0)
                 .buffer_image_height(0)
                 .image_subresource(vk::ImageSubresourceLayers {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
-                    mip_level: 0,
+                    mip_level: mip,
                     base_array_layer: 0,
                     layer_count: 1,
                 })
-                .image_extent(extent);
-            let copy_regions = [copy_region];
-
-            unsafe {
-                device.cmd_copy_buffer_to_image(
-                    cmd_buf,
-                    buffer.buffer,
-                    new_image.img,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    &copy_regions[..],
-                );
-            }
-
-            if mipmapped {
-                generate_mipmaps(
-                    device,
-                    cmd_buf,
-                    new_image.img,
-                    vk::Extent2D {
-                        width: extent.width,
-                        height: extent.height,
-                    },
-                );
-            } else {
-                transition_image(
-                    device,
-                    cmd_buf,
-                    new_image.img,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                );
-            }
-        });
+                .image_extent(vk::Extent3D {
+                    width: (extent.width >> mip).max(1),
+                    height: (extent.height >> mip).max(1),
+                    depth: 1,
+                })
+        })
+        .collect();
+
+    unsafe {
+        device.cmd_copy_buffer_to_image(
+            cmd_buf,
+            src,
+            dst,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &copy_regions[..],
+        );
+    }
 
-        new_image
+    if generate_mips {
+        generate_mipmaps(
+            device,
+            cmd_buf,
+            dst,
+            vk::Extent2D {
+                width: extent.width,
+                height: extent.height,
+            },
+        );
+    } else {
+        transition_image(
+            device,
+            cmd_buf,
+            dst,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
     }
 }
 
@@ -418,10 +818,10 @@ fn generate_mipmaps(
         let aspect_mask = vk::ImageAspectFlags::COLOR;
 
         let image_barrier = vk::ImageMemoryBarrier2::default()
-            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
-            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-            .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ)
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
             .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
             .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
             .subresource_range(image_subresource_range(aspect_mask, 1, mip))
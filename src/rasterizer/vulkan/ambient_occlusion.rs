@@ -0,0 +1,235 @@
+//! Screen-space ambient occlusion of the deferred shading.
+//!
+//! Computed from the depth and the normals of the G-buffer, then blurred along x and y while
+//! keeping the edges. The deferred lighting multiplies it into the ambient term.
+
+use std::{
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+
+use super::{
+    allocated::AllocatedImage,
+    compute_shaders::compute_pipeline,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    shaders_loader::{ShaderName, ShadersLoader},
+};
+use crate::rasterizer::settings::AoSettings;
+
+/// Side of the workgroups of the occlusion and of the blur.
+pub const AO_GROUP_SIZE: u32 = 8;
+
+/// The occlusion and the intermediate result of the blur, with the sets of the three passes.
+///
+/// Has the size of the G-buffer, which owns it.
+pub struct AoTargets {
+    device_copy: Rc<Device>,
+
+    /// The result, after the blur.
+    pub occlusion: AllocatedImage,
+    /// Blurred along x.
+    pub blurred: AllocatedImage,
+
+    _descriptor: DescriptorAllocator,
+    sampler: vk::Sampler,
+    desc_layout: vk::DescriptorSetLayout,
+    /// Writes `occlusion`.
+    pub ssao_desc: vk::DescriptorSet,
+    /// From `occlusion` to `blurred`, then back.
+    pub blur_descs: [vk::DescriptorSet; 2],
+}
+
+impl AoTargets {
+    pub fn new(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        normal: &AllocatedImage,
+        depth_img: &AllocatedImage,
+    ) -> Self {
+        let [occlusion, blurred] = [(); 2].map(|_| {
+            AllocatedImage::new_ambient_occlusion(device.clone(), allocator.clone(), normal.extent)
+        });
+
+        // Only read with `texelFetch`.
+        let sampler = {
+            let create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST);
+            unsafe { device.create_sampler(&create_info, None).unwrap() }
+        };
+        let sizes = [
+            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 3.),
+            (vk::DescriptorType::STORAGE_IMAGE, 1.),
+        ];
+        let descriptor = DescriptorAllocator::new(device.clone(), 3, &sizes[..]);
+        let desc_layout = ao_layout(&device);
+        // In the layouts of `ImageUsage::DepthRead` and `ImageUsage::Storage`.
+        let write = |src: &AllocatedImage, dst: &AllocatedImage| {
+            let desc = descriptor.allocate(desc_layout);
+            let mut writer = DescriptorWriter::default();
+            writer.write_image(
+                0,
+                depth_img.img_view,
+                sampler,
+                vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            [normal, src].iter().enumerate().for_each(|(i, image)| {
+                writer.write_image(
+                    1 + i as u32,
+                    image.img_view,
+                    sampler,
+                    vk::ImageLayout::GENERAL,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                );
+            });
+            writer.write_image(
+                3,
+                dst.img_view,
+                vk::Sampler::null(),
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::STORAGE_IMAGE,
+            );
+            writer.update_set(&device, desc);
+            desc
+        };
+        // The occlusion pass doesn't read the source.
+        let ssao_desc = write(&blurred, &occlusion);
+        let blur_descs = [write(&occlusion, &blurred), write(&blurred, &occlusion)];
+
+        Self {
+            device_copy: device,
+            occlusion,
+            blurred,
+            _descriptor: descriptor,
+            sampler,
+            desc_layout,
+            ssao_desc,
+            blur_descs,
+        }
+    }
+
+    pub fn images(&self) -> [&AllocatedImage; 2] {
+        [&self.occlusion, &self.blurred]
+    }
+}
+
+impl Drop for AoTargets {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop AoTargets");
+        unsafe {
+            self.device_copy
+                .destroy_descriptor_set_layout(self.desc_layout, None);
+            self.device_copy.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+/// The depth, the normals and the occlusion to blur, then the image written.
+///
+/// Created again with every [`AoTargets`], like the layout of the G-buffer.
+fn ao_layout(device: &Device) -> vk::DescriptorSetLayout {
+    DescriptorLayoutBuilder::default()
+        .add_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .add_binding(2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .add_binding(3, vk::DescriptorType::STORAGE_IMAGE)
+        .build(device, vk::ShaderStageFlags::COMPUTE)
+}
+
+/// Compute pipelines of the occlusion and of the blur.
+///
+/// Their sets are the scene data and one of [`AoTargets`].
+pub struct AmbientOcclusion {
+    device_copy: Rc<Device>,
+
+    ao_layout: vk::DescriptorSetLayout,
+    pub ssao_layout: vk::PipelineLayout,
+    pub ssao_pipeline: vk::Pipeline,
+    pub blur_layout: vk::PipelineLayout,
+    pub blur_pipeline: vk::Pipeline,
+}
+
+impl AmbientOcclusion {
+    pub fn new(
+        device: Rc<Device>,
+        shaders: &ShadersLoader,
+        scene_data_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let ao_layout = ao_layout(&device);
+        let set_layouts = [scene_data_layout, ao_layout];
+        let (ssao_layout, ssao_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::Ssao,
+            &set_layouts[..],
+            size_of::<AoPushConstants>(),
+        );
+        let (blur_layout, blur_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::SsaoBlur,
+            &set_layouts[..],
+            size_of::<AoPushConstants>(),
+        );
+
+        Self {
+            device_copy: device,
+            ao_layout,
+            ssao_layout,
+            ssao_pipeline,
+            blur_layout,
+            blur_pipeline,
+        }
+    }
+}
+
+impl Drop for AmbientOcclusion {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop AmbientOcclusion");
+        unsafe {
+            self.device_copy.destroy_pipeline(self.ssao_pipeline, None);
+            self.device_copy
+                .destroy_pipeline_layout(self.ssao_layout, None);
+            self.device_copy.destroy_pipeline(self.blur_pipeline, None);
+            self.device_copy
+                .destroy_pipeline_layout(self.blur_layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.ao_layout, None);
+        }
+    }
+}
+
+/// See `resources/ambient_occlusion.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct AoPushConstants {
+    pub size: [u32; 2],
+    pub radius: f32,
+    pub intensity: f32,
+    pub samples: u32,
+    _padding: u32,
+    /// One pixel along the axis of the blur, unused by the occlusion.
+    pub direction: [i32; 2],
+}
+
+impl AoPushConstants {
+    pub fn new(settings: &AoSettings, extent: vk::Extent2D) -> Self {
+        Self {
+            size: [extent.width, extent.height],
+            radius: settings.radius,
+            intensity: settings.intensity,
+            samples: settings.samples.clamp(1, AoSettings::MAX_SAMPLES),
+            _padding: 0,
+            direction: [0, 0],
+        }
+    }
+
+    pub fn with_direction(self, direction: [i32; 2]) -> Self {
+        Self { direction, ..self }
+    }
+}
@@ -13,6 +13,7 @@ use std::{
 use ash::ext::debug_utils;
 use ash::{
     Device, Entry, Instance,
+    ext::swapchain_colorspace,
     khr::{surface, swapchain},
     vk,
 };
@@ -34,28 +35,60 @@ pub struct VulkanBase {
     // would crash !
     _entry: Entry,
 
-    pub window: Rc<Window>,
+    /// `None` when headless, see [`Self::new_headless`].
+    presentation: Option<Presentation>,
 
     pub instance: Instance,
     #[cfg(feature = "vulkan_validation_layers")]
     debug_utils_loader: debug_utils::Instance,
     #[cfg(feature = "vulkan_validation_layers")]
     debug_messenger: vk::DebugUtilsMessengerEXT,
-    pub surface_loader: surface::Instance,
     pub chosen_gpu: vk::PhysicalDevice,
     pub device: Rc<Device>,
-    pub surface: vk::SurfaceKHR,
     pub queue_family_index: u32,
+    /// Optional feature, for the GPU stats.
+    #[cfg(feature = "vulkan_stats")]
+    pub pipeline_statistics: bool,
+    /// Optional feature, BCn textures are decompressed on the CPU without it.
+    pub texture_compression_bc: bool,
+    /// Optional feature, for the MSAA shaded per sample.
+    pub sample_rate_shading: bool,
+    /// Sample counts of both the colour and the depth attachments, for the MSAA.
+    pub msaa_samples: vk::SampleCountFlags,
+    /// Names of all the GPUs, by index for [`gpu_selection`].
+    pub gpus: Vec<String>,
+    /// Of `chosen_gpu` in `gpus`.
+    pub gpu_index: usize,
+}
+
+/// The window and its surface, what is needed to present.
+pub struct Presentation {
+    pub window: Rc<Window>,
+    pub surface_loader: surface::Instance,
+    pub surface: vk::SurfaceKHR,
+    /// Supported by the surface with `chosen_gpu`.
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
 }
 
 impl VulkanBase {
     pub fn new(window: Rc<Window>) -> Self {
+        Self::create(Some(window))
+    }
+
+    /// Without any window nor surface extension, for rendering offscreen only, e.g. on a CI
+    /// runner with lavapipe.
+    pub fn new_headless() -> Self {
+        Self::create(None)
+    }
+
+    fn create(window: Option<Rc<Window>>) -> Self {
         #[cfg(not(feature = "vulkan_linked"))]
         let entry = unsafe { Entry::load().unwrap() };
         #[cfg(feature = "vulkan_linked")]
         let entry = Entry::linked();
 
-        let extension_names = extension_names(&window);
+        let extension_names = extension_names(&entry, window.as_ref());
         let app_info = app_info();
         let create_flags = instance_create_flags();
         let create_info = vk::InstanceCreateInfo::default()
@@ -72,41 +105,89 @@ impl VulkanBase {
 
         #[cfg(feature = "vulkan_validation_layers")]
         let (debug_utils_loader, debug_messenger) = debug_messenger(&entry, &instance);
-        let surface = surface(&window, &entry, &instance);
-
-        let (surface_loader, chosen_gpu, queue_family_index) =
-            find_physical_device(&entry, &instance, &app_info, &surface);
+        let mut presentation = window.map(|window| Presentation {
+            surface: surface(&window, &entry, &instance),
+            surface_loader: surface::Instance::new(&entry, &instance),
+            window,
+            present_modes: Vec::new(),
+            surface_formats: Vec::new(),
+        });
+
+        let (chosen_gpu, queue_family_index) =
+            find_physical_device(&instance, &app_info, presentation.as_ref());
+        let gpus = gpu_names(&instance);
+        let gpu_index = unsafe { instance.enumerate_physical_devices().unwrap() }
+            .iter()
+            .position(|p| *p == chosen_gpu)
+            .unwrap();
+        if let Some(p) = &mut presentation {
+            unsafe {
+                p.present_modes = p
+                    .surface_loader
+                    .get_physical_device_surface_present_modes(chosen_gpu, p.surface)
+                    .unwrap();
+                p.surface_formats = p
+                    .surface_loader
+                    .get_physical_device_surface_formats(chosen_gpu, p.surface)
+                    .unwrap();
+            }
+        }
 
-        {
+        let msaa_samples = {
             let props = unsafe { instance.get_physical_device_properties(chosen_gpu) };
-            // https://registry.khronos.org/vulkan/specs/latest/man/html/VkPhysicalDeviceLimits.html
-            // https://vulkan.gpuinfo.org/displaydevicelimit.php?platform=linux&name=minUniformBufferOffsetAlignment
-            eprintln!(
-                "TODO: min_uniform_buffer_offset_alignment : {}",
-                props.limits.min_uniform_buffer_offset_alignment
-            );
-        }
+            props.limits.framebuffer_color_sample_counts
+                & props.limits.framebuffer_depth_sample_counts
+        };
 
-        let device = device(&instance, chosen_gpu, queue_family_index);
+        let features = unsafe { instance.get_physical_device_features(chosen_gpu) };
+        let pipeline_statistics = features.pipeline_statistics_query == vk::TRUE;
+        let texture_compression_bc = features.texture_compression_bc == vk::TRUE;
+        let sample_rate_shading = features.sample_rate_shading == vk::TRUE;
+        let device = device(
+            &instance,
+            chosen_gpu,
+            queue_family_index,
+            presentation.is_some(),
+            pipeline_statistics,
+            texture_compression_bc,
+            sample_rate_shading,
+        );
 
         VulkanBase {
             _entry: entry,
 
-            window,
+            presentation,
 
             instance,
             #[cfg(feature = "vulkan_validation_layers")]
             debug_utils_loader,
             #[cfg(feature = "vulkan_validation_layers")]
             debug_messenger,
-            surface_loader,
             chosen_gpu,
             device: Rc::new(device),
-            surface,
 
             queue_family_index,
+            #[cfg(feature = "vulkan_stats")]
+            pipeline_statistics,
+            texture_compression_bc,
+            sample_rate_shading,
+            msaa_samples,
+            gpus,
+            gpu_index,
         }
     }
+
+    /// Panics when headless.
+    pub fn presentation(&self) -> &Presentation {
+        self.presentation
+            .as_ref()
+            .expect("No window nor surface when headless")
+    }
+
+    /// Panics when headless.
+    pub fn window(&self) -> &Rc<Window> {
+        &self.presentation().window
+    }
 }
 
 impl Drop for VulkanBase {
@@ -120,7 +201,11 @@ impl Drop for VulkanBase {
                 "About to destroy device but it is referenced elsewhere !"
             );
             self.device.destroy_device(None);
-            self.surface_loader.destroy_surface(self.surface, None);
+            if let Some(presentation) = &self.presentation {
+                presentation
+                    .surface_loader
+                    .destroy_surface(presentation.surface, None);
+            }
             #[cfg(feature = "vulkan_validation_layers")]
             self.debug_utils_loader
                 .destroy_debug_utils_messenger(self.debug_messenger, None);
@@ -161,18 +246,32 @@ unsafe extern "system" fn vulkan_debug_callback(
     vk::FALSE
 }
 
-fn extension_names<W: Deref<Target = Window>>(window: &W) -> Vec<*const c_char> {
-    let extension_names =
-        ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
-            .unwrap()
-            .to_vec();
+/// Surface extensions are only needed with a `window`.
+///
+/// The HDR colour spaces of the swapchain also need `VK_EXT_swapchain_colorspace`, if available.
+fn extension_names<W: Deref<Target = Window>>(
+    entry: &Entry,
+    window: Option<&W>,
+) -> Vec<*const c_char> {
+    let mut extension_names = window
+        .map(|window| {
+            ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
+                .unwrap()
+                .to_vec()
+        })
+        .unwrap_or_default();
+
+    let available = unsafe { entry.enumerate_instance_extension_properties(None).unwrap() };
+    if window.is_some()
+        && available
+            .iter()
+            .any(|e| e.extension_name_as_c_str() == Ok(swapchain_colorspace::NAME))
+    {
+        extension_names.push(swapchain_colorspace::NAME.as_ptr());
+    }
 
     #[cfg(feature = "vulkan_validation_layers")]
-    let extension_names = {
-        let mut extension_names = extension_names;
-        extension_names.push(debug_utils::NAME.as_ptr());
-        extension_names
-    };
+    extension_names.push(debug_utils::NAME.as_ptr());
 
     #[cfg(any(target_os = "macos", target_os = "ios"))]
     {
@@ -257,21 +356,68 @@ fn surface<W: Deref<Target = Window>>(
     }
 }
 
+/// GPU requested with `--gpu <index|name>` on the command line, or else with the `GRAPHICS_GPU`
+/// environment variable. A name only needs to contain the given one, ignoring the case.
+fn gpu_selection() -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|a| a == "--gpu")
+        .and_then(|i| args.get(i + 1).cloned())
+        .or_else(|| std::env::var("GRAPHICS_GPU").ok())
+}
+
+fn gpu_names(instance: &Instance) -> Vec<String> {
+    unsafe { instance.enumerate_physical_devices().unwrap() }
+        .iter()
+        .map(|pdevice| {
+            let properties = unsafe { instance.get_physical_device_properties(*pdevice) };
+            properties
+                .device_name_as_c_str()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect()
+}
+
+/// Favours the discrete GPUs, then the ones with the most device local memory.
+fn gpu_score(instance: &Instance, pdevice: vk::PhysicalDevice) -> (u32, u64) {
+    let properties = unsafe { instance.get_physical_device_properties(pdevice) };
+    let type_score = match properties.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    };
+    let memory = unsafe { instance.get_physical_device_memory_properties(pdevice) };
+    let local_memory = memory
+        .memory_heaps
+        .iter()
+        .take(memory.memory_heap_count as usize)
+        .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|h| h.size)
+        .sum();
+    (type_score, local_memory)
+}
+
 /// Enable features in create device info in [`device`]
+///
+/// Among the suitable GPUs, takes the one of [`gpu_selection`], or else the best of
+/// [`gpu_score`].
 fn find_physical_device(
-    entry: &Entry,
     instance: &Instance,
     app_info: &vk::ApplicationInfo,
-    surface: &vk::SurfaceKHR,
-) -> (surface::Instance, vk::PhysicalDevice, u32) {
-    let surface_loader = surface::Instance::new(entry, instance);
+    presentation: Option<&Presentation>,
+) -> (vk::PhysicalDevice, u32) {
+    let device_extension_names = device_extension_names(presentation.is_some());
     let pdevices = unsafe {
         instance
             .enumerate_physical_devices()
             .expect("Physical device error")
     };
     println!("There are {} found GPUs : ", pdevices.len());
-    let (pdevice, queue_family_index) = pdevices
+    let suitable = pdevices
         .iter()
         .filter(|pdevice| {
             let properties = unsafe { instance.get_physical_device_properties(**pdevice) };
@@ -306,6 +452,14 @@ fn find_physical_device(
             unsafe { instance.get_physical_device_features2(**pdevice, &mut features) };
 
             let mut has_features = true;
+            if features.features.fill_mode_non_solid == vk::FALSE {
+                eprintln!("\tMissing feature : fill_mode_non_solid");
+                has_features = false;
+            }
+            if features.features.draw_indirect_first_instance == vk::FALSE {
+                eprintln!("\tMissing feature : draw_indirect_first_instance");
+                has_features = false;
+            }
             if features12.buffer_device_address == vk::FALSE {
                 eprintln!("\tMissing feature 1.2 : buffer_device_address");
                 has_features = false;
@@ -315,6 +469,31 @@ fn find_physical_device(
 
                 has_features = false;
             }
+            // Bindless, see `BindlessTable`
+            if features12.runtime_descriptor_array == vk::FALSE {
+                eprintln!("\tMissing feature 1.2 : runtime_descriptor_array");
+                has_features = false;
+            }
+            if features12.descriptor_binding_partially_bound == vk::FALSE {
+                eprintln!("\tMissing feature 1.2 : descriptor_binding_partially_bound");
+                has_features = false;
+            }
+            if features12.descriptor_binding_sampled_image_update_after_bind == vk::FALSE {
+                eprintln!(
+                    "\tMissing feature 1.2 : descriptor_binding_sampled_image_update_after_bind"
+                );
+                has_features = false;
+            }
+            if features12.shader_sampled_image_array_non_uniform_indexing == vk::FALSE {
+                eprintln!(
+                    "\tMissing feature 1.2 : shader_sampled_image_array_non_uniform_indexing"
+                );
+                has_features = false;
+            }
+            if features12.draw_indirect_count == vk::FALSE {
+                eprintln!("\tMissing feature 1.2 : draw_indirect_count");
+                has_features = false;
+            }
             if features13.dynamic_rendering == vk::FALSE {
                 eprintln!("\tMissing feature 1.3 : dynamic_rendering");
                 has_features = false;
@@ -336,30 +515,30 @@ fn find_physical_device(
                 .iter()
                 .map(|e| e.extension_name_as_c_str().unwrap())
                 .collect();
-            DEVICE_EXTENSION_NAMES
+            device_extension_names
                 .iter()
                 .filter(|e| !available_exts_names.contains(e))
                 .inspect(|e| eprintln!("\tExtension not found : {}", e.to_string_lossy()))
                 .next()
                 .is_none()
         })
-        .find_map(|pdevice| {
-            // Find a queue that can do graphics and that is supported by surface.
+        .filter_map(|pdevice| {
+            // Find a queue that can do graphics and that is supported by surface, if any.
             unsafe { instance.get_physical_device_queue_family_properties(*pdevice) }
                 .iter()
                 .enumerate()
                 .find_map(|(index, info)| {
                     let supports_graphic_and_surface =
                         info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                            && unsafe {
-                                surface_loader
+                            && presentation.is_none_or(|p| unsafe {
+                                p.surface_loader
                                     .get_physical_device_surface_support(
                                         *pdevice,
                                         index as u32,
-                                        *surface,
+                                        p.surface,
                                     )
                                     .unwrap()
-                            };
+                            });
                     if supports_graphic_and_surface {
                         Some((*pdevice, index))
                     } else {
@@ -367,7 +546,39 @@ fn find_physical_device(
                     }
                 })
         })
+        .collect::<Vec<_>>();
+
+    let names = gpu_names(instance);
+    let name = |pdevice: &vk::PhysicalDevice| {
+        let index = pdevices.iter().position(|p| p == pdevice).unwrap();
+        (index, &names[index])
+    };
+    let selected = gpu_selection().and_then(|selection| {
+        let found = suitable.iter().find(|(pdevice, _)| {
+            let (index, name) = name(pdevice);
+            selection.parse::<usize>().map_or_else(
+                |_| name.to_lowercase().contains(&selection.to_lowercase()),
+                |i| i == index,
+            )
+        });
+        if found.is_none() {
+            eprintln!("No suitable GPU matches '{selection}', using the default one.");
+        }
+        found
+    });
+    // Reversed so that the first one wins ties.
+    let (pdevice, queue_family_index) = *selected
+        .or_else(|| {
+            suitable
+                .iter()
+                .rev()
+                .max_by_key(|(pdevice, _)| gpu_score(instance, *pdevice))
+        })
         .expect("Couldn't find suitable device.");
+    {
+        let (index, name) = name(&pdevice);
+        println!("Chosen GPU : {index} {name}");
+    }
     {
         let properties = unsafe { instance.get_physical_device_memory_properties(pdevice) };
         println!("    Memory types :");
@@ -379,7 +590,7 @@ fn find_physical_device(
             .for_each(|(i, mt)| println!("    - {i:2} {:?}", mt.property_flags));
     }
 
-    (surface_loader, pdevice, queue_family_index as u32)
+    (pdevice, queue_family_index as u32)
 }
 
 const DEVICE_EXTENSION_NAMES: &[&CStr] = &[
@@ -388,24 +599,56 @@ const DEVICE_EXTENSION_NAMES: &[&CStr] = &[
     ash::khr::portability_subset::NAME,
 ];
 
+/// Without presentation, the swapchain extension isn't needed.
+fn device_extension_names(presentation: bool) -> Vec<&'static CStr> {
+    DEVICE_EXTENSION_NAMES
+        .iter()
+        .copied()
+        .filter(|e| presentation || *e != swapchain::NAME)
+        .collect()
+}
+
 /// Sync features with device search in [`find_physical_device`]
-fn device(instance: &Instance, chosen_gpu: vk::PhysicalDevice, queue_family_index: u32) -> Device {
+fn device(
+    instance: &Instance,
+    chosen_gpu: vk::PhysicalDevice,
+    queue_family_index: u32,
+    presentation: bool,
+    pipeline_statistics: bool,
+    texture_compression_bc: bool,
+    sample_rate_shading: bool,
+) -> Device {
     let queue_info = vk::DeviceQueueCreateInfo::default()
         .queue_family_index(queue_family_index)
         .queue_priorities(&[1.0]);
 
     let mut features12 = vk::PhysicalDeviceVulkan12Features::default()
         .buffer_device_address(true)
-        .descriptor_indexing(true);
+        .descriptor_indexing(true)
+        .runtime_descriptor_array(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .shader_sampled_image_array_non_uniform_indexing(true)
+        .draw_indirect_count(true);
     let mut features13 = vk::PhysicalDeviceVulkan13Features::default()
         .dynamic_rendering(true)
         .synchronization2(true);
     let mut features = vk::PhysicalDeviceFeatures2::default()
+        .features(
+            vk::PhysicalDeviceFeatures::default()
+                .fill_mode_non_solid(true)
+                .draw_indirect_first_instance(true)
+                .pipeline_statistics_query(pipeline_statistics)
+                .texture_compression_bc(texture_compression_bc)
+                .sample_rate_shading(sample_rate_shading),
+        )
         .push_next(&mut features12)
         .push_next(&mut features13);
 
-    let device_extension_names_raw: Vec<*const _> =
-        DEVICE_EXTENSION_NAMES.iter().map(|e| e.as_ptr()).collect();
+    let device_extension_names_raw: Vec<*const _> = device_extension_names(presentation)
+        .iter()
+        .map(|e| e.as_ptr())
+        .collect();
 
     let device_create_info = vk::DeviceCreateInfo::default()
         .queue_create_infos(std::slice::from_ref(&queue_info))
@@ -0,0 +1,777 @@
+//! CPU decompression of BCn blocks, for the GPUs without `textureCompressionBC`.
+//!
+//! BC1 to BC5 and BC7 are decoded to 8 bits per channel. BC6H isn't, its textures fail to load
+//! on those GPUs.
+
+use ash::vk;
+
+const BLOCK_TEXELS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+enum BlockKind {
+    Bc1 { alpha: bool },
+    Bc2,
+    Bc3,
+    Bc4 { signed: bool },
+    Bc5 { signed: bool },
+    Bc6h,
+    Bc7,
+}
+
+impl BlockKind {
+    fn of(format: vk::Format) -> Option<Self> {
+        use vk::Format as F;
+
+        Some(match format {
+            F::BC1_RGB_UNORM_BLOCK | F::BC1_RGB_SRGB_BLOCK => Self::Bc1 { alpha: false },
+            F::BC1_RGBA_UNORM_BLOCK | F::BC1_RGBA_SRGB_BLOCK => Self::Bc1 { alpha: true },
+            F::BC2_UNORM_BLOCK | F::BC2_SRGB_BLOCK => Self::Bc2,
+            F::BC3_UNORM_BLOCK | F::BC3_SRGB_BLOCK => Self::Bc3,
+            F::BC4_UNORM_BLOCK => Self::Bc4 { signed: false },
+            F::BC4_SNORM_BLOCK => Self::Bc4 { signed: true },
+            F::BC5_UNORM_BLOCK => Self::Bc5 { signed: false },
+            F::BC5_SNORM_BLOCK => Self::Bc5 { signed: true },
+            F::BC6H_UFLOAT_BLOCK | F::BC6H_SFLOAT_BLOCK => Self::Bc6h,
+            F::BC7_UNORM_BLOCK | F::BC7_SRGB_BLOCK => Self::Bc7,
+            _ => return None,
+        })
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            Self::Bc1 { .. } | Self::Bc4 { .. } => 8,
+            _ => 16,
+        }
+    }
+
+    fn texel_size(self) -> usize {
+        match self {
+            Self::Bc4 { .. } => 1,
+            Self::Bc5 { .. } => 2,
+            _ => 4,
+        }
+    }
+}
+
+pub fn is_bcn(format: vk::Format) -> bool {
+    BlockKind::of(format).is_some()
+}
+
+/// The format of the texels returned by [`decode`], keeping the color space.
+fn decoded_format(format: vk::Format) -> vk::Format {
+    use vk::Format as F;
+
+    match format {
+        F::BC1_RGB_SRGB_BLOCK
+        | F::BC1_RGBA_SRGB_BLOCK
+        | F::BC2_SRGB_BLOCK
+        | F::BC3_SRGB_BLOCK
+        | F::BC7_SRGB_BLOCK => F::R8G8B8A8_SRGB,
+        F::BC4_UNORM_BLOCK => F::R8_UNORM,
+        F::BC4_SNORM_BLOCK => F::R8_SNORM,
+        F::BC5_UNORM_BLOCK => F::R8G8_UNORM,
+        F::BC5_SNORM_BLOCK => F::R8G8_SNORM,
+        _ => F::R8G8B8A8_UNORM,
+    }
+}
+
+/// Decodes a `width`×`height` image, returning its new format and texels.
+pub fn decode(
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    blocks: &[u8],
+) -> Result<(vk::Format, Vec<u8>), String> {
+    let Some(kind) = BlockKind::of(format) else {
+        return Err(format!("{format:?} isn't a BCn format"));
+    };
+    if let BlockKind::Bc6h = kind {
+        return Err("BC6H can't be decoded on the CPU".into());
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let blocks_x = width.div_ceil(4);
+    let block_count = blocks_x * height.div_ceil(4);
+    if blocks.len() < block_count * kind.block_size() {
+        return Err(format!(
+            "Expected {block_count} blocks of {format:?}, got {} bytes",
+            blocks.len()
+        ));
+    }
+
+    let texel_size = kind.texel_size();
+    let mut texels = vec![0; width * height * texel_size];
+    let mut decoded = [0; BLOCK_TEXELS * 4];
+    for (i, block) in blocks
+        .chunks_exact(kind.block_size())
+        .take(block_count)
+        .enumerate()
+    {
+        decode_block(kind, block, &mut decoded);
+
+        // The blocks on the right and bottom edges are cropped.
+        let (x0, y0) = (i % blocks_x * 4, i / blocks_x * 4);
+        for y in 0..4.min(height - y0) {
+            let row = 4.min(width - x0) * texel_size;
+            let dst = ((y0 + y) * width + x0) * texel_size;
+            let src = y * 4 * texel_size;
+            texels[dst..dst + row].copy_from_slice(&decoded[src..src + row]);
+        }
+    }
+
+    Ok((decoded_format(format), texels))
+}
+
+/// Writes the 16 texels of the block, in rows.
+fn decode_block(kind: BlockKind, block: &[u8], out: &mut [u8; BLOCK_TEXELS * 4]) {
+    match kind {
+        BlockKind::Bc1 { alpha } => {
+            let colors = bc1_colors(block, false);
+            for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+                texel.copy_from_slice(&colors[color_index(block, i)]);
+                if !alpha {
+                    texel[3] = 255;
+                }
+            }
+        }
+        BlockKind::Bc2 => {
+            let colors = bc1_colors(&block[8..], true);
+            let alphas = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+                texel.copy_from_slice(&colors[color_index(&block[8..], i)]);
+                texel[3] = ((alphas >> (4 * i)) & 0xf) as u8 * 17;
+            }
+        }
+        BlockKind::Bc3 => {
+            let colors = bc1_colors(&block[8..], true);
+            let alphas = bc4_values(&block[..8], false);
+            for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+                texel.copy_from_slice(&colors[color_index(&block[8..], i)]);
+                texel[3] = alphas[i];
+            }
+        }
+        BlockKind::Bc4 { signed } => {
+            out[..BLOCK_TEXELS].copy_from_slice(&bc4_values(block, signed));
+        }
+        BlockKind::Bc5 { signed } => {
+            let red = bc4_values(&block[..8], signed);
+            let green = bc4_values(&block[8..], signed);
+            for (i, texel) in out[..BLOCK_TEXELS * 2].chunks_exact_mut(2).enumerate() {
+                texel.copy_from_slice(&[red[i], green[i]]);
+            }
+        }
+        BlockKind::Bc6h => unreachable!(),
+        BlockKind::Bc7 => bc7(block, out),
+    }
+}
+
+/// The 4 colors of a BC1 color block, BC2 and BC3 always use the `four_colors` mode.
+fn bc1_colors(block: &[u8], four_colors: bool) -> [[u8; 4]; 4] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let rgb565 = |c: u16| {
+        let (r, g, b) = ((c >> 11) & 31, (c >> 5) & 63, c & 31);
+        [(r << 3 | r >> 2), (g << 2 | g >> 4), (b << 3 | b >> 2)].map(|v| v as u32)
+    };
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let mix = |w0: u32, w1: u32| {
+        let c = |i: usize| ((e0[i] * w0 + e1[i] * w1 + (w0 + w1) / 2) / (w0 + w1)) as u8;
+        [c(0), c(1), c(2), 255]
+    };
+
+    if four_colors || c0 > c1 {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0; 4]]
+    }
+}
+
+fn color_index(color_block: &[u8], texel: usize) -> usize {
+    let indices = u32::from_le_bytes(color_block[4..8].try_into().unwrap());
+    ((indices >> (2 * texel)) & 3) as usize
+}
+
+/// One channel, the `signed` values are stored as `i8`.
+fn bc4_values(block: &[u8], signed: bool) -> [u8; BLOCK_TEXELS] {
+    let endpoint = |b: u8| {
+        if signed {
+            (b as i8).max(-127) as i32
+        } else {
+            b as i32
+        }
+    };
+    let (e0, e1) = (endpoint(block[0]), endpoint(block[1]));
+    let (min, max) = if signed { (-127, 127) } else { (0, 255) };
+    let lerp = |i: i32, n: i32| (((n - i) * e0 + i * e1) as f32 / n as f32).round() as i32;
+
+    let palette = if e0 > e1 {
+        [
+            e0,
+            e1,
+            lerp(1, 7),
+            lerp(2, 7),
+            lerp(3, 7),
+            lerp(4, 7),
+            lerp(5, 7),
+            lerp(6, 7),
+        ]
+    } else {
+        [
+            e0,
+            e1,
+            lerp(1, 5),
+            lerp(2, 5),
+            lerp(3, 5),
+            lerp(4, 5),
+            min,
+            max,
+        ]
+    };
+
+    let mut indices = [0; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+    std::array::from_fn(|i| palette[((indices >> (3 * i)) & 7) as usize] as u8)
+}
+
+/// Reads the fields of a BC7 block, from the least significant bit.
+struct BitReader(u128);
+
+impl BitReader {
+    fn read(&mut self, bits: u32) -> u32 {
+        let value = (self.0 & ((1 << bits) - 1)) as u32;
+        self.0 >>= bits;
+        value
+    }
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p-bit per endpoint.
+    endpoint_pbits: bool,
+    /// One p-bit per subset, shared by its endpoints.
+    shared_pbits: bool,
+    index_bits: u32,
+    /// Of the second set of indices, only in modes 4 and 5.
+    index2_bits: u32,
+}
+
+const fn bc7_mode(
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    endpoint_bits: (u32, u32),
+    pbits: (bool, bool),
+    index_bits: (u32, u32),
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        index_selection_bits,
+        color_bits: endpoint_bits.0,
+        alpha_bits: endpoint_bits.1,
+        endpoint_pbits: pbits.0,
+        shared_pbits: pbits.1,
+        index_bits: index_bits.0,
+        index2_bits: index_bits.1,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, 0, 0, (4, 0), (true, false), (3, 0)),
+    bc7_mode(2, 6, 0, 0, (6, 0), (false, true), (3, 0)),
+    bc7_mode(3, 6, 0, 0, (5, 0), (false, false), (2, 0)),
+    bc7_mode(2, 6, 0, 0, (7, 0), (true, false), (2, 0)),
+    bc7_mode(1, 0, 2, 1, (5, 6), (false, false), (2, 3)),
+    bc7_mode(1, 0, 2, 0, (7, 8), (false, false), (2, 2)),
+    bc7_mode(1, 0, 0, 0, (7, 7), (true, false), (4, 0)),
+    bc7_mode(2, 6, 0, 0, (5, 5), (true, false), (2, 0)),
+];
+
+fn bc7(block: &[u8], out: &mut [u8; BLOCK_TEXELS * 4]) {
+    let mut bits = BitReader(u128::from_le_bytes(block.try_into().unwrap()));
+    let mode_index = block[0].trailing_zeros();
+    // Reserved mode, decoded as transparent black.
+    let Some(mode) = BC7_MODES.get(mode_index as usize) else {
+        out.fill(0);
+        return;
+    };
+    bits.read(mode_index + 1);
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    let channels = if mode.alpha_bits > 0 { 4 } else { 3 };
+    for channel in 0..channels {
+        let channel_bits = if channel < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[channel] = bits.read(channel_bits);
+        }
+    }
+
+    let pbits: Vec<u32> = if mode.endpoint_pbits {
+        (0..endpoint_count).map(|_| bits.read(1)).collect()
+    } else if mode.shared_pbits {
+        (0..mode.subsets)
+            .map(|_| bits.read(1))
+            .flat_map(|pbit| [pbit, pbit])
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for (i, endpoint) in endpoints[..endpoint_count].iter_mut().enumerate() {
+        for (channel, value) in endpoint.iter_mut().enumerate().take(channels) {
+            let mut precision = if channel < 3 {
+                mode.color_bits
+            } else {
+                mode.alpha_bits
+            };
+            if let Some(&pbit) = pbits.get(i) {
+                *value = *value << 1 | pbit;
+                precision += 1;
+            }
+            *value = *value << (8 - precision) | *value >> (2 * precision - 8);
+        }
+        if channels == 3 {
+            endpoint[3] = 255;
+        }
+    }
+
+    let subset = |texel: usize| match mode.subsets {
+        1 => 0,
+        2 => ((BC7_PARTITIONS_2[partition] >> texel) & 1) as usize,
+        _ => BC7_PARTITIONS_3[partition][texel] as usize,
+    };
+    let is_anchor = |texel: usize| {
+        texel == 0
+            || (mode.subsets == 2 && texel == BC7_ANCHORS_2[partition] as usize)
+            || (mode.subsets == 3
+                && (texel == BC7_ANCHORS_3_SUBSET_1[partition] as usize
+                    || texel == BC7_ANCHORS_3_SUBSET_2[partition] as usize))
+    };
+    // The anchors have an implicit 0 as their most significant bit.
+    let indices: [u32; BLOCK_TEXELS] =
+        std::array::from_fn(|i| bits.read(mode.index_bits - is_anchor(i) as u32));
+    let indices2: [u32; BLOCK_TEXELS] = if mode.index2_bits > 0 {
+        std::array::from_fn(|i| bits.read(mode.index2_bits - (i == 0) as u32))
+    } else {
+        [0; BLOCK_TEXELS]
+    };
+
+    let (color_indices, alpha_indices) = match (mode.index2_bits, index_selection) {
+        (0, _) => ((&indices, mode.index_bits), (&indices, mode.index_bits)),
+        (_, 0) => ((&indices, mode.index_bits), (&indices2, mode.index2_bits)),
+        _ => ((&indices2, mode.index2_bits), (&indices, mode.index_bits)),
+    };
+
+    for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+        let s = subset(i);
+        let (e0, e1) = (endpoints[2 * s], endpoints[2 * s + 1]);
+        let color_weight = bc7_weight(color_indices.1, color_indices.0[i]);
+        let alpha_weight = bc7_weight(alpha_indices.1, alpha_indices.0[i]);
+        let mut color: [u8; 4] = std::array::from_fn(|channel| {
+            let weight = if channel < 3 {
+                color_weight
+            } else {
+                alpha_weight
+            };
+            ((e0[channel] * (64 - weight) + e1[channel] * weight + 32) >> 6) as u8
+        });
+        if rotation > 0 {
+            color.swap(3, rotation as usize - 1);
+        }
+        texel.copy_from_slice(&color);
+    }
+}
+
+fn bc7_weight(bits: u32, index: u32) -> u32 {
+    const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+    const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+    const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+    match bits {
+        2 => WEIGHTS_2[index as usize],
+        3 => WEIGHTS_3[index as usize],
+        _ => WEIGHTS_4[index as usize],
+    }
+}
+
+/// Bit `i` is the subset of texel `i`.
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+const BC7_PARTITIONS_3: [[u8; BLOCK_TEXELS]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+/// Texel whose index is one bit shorter, in subset 1. Subset 0 always starts at texel 0.
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+const BC7_ANCHORS_3_SUBSET_1: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+
+const BC7_ANCHORS_3_SUBSET_2: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs the fields of a BC7 block, from the least significant bit.
+    #[derive(Default)]
+    struct BitWriter {
+        bits: u128,
+        len: u32,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, bits: u32) -> &mut Self {
+            assert_eq!(value as u128 >> bits, 0);
+            self.bits |= (value as u128) << self.len;
+            self.len += bits;
+            self
+        }
+
+        fn block(&self) -> [u8; 16] {
+            assert_eq!(self.len, 128);
+            self.bits.to_le_bytes()
+        }
+    }
+
+    fn decode_bc7(block: [u8; 16]) -> [[u8; 4]; BLOCK_TEXELS] {
+        let mut out = [0; BLOCK_TEXELS * 4];
+        bc7(&block, &mut out);
+        std::array::from_fn(|i| out[4 * i..4 * i + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn bc7_mode_0_three_subsets() {
+        let mut bits = BitWriter::default();
+        bits.write(1, 1).write(0, 4);
+        // Red, green and blue subsets, both endpoints alike.
+        let channels = [
+            [15, 15, 0, 0, 0, 0],
+            [0, 0, 15, 15, 0, 0],
+            [0, 0, 0, 0, 15, 15],
+        ];
+        channels.iter().flatten().for_each(|v| {
+            bits.write(*v, 4);
+        });
+        bits.write(0, 6).write(0, 45);
+
+        let [r, g, b] = [[247, 0, 0, 255], [0, 247, 0, 255], [0, 0, 247, 255]];
+        assert_eq!(
+            decode_bc7(bits.block()),
+            [r, r, g, g, r, r, g, g, r, b, b, g, b, b, b, b]
+        );
+    }
+
+    #[test]
+    fn bc7_mode_1_interpolates_with_anchors() {
+        let mut bits = BitWriter::default();
+        bits.write(0b10, 2).write(0, 6);
+        // Subset 0 from black to white, subset 1 the other way.
+        (0..3).for_each(|_| {
+            [0, 63, 63, 0].iter().for_each(|v| {
+                bits.write(*v, 6);
+            })
+        });
+        bits.write(0, 2);
+        // Texel `i` has the index `i % 8`, the anchors 0 and 15 are one bit shorter.
+        (0..BLOCK_TEXELS as u32).for_each(|i| match i {
+            0 => {
+                bits.write(0, 2);
+            }
+            15 => {
+                bits.write(3, 2);
+            }
+            i => {
+                bits.write(i % 8, 3);
+            }
+        });
+
+        let greys = [
+            0, 36, 182, 146, 146, 182, 36, 0, 0, 36, 182, 146, 146, 182, 36, 146,
+        ];
+        let expected: [[u8; 4]; BLOCK_TEXELS] = greys.map(|v| [v, v, v, 255]);
+        assert_eq!(decode_bc7(bits.block()), expected);
+    }
+
+    #[test]
+    fn bc7_mode_2_partition_1() {
+        let mut bits = BitWriter::default();
+        bits.write(0b100, 3).write(1, 6);
+        (0..3).for_each(|_| {
+            [31, 0, 0, 31, 16, 16].iter().for_each(|v| {
+                bits.write(*v, 5);
+            })
+        });
+        // Every index is 1, the anchors 0, 3 and 8 included.
+        (0..BLOCK_TEXELS).for_each(|i| {
+            bits.write(1, if [0, 3, 8].contains(&i) { 1 } else { 2 });
+        });
+
+        let [a, b, c] = [171, 84, 132].map(|v| [v, v, v, 255]);
+        assert_eq!(
+            decode_bc7(bits.block()),
+            [a, a, a, b, a, a, b, b, c, c, b, b, c, c, c, b]
+        );
+    }
+
+    #[test]
+    fn bc7_mode_3_endpoint_pbits() {
+        let mut bits = BitWriter::default();
+        bits.write(0b1000, 4).write(13, 6);
+        let channels = [[127, 0, 0, 0], [0, 0, 127, 0], [64, 0, 32, 0]];
+        channels.iter().flatten().for_each(|v| {
+            bits.write(*v, 7);
+        });
+        bits.write(0b0001, 4);
+        // Texel 1 takes the second endpoint, the anchors are 0 and 15.
+        (0..BLOCK_TEXELS).for_each(|i| {
+            bits.write(
+                (i == 1) as u32 * 3,
+                if [0, 15].contains(&i) { 1 } else { 2 },
+            );
+        });
+
+        let (s0, e1, s1) = ([255, 1, 129, 255], [0, 0, 0, 255], [0, 254, 64, 255]);
+        let mut expected = [s0; BLOCK_TEXELS];
+        expected[1] = e1;
+        expected[8..].fill(s1);
+        assert_eq!(decode_bc7(bits.block()), expected);
+    }
+
+    #[test]
+    fn bc7_mode_4_rotation_and_index_selection() {
+        let mut bits = BitWriter::default();
+        bits.write(0b10000, 5).write(1, 2).write(1, 1);
+        [[16, 0], [0, 31], [0, 0]].iter().flatten().for_each(|v| {
+            bits.write(*v, 5);
+        });
+        bits.write(0, 6).write(63, 6);
+        // The 2 bits indices are for the alpha, the 3 bits ones for the color.
+        bits.write(1, 1);
+        (1..BLOCK_TEXELS).for_each(|_| {
+            bits.write(3, 2);
+        });
+        bits.write(0, 47);
+
+        // The alpha is swapped with the red.
+        let mut expected = [[255, 0, 0, 132]; BLOCK_TEXELS];
+        expected[0] = [84, 0, 0, 132];
+        assert_eq!(decode_bc7(bits.block()), expected);
+    }
+
+    #[test]
+    fn bc7_mode_5_separate_alpha() {
+        let mut bits = BitWriter::default();
+        bits.write(0b100000, 6).write(0, 2);
+        (0..3).for_each(|_| {
+            bits.write(127, 7).write(0, 7);
+        });
+        bits.write(0, 8).write(255, 8);
+        bits.write(0, 31);
+        bits.write(0, 1);
+        (1..BLOCK_TEXELS).for_each(|_| {
+            bits.write(2, 2);
+        });
+
+        let mut expected = [[255, 255, 255, 171]; BLOCK_TEXELS];
+        expected[0] = [255, 255, 255, 0];
+        assert_eq!(decode_bc7(bits.block()), expected);
+    }
+
+    #[test]
+    fn bc7_mode_6_four_bits_indices() {
+        let mut bits = BitWriter::default();
+        bits.write(0b1000000, 7);
+        (0..4).for_each(|_| {
+            bits.write(0, 7).write(127, 7);
+        });
+        bits.write(0, 1).write(1, 1);
+        bits.write(0, 3);
+        (1..BLOCK_TEXELS as u32).for_each(|i| {
+            bits.write(i, 4);
+        });
+
+        let greys = [
+            0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255,
+        ];
+        assert_eq!(decode_bc7(bits.block()), greys.map(|v| [v; 4]));
+    }
+
+    #[test]
+    fn bc7_mode_7_color_and_alpha_pbits() {
+        let mut bits = BitWriter::default();
+        bits.write(0b10000000, 8).write(0, 6);
+        let channels = [[31, 0, 0, 0], [0, 0, 0, 0], [0, 0, 31, 0], [31, 0, 0, 0]];
+        channels.iter().flatten().for_each(|v| {
+            bits.write(*v, 5);
+        });
+        bits.write(0b0001, 4).write(0, 30);
+
+        let (s0, s1) = ([255, 4, 4, 255], [0, 0, 251, 0]);
+        assert_eq!(
+            decode_bc7(bits.block()),
+            [
+                s0, s0, s1, s1, s0, s0, s1, s1, s0, s0, s1, s1, s0, s0, s1, s1
+            ]
+        );
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_transparent_black() {
+        assert_eq!(decode_bc7([0; 16]), [[0; 4]; BLOCK_TEXELS]);
+    }
+
+    /// Endpoints `c0` and `c1` in RGB565, then the 2 bits index of each texel.
+    fn bc1_block(c0: u16, c1: u16, indices: u32) -> Vec<u8> {
+        [c0.to_le_bytes(), c1.to_le_bytes()]
+            .concat()
+            .into_iter()
+            .chain(indices.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn bc1_three_colors_with_transparent_black() {
+        // Blue is under red, so the block has 3 colors and the transparent black.
+        let block = bc1_block(0x001f, 0xf800, 0b11_10_01_00);
+        let (format, rgba) = decode(vk::Format::BC1_RGBA_UNORM_BLOCK, 4, 1, &block).unwrap();
+        assert_eq!(format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(
+            rgba,
+            [0, 0, 255, 255, 255, 0, 0, 255, 128, 0, 128, 255, 0, 0, 0, 0]
+        );
+
+        // Opaque without alpha.
+        let (format, rgb) = decode(vk::Format::BC1_RGB_SRGB_BLOCK, 4, 1, &block).unwrap();
+        assert_eq!(format, vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(rgb[12..], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc1_four_colors() {
+        let block = bc1_block(0xf800, 0x001f, 0b11_10_01_00);
+        let (_, rgba) = decode(vk::Format::BC1_RGBA_UNORM_BLOCK, 4, 1, &block).unwrap();
+        assert_eq!(
+            rgba,
+            [
+                255, 0, 0, 255, 0, 0, 255, 255, 170, 0, 85, 255, 85, 0, 170, 255
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_crops_the_edge_blocks() {
+        // 5×5 texels take 2×2 blocks, each of a single color.
+        let blocks: Vec<u8> = [0xf800, 0x07e0, 0x001f, 0xffff]
+            .into_iter()
+            .flat_map(|c| bc1_block(c, 0, 0))
+            .collect();
+        let (_, rgba) = decode(vk::Format::BC1_RGB_UNORM_BLOCK, 5, 5, &blocks).unwrap();
+        assert_eq!(rgba.len(), 5 * 5 * 4);
+        let texel = |x: usize, y: usize| &rgba[(y * 5 + x) * 4..(y * 5 + x) * 4 + 4];
+        assert_eq!(texel(3, 3), [255, 0, 0, 255]);
+        assert_eq!(texel(4, 0), [0, 255, 0, 255]);
+        assert_eq!(texel(0, 4), [0, 0, 255, 255]);
+        assert_eq!(texel(4, 4), [255, 255, 255, 255]);
+
+        assert!(decode(vk::Format::BC1_RGB_UNORM_BLOCK, 5, 5, &blocks[..24]).is_err());
+        assert!(decode(vk::Format::BC6H_UFLOAT_BLOCK, 4, 4, &[0; 16]).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Global table of every texture and material, bound once per pipeline.
+//!
+//! Materials are indices in a storage buffer of [`MaterialConstants`], themselves pointing to
+//! textures in a partially bound `sampler2D[]`. See `resources/input_structures.glsl`.
+
+use std::{
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+
+use super::{
+    allocated::{AllocatedBuffer, AllocatedImage, MyMemoryUsage},
+    descriptors::{DescriptorLayoutBuilder, DescriptorWriter},
+    textures::MaterialConstants,
+};
+
+/// Length of the `textures` array.
+const MAX_TEXTURES: u32 = 4096;
+/// Length of the `materials` buffer.
+const MAX_MATERIALS: u32 = 4096;
+
+/// Of the first texture and material added, the white texture and the default material, used
+/// when the table is full. They are never freed.
+const DEFAULT_SLOT: u32 = 0;
+
+const MATERIALS_BINDING: u32 = 0;
+const TEXTURES_BINDING: u32 = 1;
+
+/// Slots freed with an unloaded scene are reused, see [`Self::free`].
+pub struct BindlessTable<'a> {
+    device_copy: Rc<Device>,
+
+    pool: vk::DescriptorPool,
+    pub layout: vk::DescriptorSetLayout,
+    pub set: vk::DescriptorSet,
+
+    materials: AllocatedBuffer,
+    material_count: u32,
+    texture_count: u32,
+    free_materials: Vec<u32>,
+    free_textures: Vec<u32>,
+
+    writer: DescriptorWriter<'a>,
+}
+
+impl BindlessTable<'_> {
+    pub fn new(device: Rc<Device>, allocator: Arc<Mutex<vk_mem::Allocator>>) -> Self {
+        // Textures can be added while a frame using the set is in flight.
+        let flags = [
+            vk::DescriptorBindingFlags::empty(),
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND,
+        ];
+        let mut binding_flags =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&flags[..]);
+        let layout = DescriptorLayoutBuilder::default()
+            .add_binding(MATERIALS_BINDING, vk::DescriptorType::STORAGE_BUFFER)
+            .add_array_binding(
+                TEXTURES_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                MAX_TEXTURES,
+            )
+            .build_p_next(
+                &device,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                &mut binding_flags,
+                vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+            );
+
+        let pool = {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: MAX_TEXTURES,
+                },
+            ];
+            let pool_info = vk::DescriptorPoolCreateInfo::default()
+                .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+                .max_sets(1)
+                .pool_sizes(&pool_sizes[..]);
+            unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() }
+        };
+
+        let set = {
+            let layouts = [layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts[..]);
+            unsafe { device.allocate_descriptor_sets(&alloc_info).unwrap()[0] }
+        };
+
+        let materials_size = size_of::<MaterialConstants>() as u64 * MAX_MATERIALS as u64;
+        let materials = AllocatedBuffer::new(
+            allocator,
+            materials_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MyMemoryUsage::CpuToGpu,
+        );
+
+        let mut writer = DescriptorWriter::default();
+        writer.write_buffer(
+            MATERIALS_BINDING,
+            materials.buffer,
+            materials_size,
+            0,
+            vk::DescriptorType::STORAGE_BUFFER,
+        );
+        writer.update_set(&device, set);
+
+        Self {
+            device_copy: device,
+            pool,
+            layout,
+            set,
+            materials,
+            material_count: 0,
+            texture_count: 0,
+            free_materials: Default::default(),
+            free_textures: Default::default(),
+            writer: Default::default(),
+        }
+    }
+
+    /// Returns the index of the texture in `textures`, the default one if the table is full.
+    pub fn add_texture(&mut self, image: &AllocatedImage, sampler: vk::Sampler) -> u32 {
+        let index = match self.free_textures.pop() {
+            Some(index) => index,
+            None if self.texture_count < MAX_TEXTURES => {
+                self.texture_count += 1;
+                self.texture_count - 1
+            }
+            None => {
+                eprintln!("Bindless table full : {MAX_TEXTURES} textures, using the default one");
+                return DEFAULT_SLOT;
+            }
+        };
+
+        self.writer.clear();
+        self.writer.write_image_at(
+            TEXTURES_BINDING,
+            index,
+            image.img_view,
+            sampler,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        );
+        self.writer.update_set(&self.device_copy, self.set);
+
+        index
+    }
+
+    /// Returns the index of the material in `materials`, the material id. The default one if
+    /// the table is full.
+    pub fn add_material(&mut self, constants: MaterialConstants) -> u32 {
+        let index = match self.free_materials.pop() {
+            Some(index) => index,
+            None if self.material_count < MAX_MATERIALS => {
+                self.material_count += 1;
+                self.material_count - 1
+            }
+            None => {
+                eprintln!("Bindless table full : {MAX_MATERIALS} materials, using the default one");
+                return DEFAULT_SLOT;
+            }
+        };
+
+        let materials: &mut [MaterialConstants] = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.materials.mapped_data() as *mut MaterialConstants,
+                MAX_MATERIALS as usize,
+            )
+        };
+        materials[index as usize] = constants;
+
+        index
+    }
+
+    /// Once no frame in flight uses them. The descriptors are left as is, they're partially bound.
+    pub fn free(&mut self, textures: &[u32], materials: &[u32]) {
+        let not_default = |i: &&u32| **i != DEFAULT_SLOT;
+        self.free_textures
+            .extend(textures.iter().filter(not_default));
+        self.free_materials
+            .extend(materials.iter().filter(not_default));
+    }
+}
+
+impl Drop for BindlessTable<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop BindlessTable");
+        unsafe {
+            self.device_copy.destroy_descriptor_pool(self.pool, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
@@ -1,7 +1,6 @@
 use std::{
     cell::{RefCell, RefMut},
     cmp::Ordering,
-    ops::Deref,
     ptr,
     rc::Rc,
     sync::{Arc, Mutex},
@@ -14,23 +13,82 @@ use ash::{Device, vk};
 use glam::Mat4;
 
 #[cfg(feature = "vulkan_stats")]
-use super::{VulkanStats, VulkanStatsCounts};
+use super::{
+    VulkanStats, VulkanStatsCounts,
+    gpu_timings::{GpuFrameTimings, GpuPass, GpuTimings},
+};
 
 use super::{
-    MeshSorting, VulkanSettings,
-    allocated::AllocatedBuffer,
+    FrustumCulling, MeshSorting, VulkanSettings,
+    ambient_occlusion::{AO_GROUP_SIZE, AmbientOcclusion, AoPushConstants, AoTargets},
     base::VulkanBase,
     compute_shaders::ComputePushConstants,
+    deferred::{DeferredLighting, GBuffer, LIGHTING_TILE_SIZE, LightingPushConstants},
     descriptors::DescriptorAllocatorGrowable,
+    frame_ring::RingSegment,
     gfx_pipeline::GpuDrawPushConstants,
+    gpu_culling::{
+        CULL_GROUP_SIZE, IndirectBuffers, IndirectDraws, REDUCE_GROUP_SIZE, ReducePushConstants,
+    },
     gui::{GeneratedUi, VulkanGui},
+    ibl::{Ibl, SkyboxPushConstants},
+    instancing::{MeshDraw, instanced_draws},
+    msaa::MsaaTargets,
+    post_process::{BloomPushConstants, POST_GROUP_SIZE, PostSettings},
+    render_graph::{RenderGraph, TransientImages},
     scene::{DrawContext, RenderObject},
     swapchain::VulkanSwapchain,
-    textures::{MaterialInstance, MaterialPipeline},
+    textures::{GltfMetallicRoughness, MaterialPass, MaterialPipeline},
 };
+use crate::rasterizer::settings::{ViewMode, Wireframe};
 
 pub const FRAME_OVERLAP: usize = 2;
 
+/// What [`FrameData::draw_geometries`] draws, and in which attachments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeometryPass {
+    /// Every surface in `draw_img`, or in the multisampled attachments.
+    Forward,
+    /// The opaque surfaces in the `GBuffer`.
+    GBuffer,
+    /// The transparent surfaces and the wireframe overlay, over the deferred lighting and
+    /// keeping the depth of the G-buffer pass.
+    Transparent,
+}
+
+/// What every [`GeometryPass`] of a frame draws with.
+pub struct GeometryInputs<'a> {
+    pub settings: &'a VulkanSettings,
+    pub view_mode: ViewMode,
+    pub wireframe: Wireframe,
+    pub materials: &'a GltfMetallicRoughness<'a>,
+    pub swapchain: &'a VulkanSwapchain,
+    pub view_proj: &'a Mat4,
+    pub draw_ctx: &'a DrawContext,
+    /// `None` with the CPU draw lists.
+    pub indirect_draws: Option<&'a IndirectDraws>,
+    pub global_desc: vk::DescriptorSet,
+    pub ibl_desc: vk::DescriptorSet,
+}
+
+/// Drawn by one pipeline of a [`GeometryPass`], by their [`MaterialPass`].
+#[derive(Debug, Clone, Copy)]
+enum Surfaces {
+    All,
+    Opaque,
+    Transparent,
+}
+
+impl Surfaces {
+    fn contains(self, pass: &MaterialPass) -> bool {
+        match self {
+            Surfaces::All => true,
+            Surfaces::Opaque => !matches!(pass, MaterialPass::Transparent),
+            Surfaces::Transparent => matches!(pass, MaterialPass::Transparent),
+        }
+    }
+}
+
 pub struct FrameData {
     device_copy: Rc<Device>,
 
@@ -42,8 +100,14 @@ pub struct FrameData {
 
     descriptors: RefCell<DescriptorAllocatorGrowable>,
 
-    /// Buffers used in current rendering which need to be released before beginning next one.
-    buffers_in_use: Vec<AllocatedBuffer>,
+    transients: RefCell<TransientImages>,
+    indirect_buffers: RefCell<IndirectBuffers>,
+    /// Data uploaded for this frame only.
+    ring: RingSegment,
+
+    /// Reset with the command buffer, read when the frame is reused.
+    #[cfg(feature = "vulkan_stats")]
+    timings: GpuTimings,
 }
 
 impl Drop for FrameData {
@@ -61,9 +125,12 @@ impl Drop for FrameData {
 impl FrameData {
     pub fn new(
         device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
         pool_create_info: &vk::CommandPoolCreateInfo,
         fence_create_info: &vk::FenceCreateInfo,
         sem_create_info: &vk::SemaphoreCreateInfo,
+        ring: RingSegment,
+        #[cfg(feature = "vulkan_stats")] timings: GpuTimings,
     ) -> Self {
         let cmd_pool = unsafe { device.create_command_pool(pool_create_info, None).unwrap() };
         let cmd_buf = cmd_buffer(&device, cmd_pool);
@@ -84,6 +151,9 @@ impl FrameData {
             sizes,
         ));
 
+        let transients = RefCell::new(TransientImages::new(device.clone(), allocator.clone()));
+        let indirect_buffers = RefCell::new(IndirectBuffers::new(allocator));
+
         Self {
             device_copy: device,
             cmd_pool,
@@ -91,35 +161,36 @@ impl FrameData {
             fence_render,
             sem_swapchain,
             descriptors,
-            buffers_in_use: Default::default(),
+            transients,
+            indirect_buffers,
+            ring,
+            #[cfg(feature = "vulkan_stats")]
+            timings,
         }
     }
 
-    pub fn transition_image(
-        &self,
-        image: vk::Image,
-        current_layout: vk::ImageLayout,
-        new_layout: vk::ImageLayout,
-    ) {
-        transition_image(
-            &self.device_copy,
-            self.cmd_buf,
-            image,
-            current_layout,
-            new_layout,
-        );
+    /// For the passes of this frame, after [`Self::begin_cmd_buf`].
+    pub fn render_graph(&self) -> RenderGraph<'_> {
+        RenderGraph::new(&self.device_copy, self.cmd_buf, &self.transients)
     }
 
     pub fn clear_descriptors(&mut self) {
         self.descriptors.borrow_mut().clear_pools();
     }
 
+    /// Frees the allocations of the [`Self::ring`].
     pub fn clear_buffers_in_use(&mut self) {
-        self.buffers_in_use.clear();
+        self.ring.reset();
     }
 
-    pub fn push_buffer_in_use(&mut self, buffer_in_use: AllocatedBuffer) {
-        self.buffers_in_use.push(buffer_in_use);
+    /// Kept from the previous renders of this frame.
+    pub fn indirect_buffers(&self) -> RefMut<'_, IndirectBuffers> {
+        self.indirect_buffers.borrow_mut()
+    }
+
+    /// Reset by [`Self::clear_buffers_in_use`].
+    pub fn ring(&self) -> &RingSegment {
+        &self.ring
     }
 
     pub fn wait_for_fences(&self) {
@@ -138,13 +209,33 @@ impl FrameData {
 
     pub fn begin_cmd_buf(&self) {
         begin_cmd_buf(&self.device_copy, self.cmd_buf);
+        #[cfg(feature = "vulkan_stats")]
+        self.timings.reset(self.cmd_buf);
+    }
+
+    #[cfg(feature = "vulkan_stats")]
+    pub fn begin_pass(&self, pass: GpuPass) {
+        self.timings.begin(self.cmd_buf, pass);
+    }
+
+    #[cfg(feature = "vulkan_stats")]
+    pub fn end_pass(&self, pass: GpuPass) {
+        self.timings.end(self.cmd_buf, pass);
+    }
+
+    /// Of the last submit of this frame, after waiting for its fence.
+    #[cfg(feature = "vulkan_stats")]
+    pub fn read_timings(&self) -> Option<GpuFrameTimings> {
+        self.timings.read()
     }
 
     pub fn end_cmd_buf(&self) {
         end_cmd_buf(&self.device_copy, self.cmd_buf);
     }
 
-    pub fn submit(&self, sem_render: &vk::Semaphore, queue: vk::Queue) {
+    /// Without `sem_render` (headless), nothing was acquired so there is no semaphore at all,
+    /// only the fence.
+    pub fn submit(&self, sem_render: Option<&vk::Semaphore>, queue: vk::Queue) {
         let cmd_buf_submit_info =
             [vk::CommandBufferSubmitInfo::default().command_buffer(self.cmd_buf)];
         let wait_semaphore_info = [vk::SemaphoreSubmitInfo::default()
@@ -152,16 +243,21 @@ impl FrameData {
             .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT_KHR)
             .device_index(0)
             .value(1)];
-        let signal_semaphore_info = [vk::SemaphoreSubmitInfo::default()
-            .semaphore(*sem_render)
-            .stage_mask(vk::PipelineStageFlags2::ALL_GRAPHICS)
-            .device_index(0)
-            .value(1)];
-
-        let submit_info = vk::SubmitInfo2::default()
-            .wait_semaphore_infos(&wait_semaphore_info)
-            .signal_semaphore_infos(&signal_semaphore_info)
-            .command_buffer_infos(&cmd_buf_submit_info);
+        let signal_semaphore_info = sem_render.map(|sem_render| {
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(*sem_render)
+                .stage_mask(vk::PipelineStageFlags2::ALL_GRAPHICS)
+                .device_index(0)
+                .value(1)
+        });
+
+        let submit_info = match &signal_semaphore_info {
+            Some(signal_semaphore_info) => vk::SubmitInfo2::default()
+                .wait_semaphore_infos(&wait_semaphore_info)
+                .signal_semaphore_infos(std::slice::from_ref(signal_semaphore_info)),
+            None => vk::SubmitInfo2::default(),
+        }
+        .command_buffer_infos(&cmd_buf_submit_info);
 
         unsafe {
             self.device_copy
@@ -209,6 +305,32 @@ impl FrameData {
         unsafe { self.device_copy.cmd_blit_image2(self.cmd_buf, &blit_info) };
     }
 
+    /// `src` in [`vk::ImageLayout::TRANSFER_SRC_OPTIMAL`], tightly packed in `dst`.
+    pub fn copy_img_to_buffer(&self, src: vk::Image, dst: vk::Buffer, size: vk::Extent2D) {
+        let region = vk::BufferImageCopy2::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1),
+            )
+            .image_extent(vk::Extent3D {
+                width: size.width,
+                height: size.height,
+                depth: 1,
+            });
+        let regions = [region];
+        let copy_info = vk::CopyImageToBufferInfo2::default()
+            .src_image(src)
+            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .dst_buffer(dst)
+            .regions(&regions);
+
+        unsafe {
+            self.device_copy
+                .cmd_copy_image_to_buffer2(self.cmd_buf, &copy_info);
+        }
+    }
+
     pub fn draw_background(
         &self,
         swapchain: &VulkanSwapchain,
@@ -248,6 +370,508 @@ impl FrameData {
         }
     }
 
+    /// Replaces [`Self::draw_background`].
+    pub fn draw_skybox(
+        &self,
+        swapchain: &VulkanSwapchain,
+        ibl: &Ibl,
+        push_constants: &SkyboxPushConstants,
+    ) {
+        let groups = |size: u32| size.div_ceil(16);
+        unsafe {
+            self.device_copy.cmd_bind_pipeline(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                ibl.skybox_pipeline,
+            );
+            // The layout of `draw_img_descs` is identical to the one of the skybox.
+            self.device_copy.cmd_bind_descriptor_sets(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                ibl.skybox_layout,
+                0,
+                &[swapchain.effects.draw_img_descs, ibl.set()],
+                &[],
+            );
+            self.device_copy.cmd_push_constants(
+                self.cmd_buf,
+                ibl.skybox_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                as_u8_slice(push_constants),
+            );
+            self.device_copy.cmd_dispatch(
+                self.cmd_buf,
+                groups(push_constants.size[0]),
+                groups(push_constants.size[1]),
+                1,
+            );
+        }
+    }
+
+    /// Fills the indirect commands of `draws`, to be done before [`Self::draw_geometries`].
+    ///
+    /// The barriers on the depth pyramid and before the indirect draws are up to the render graph.
+    pub fn cull_objects(&self, swapchain: &VulkanSwapchain, draws: &IndirectDraws) {
+        let gpu_culling = &swapchain.gpu_culling;
+
+        unsafe {
+            self.device_copy
+                .cmd_fill_buffer(self.cmd_buf, draws.counts, 0, vk::WHOLE_SIZE, 0);
+        }
+        // Counts cleared.
+        self.memory_barrier(
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        );
+
+        unsafe {
+            self.device_copy.cmd_bind_pipeline(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                gpu_culling.cull_pipeline,
+            );
+            self.device_copy.cmd_bind_descriptor_sets(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                gpu_culling.cull_layout,
+                0,
+                &[gpu_culling.cull_desc],
+                &[],
+            );
+            self.device_copy.cmd_push_constants(
+                self.cmd_buf,
+                gpu_culling.cull_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                as_u8_slice(&draws.push_constants),
+            );
+            self.device_copy.cmd_dispatch(
+                self.cmd_buf,
+                draws.object_count.div_ceil(CULL_GROUP_SIZE),
+                1,
+                1,
+            );
+        }
+    }
+
+    /// Reduces the depth image into the depth pyramid read by the next [`Self::cull_objects`].
+    ///
+    /// The depth image must be in [`vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL`].
+    pub fn build_depth_pyramid(&self, swapchain: &VulkanSwapchain) {
+        let gpu_culling = &swapchain.gpu_culling;
+
+        unsafe {
+            self.device_copy.cmd_bind_pipeline(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                gpu_culling.reduce_pipeline,
+            );
+        }
+
+        let draw_extent = swapchain.draw_extent();
+        gpu_culling
+            .reduce_descs
+            .iter()
+            .enumerate()
+            .for_each(|(level, desc)| {
+                let src = if level == 0 {
+                    draw_extent
+                } else {
+                    gpu_culling.mip_extent(level - 1)
+                };
+                let dst = gpu_culling.mip_extent(level);
+                let push_constants = ReducePushConstants {
+                    src_size: [src.width, src.height],
+                    dst_size: [dst.width, dst.height],
+                };
+
+                unsafe {
+                    self.device_copy.cmd_bind_descriptor_sets(
+                        self.cmd_buf,
+                        vk::PipelineBindPoint::COMPUTE,
+                        gpu_culling.reduce_layout,
+                        0,
+                        &[*desc],
+                        &[],
+                    );
+                    self.device_copy.cmd_push_constants(
+                        self.cmd_buf,
+                        gpu_culling.reduce_layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        as_u8_slice(&push_constants),
+                    );
+                    self.device_copy.cmd_dispatch(
+                        self.cmd_buf,
+                        dst.width.div_ceil(REDUCE_GROUP_SIZE),
+                        dst.height.div_ceil(REDUCE_GROUP_SIZE),
+                        1,
+                    );
+                }
+
+                // The next level reads this one.
+                self.memory_barrier(
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_SAMPLED_READ,
+                );
+            });
+
+        gpu_culling.set_pyramid_built(true);
+    }
+
+    /// Auto-exposure, bloom and tone mapping of `draw_img`, which must be in
+    /// [`vk::ImageLayout::GENERAL`] like the bloom image.
+    ///
+    /// `delta_secs` since the previous frame, for the exposure adaptation.
+    pub fn post_process(
+        &self,
+        swapchain: &VulkanSwapchain,
+        settings: &PostSettings,
+        delta_secs: f32,
+    ) {
+        let post = &swapchain.post_process;
+        let draw_extent = swapchain.draw_extent();
+        let dispatch = |layout: vk::PipelineLayout,
+                        desc: Option<vk::DescriptorSet>,
+                        push_constants: &[u8],
+                        groups: vk::Extent2D| unsafe {
+            if let Some(desc) = desc {
+                self.device_copy.cmd_bind_descriptor_sets(
+                    self.cmd_buf,
+                    vk::PipelineBindPoint::COMPUTE,
+                    layout,
+                    0,
+                    &[desc],
+                    &[],
+                );
+            }
+            self.device_copy.cmd_push_constants(
+                self.cmd_buf,
+                layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                push_constants,
+            );
+            self.device_copy
+                .cmd_dispatch(self.cmd_buf, groups.width, groups.height, 1);
+        };
+        let bind = |pipeline: vk::Pipeline| unsafe {
+            self.device_copy.cmd_bind_pipeline(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline,
+            );
+        };
+        let groups = |extent: vk::Extent2D| vk::Extent2D {
+            width: extent.width.div_ceil(POST_GROUP_SIZE),
+            height: extent.height.div_ceil(POST_GROUP_SIZE),
+        };
+        // Each pass reads the writes of the previous one.
+        let compute_barrier = || {
+            self.memory_barrier(
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ
+                    | vk::AccessFlags2::SHADER_STORAGE_WRITE
+                    | vk::AccessFlags2::SHADER_SAMPLED_READ,
+            )
+        };
+
+        if settings.auto_exposure {
+            unsafe {
+                self.device_copy.cmd_fill_buffer(
+                    self.cmd_buf,
+                    post.histogram.buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                    0,
+                );
+            }
+            self.memory_barrier(
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            );
+            bind(post.histogram_pipeline);
+            dispatch(
+                post.histogram_layout,
+                Some(post.histogram_desc),
+                as_u8_slice(&post.histogram_push_constants(draw_extent)),
+                groups(draw_extent),
+            );
+            compute_barrier();
+        }
+        // Also writes the manual exposure.
+        bind(post.average_pipeline);
+        dispatch(
+            post.average_layout,
+            None,
+            as_u8_slice(&post.average_push_constants(settings, draw_extent, delta_secs)),
+            vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+        );
+        compute_barrier();
+
+        if settings.bloom {
+            bind(post.downsample_pipeline);
+            post.downsample_descs
+                .iter()
+                .enumerate()
+                .for_each(|(level, desc)| {
+                    let src = if level == 0 {
+                        draw_extent
+                    } else {
+                        post.bloom_mip_extent(draw_extent, level - 1)
+                    };
+                    let dst = post.bloom_mip_extent(draw_extent, level);
+                    let push_constants = BloomPushConstants {
+                        src_size: [src.width, src.height],
+                        dst_size: [dst.width, dst.height],
+                        threshold: settings.bloom_threshold,
+                        radius: settings.bloom_radius,
+                        first_pass: (level == 0) as u32,
+                    };
+                    dispatch(
+                        post.downsample_layout,
+                        Some(*desc),
+                        as_u8_slice(&push_constants),
+                        groups(dst),
+                    );
+                    compute_barrier();
+                });
+
+            // From the smallest mip.
+            bind(post.upsample_pipeline);
+            post.upsample_descs
+                .iter()
+                .enumerate()
+                .rev()
+                .for_each(|(level, desc)| {
+                    let src = post.bloom_mip_extent(draw_extent, level + 1);
+                    let dst = post.bloom_mip_extent(draw_extent, level);
+                    let push_constants = BloomPushConstants {
+                        src_size: [src.width, src.height],
+                        dst_size: [dst.width, dst.height],
+                        threshold: settings.bloom_threshold,
+                        radius: settings.bloom_radius,
+                        first_pass: 0,
+                    };
+                    dispatch(
+                        post.upsample_layout,
+                        Some(*desc),
+                        as_u8_slice(&push_constants),
+                        groups(dst),
+                    );
+                    compute_barrier();
+                });
+        }
+
+        bind(post.tone_map_pipeline);
+        dispatch(
+            post.tone_map_layout,
+            Some(post.tone_map_desc),
+            as_u8_slice(&post.tone_map_push_constants(
+                settings,
+                draw_extent,
+                swapchain.output_transfer(),
+                swapchain.paper_white(),
+            )),
+            groups(draw_extent),
+        );
+    }
+
+    fn memory_barrier(
+        &self,
+        src_stage_mask: vk::PipelineStageFlags2,
+        src_access_mask: vk::AccessFlags2,
+        dst_stage_mask: vk::PipelineStageFlags2,
+        dst_access_mask: vk::AccessFlags2,
+    ) {
+        let barriers = [vk::MemoryBarrier2::default()
+            .src_stage_mask(src_stage_mask)
+            .src_access_mask(src_access_mask)
+            .dst_stage_mask(dst_stage_mask)
+            .dst_access_mask(dst_access_mask)];
+        let dep_info = vk::DependencyInfo::default().memory_barriers(&barriers);
+
+        unsafe {
+            self.device_copy
+                .cmd_pipeline_barrier2(self.cmd_buf, &dep_info)
+        };
+    }
+
+    /// Computes the occlusion from the G-buffer, then blurs it along x and y.
+    pub fn ambient_occlusion(
+        &self,
+        ao: &AmbientOcclusion,
+        targets: &AoTargets,
+        global_desc: vk::DescriptorSet,
+        push_constants: &AoPushConstants,
+    ) {
+        let groups = |size: u32| size.div_ceil(AO_GROUP_SIZE);
+        let dispatch = |pipeline: vk::Pipeline,
+                        layout: vk::PipelineLayout,
+                        desc: vk::DescriptorSet,
+                        push_constants: &AoPushConstants| unsafe {
+            self.device_copy.cmd_bind_pipeline(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline,
+            );
+            self.device_copy.cmd_bind_descriptor_sets(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                layout,
+                0,
+                &[global_desc, desc],
+                &[],
+            );
+            self.device_copy.cmd_push_constants(
+                self.cmd_buf,
+                layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                as_u8_slice(push_constants),
+            );
+            self.device_copy.cmd_dispatch(
+                self.cmd_buf,
+                groups(push_constants.size[0]),
+                groups(push_constants.size[1]),
+                1,
+            );
+        };
+        // Each pass reads the writes of the previous one.
+        let compute_barrier = || {
+            self.memory_barrier(
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_SAMPLED_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            )
+        };
+
+        dispatch(
+            ao.ssao_pipeline,
+            ao.ssao_layout,
+            targets.ssao_desc,
+            push_constants,
+        );
+        [[1, 0], [0, 1]]
+            .into_iter()
+            .zip(targets.blur_descs)
+            .for_each(|(direction, desc)| {
+                compute_barrier();
+                dispatch(
+                    ao.blur_pipeline,
+                    ao.blur_layout,
+                    desc,
+                    &push_constants.with_direction(direction),
+                );
+            });
+    }
+
+    /// Culls the point lights per tile and shades the G-buffer in `draw_img`, after
+    /// [`Self::draw_geometries`] with [`GeometryPass::GBuffer`].
+    pub fn deferred_lighting(
+        &self,
+        lighting: &DeferredLighting,
+        gbuffer: &GBuffer,
+        global_desc: vk::DescriptorSet,
+        ibl_desc: vk::DescriptorSet,
+        push_constants: &LightingPushConstants,
+    ) {
+        let groups = |size: u32| size.div_ceil(LIGHTING_TILE_SIZE);
+        unsafe {
+            self.device_copy.cmd_bind_pipeline(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                lighting.pipeline,
+            );
+            self.device_copy.cmd_bind_descriptor_sets(
+                self.cmd_buf,
+                vk::PipelineBindPoint::COMPUTE,
+                lighting.layout,
+                0,
+                &[global_desc, gbuffer.desc, ibl_desc],
+                &[],
+            );
+            self.device_copy.cmd_push_constants(
+                self.cmd_buf,
+                lighting.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                as_u8_slice(push_constants),
+            );
+            self.device_copy.cmd_dispatch(
+                self.cmd_buf,
+                groups(push_constants.size[0]),
+                groups(push_constants.size[1]),
+                1,
+            );
+        }
+    }
+
+    /// Copies the background of `draw_img` to every sample of the multisampled colour
+    /// attachment, before the geometry is drawn over it.
+    pub fn draw_msaa_background(&self, swapchain: &VulkanSwapchain, msaa: &MsaaTargets) {
+        let color_attachments = [attachment_info(
+            msaa.color.img_view,
+            None,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)];
+        let render_info = rendering_info(swapchain.draw_extent(), &color_attachments[..], None);
+
+        unsafe {
+            self.device_copy
+                .cmd_begin_rendering(self.cmd_buf, &render_info);
+        }
+
+        self.set_viewport(swapchain.draw_extent());
+        unsafe {
+            self.device_copy.cmd_bind_pipeline(
+                self.cmd_buf,
+                vk::PipelineBindPoint::GRAPHICS,
+                msaa.background_pipeline,
+            );
+            self.device_copy.cmd_bind_descriptor_sets(
+                self.cmd_buf,
+                vk::PipelineBindPoint::GRAPHICS,
+                msaa.background_layout,
+                0,
+                &[msaa.background_desc],
+                &[],
+            );
+            self.device_copy.cmd_draw(self.cmd_buf, 3, 1, 0, 0);
+            self.device_copy.cmd_end_rendering(self.cmd_buf);
+        }
+    }
+
+    /// Viewport and scissor of the graphics pipelines, which are dynamic.
+    fn set_viewport(&self, extent: vk::Extent2D) {
+        let viewports = [vk::Viewport::default()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.)
+            .max_depth(1.)];
+        let scissors = [vk::Rect2D::default().extent(extent)];
+        unsafe {
+            self.device_copy
+                .cmd_set_viewport(self.cmd_buf, 0, &viewports[..]);
+            self.device_copy
+                .cmd_set_scissor(self.cmd_buf, 0, &scissors[..]);
+        }
+    }
+
     pub fn draw_gui(
         &self,
         swapchain: &VulkanSwapchain,
@@ -284,22 +908,97 @@ impl FrameData {
 
     pub fn draw_geometries(
         &self,
-        settings: &VulkanSettings,
-        swapchain: &VulkanSwapchain,
-        view_proj: &Mat4,
-        draw_ctx: &DrawContext,
-        global_desc: vk::DescriptorSet,
+        pass: GeometryPass,
+        inputs: &GeometryInputs,
         #[cfg(feature = "vulkan_stats")] stats: &mut VulkanStats,
     ) {
-        let color_attachments = [attachment_info(
-            *swapchain.draw_img_view(),
-            None,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        )];
-        let depth_attachment = depth_attachment_info(
-            *swapchain.depth_img_view(),
-            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
-        );
+        let &GeometryInputs {
+            settings,
+            view_mode,
+            wireframe,
+            materials,
+            swapchain,
+            view_proj,
+            draw_ctx,
+            indirect_draws,
+            global_desc,
+            ibl_desc,
+        } = inputs;
+        // Debug views replace the background.
+        let clear = (view_mode != ViewMode::Shaded || wireframe == Wireframe::Only).then(|| {
+            let mut clear = vk::ClearValue::default();
+            clear.color.float32 = [0., 0., 0., 1.];
+            clear
+        });
+        let (color_attachments, depth_attachment) = match pass {
+            // With MSAA, the multisampled attachments are resolved into `draw_img` and
+            // `depth_img`.
+            GeometryPass::Forward => match swapchain.msaa() {
+                Some(msaa) => (
+                    vec![
+                        attachment_info(
+                            msaa.color.img_view,
+                            clear,
+                            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        )
+                        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                        .resolve_image_view(*swapchain.draw_img_view())
+                        .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                    ],
+                    depth_attachment_info(
+                        msaa.depth.img_view,
+                        vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                    )
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .resolve_mode(msaa.depth_resolve)
+                    .resolve_image_view(*swapchain.depth_img_view())
+                    .resolve_image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL),
+                ),
+                None => (
+                    vec![attachment_info(
+                        *swapchain.draw_img_view(),
+                        clear,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    )],
+                    depth_attachment_info(
+                        *swapchain.depth_img_view(),
+                        vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                    ),
+                ),
+            },
+            GeometryPass::GBuffer => (
+                swapchain
+                    .gbuffer()
+                    .unwrap()
+                    .images()
+                    .iter()
+                    .map(|image| {
+                        attachment_info(
+                            image.img_view,
+                            Some(vk::ClearValue::default()),
+                            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        )
+                    })
+                    .collect(),
+                depth_attachment_info(
+                    *swapchain.depth_img_view(),
+                    vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                ),
+            ),
+            GeometryPass::Transparent => (
+                vec![attachment_info(
+                    *swapchain.draw_img_view(),
+                    None,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )],
+                depth_attachment_info(
+                    *swapchain.depth_img_view(),
+                    vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                )
+                .load_op(vk::AttachmentLoadOp::LOAD),
+            ),
+        };
         let render_info = rendering_info(
             swapchain.swapchain_extent(),
             &color_attachments[..],
@@ -311,35 +1010,68 @@ impl FrameData {
                 .cmd_begin_rendering(self.cmd_buf, &render_info);
         }
 
-        let draw_extent = swapchain.draw_extent();
-        {
-            let viewports = [vk::Viewport::default()
-                .width(draw_extent.width as f32)
-                .height(draw_extent.height as f32)
-                .min_depth(0.)
-                .max_depth(1.)];
-            unsafe {
-                self.device_copy
-                    .cmd_set_viewport(self.cmd_buf, 0, &viewports[..]);
+        self.set_viewport(swapchain.draw_extent());
+
+        let pipeline_override = if wireframe == Wireframe::Only {
+            Some(&materials.pipeline_wireframe)
+        } else if view_mode == ViewMode::Overdraw {
+            Some(&materials.pipeline_overdraw)
+        } else {
+            None
+        };
+        // The last element tells if the draws are counted in the stats.
+        let mut passes = match pass {
+            GeometryPass::Forward => vec![(pipeline_override, Surfaces::All, true)],
+            GeometryPass::GBuffer => {
+                vec![(Some(&materials.pipeline_gbuffer), Surfaces::Opaque, true)]
             }
+            GeometryPass::Transparent => vec![(None, Surfaces::Transparent, true)],
+        };
+        // Draws the same surfaces again, they are only counted once.
+        if wireframe == Wireframe::Overlay && pass != GeometryPass::GBuffer {
+            passes.push((
+                Some(&materials.pipeline_wireframe_overlay),
+                Surfaces::All,
+                false,
+            ));
         }
+        // Every material is in the bindless set, so they are only bound with the pipelines.
+        let descs = [global_desc, materials.bindless.set, ibl_desc];
 
-        {
-            let scissors = [vk::Rect2D::default().extent(draw_extent)];
-            unsafe {
-                self.device_copy
-                    .cmd_set_scissor(self.cmd_buf, 0, &scissors[..]);
+        if let Some(indirect_draws) = indirect_draws {
+            #[cfg(feature = "vulkan_stats")]
+            let t = Instant::now();
+            passes
+                .into_iter()
+                .for_each(|(pipeline, surfaces, _counted)| {
+                    #[cfg(feature = "vulkan_stats")]
+                    let drawcall_count = stats.counts.drawcall_count;
+                    self.draw_indirect(
+                        &descs,
+                        indirect_draws,
+                        pipeline,
+                        surfaces,
+                        #[cfg(feature = "vulkan_stats")]
+                        &mut stats.counts,
+                    );
+                    #[cfg(feature = "vulkan_stats")]
+                    if !_counted {
+                        stats.counts.drawcall_count = drawcall_count;
+                    }
+                });
+            #[cfg(feature = "vulkan_stats")]
+            {
+                stats.mesh_draw_micros = t.elapsed().as_micros();
             }
         }
-
-        // Sorting by material and index_buffer to minimize rebinding
-        // Binding material is longer, so we order by it first.
-        {
+        // Sorting by pipeline and index_buffer to minimize rebinding
+        // Binding pipeline is longer, so we order by it first.
+        else {
             #[cfg(feature = "vulkan_stats")]
             let t = Instant::now();
             let opaque_draws = create_list(
                 settings.opaque_sorting,
-                settings.frustum_culling,
+                settings.frustum_culling == FrustumCulling::Linear,
                 view_proj,
                 &draw_ctx.opaque_surfaces[..],
             );
@@ -351,7 +1083,7 @@ impl FrameData {
             let t = Instant::now();
             let transparent_draws = create_list(
                 settings.transparent_sorting,
-                settings.frustum_culling,
+                settings.frustum_culling == FrustumCulling::Linear,
                 view_proj,
                 &draw_ctx.transparent_surfaces[..],
             );
@@ -363,36 +1095,45 @@ impl FrameData {
             #[cfg(feature = "vulkan_stats")]
             let t = Instant::now();
 
-            let mut last_pip = None;
-            let mut last_mat = None;
-            let mut last_index_buffer = None;
+            let opaque = opaque_draws.iter().map(|i| &draw_ctx.opaque_surfaces[*i]);
+            let transparent = transparent_draws
+                .iter()
+                .map(|i| &draw_ctx.transparent_surfaces[*i]);
             // Transparent objects don't write to depth buffer.
             // To avoid clipping with them, we draw them after.
-            opaque_draws
-                .iter()
-                .map(|i| &draw_ctx.opaque_surfaces[*i])
-                .chain(
-                    transparent_draws
-                        .iter()
-                        .map(|i| &draw_ctx.transparent_surfaces[*i]),
-                )
-                .for_each(|d| {
-                    #[cfg(feature = "vulkan_stats")]
-                    {
-                        stats.counts.drawcall_count += 1;
-                        stats.counts.triangle_count += d.index_count / 3;
-                    }
-                    self.draw_mesh(
-                        settings,
-                        /* draw_extent, */
-                        global_desc,
-                        d,
-                        &mut last_pip,
-                        &mut last_mat,
-                        &mut last_index_buffer,
+            let draws: Vec<MeshDraw> = if settings.instancing {
+                instanced_draws(&self.ring, opaque, transparent)
+            } else {
+                opaque.chain(transparent).map(MeshDraw::from).collect()
+            };
+
+            passes
+                .into_iter()
+                .for_each(|(pipeline, surfaces, _counted)| {
+                    let mut last_pip = None;
+                    let mut last_index_buffer = None;
+                    draws.iter().for_each(|d| {
+                        if !surfaces.contains(d.object.material.pass_type()) {
+                            return;
+                        }
                         #[cfg(feature = "vulkan_stats")]
-                        &mut stats.counts,
-                    )
+                        if _counted {
+                            stats.counts.drawcall_count += 1;
+                            stats.counts.triangle_count +=
+                                d.object.index_count / 3 * d.instance_count;
+                        }
+                        self.draw_mesh(
+                            settings,
+                            /* draw_extent, */
+                            &descs,
+                            d,
+                            pipeline,
+                            &mut last_pip,
+                            &mut last_index_buffer,
+                            #[cfg(feature = "vulkan_stats")]
+                            &mut stats.counts,
+                        )
+                    });
                 });
             #[cfg(feature = "vulkan_stats")]
             {
@@ -409,88 +1150,61 @@ impl FrameData {
         &self,
         settings: &VulkanSettings,
         // draw_extent: vk::Extent2D,
-        global_desc: vk::DescriptorSet,
-        d: &RenderObject,
+        descs: &[vk::DescriptorSet],
+        draw: &MeshDraw,
+        pipeline_override: Option<&MaterialPipeline>,
         last_pip: &mut Option<*const MaterialPipeline>,
-        last_mat: &mut Option<*const MaterialInstance>,
         last_index_buffer: &mut Option<vk::Buffer>,
         #[cfg(feature = "vulkan_stats")] stats: &mut VulkanStatsCounts,
     ) {
-        let mat_pip = d.material.pipeline();
-        if settings.rebinding
-            || last_mat
-                .map(|l| !ptr::eq(l, d.material.deref()))
-                .unwrap_or(true)
-        {
+        let d = draw.object;
+        let material_pip = d.material.pipeline();
+        let mat_pip = pipeline_override.unwrap_or(&material_pip);
+        if settings.rebinding || last_pip.map(|l| !ptr::eq(l, mat_pip)).unwrap_or(true) {
             #[cfg(feature = "vulkan_stats")]
             {
-                stats.bound_mat += 1;
+                stats.bound_mat_pip += 1;
             }
-            *last_mat = Some(Rc::as_ptr(&d.material));
-
-            if settings.rebinding
-                || last_pip
-                    .map(|l| !ptr::eq(l, mat_pip.deref()))
-                    .unwrap_or(true)
-            {
-                #[cfg(feature = "vulkan_stats")]
-                {
-                    stats.bound_mat_pip += 1;
-                }
-                *last_pip = Some(mat_pip.deref());
-
-                unsafe {
-                    self.device_copy.cmd_bind_pipeline(
-                        self.cmd_buf,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        mat_pip.pipeline,
-                    );
+            *last_pip = Some(mat_pip);
 
-                    let descs = [global_desc];
-                    self.device_copy.cmd_bind_descriptor_sets(
-                        self.cmd_buf,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        mat_pip.layout,
-                        0,
-                        &descs[..],
-                        &[],
-                    );
-                }
-
-                /*
-                // Why the need to do it here ?
-                {
-                    let viewports = [vk::Viewport::default()
-                        .width(draw_extent.width as f32)
-                        .height(draw_extent.height as f32)
-                        .min_depth(0.)
-                        .max_depth(1.)];
-                    unsafe {
-                        self.device_copy
-                            .cmd_set_viewport(self.cmd_buf, 0, &viewports[..]);
-                    }
-                }
-                {
-                    let scissors = [vk::Rect2D::default().extent(draw_extent)];
-                    unsafe {
-                        self.device_copy
-                            .cmd_set_scissor(self.cmd_buf, 0, &scissors[..]);
-                    }
-                }
-                */
-            }
-
-            let descs = [d.material.material_set];
             unsafe {
+                self.device_copy.cmd_bind_pipeline(
+                    self.cmd_buf,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    mat_pip.pipeline.get(),
+                );
+
                 self.device_copy.cmd_bind_descriptor_sets(
                     self.cmd_buf,
                     vk::PipelineBindPoint::GRAPHICS,
                     mat_pip.layout,
-                    1,
-                    &descs[..],
+                    0,
+                    descs,
                     &[],
                 );
             }
+
+            /*
+            // Why the need to do it here ?
+            {
+                let viewports = [vk::Viewport::default()
+                    .width(draw_extent.width as f32)
+                    .height(draw_extent.height as f32)
+                    .min_depth(0.)
+                    .max_depth(1.)];
+                unsafe {
+                    self.device_copy
+                        .cmd_set_viewport(self.cmd_buf, 0, &viewports[..]);
+                }
+            }
+            {
+                let scissors = [vk::Rect2D::default().extent(draw_extent)];
+                unsafe {
+                    self.device_copy
+                        .cmd_set_scissor(self.cmd_buf, 0, &scissors[..]);
+                }
+            }
+            */
         }
 
         if settings.rebinding
@@ -520,14 +1234,100 @@ impl FrameData {
                 mat_pip.layout,
                 vk::ShaderStageFlags::VERTEX,
                 0,
-                as_u8_slice(&GpuDrawPushConstants::from(d)),
+                as_u8_slice(&draw.push_constants),
             );
 
-            self.device_copy
-                .cmd_draw_indexed(self.cmd_buf, d.index_count, 1, d.first_index, 0, 0);
+            self.device_copy.cmd_draw_indexed(
+                self.cmd_buf,
+                d.index_count,
+                draw.instance_count,
+                d.first_index,
+                0,
+                draw.first_instance,
+            );
         }
     }
 
+    /// One indirect draw per batch of the `surfaces`, the counts were written by
+    /// [`Self::cull_objects`].
+    fn draw_indirect(
+        &self,
+        descs: &[vk::DescriptorSet],
+        draws: &IndirectDraws,
+        pipeline_override: Option<&MaterialPipeline>,
+        surfaces: Surfaces,
+        #[cfg(feature = "vulkan_stats")] stats: &mut VulkanStatsCounts,
+    ) {
+        let push_constants = GpuDrawPushConstants {
+            objects: draws.objects_address,
+            draw_indirect: 1,
+            ..Default::default()
+        };
+        let mut last_pip: Option<*const MaterialPipeline> = None;
+        // The count of a batch is at its index, even when skipped.
+        draws.batches.iter().enumerate().for_each(|(i, batch)| {
+            if !surfaces.contains(batch.material.pass_type()) {
+                return;
+            }
+            let material_pip = batch.material.pipeline();
+            let mat_pip = pipeline_override.unwrap_or(&material_pip);
+            if last_pip.map(|l| !ptr::eq(l, mat_pip)).unwrap_or(true) {
+                #[cfg(feature = "vulkan_stats")]
+                {
+                    stats.bound_mat_pip += 1;
+                }
+                last_pip = Some(mat_pip);
+
+                unsafe {
+                    self.device_copy.cmd_bind_pipeline(
+                        self.cmd_buf,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        mat_pip.pipeline.get(),
+                    );
+                    self.device_copy.cmd_bind_descriptor_sets(
+                        self.cmd_buf,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        mat_pip.layout,
+                        0,
+                        descs,
+                        &[],
+                    );
+                    self.device_copy.cmd_push_constants(
+                        self.cmd_buf,
+                        mat_pip.layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        as_u8_slice(&push_constants),
+                    );
+                }
+            }
+
+            #[cfg(feature = "vulkan_stats")]
+            {
+                // Triangles are unknown, the culling is done on the GPU.
+                stats.drawcall_count += 1;
+                stats.bound_index_buf += 1;
+            }
+            unsafe {
+                self.device_copy.cmd_bind_index_buffer(
+                    self.cmd_buf,
+                    batch.index_buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                self.device_copy.cmd_draw_indexed_indirect_count(
+                    self.cmd_buf,
+                    draws.commands,
+                    batch.first_command as u64 * IndirectDraws::COMMAND_STRIDE as u64,
+                    draws.counts,
+                    (i * size_of::<u32>()) as u64,
+                    batch.max_count,
+                    IndirectDraws::COMMAND_STRIDE,
+                );
+            }
+        });
+    }
+
     pub fn descriptors_mut<'a>(&'a self) -> RefMut<'a, DescriptorAllocatorGrowable> {
         self.descriptors.borrow_mut()
     }
@@ -559,9 +1359,13 @@ impl VulkanCommands {
             .map(|_| {
                 FrameData::new(
                     base.device.clone(),
+                    allocator.clone(),
                     &pool_create_info,
                     &fence_create_info,
                     &sem_create_info,
+                    RingSegment::new(base, allocator.clone()),
+                    #[cfg(feature = "vulkan_stats")]
+                    GpuTimings::new(base),
                 )
             })
             .collect();
@@ -750,9 +1554,10 @@ fn rendering_info<'a>(
     }
 }
 
-fn as_u8_slice<T>(value: &T) -> &[u8] {
+/// Also for slices.
+pub fn as_u8_slice<T: ?Sized>(value: &T) -> &[u8] {
     let ptr = value as *const T as *const u8;
-    unsafe { std::slice::from_raw_parts(ptr, size_of::<T>()) }
+    unsafe { std::slice::from_raw_parts(ptr, size_of_val(value)) }
 }
 
 pub fn transition_image(
@@ -762,7 +1567,11 @@ pub fn transition_image(
     current_layout: vk::ImageLayout,
     new_layout: vk::ImageLayout,
 ) {
-    let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL {
+    let is_depth = |layout| {
+        layout == vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+            || layout == vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL
+    };
+    let aspect_mask = if is_depth(current_layout) || is_depth(new_layout) {
         vk::ImageAspectFlags::DEPTH
     } else {
         vk::ImageAspectFlags::COLOR
@@ -770,13 +1579,13 @@ pub fn transition_image(
 
     let sub_image = image_subresource_range_default(aspect_mask);
 
-    // TODO: replace ALL_COMMANDS by more accurate masks to not stop whole GPU pipeline
-    // https://github.com/KhronosGroup/Vulkan-Docs/wiki/Synchronization-Examples
+    let (src_stages, _, src_access) = layout_usage(current_layout);
+    let (dst_stages, dst_read, dst_write) = layout_usage(new_layout);
     let image_barrier = vk::ImageMemoryBarrier2::default()
-        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-        .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
-        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-        .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ)
+        .src_stage_mask(src_stages)
+        .src_access_mask(src_access)
+        .dst_stage_mask(dst_stages)
+        .dst_access_mask(dst_read | dst_write)
         .old_layout(current_layout)
         .new_layout(new_layout)
         .subresource_range(sub_image)
@@ -788,6 +1597,47 @@ pub fn transition_image(
     unsafe { device.cmd_pipeline_barrier2(cmd_buf, &dep_info) };
 }
 
+/// Stages, read and write accesses of an image in `layout`, for the transitions outside the
+/// render graph, of the uploads and the bakes.
+fn layout_usage(
+    layout: vk::ImageLayout,
+) -> (vk::PipelineStageFlags2, vk::AccessFlags2, vk::AccessFlags2) {
+    match layout {
+        vk::ImageLayout::UNDEFINED => (
+            vk::PipelineStageFlags2::NONE,
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::NONE,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_READ,
+            vk::AccessFlags2::NONE,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        ),
+        // Baked by compute shaders.
+        vk::ImageLayout::GENERAL => (
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_SAMPLED_READ,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        ),
+        // Textures, and the IBL maps also read by the deferred lighting.
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::PipelineStageFlags2::FRAGMENT_SHADER | vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            vk::AccessFlags2::NONE,
+        ),
+        _ => (
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ,
+            vk::AccessFlags2::MEMORY_WRITE,
+        ),
+    }
+}
+
 fn create_list(
     sorting: MeshSorting,
     frustum_culling: bool,
@@ -808,12 +1658,12 @@ fn create_list(
                 let a = &meshes[*a];
                 let b = &meshes[*b];
 
-                let cmp_mat = Rc::as_ptr(&a.material).cmp(&Rc::as_ptr(&b.material));
+                let cmp_pip = a.material.pipeline_ptr().cmp(&b.material.pipeline_ptr());
 
-                if let Ordering::Equal = cmp_mat {
+                if let Ordering::Equal = cmp_pip {
                     vk::Buffer::cmp(&a.index_buffer, &b.index_buffer)
                 } else {
-                    cmp_mat
+                    cmp_pip
                 }
             });
         }
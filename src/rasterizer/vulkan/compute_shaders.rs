@@ -1,14 +1,25 @@
 use ash::{Device, vk};
-use std::rc::Rc;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use crate::rasterizer::vulkan::descriptors::DescriptorLayoutBuilder;
 
 use super::{
     descriptors::{DescriptorAllocator, DescriptorWriter},
+    effect_params::{EffectParam, params_ui, parse_params},
     shaders_loader::{ShaderName, ShadersLoader},
 };
 
+/// Every `*.comp.glsl` file in there is a background effect.
+const BACKGROUNDS_FOLDER: &str = "./resources/backgrounds/";
+const BACKGROUND_EXT: &str = ".comp.glsl";
+
 /// This struct manages the background effects based on compute shaders.
+///
+/// They are discovered in [`BACKGROUNDS_FOLDER`], sorted by file name.
 pub struct Effects {
     device_copy: Rc<Device>,
 
@@ -52,8 +63,24 @@ impl Effects {
             unsafe { device.create_pipeline_layout(&create_info, None).unwrap() }
         };
 
-        let gradient = ComputeEffect::gradient(device.clone(), shaders, pipeline_layout);
-        let sky = ComputeEffect::sky(device.clone(), shaders, pipeline_layout);
+        let mut paths: Vec<PathBuf> = fs::read_dir(BACKGROUNDS_FOLDER)
+            .inspect_err(|e| eprintln!("Can't list the background effects : {e}"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.to_string_lossy().ends_with(BACKGROUND_EXT))
+            .collect();
+        paths.sort();
+        // A broken effect is skipped, the others stay usable.
+        let bg_effects = paths
+            .iter()
+            .filter_map(|path| {
+                ComputeEffect::load(device.clone(), shaders, pipeline_layout, path)
+                    .inspect_err(|e| eprintln!("Skipping the background effect : {e}"))
+                    .ok()
+            })
+            .collect();
 
         Self {
             device_copy: device,
@@ -61,7 +88,7 @@ impl Effects {
             draw_img_descs,
             draw_img_desc_layout,
             pipeline_layout,
-            bg_effects: vec![gradient, sky],
+            bg_effects,
         }
     }
 }
@@ -79,34 +106,44 @@ impl Drop for Effects {
     }
 }
 
+/// Filled from the parameters of the effect, see [`effect_params`](super::effect_params).
 #[derive(Default, Debug, Clone, Copy)]
 #[repr(C)]
 pub struct ComputePushConstants {
-    pub data0: [f32; 4],
-    pub data1: [f32; 4],
-    pub data2: [f32; 4],
-    pub data3: [f32; 4],
+    /// The four `vec4`s of the push constants block.
+    pub data: [f32; 16],
 }
 
 /// Loaded compute shader pipeline.
 pub struct ComputeEffect {
     device_copy: Rc<Device>,
 
-    pub name: ShaderName,
+    /// File name without the extension.
+    pub name: String,
     pub pipeline: vk::Pipeline,
 
+    params: Vec<EffectParam>,
     default_data: ComputePushConstants,
 }
 
 impl ComputeEffect {
-    fn new(
+    fn load(
         device: Rc<Device>,
         shaders: &ShadersLoader,
         pipeline_layout: vk::PipelineLayout,
-        name: ShaderName,
-        default_data: ComputePushConstants,
-    ) -> Self {
-        let shader = shaders.get(name);
+        path: &Path,
+    ) -> Result<Self, String> {
+        let glsl = fs::read_to_string(path)
+            .map_err(|e| format!("Can't read {} : {e}", path.to_string_lossy()))?;
+        let (params, default_data) = parse_params(path, &glsl)?;
+        let name = path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .trim_end_matches(BACKGROUND_EXT)
+            .to_owned();
+
+        let shader = shaders.get_compute(path);
         let pipeline = {
             let stage_info = vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::COMPUTE)
@@ -118,7 +155,7 @@ impl ComputeEffect {
             unsafe {
                 device
                     .create_compute_pipelines(
-                        vk::PipelineCache::null(),
+                        shaders.pipeline_cache(),
                         &compute_pipeline_create_infos[..],
                         None,
                     )
@@ -126,52 +163,30 @@ impl ComputeEffect {
             }
         };
 
-        ComputeEffect {
+        Ok(ComputeEffect {
             device_copy: device,
             name,
             pipeline,
+            params,
             default_data,
-        }
-    }
-
-    pub fn gradient(
-        device: Rc<Device>,
-        shaders: &ShadersLoader,
-        pipeline_layout: vk::PipelineLayout,
-    ) -> Self {
-        Self::new(
-            device,
-            shaders,
-            pipeline_layout,
-            ShaderName::ParametrableGradient,
-            ComputePushConstants {
-                data0: [1., 0., 0., 1.],
-                data1: [0., 0., 1., 1.],
-                ..Default::default()
-            },
-        )
-    }
-
-    pub fn sky(
-        device: Rc<Device>,
-        shaders: &ShadersLoader,
-        pipeline_layout: vk::PipelineLayout,
-    ) -> Self {
-        Self::new(
-            device,
-            shaders,
-            pipeline_layout,
-            ShaderName::Sky,
-            ComputePushConstants {
-                data0: [0.1, 0.2, 0.4, 0.97],
-                ..Default::default()
-            },
-        )
+        })
     }
 
     pub fn default_data(&self) -> &ComputePushConstants {
         &self.default_data
     }
+
+    /// The parameters of the effect, and a button to reset them.
+    pub fn ui(&self, ui: &mut egui::Ui, data: &mut ComputePushConstants) {
+        if self.params.is_empty() {
+            ui.label("No parameters");
+            return;
+        }
+        params_ui(ui, &self.params, data);
+        if ui.button("Reset").clicked() {
+            *data = self.default_data;
+        }
+    }
 }
 
 impl Drop for ComputeEffect {
@@ -183,3 +198,38 @@ impl Drop for ComputeEffect {
         }
     }
 }
+
+/// Pipeline and its layout, with push constants of `push_constants_size`.
+pub fn compute_pipeline(
+    device: &Device,
+    shaders: &ShadersLoader,
+    name: ShaderName,
+    desc_layouts: &[vk::DescriptorSetLayout],
+    push_constants_size: usize,
+) -> (vk::PipelineLayout, vk::Pipeline) {
+    let push_constants = [vk::PushConstantRange::default()
+        .size(push_constants_size as u32)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)];
+    let layout = {
+        let create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(desc_layouts)
+            .push_constant_ranges(&push_constants[..]);
+        unsafe { device.create_pipeline_layout(&create_info, None).unwrap() }
+    };
+
+    let shader = shaders.get(name);
+    let stage_info = vk::PipelineShaderStageCreateInfo::default()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader.module_copy())
+        .name(c"main");
+    let create_infos = [vk::ComputePipelineCreateInfo::default()
+        .layout(layout)
+        .stage(stage_info)];
+    let pipeline = unsafe {
+        device
+            .create_compute_pipelines(shaders.pipeline_cache(), &create_infos[..], None)
+            .unwrap()[0]
+    };
+
+    (layout, pipeline)
+}
@@ -0,0 +1,315 @@
+//! Deferred shading of the opaque surfaces.
+//!
+//! They are drawn in the G-buffer first, then a compute shader culls the point lights per tile
+//! of the screen and lights every pixel with the lights of its tile, in `draw_img`. The
+//! transparent surfaces are drawn after, with the forward shading. The ambient occlusion, if
+//! any, is computed between the two, see [`super::ambient_occlusion`].
+
+use std::{
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+use glam::Mat4;
+
+use super::{
+    allocated::AllocatedImage,
+    ambient_occlusion::AoTargets,
+    compute_shaders::compute_pipeline,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    shaders_loader::{ShaderName, ShadersLoader},
+};
+
+/// Albedo with the highlight in alpha, world space normal and metal/roughness, see
+/// `resources/gbuffer.frag.glsl`.
+pub const GBUFFER_FORMATS: [vk::Format; 3] = [
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::R8G8_UNORM,
+];
+/// Side of the tiles culling the lights, one workgroup each.
+pub const LIGHTING_TILE_SIZE: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shading {
+    /// Every fragment is shaded with every light when drawn.
+    Forward,
+    /// The opaque surfaces are shaded once per pixel, with the lights of their tile.
+    Deferred,
+}
+
+/// Replaces the shaded pixels of the deferred shading, without post-processing.
+///
+/// See `GBUFFER_VIEW_*` in `resources/deferred_lighting.comp.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GBufferView {
+    Off,
+    Albedo,
+    Normals,
+    MetalRough,
+    Depth,
+    /// Of the tiles, after the culling. White where some lights are dropped.
+    LightCount,
+    /// Ambient occlusion, white without it.
+    Occlusion,
+}
+
+impl GBufferView {
+    pub const ALL: [GBufferView; 7] = [
+        GBufferView::Off,
+        GBufferView::Albedo,
+        GBufferView::Normals,
+        GBufferView::MetalRough,
+        GBufferView::Depth,
+        GBufferView::LightCount,
+        GBufferView::Occlusion,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadingSettings {
+    /// Applied by `VulkanEngine::apply_shading`. The deferred shading disables the MSAA.
+    pub shading: Shading,
+    pub gbuffer_view: GBufferView,
+}
+
+impl Default for ShadingSettings {
+    fn default() -> Self {
+        Self {
+            shading: Shading::Forward,
+            gbuffer_view: GBufferView::Off,
+        }
+    }
+}
+
+impl ShadingSettings {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Shading :");
+        ui.radio_value(&mut self.shading, Shading::Forward, "forward");
+        ui.radio_value(&mut self.shading, Shading::Deferred, "deferred");
+        ui.add_enabled_ui(self.shading == Shading::Deferred, |ui| {
+            egui::ComboBox::from_label("G-buffer view")
+                .selected_text(format!("{:?}", self.gbuffer_view))
+                .show_ui(ui, |ui| {
+                    GBufferView::ALL.iter().for_each(|v| {
+                        ui.selectable_value(&mut self.gbuffer_view, *v, format!("{v:?}"));
+                    });
+                });
+        });
+    }
+
+    /// Only with the deferred shading.
+    pub fn gbuffer_view(&self) -> GBufferView {
+        match self.shading {
+            Shading::Forward => GBufferView::Off,
+            Shading::Deferred => self.gbuffer_view,
+        }
+    }
+}
+
+/// Attachments of the G-buffer, and the set reading them with `depth_img` to light
+/// `draw_img`.
+///
+/// Has the size of `draw_img`, so needs to be recreated with it.
+pub struct GBuffer {
+    device_copy: Rc<Device>,
+
+    pub albedo: AllocatedImage,
+    pub normal: AllocatedImage,
+    pub metal_rough: AllocatedImage,
+    pub ao: AoTargets,
+
+    _descriptor: DescriptorAllocator,
+    sampler: vk::Sampler,
+    desc_layout: vk::DescriptorSetLayout,
+    /// Set 1 of [`DeferredLighting`].
+    pub desc: vk::DescriptorSet,
+}
+
+impl GBuffer {
+    pub fn new(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        draw_img: &AllocatedImage,
+        depth_img: &AllocatedImage,
+    ) -> Self {
+        let [albedo, normal, metal_rough] = GBUFFER_FORMATS.map(|format| {
+            AllocatedImage::new_gbuffer(device.clone(), allocator.clone(), draw_img.extent, format)
+        });
+        let ao = AoTargets::new(device.clone(), allocator, &normal, depth_img);
+
+        // Only read with `texelFetch`.
+        let sampler = {
+            let create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST);
+            unsafe { device.create_sampler(&create_info, None).unwrap() }
+        };
+        let sizes = [
+            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 5.),
+            (vk::DescriptorType::STORAGE_IMAGE, 1.),
+        ];
+        let descriptor = DescriptorAllocator::new(device.clone(), 1, &sizes[..]);
+        let desc_layout = gbuffer_layout(&device);
+        let desc = descriptor.allocate(desc_layout);
+        {
+            let mut writer = DescriptorWriter::default();
+            // In the layouts of `ImageUsage::Storage` and `ImageUsage::DepthRead`.
+            [&albedo, &normal, &metal_rough]
+                .iter()
+                .enumerate()
+                .for_each(|(i, image)| {
+                    writer.write_image(
+                        i as u32,
+                        image.img_view,
+                        sampler,
+                        vk::ImageLayout::GENERAL,
+                        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    );
+                });
+            writer.write_image(
+                3,
+                depth_img.img_view,
+                sampler,
+                vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.write_image(
+                4,
+                draw_img.img_view,
+                vk::Sampler::null(),
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::STORAGE_IMAGE,
+            );
+            writer.write_image(
+                5,
+                ao.occlusion.img_view,
+                sampler,
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.update_set(&device, desc);
+        }
+
+        Self {
+            device_copy: device,
+            albedo,
+            normal,
+            metal_rough,
+            ao,
+            _descriptor: descriptor,
+            sampler,
+            desc_layout,
+            desc,
+        }
+    }
+
+    pub fn images(&self) -> [&AllocatedImage; 3] {
+        [&self.albedo, &self.normal, &self.metal_rough]
+    }
+}
+
+impl Drop for GBuffer {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop GBuffer");
+        unsafe {
+            self.device_copy
+                .destroy_descriptor_set_layout(self.desc_layout, None);
+            self.device_copy.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+/// The G-buffer and depth samplers, then `draw_img` and the ambient occlusion.
+///
+/// Created again with every [`GBuffer`], identical so that their sets stay compatible with the
+/// pipeline of [`DeferredLighting`].
+fn gbuffer_layout(device: &Device) -> vk::DescriptorSetLayout {
+    DescriptorLayoutBuilder::default()
+        .add_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .add_binding(2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .add_binding(3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .add_binding(4, vk::DescriptorType::STORAGE_IMAGE)
+        .add_binding(5, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .build(device, vk::ShaderStageFlags::COMPUTE)
+}
+
+/// Compute pipeline of the tiled light culling and of the lighting.
+///
+/// Its sets are the scene data with the lights, a [`GBuffer`] and the image-based lighting.
+pub struct DeferredLighting {
+    device_copy: Rc<Device>,
+
+    gbuffer_layout: vk::DescriptorSetLayout,
+    pub layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+}
+
+impl DeferredLighting {
+    pub fn new(
+        device: Rc<Device>,
+        shaders: &ShadersLoader,
+        scene_data_layout: vk::DescriptorSetLayout,
+        ibl_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let gbuffer_layout = gbuffer_layout(&device);
+        let (layout, pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::DeferredLighting,
+            &[scene_data_layout, gbuffer_layout, ibl_layout],
+            size_of::<LightingPushConstants>(),
+        );
+
+        Self {
+            device_copy: device,
+            gbuffer_layout,
+            layout,
+            pipeline,
+        }
+    }
+}
+
+impl Drop for DeferredLighting {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop DeferredLighting");
+        unsafe {
+            self.device_copy.destroy_pipeline(self.pipeline, None);
+            self.device_copy.destroy_pipeline_layout(self.layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.gbuffer_layout, None);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct LightingPushConstants {
+    /// To get the view space positions back from the depth.
+    pub inv_proj: Mat4,
+    pub size: [u32; 2],
+    /// See [`GBufferView`].
+    pub gbuffer_view: u32,
+    /// Boolean, the ambient occlusion of the [`GBuffer`] was computed.
+    pub ambient_occlusion: u32,
+}
+
+impl LightingPushConstants {
+    pub fn new(
+        proj: &Mat4,
+        extent: vk::Extent2D,
+        gbuffer_view: GBufferView,
+        ambient_occlusion: bool,
+    ) -> Self {
+        Self {
+            inv_proj: proj.inverse(),
+            size: [extent.width, extent.height],
+            gbuffer_view: gbuffer_view as u32,
+            ambient_occlusion: ambient_occlusion as u32,
+        }
+    }
+}
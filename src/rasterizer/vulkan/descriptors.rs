@@ -238,6 +238,19 @@ impl<'a> DescriptorWriter<'a> {
         sampler: vk::Sampler,
         image_layout: vk::ImageLayout,
         desc_type: vk::DescriptorType,
+    ) {
+        self.write_image_at(binding, 0, image_view, sampler, image_layout, desc_type);
+    }
+
+    /// Like [`Self::write_image`], for the element `array_element` of an array binding.
+    pub fn write_image_at(
+        &mut self,
+        binding: u32,
+        array_element: u32,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        image_layout: vk::ImageLayout,
+        desc_type: vk::DescriptorType,
     ) {
         let info = vk::DescriptorImageInfo::default()
             .sampler(sampler)
@@ -249,6 +262,7 @@ impl<'a> DescriptorWriter<'a> {
 
         let write = vk::WriteDescriptorSet::default()
             .dst_binding(binding)
+            .dst_array_element(array_element)
             .dst_set(vk::DescriptorSet::null())
             .descriptor_count(1)
             .descriptor_type(desc_type)
@@ -298,11 +312,20 @@ pub struct DescriptorLayoutBuilder<'a> {
 }
 
 impl<'a> DescriptorLayoutBuilder<'a> {
-    pub fn add_binding(mut self, binding: u32, desc_type: vk::DescriptorType) -> Self {
+    pub fn add_binding(self, binding: u32, desc_type: vk::DescriptorType) -> Self {
+        self.add_array_binding(binding, desc_type, 1)
+    }
+
+    pub fn add_array_binding(
+        mut self,
+        binding: u32,
+        desc_type: vk::DescriptorType,
+        count: u32,
+    ) -> Self {
         let newbind = vk::DescriptorSetLayoutBinding::default()
             .binding(binding)
             .descriptor_type(desc_type)
-            .descriptor_count(1);
+            .descriptor_count(count);
 
         self.bindings.push(newbind);
 
@@ -323,22 +346,23 @@ impl<'a> DescriptorLayoutBuilder<'a> {
         unsafe { device.create_descriptor_set_layout(&info, None).unwrap() }
     }
 
-    // fn build_2<T: vk::ExtendsDescriptorSetLayoutCreateInfo>(
-    //     mut self,
-    //     device: &Device,
-    //     shader_stages: vk::ShaderStageFlags,
-    //     p_next: &mut T,
-    //     flags: vk::DescriptorSetLayoutCreateFlags,
-    // ) -> vk::DescriptorSetLayout {
-    //     self.bindings
-    //         .iter_mut()
-    //         .for_each(|b| b.stage_flags |= shader_stages);
-
-    //     let info = vk::DescriptorSetLayoutCreateInfo::default()
-    //         .bindings(&self.bindings[..])
-    //         .push_next(p_next)
-    //         .flags(flags);
-
-    //     unsafe { device.create_descriptor_set_layout(&info, None).unwrap() }
-    // }
+    /// Like [`Self::build`], with binding flags for example.
+    pub fn build_p_next<T: vk::ExtendsDescriptorSetLayoutCreateInfo>(
+        mut self,
+        device: &Device,
+        shader_stages: vk::ShaderStageFlags,
+        p_next: &mut T,
+        flags: vk::DescriptorSetLayoutCreateFlags,
+    ) -> vk::DescriptorSetLayout {
+        self.bindings
+            .iter_mut()
+            .for_each(|b| b.stage_flags |= shader_stages);
+
+        let info = vk::DescriptorSetLayoutCreateInfo::default()
+            .bindings(&self.bindings[..])
+            .push_next(p_next)
+            .flags(flags);
+
+        unsafe { device.create_descriptor_set_layout(&info, None).unwrap() }
+    }
 }
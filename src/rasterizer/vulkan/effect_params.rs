@@ -0,0 +1,261 @@
+//! Named parameters of the background effects, declared in a header of their `.comp.glsl` file.
+//!
+//! Each `//@param <name>: <type> = <default> [in <min> <max>]` line takes the next floats of the
+//! push constants block, with the types `float`, `vec2`, `vec3`, `vec4`, `color3` and `color4`.
+//! A parameter never straddles two `vec4`s of the block, it starts at the next one instead :
+//!
+//! ```glsl
+//! //@param sky_color: color3 = 0.1 0.2 0.4
+//! //@param star_threshold: float = 0.97 in 0.9 1
+//! ```
+//!
+//! Here `sky_color` is `data1.xyz` and `star_threshold` is `data1.w`.
+
+use std::path::Path;
+
+use super::compute_shaders::ComputePushConstants;
+
+const PARAM_PREFIX: &str = "//@param";
+/// Range of the values without `in <min> <max>`.
+const DEFAULT_RANGE: (f32, f32) = (0., 1.);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    /// `float` or `vecN`, edited with drag values.
+    Values { len: usize, min: f32, max: f32 },
+    /// `color3` or `color4`.
+    Color { alpha: bool },
+}
+
+impl ParamKind {
+    fn len(self) -> usize {
+        match self {
+            ParamKind::Values { len, .. } => len,
+            ParamKind::Color { alpha: false } => 3,
+            ParamKind::Color { alpha: true } => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EffectParam {
+    pub name: String,
+    pub kind: ParamKind,
+    /// Index of the first float in [`ComputePushConstants::data`].
+    offset: usize,
+}
+
+/// Returns the parameters in declaration order, and the push constants filled with their defaults.
+///
+/// The error of a malformed header points to its line.
+pub fn parse_params(
+    path: &Path,
+    glsl: &str,
+) -> Result<(Vec<EffectParam>, ComputePushConstants), String> {
+    let mut params = Vec::new();
+    let mut defaults = ComputePushConstants::default();
+    let mut offset = 0;
+
+    for (i, line) in glsl.lines().enumerate() {
+        let Some(decl) = line.trim().strip_prefix(PARAM_PREFIX) else {
+            continue;
+        };
+        let Some((name, rest)) = decl.split_once(':') else {
+            return Err(invalid(path, i, line, "missing `:` after the name"));
+        };
+        let Some((ty, rest)) = rest.split_once('=') else {
+            return Err(invalid(path, i, line, "missing `=` before the default"));
+        };
+        let (values, range) = match rest.split_once(" in ") {
+            Some((values, range)) => (values, Some(range)),
+            None => (rest, None),
+        };
+        let values = parse_floats(path, i, line, values)?;
+
+        let (min, max) = match range.map(|r| parse_floats(path, i, line, r)).transpose()? {
+            Some(range) => match range[..] {
+                [min, max] if min < max => (min, max),
+                _ => {
+                    return Err(invalid(
+                        path,
+                        i,
+                        line,
+                        "the range should be `in <min> <max>`",
+                    ));
+                }
+            },
+            None => DEFAULT_RANGE,
+        };
+        let kind = match ty.trim() {
+            "float" => ParamKind::Values { len: 1, min, max },
+            "vec2" => ParamKind::Values { len: 2, min, max },
+            "vec3" => ParamKind::Values { len: 3, min, max },
+            "vec4" => ParamKind::Values { len: 4, min, max },
+            "color3" if range.is_none() => ParamKind::Color { alpha: false },
+            "color4" if range.is_none() => ParamKind::Color { alpha: true },
+            "color3" | "color4" => return Err(invalid(path, i, line, "colors don't have a range")),
+            _ => return Err(invalid(path, i, line, "unknown type")),
+        };
+
+        let len = kind.len();
+        if values.len() != len {
+            return Err(invalid(
+                path,
+                i,
+                line,
+                &format!("expected {len} default values"),
+            ));
+        }
+        if offset / 4 != (offset + len - 1) / 4 {
+            offset = offset.next_multiple_of(4);
+        }
+        if offset + len > defaults.data.len() {
+            return Err(invalid(path, i, line, "the push constants block is full"));
+        }
+
+        defaults.data[offset..offset + len].copy_from_slice(&values);
+        params.push(EffectParam {
+            name: name.trim().to_owned(),
+            kind,
+            offset,
+        });
+        offset += len;
+    }
+
+    Ok((params, defaults))
+}
+
+fn parse_floats(path: &Path, i: usize, line: &str, values: &str) -> Result<Vec<f32>, String> {
+    values
+        .split_whitespace()
+        .map(|v| {
+            v.parse()
+                .map_err(|_| invalid(path, i, line, &format!("invalid number `{v}`")))
+        })
+        .collect()
+}
+
+fn invalid(path: &Path, i: usize, line: &str, msg: &str) -> String {
+    format!(
+        "Invalid effect parameter in {}:{} : {msg}\n    {}",
+        path.to_string_lossy(),
+        i + 1,
+        line.trim()
+    )
+}
+
+/// One labelled widget per parameter.
+pub fn params_ui(ui: &mut egui::Ui, params: &[EffectParam], data: &mut ComputePushConstants) {
+    egui::Grid::new("effect_params")
+        .num_columns(2)
+        .show(ui, |ui| {
+            for param in params {
+                ui.label(param.name.replace('_', " "));
+                let values = &mut data.data[param.offset..param.offset + param.kind.len()];
+                match param.kind {
+                    ParamKind::Values { min, max, .. } => {
+                        ui.horizontal(|ui| {
+                            values.iter_mut().for_each(|v| {
+                                ui.add(
+                                    egui::DragValue::new(v)
+                                        .speed((max - min) / 100.)
+                                        .range(min..=max),
+                                );
+                            });
+                        });
+                    }
+                    ParamKind::Color { alpha: false } => {
+                        ui.color_edit_button_rgb(values.try_into().unwrap());
+                    }
+                    ParamKind::Color { alpha: true } => {
+                        ui.color_edit_button_rgba_unmultiplied(values.try_into().unwrap());
+                    }
+                }
+                ui.end_row();
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(glsl: &str) -> Result<(Vec<EffectParam>, ComputePushConstants), String> {
+        parse_params(Path::new("test.comp.glsl"), glsl)
+    }
+
+    #[test]
+    fn params_take_the_next_floats() {
+        let (params, defaults) = parse(
+            "#version 460\n\
+             //@param sky_color: color3 = 0.1 0.2 0.4\n\
+             //@param star_threshold: float = 0.97 in 0.9 1\n\
+             void main() {}",
+        )
+        .unwrap();
+
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0].name, "sky_color");
+        assert_eq!(params[0].kind, ParamKind::Color { alpha: false });
+        assert_eq!(params[0].offset, 0);
+        assert_eq!(params[1].name, "star_threshold");
+        assert_eq!(
+            params[1].kind,
+            ParamKind::Values {
+                len: 1,
+                min: 0.9,
+                max: 1.
+            }
+        );
+        assert_eq!(params[1].offset, 3);
+        assert_eq!(defaults.data[..4], [0.1, 0.2, 0.4, 0.97]);
+    }
+
+    #[test]
+    fn params_dont_straddle_two_vec4s() {
+        let (params, defaults) = parse(
+            "//@param a: vec3 = 1 2 3\n\
+             //@param b: vec2 = 4 5\n\
+             //@param c: float = 6",
+        )
+        .unwrap();
+
+        assert_eq!(params[1].offset, 4);
+        assert_eq!(params[2].offset, 6);
+        assert_eq!(defaults.data[..8], [1., 2., 3., 0., 4., 5., 6., 0.]);
+        let (min, max) = DEFAULT_RANGE;
+        assert_eq!(params[0].kind, ParamKind::Values { len: 3, min, max });
+    }
+
+    #[test]
+    fn malformed_headers_point_to_their_line() {
+        let errors = [
+            ("//@param a float = 1", "missing `:`"),
+            ("//@param a: float 1", "missing `=`"),
+            ("//@param a: mat4 = 1", "unknown type"),
+            ("//@param a: vec2 = 1", "expected 2 default values"),
+            ("//@param a: float = one", "invalid number `one`"),
+            ("//@param a: float = 0.5 in 1 0", "the range should be"),
+            (
+                "//@param a: color3 = 1 1 1 in 0 1",
+                "colors don't have a range",
+            ),
+        ];
+        for (line, expected) in errors {
+            let error = parse(&format!("#version 460\n{line}")).unwrap_err();
+            assert!(error.contains("test.comp.glsl:2"), "{error}");
+            assert!(error.contains(expected), "{error}");
+        }
+    }
+
+    #[test]
+    fn full_push_constants_block() {
+        let glsl = "//@param v: vec4 = 0 0 0 0\n".repeat(4);
+        assert!(parse(&glsl).is_ok());
+        let error = parse(&format!("{glsl}//@param f: float = 0")).unwrap_err();
+        assert!(
+            error.contains("the push constants block is full"),
+            "{error}"
+        );
+    }
+}
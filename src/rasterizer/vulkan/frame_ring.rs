@@ -0,0 +1,150 @@
+//! Linear allocator for the data uploaded every frame : the scene uniforms, the culled objects...
+//!
+//! Each `FrameData` has its own [`RingSegment`], reset when the frame is reused, after its fence,
+//! so what's allocated in it only lives for one frame. A full segment continues in a larger
+//! buffer, the previous ones are kept until the reset since the frame still reads them.
+
+use std::{
+    cell::{Cell, RefCell},
+    ptr,
+    rc::Rc,
+    slice,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+
+use super::{
+    allocated::{AllocatedBuffer, MyMemoryUsage},
+    base::VulkanBase,
+};
+
+/// Bytes per frame at the start, doubled when a frame needs more.
+const INITIAL_SIZE: u64 = 8 * 1024 * 1024;
+/// Of the `vec4`s read by address, like `ObjectBuffer` in `resources/draw_objects.glsl`.
+const MIN_ALIGNMENT: u64 = 16;
+
+/// Where a value was written, to bind it with a descriptor or to read it by address.
+#[derive(Debug, Clone, Copy)]
+pub struct RingAllocation {
+    pub buffer: vk::Buffer,
+    pub offset: u64,
+    pub size: u64,
+    pub address: vk::DeviceAddress,
+}
+
+/// A persistently mapped buffer of a [`RingSegment`].
+struct RingBuffer {
+    buffer: AllocatedBuffer,
+    address: vk::DeviceAddress,
+    size: u64,
+}
+
+impl RingBuffer {
+    fn new(device: &Device, allocator: Arc<Mutex<vk_mem::Allocator>>, size: u64) -> Self {
+        let buffer = AllocatedBuffer::new(
+            allocator,
+            size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MyMemoryUsage::CpuToGpu,
+        );
+        let address = buffer.device_address(device);
+        Self {
+            buffer,
+            address,
+            size,
+        }
+    }
+}
+
+/// Allocations of one frame.
+pub struct RingSegment {
+    device_copy: Rc<Device>,
+    allocator_copy: Arc<Mutex<vk_mem::Allocator>>,
+    /// Every allocation starts at a multiple of it, so it can be bound as a uniform or storage
+    /// buffer.
+    alignment: u64,
+    current: RefCell<RingBuffer>,
+    /// The full ones, dropped on [`Self::reset`].
+    retired: RefCell<Vec<RingBuffer>>,
+    /// Offset of the next allocation in `current`, before alignment.
+    head: Cell<u64>,
+}
+
+impl RingSegment {
+    pub fn new(base: &VulkanBase, allocator: Arc<Mutex<vk_mem::Allocator>>) -> Self {
+        let limits = unsafe {
+            base.instance
+                .get_physical_device_properties(base.chosen_gpu)
+                .limits
+        };
+        let alignment = limits
+            .min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment)
+            .max(MIN_ALIGNMENT);
+        let current = RingBuffer::new(&base.device, allocator.clone(), INITIAL_SIZE);
+
+        Self {
+            device_copy: base.device.clone(),
+            allocator_copy: allocator,
+            alignment,
+            current: RefCell::new(current),
+            retired: Default::default(),
+            head: Cell::new(0),
+        }
+    }
+
+    /// Once the frame is done, see `FrameData::wait_for_fences`. Keeps the largest buffer.
+    pub fn reset(&self) {
+        self.retired.borrow_mut().clear();
+        self.head.set(0);
+    }
+
+    pub fn push<T: Copy>(&self, value: &T) -> RingAllocation {
+        self.push_slice(slice::from_ref(value))
+    }
+
+    /// An empty slice still gets an allocation of one byte, empty buffers aren't allowed.
+    pub fn push_slice<T: Copy>(&self, values: &[T]) -> RingAllocation {
+        let size = size_of_val(values) as u64;
+        let allocated = size.max(1);
+        let mut offset = self.head.get().next_multiple_of(self.alignment);
+        if offset + allocated > self.current.borrow().size {
+            self.grow(allocated);
+            offset = 0;
+        }
+        let current = self.current.borrow();
+
+        // The memory is only written sequentially, see `MyMemoryUsage::CpuToGpu`.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                values.as_ptr().cast::<u8>(),
+                current
+                    .buffer
+                    .mapped_data()
+                    .cast::<u8>()
+                    .add(offset as usize),
+                size as usize,
+            );
+        }
+        self.head.set(offset + allocated);
+
+        RingAllocation {
+            buffer: current.buffer.buffer,
+            offset,
+            size: allocated,
+            address: current.address + offset,
+        }
+    }
+
+    /// Continues in a buffer twice larger, or large enough for `size`.
+    fn grow(&self, size: u64) {
+        let new_size = (self.current.borrow().size * 2).max(size.next_power_of_two());
+        let larger = RingBuffer::new(&self.device_copy, self.allocator_copy.clone(), new_size);
+        let full = self.current.replace(larger);
+        self.retired.borrow_mut().push(full);
+        self.head.set(0);
+    }
+}
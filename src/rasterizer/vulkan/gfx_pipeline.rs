@@ -8,6 +8,19 @@ use super::shaders_loader::ShaderModule;
 pub struct GpuDrawPushConstants {
     pub world_mat: Mat4,
     pub vertex_buffer: vk::DeviceAddress,
+    /// Objects written by `GpuCulling`, read instead of the other fields if `draw_indirect` is set.
+    pub objects: vk::DeviceAddress,
+    /// Ids for the false colour debug views.
+    pub mesh_id: u32,
+    pub surface_id: u32,
+    /// Level of detail drawn, 0 for the original surface.
+    pub lod: u32,
+    /// Boolean, tints the selected nodes.
+    pub highlighted: u32,
+    /// Index in the bindless materials.
+    pub material_id: u32,
+    /// Boolean, the object is `objects[gl_InstanceIndex]`.
+    pub draw_indirect: u32,
 }
 
 // pub struct VkGraphicsPipeline {
@@ -98,12 +111,15 @@ impl<'a> PipelineBuilder<'a> {
             render_info: Default::default(),
         }
     }
-    pub fn build(&mut self, device: &Device) -> vk::Pipeline {
+    pub fn build(&mut self, device: &Device, cache: vk::PipelineCache) -> vk::Pipeline {
         let viewport_state = vk::PipelineViewportStateCreateInfo::default()
             .viewport_count(1)
             .scissor_count(1);
-        // For now, no transparancy, disabled :
-        let color_blend_attachments = [self.color_blend_attachment];
+        // The same blending for every colour attachment, e.g. of the G-buffer.
+        let color_blend_attachments = vec![
+            self.color_blend_attachment;
+            self.render_info.color_attachment_count.max(1) as usize
+        ];
         let color_blending = vk::PipelineColorBlendStateCreateInfo::default()
             .logic_op_enable(false)
             .logic_op(vk::LogicOp::COPY)
@@ -130,7 +146,7 @@ impl<'a> PipelineBuilder<'a> {
 
         unsafe {
             device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_infos[..], None)
+                .create_graphics_pipelines(cache, &pipeline_infos[..], None)
                 .unwrap()[0]
         }
     }
@@ -176,6 +192,16 @@ impl<'a> PipelineBuilder<'a> {
             .alpha_to_one_enable(false);
     }
 
+    /// With `sample_shading`, the fragment shader runs once per sample instead of once per
+    /// pixel, which needs the `sampleRateShading` feature.
+    pub fn set_multisampling(&mut self, samples: vk::SampleCountFlags, sample_shading: bool) {
+        self.set_multisampling_none();
+        self.multisampling = self
+            .multisampling
+            .rasterization_samples(samples)
+            .sample_shading_enable(sample_shading);
+    }
+
     pub fn set_color_attachment_format(&mut self, formats: &'a [vk::Format]) {
         self.render_info = self.render_info.color_attachment_formats(formats);
     }
@@ -1,34 +1,50 @@
 use std::{
     cell::RefCell,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fs,
     iter::zip,
+    num::NonZero,
     path::Path,
     rc::Rc,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
 };
 
-use ::image::{DynamicImage, GrayImage, RgbImage};
 use ash::{Device, vk};
-use glam::{Mat4, Vec3, Vec4, vec4};
+use glam::{Mat4, vec4};
 use gltf::{
-    Document, buffer, image,
+    Document, Gltf, buffer, image,
     material::AlphaMode,
     texture::{MagFilter, MinFilter},
 };
 use vk_mem::Allocator;
 
 use super::{
-    allocated::{AllocatedBuffer, AllocatedImage, MyMemoryUsage},
+    allocated::{AllocatedImage, UploadBatch},
     commands::VulkanCommands,
-    descriptors::DescriptorAllocatorGrowable,
-    scene::{GeoSurface, GpuMeshBuffers, MeshAsset, MeshNode, Node, NodeData, Renderable},
-    textures::{MaterialConstants, MaterialInstance, MaterialPass, MaterialResources, Textures},
+    scene::{
+        DrawContext, GeoSurface, GpuMeshBuffers, MeshAsset, MeshNode, Node, NodeData, Renderable,
+    },
+    texture_data::{self, FormatSupport, TextureData},
+    textures::{MaterialConstants, MaterialInstance, MaterialPass, Textures},
+};
+use crate::scene::{
+    Bounds, Vertex,
+    bvh::{Aabb, Bvh, Frustum, Ray, TransformEpoch},
+    gltf_file::{instance_transforms, open_gltf, read_primitive},
+    lod::{self, LodLevel},
 };
-use crate::scene::{Bounds, Vertex};
 
 /// Override colors with normal value
 const OVERRIDE_COLORS: bool = false;
 
+/// Textures in KTX2 containers, with their mip chain and possibly compressed.
+const KTX2_EXTENSION: &str = "KHR_texture_basisu";
+const KTX2_MIME: &str = "image/ktx2";
+
 /*
 /// Loads the glTF file and uploads it to GPU memory
 pub fn load_gltf_meshes(
@@ -129,11 +145,21 @@ pub struct LoadedGLTF {
 
     pub top_nodes: Vec<Rc<RefCell<dyn Node>>>,
 
-    samplers: Vec<vk::Sampler>,
+    /// Over the world bounds of every surface, refitted when transforms change.
+    bvh: Bvh,
+    /// Node and surface index of each BVH item.
+    bvh_surfaces: Vec<(Rc<RefCell<dyn Node>>, usize)>,
+    /// Bumped by the nodes of the scene.
+    transform_epoch: TransformEpoch,
+    /// Transforms epoch of the last build or refit.
+    bvh_epoch: u64,
+    /// Name of the node drawn highlighted.
+    highlighted: String,
 
-    descriptor_pool: DescriptorAllocatorGrowable,
-
-    material_data_buffer: AllocatedBuffer,
+    samplers: Vec<vk::Sampler>,
+    /// Indices in the bindless table.
+    bindless_textures: Vec<u32>,
+    bindless_materials: Vec<u32>,
 }
 
 impl Drop for LoadedGLTF {
@@ -156,17 +182,215 @@ impl Renderable for LoadedGLTF {
     }
 }
 
+/// CPU side of a glTF file, read by a [`GltfLoading`].
+pub struct GltfData {
+    document: Document,
+    /// By node, see [`instance_transforms`].
+    instances: Vec<Option<Vec<Mat4>>>,
+    meshes: Vec<MeshData>,
+    /// `None` if no texture samples it. An image failing to load is replaced by the error
+    /// checkerboard.
+    images: Vec<Option<Result<TextureData, String>>>,
+}
+
+/// A mesh with its levels of detail, ready to be uploaded.
+struct MeshData {
+    name: Option<String>,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    surfaces: Vec<SurfaceData>,
+}
+
+/// A [`GeoSurface`] with the index of its material in the document.
+struct SurfaceData {
+    start_index: u32,
+    count: u32,
+    material: usize,
+    bounds: Bounds,
+    lods: Vec<LodLevel>,
+}
+
+/// Written by the worker threads of a [`GltfLoading`].
+#[derive(Default)]
+struct LoadingProgress {
+    /// The document and its buffers are read.
+    parsed: AtomicBool,
+    meshes_total: AtomicUsize,
+    meshes_read: AtomicUsize,
+    images_total: AtomicUsize,
+    images_decoded: AtomicUsize,
+}
+
+/// A glTF file read and decoded on worker threads, see [`LoadedGLTF::upload`].
+pub struct GltfLoading {
+    progress: Arc<LoadingProgress>,
+    worker: JoinHandle<Result<GltfData, String>>,
+}
+
+impl GltfLoading {
+    pub fn start(path: &Path, support: FormatSupport) -> Self {
+        println!("Loading glTF : {}", path.to_string_lossy());
+
+        let progress = Arc::new(LoadingProgress::default());
+        let path = path.to_owned();
+        let worker = {
+            let progress = progress.clone();
+            thread::spawn(move || read_gltf(&path, &progress, &support))
+        };
+
+        Self { progress, worker }
+    }
+
+    /// Between 0 and 1, with what's being done.
+    pub fn progress(&self) -> (f32, String) {
+        if !self.progress.parsed.load(Ordering::Relaxed) {
+            return (0., "Reading".into());
+        }
+        let meshes_total = self.progress.meshes_total.load(Ordering::Relaxed);
+        let meshes_read = self.progress.meshes_read.load(Ordering::Relaxed);
+        let images_total = self.progress.images_total.load(Ordering::Relaxed);
+        let decoded = self.progress.images_decoded.load(Ordering::Relaxed);
+        let fraction = (meshes_read + decoded) as f32 / (meshes_total + images_total) as f32;
+        if meshes_read < meshes_total {
+            (
+                fraction,
+                format!("Processing meshes {meshes_read}/{meshes_total}"),
+            )
+        } else if decoded < images_total {
+            (
+                fraction,
+                format!("Decoding images {decoded}/{images_total}"),
+            )
+        } else {
+            (1., "Uploading".into())
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.worker.is_finished()
+    }
+
+    /// Blocks until the worker is done.
+    pub fn finish(self) -> Result<GltfData, String> {
+        self.worker
+            .join()
+            .unwrap_or_else(|_| Err("The loading thread panicked".into()))
+    }
+}
+
+/// Runs on the worker thread, the images are decoded in parallel while it processes the meshes.
+fn read_gltf(
+    path: &Path,
+    progress: &LoadingProgress,
+    support: &FormatSupport,
+) -> Result<GltfData, String> {
+    let base = path.parent().unwrap_or(Path::new("./"));
+    let Gltf { document, blob } = open_gltf(path).map_err(|e| e.to_string())?;
+    let buffers = gltf::import_buffers(&document, Some(base), blob).map_err(|e| e.to_string())?;
+
+    let sources: Vec<image::Image> = document.images().collect();
+    let mut sampled: Vec<usize> = document
+        .textures()
+        .filter_map(|t| texture_image(&t))
+        .collect();
+    sampled.sort_unstable();
+    sampled.dedup();
+    let colors: HashSet<usize> = document
+        .materials()
+        .flat_map(|m| {
+            [
+                m.pbr_metallic_roughness()
+                    .base_color_texture()
+                    .map(|t| t.texture()),
+                m.emissive_texture().map(|t| t.texture()),
+            ]
+        })
+        .flatten()
+        .filter_map(|t| texture_image(&t))
+        .collect();
+    progress
+        .meshes_total
+        .store(document.meshes().len(), Ordering::Relaxed);
+    progress
+        .images_total
+        .store(sampled.len(), Ordering::Relaxed);
+    progress.parsed.store(true, Ordering::Relaxed);
+
+    let instances = document
+        .nodes()
+        .map(|node| instance_transforms(&document, &buffers, &node))
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZero::get)
+        .min(sampled.len());
+    let (meshes, decoded): (_, Vec<(usize, Result<TextureData, String>)>) =
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut decoded = Vec::new();
+                        loop {
+                            let Some(&i) = sampled.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                                break decoded;
+                            };
+                            let image = decode_image(
+                                &sources[i],
+                                base,
+                                &buffers,
+                                colors.contains(&i),
+                                support,
+                            );
+                            progress.images_decoded.fetch_add(1, Ordering::Relaxed);
+                            decoded.push((i, image));
+                        }
+                    })
+                })
+                .collect();
+            let meshes = document
+                .meshes()
+                .map(|mesh| {
+                    let mesh = read_mesh(&mesh, &buffers);
+                    progress.meshes_read.fetch_add(1, Ordering::Relaxed);
+                    mesh
+                })
+                .collect();
+            let decoded = handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect();
+            (meshes, decoded)
+        });
+
+    let mut images: Vec<_> = sources.iter().map(|_| None).collect();
+    for (i, image) in decoded {
+        images[i] = Some(image);
+    }
+
+    Ok(GltfData {
+        document,
+        instances,
+        meshes,
+        images,
+    })
+}
+
 impl LoadedGLTF {
-    pub fn load(
+    /// Uploads the data read by a [`GltfLoading`], with a single submit.
+    pub fn upload(
         device: Rc<Device>,
         allocator: Arc<Mutex<Allocator>>,
         commands: &VulkanCommands,
         textures: &mut Textures,
-        path: impl AsRef<Path>,
+        data: GltfData,
     ) -> Self {
-        println!("Loading glTF : {}", path.as_ref().to_string_lossy());
-
-        let (document, buffers, mut images_data) = gltf::import(path).unwrap();
+        let GltfData {
+            document,
+            instances,
+            meshes,
+            images: decoded_images,
+        } = data;
 
         let samplers: Vec<vk::Sampler> = document
             .samplers()
@@ -181,76 +405,38 @@ impl LoadedGLTF {
             })
             .collect();
 
-        // - vertices : 1 storage buffer
-        // - MaterialConstants : uniform buffer
-        // - actual Texture : img sampl
-        // See texture : 2 image sampler + 2 uniform
-        // - GpuSceneData uniform
-        // - GLTFMaterialData uniform
-        // - colorTexture img samp
-        // - metalRoughTexture img samp
-        let sizes = vec![
-            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 3.),
-            (vk::DescriptorType::UNIFORM_BUFFER, 3.),
-            (vk::DescriptorType::STORAGE_BUFFER, 1.),
-        ];
-        // TODO: count ? get len directly ? .as_json().materials.len()
-        let materials_len = document.materials().count();
-        let mut descriptor_pool =
-            DescriptorAllocatorGrowable::new(device.clone(), materials_len as u32, sizes);
-
         // Chargement dans l'ordre des dépendences
 
-        // TODO: parallel loading/converting images ?
-
-        // let mut images = HashMap::new();
-        // let images_vec: Vec<Rc<AllocatedImage>> = zip(images_data.drain(..), document.images())
-        //     .enumerate()
-        //     .map(|(i, (img_data, img))| {
-        //         load_image(commands, device.clone(), allocator.clone(), img_data)
-        //             .map(Rc::new)
-        //             .inspect(|img_data| {
-        //                 if let Some(name) = img.name() {
-        //                     images.insert(name.into(), img_data.clone());
-        //                 }
-        //             })
-        //             .unwrap_or_else(|err| {
-        //                 eprintln!("Failed to load image #{i}, using default : {err}");
-        //                 textures.error_checkerboard.clone()
-        //             })
-        //     })
-        //     .collect();
-        let images: Vec<Rc<AllocatedImage>> = images_data
-            .drain(..)
+        let mut batch = UploadBatch::new(allocator);
+        let images: Vec<Rc<AllocatedImage>> = decoded_images
+            .into_iter()
             .enumerate()
-            .map(|(i, img_data)| {
-                load_image(commands, device.clone(), allocator.clone(), img_data)
-                    .map(Rc::new)
-                    .unwrap_or_else(|err| {
-                        eprintln!("Failed to load image #{i}, using default : {err}");
-                        textures.error_checkerboard.clone()
-                    })
+            .map(|(i, image)| match image {
+                Some(Ok(texture)) => {
+                    Rc::new(batch.texture(device.clone(), &texture, vk::ImageUsageFlags::SAMPLED))
+                }
+                Some(Err(err)) => {
+                    eprintln!("Failed to load image #{i}, using default : {err}");
+                    textures.error_checkerboard.clone()
+                }
+                None => textures.error_checkerboard.clone(),
             })
             .collect();
 
-        let (material_data_buffer, materials_vec, materials) = load_materials(
-            allocator,
-            textures,
-            &document,
-            &images[..],
-            &samplers[..],
-            materials_len,
-            &mut descriptor_pool,
-        );
+        let bindless_textures = load_textures(textures, &document, &images[..], &samplers[..]);
+        let (materials_vec, materials) = load_materials(textures, &document, &bindless_textures);
+        let bindless_materials = materials_vec.iter().map(|m| m.material_id).collect();
 
-        let (meshes_vec, meshes) =
-            load_meshes(&device, commands, &document, buffers, materials_vec);
+        let (meshes_vec, meshes) = load_meshes(&device, &mut batch, meshes, &materials_vec);
+        batch.submit(commands);
 
         let mut nodes = HashMap::new();
-        let nodes_vec: Vec<Rc<RefCell<dyn Node>>> = document
-            .nodes()
-            .map(|node| {
-                let new_node: Rc<RefCell<dyn Node>> = if let Some(mesh) = node.mesh() {
+        let mut instance_nodes: Vec<Rc<RefCell<dyn Node>>> = Vec::new();
+        let nodes_vec: Vec<Rc<RefCell<dyn Node>>> = zip(document.nodes(), instances)
+            .map(|(node, instances)| {
+                let new_node: Rc<RefCell<dyn Node>> = if let Some(mesh) = node.mesh()
+                    && instances.is_none()
+                {
                     Rc::new(RefCell::new(MeshNode::from(
                         meshes_vec[mesh.index()].clone(),
                     )))
@@ -265,6 +451,23 @@ impl LoadedGLTF {
                 new_node.borrow_mut().node_data_mut().local_transform =
                     Mat4::from_cols_array_2d(&node.transform().matrix());
 
+                // Children sharing the mesh, grouped again in instanced draws.
+                if let (Some(mesh), Some(instances)) = (node.mesh(), instances) {
+                    let children = instances.into_iter().map(|transform| {
+                        let mut instance = MeshNode::from(meshes_vec[mesh.index()].clone());
+                        instance.node_data_mut().local_transform = transform;
+                        instance.node_data_mut().parent = Rc::downgrade(&new_node);
+                        Rc::new(RefCell::new(instance)) as Rc<RefCell<dyn Node>>
+                    });
+                    let first = instance_nodes.len();
+                    instance_nodes.extend(children);
+                    new_node
+                        .borrow_mut()
+                        .node_data_mut()
+                        .children
+                        .extend_from_slice(&instance_nodes[first..]);
+                }
+
                 new_node
             })
             .collect();
@@ -283,6 +486,12 @@ impl LoadedGLTF {
                 }));
         });
 
+        let transform_epoch = TransformEpoch::default();
+        nodes_vec
+            .iter()
+            .chain(instance_nodes.iter())
+            .for_each(|n| n.borrow_mut().node_data_mut().transform_epoch = transform_epoch.clone());
+
         // Searching for parent-less nodes
         let top_nodes = nodes_vec
             .iter()
@@ -291,6 +500,17 @@ impl LoadedGLTF {
             .inspect(|n| n.borrow_mut().refresh_transform(&Mat4::IDENTITY))
             .collect();
 
+        let bvh_surfaces: Vec<(Rc<RefCell<dyn Node>>, usize)> = nodes_vec
+            .iter()
+            .chain(instance_nodes.iter())
+            .flat_map(|n| {
+                let surfaces_len = n.borrow().mesh().map_or(0, |m| m.surfaces.len());
+                (0..surfaces_len).map(|i| (n.clone(), i))
+            })
+            .collect();
+        let bvh_epoch = transform_epoch.get();
+        let bvh = Bvh::build(surfaces_bounds(&bvh_surfaces));
+
         Self {
             device_copy: device.clone(),
             meshes,
@@ -298,11 +518,105 @@ impl LoadedGLTF {
             images,
             materials,
             top_nodes,
+            bvh,
+            bvh_surfaces,
+            transform_epoch,
+            bvh_epoch,
+            highlighted: Default::default(),
             samplers,
-            descriptor_pool,
-            material_data_buffer,
+            bindless_textures,
+            bindless_materials,
         }
     }
+
+    /// Slots in the bindless table, to free when unloading.
+    pub fn bindless_slots(&self) -> (&[u32], &[u32]) {
+        (&self.bindless_textures, &self.bindless_materials)
+    }
+}
+
+impl LoadedGLTF {
+    fn refit_bvh_if_needed(&mut self) {
+        let epoch = self.transform_epoch.get();
+        if epoch != self.bvh_epoch {
+            self.bvh_epoch = epoch;
+            self.bvh.refit(surfaces_bounds(&self.bvh_surfaces));
+        }
+    }
+
+    /// World space box around every surface.
+    pub fn bounds(&mut self) -> Aabb {
+        self.refit_bvh_if_needed();
+        self.bvh.bounds()
+    }
+
+    /// Like [`Renderable::draw`] but only with the surfaces in the `frustum`.
+    pub fn draw_visible(&mut self, frustum: &Frustum, ctx: &mut DrawContext) {
+        self.refit_bvh_if_needed();
+
+        self.bvh.query_frustum(frustum, |i| {
+            let (node, s_index) = &self.bvh_surfaces[i];
+            let node = node.borrow();
+            let mesh = node.mesh().unwrap();
+            ctx.push_surface(
+                mesh,
+                &mesh.surfaces[*s_index],
+                node.node_data().world_transform,
+                node.node_data().highlighted,
+            );
+        });
+    }
+
+    /// Name of the node with the closest triangle hit by the `ray`, or of its closest named
+    /// parent.
+    pub fn pick(&mut self, ray: &Ray) -> Option<String> {
+        self.refit_bvh_if_needed();
+
+        let (i, _) = self.bvh.query_ray(ray, f32::INFINITY, |i, _| {
+            let (node, s_index) = &self.bvh_surfaces[i];
+            let node = node.borrow();
+            let mesh = node.mesh().unwrap();
+            let local_ray = ray.transformed(&node.node_data().world_transform.inverse());
+            mesh.intersect_surface(&mesh.surfaces[*s_index], &local_ray)
+        })?;
+
+        let mut node = Some(self.bvh_surfaces[i].0.clone());
+        while let Some(n) = node {
+            if let Some((name, _)) = self.nodes.iter().find(|(_, named)| Rc::ptr_eq(named, &n)) {
+                return Some(name.clone());
+            }
+            node = n.borrow().node_data().parent.upgrade();
+        }
+        None
+    }
+
+    /// Highlights the node (and its children) named `node_name`, and only it.
+    pub fn highlight(&mut self, node_name: &str) {
+        if self.highlighted == node_name {
+            return;
+        }
+        if let Some(previous) = self.nodes.get(&self.highlighted) {
+            previous.borrow_mut().node_data_mut().set_highlighted(false);
+        }
+        if let Some(node) = self.nodes.get(node_name) {
+            node.borrow_mut().node_data_mut().set_highlighted(true);
+        }
+        self.highlighted = node_name.into();
+    }
+}
+
+fn surfaces_bounds(surfaces: &[(Rc<RefCell<dyn Node>>, usize)]) -> Vec<Aabb> {
+    surfaces
+        .iter()
+        .map(|(node, s_index)| {
+            let node = node.borrow();
+            let mesh = node.mesh().unwrap();
+            Aabb::from_bounds(
+                &mesh.surfaces[*s_index].bounds,
+                &node.node_data().world_transform,
+            )
+        })
+        .collect()
 }
 
 fn extract_mag_filter(filter: Option<MagFilter>) -> vk::Filter {
@@ -333,264 +647,215 @@ fn extract_mipmap_mode(filter: Option<MinFilter>) -> vk::SamplerMipmapMode {
     }
 }
 
-fn load_materials(
-    allocator: Arc<Mutex<vk_mem::Allocator>>,
+/// Each glTF texture once in the bindless table, materials share them.
+fn load_textures(
     textures: &mut Textures,
     document: &Document,
     images: &[Rc<AllocatedImage>],
     samplers: &[vk::Sampler],
-    materials_len: usize,
-    descriptor_pool: &mut DescriptorAllocatorGrowable,
+) -> Vec<u32> {
+    document
+        .textures()
+        .map(|texture| {
+            let image =
+                texture_image(&texture).map_or(&textures.error_checkerboard, |i| &images[i]);
+            textures.metal_rough_material.bindless.add_texture(
+                image,
+                texture
+                    .sampler()
+                    .index()
+                    .map_or(textures.default_sampler_linear, |i| samplers[i]),
+            )
+        })
+        .collect()
+}
+
+fn load_materials(
+    textures: &mut Textures,
+    document: &Document,
+    bindless_textures: &[u32],
 ) -> (
-    AllocatedBuffer,
     Vec<Rc<MaterialInstance>>,
     HashMap<String, Rc<MaterialInstance>>,
 ) {
-    let material_data_buffer_size = (size_of::<MaterialConstants>() * materials_len) as u64;
-    let material_data_buffer = AllocatedBuffer::new(
-        allocator,
-        material_data_buffer_size,
-        vk::BufferUsageFlags::UNIFORM_BUFFER,
-        MyMemoryUsage::CpuToGpu,
-    );
-
-    let data = material_data_buffer.mapped_data();
-    // TODO: alignment ?
-    let scene_material_constants: &mut [MaterialConstants] =
-        unsafe { std::slice::from_raw_parts_mut(data as *mut MaterialConstants, materials_len) };
-
     let mut materials = HashMap::new();
-    let materials_vec: Vec<Rc<MaterialInstance>> =
-        zip(scene_material_constants.iter_mut(), document.materials())
-            .map(|(buf_slot, mat)| {
-                let pbr_data = mat.pbr_metallic_roughness();
-                *buf_slot = MaterialConstants {
-                    color_factors: pbr_data.base_color_factor().into(),
-                    metal_rough_factors: vec4(
-                        pbr_data.metallic_factor(),
-                        pbr_data.roughness_factor(),
-                        0.,
-                        0.,
-                    ),
-                };
-
-                let pass_type = if mat.alpha_mode() == AlphaMode::Blend {
-                    MaterialPass::Transparent
-                } else {
-                    MaterialPass::MainColor
-                };
+    let materials_vec: Vec<Rc<MaterialInstance>> = document
+        .materials()
+        .map(|mat| {
+            let pbr_data = mat.pbr_metallic_roughness();
+
+            let pass_type = if mat.alpha_mode() == AlphaMode::Blend {
+                MaterialPass::Transparent
+            } else {
+                MaterialPass::MainColor
+            };
+
+            let color_tex = pbr_data
+                .base_color_texture()
+                .map_or(textures.white_texture, |bct| {
+                    bindless_textures[bct.texture().index()]
+                });
+            let metal_rough_tex = pbr_data
+                .metallic_roughness_texture()
+                .map_or(textures.white_texture, |mrt| {
+                    bindless_textures[mrt.texture().index()]
+                });
+
+            let constants = MaterialConstants {
+                color_factors: pbr_data.base_color_factor().into(),
+                metal_rough_factors: vec4(
+                    pbr_data.metallic_factor(),
+                    pbr_data.roughness_factor(),
+                    0.,
+                    0.,
+                ),
+                color_tex,
+                metal_rough_tex,
+            };
+
+            let new_mat = Rc::new(
+                textures
+                    .metal_rough_material
+                    .write_material(pass_type, constants),
+            );
 
-                let (color_img, color_sampler) = if let Some(bct) = pbr_data.base_color_texture() {
-                    let texture = bct.texture();
-                    (
-                        &images[texture.source().index()],
-                        samplers[texture.sampler().index().unwrap_or(0)],
-                    )
-                } else {
-                    (&textures.white, textures.default_sampler_linear)
-                };
+            if let Some(name) = mat.name() {
+                materials.insert(name.into(), new_mat.clone());
+            }
 
-                let material_resources = MaterialResources {
-                    // TODO default values ?
-                    color_img,
-                    color_sampler,
-                    metal_rough_img: &textures.white,
-                    metal_rough_sampler: textures.default_sampler_linear,
-                    data_buffer: material_data_buffer.buffer,
-                    data_buffer_offset: (buf_slot as *const MaterialConstants) as u32 - data as u32,
-                };
+            new_mat
+        })
+        .collect();
 
-                let new_mat = textures.metal_rough_material.write_material(
-                    pass_type,
-                    &material_resources,
-                    descriptor_pool,
-                );
+    (materials_vec, materials)
+}
 
-                let new_mat = Rc::new(new_mat);
+/// Reads the primitives of `mesh` and simplifies them, on the worker thread.
+fn read_mesh(mesh: &gltf::Mesh, buffers: &[buffer::Data]) -> MeshData {
+    let mut indices = Vec::new();
+    let mut vertices = Vec::new();
+    let mut surfaces = mesh
+        .primitives()
+        .filter_map(|p| p.indices().map(|i| (p, i)))
+        .map(|(primitive, index_accessor)| {
+            let start_index = indices.len() as u32;
+            indices.reserve(index_accessor.count());
+
+            let initial_vtx = vertices.len();
+            let (primitive_vertices, primitive_indices) = read_primitive(&primitive, buffers);
+            vertices.extend(primitive_vertices);
+            indices.extend(primitive_indices.iter().map(|i| i + initial_vtx as u32));
+            let count = indices.len() - start_index as usize;
 
-                if let Some(name) = mat.name() {
-                    materials.insert(name.into(), new_mat.clone());
-                }
+            if OVERRIDE_COLORS {
+                vertices[initial_vtx..]
+                    .iter_mut()
+                    .for_each(|v| v.color = v.normal.extend(1.));
+            }
 
-                new_mat
-            })
-            .collect();
+            SurfaceData {
+                start_index,
+                count: count as u32,
+                material: primitive.material().index().unwrap_or(0),
+                bounds: Bounds::from_vertices(&vertices[initial_vtx..]),
+                lods: Vec::new(),
+            }
+        })
+        .collect::<Vec<_>>();
+    surfaces.iter_mut().for_each(|s| {
+        s.lods = lod::generate_lods(
+            &vertices[..],
+            &mut indices,
+            s.start_index as usize,
+            s.count as usize,
+        );
+    });
 
-    (material_data_buffer, materials_vec, materials)
+    MeshData {
+        name: mesh.name().map(String::from),
+        vertices,
+        indices,
+        surfaces,
+    }
 }
 
+/// Only records the copies of the buffers, the meshes are processed by [`read_mesh`].
 fn load_meshes(
     device: &Device,
-    commands: &VulkanCommands,
-    document: &Document,
-    buffers: Vec<buffer::Data>,
-    materials_vec: Vec<Rc<MaterialInstance>>,
+    batch: &mut UploadBatch,
+    meshes_data: Vec<MeshData>,
+    materials_vec: &[Rc<MaterialInstance>],
 ) -> (Vec<Rc<MeshAsset>>, HashMap<String, Rc<MeshAsset>>) {
     let mut meshes = HashMap::new();
-    let meshes_vec = {
-        // In common to prevent reallocating much
-        let mut indices = Vec::new();
-        let mut vertices = Vec::new();
-        document
-            .meshes()
-            .map(|mesh| {
-                indices.clear();
-                vertices.clear();
-
-                let surfaces = mesh
-                    .primitives()
-                    .filter_map(|p| p.indices().map(|i| (p, i)))
-                    .map(|(primitive, index_accessor)| {
-                        let start_index = indices.len() as u32;
-                        let count = index_accessor.count();
-
-                        let initial_vtx = vertices.len();
-                        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-                        indices.reserve(count);
-                        if let Some(iter) = reader.read_indices() {
-                            indices.extend(iter.into_u32().map(|i| i + initial_vtx as u32));
-                        }
-
-                        if let Some(pos_accessor) = primitive.get(&gltf::Semantic::Positions) {
-                            vertices.reserve(pos_accessor.count());
-                            if let Some(iter) = reader.read_positions() {
-                                vertices.extend(iter.map(|v| Vertex {
-                                    position: Vec3::from_array(v),
-                                    ..Default::default()
-                                }));
-                            }
-                        }
-
-                        if let Some(iter) = reader.read_normals() {
-                            iter.enumerate().for_each(|(i, n)| {
-                                vertices[initial_vtx + i].normal = Vec3::from_array(n)
-                            });
-                        }
-
-                        if let Some(iter) = reader.read_tex_coords(0) {
-                            iter.into_f32().enumerate().for_each(|(i, c)| {
-                                let v = &mut vertices[initial_vtx + i];
-                                v.uv_x = c[0];
-                                v.uv_y = c[1];
-                            });
-                        }
-
-                        if OVERRIDE_COLORS {
-                            vertices[initial_vtx..]
-                                .iter_mut()
-                                .for_each(|v| v.color = v.normal.extend(1.));
-                        } else if let Some(iter) = reader.read_colors(0) {
-                            iter.into_rgba_f32().enumerate().for_each(|(i, c)| {
-                                vertices[initial_vtx + i].color = Vec4::from_array(c)
-                            });
-                        }
-
-                        GeoSurface {
-                            start_index,
-                            count: count as u32,
-                            material: materials_vec[primitive.material().index().unwrap_or(0)]
-                                .clone(),
+    let meshes_vec = meshes_data
+        .into_iter()
+        .map(|mesh| {
+            let surfaces = mesh
+                .surfaces
+                .into_iter()
+                .map(|s| GeoSurface {
+                    start_index: s.start_index,
+                    count: s.count,
+                    material: materials_vec[s.material].clone(),
+                    bounds: s.bounds,
+                    lods: s.lods,
+                })
+                .collect();
 
-                            bounds: Bounds::from_vertices(&vertices[initial_vtx..]),
-                        }
-                    })
-                    .collect();
-
-                let mesh_buffers =
-                    GpuMeshBuffers::new(device, commands, &indices[..], &vertices[..]);
-                let new_mesh = Rc::new(MeshAsset::new(
-                    mesh.name().map(String::from),
-                    surfaces,
-                    mesh_buffers,
-                ));
-
-                if let Some(name) = mesh.name().map(String::from) {
-                    meshes.insert(name, new_mesh.clone());
-                }
+            let mesh_buffers = GpuMeshBuffers::new(device, batch, &mesh.indices, &mesh.vertices);
+            let new_mesh = Rc::new(MeshAsset::new(
+                mesh.name.clone(),
+                surfaces,
+                mesh_buffers,
+                mesh.vertices.iter().map(|v| v.position).collect(),
+                mesh.indices,
+            ));
+
+            if let Some(name) = mesh.name {
+                meshes.insert(name, new_mesh.clone());
+            }
 
-                new_mesh
-            })
-            .collect()
-    };
+            new_mesh
+        })
+        .collect();
 
     (meshes_vec, meshes)
 }
 
-fn load_image(
-    commands: &VulkanCommands,
-    device: Rc<Device>,
-    allocator: Arc<Mutex<Allocator>>,
-    image: image::Data,
-) -> Result<AllocatedImage, String> {
-    let extent = vk::Extent3D {
-        width: image.width,
-        height: image.height,
-        depth: 1,
-    };
-
-    let (format, data) = match image.format {
-        image::Format::R8 => {
-            println!(
-                "Image has format {:?}, converting it to R8G8B8A8.",
-                image.format
-            );
-            let pixels = DynamicImage::ImageLuma8(
-                GrayImage::from_raw(image.width, image.height, image.pixels).unwrap(),
-            )
-            .to_rgba8()
-            .into_raw();
+/// The image sampled by the texture : the KTX2 one of `KHR_texture_basisu`, or the fallback.
+fn texture_image(texture: &gltf::Texture) -> Option<usize> {
+    texture
+        .extension_value(KTX2_EXTENSION)
+        .and_then(|ext| ext.get("source")?.as_u64())
+        .map(|i| i as usize)
+        .or_else(|| texture.source().map(|image| image.index()))
+}
 
-            (vk::Format::R8G8B8A8_UNORM, pixels)
-        }
-        // Not supported for other operations, needs converting to RGBA8
-        image::Format::R8G8B8 => {
-            let pixels = DynamicImage::ImageRgb8(
-                RgbImage::from_raw(image.width, image.height, image.pixels).unwrap(),
+/// In a format sampled by the GPU, `srgb` for colour textures.
+fn decode_image(
+    image: &image::Image,
+    base: &Path,
+    buffers: &[buffer::Data],
+    srgb: bool,
+    support: &FormatSupport,
+) -> Result<TextureData, String> {
+    match image.source() {
+        image::Source::View { view, mime_type } if mime_type == KTX2_MIME => {
+            let buffer = &buffers[view.buffer().index()];
+            texture_data::from_ktx2(
+                &buffer[view.offset()..view.offset() + view.length()],
+                srgb,
+                support,
             )
-            .to_rgba8()
-            .into_raw();
-
-            (vk::Format::R8G8B8A8_UNORM, pixels)
         }
-        image::Format::R8G8B8A8 => (vk::Format::R8G8B8A8_UNORM, image.pixels),
-        // image::Format::R8 => vk::Format::R8_UNORM,
-        // image::Format::R8G8 => vk::Format::R8G8_UNORM,
-        // image::Format::R16 => vk::Format::R16_UNORM,
-        // image::Format::R16G16 => vk::Format::R16G16_UNORM,
-        // image::Format::R16G16B16 => vk::Format::R16G16B16_UNORM,
-        // image::Format::R16G16B16A16 => vk::Format::R16G16B16A16_UNORM,
-        // image::Format::R32G32B32FLOAT => vk::Format::R32G32B32_SFLOAT,
-        // image::Format::R32G32B32A32FLOAT => vk::Format::R32G32B32A32_SFLOAT,
-        _ => return Err(format!("Unsupported image format : {:?} !", image.format)),
-    };
-
-    /*
-    // From [`gltf::image::Data`] : file:///home/Guillaume.Goni/workspace/rust/graphics/target/doc/src/gltf/image.rs.html#85-97
-    // Conversion from `image` crate format :
-    let format = match image {
-        DynamicImage::ImageLuma8(_) => Format::R8,
-        DynamicImage::ImageLumaA8(_) => Format::R8G8,
-        DynamicImage::ImageRgb8(_) => Format::R8G8B8,
-        DynamicImage::ImageRgba8(_) => Format::R8G8B8A8,
-        DynamicImage::ImageLuma16(_) => Format::R16,
-        DynamicImage::ImageLumaA16(_) => Format::R16G16,
-        DynamicImage::ImageRgb16(_) => Format::R16G16B16,
-        DynamicImage::ImageRgba16(_) => Format::R16G16B16A16,
-        DynamicImage::ImageRgb32F(_) => Format::R32G32B32FLOAT,
-        DynamicImage::ImageRgba32F(_) => Format::R32G32B32A32FLOAT,
-        image => return Err(Error::UnsupportedImageFormat(image)),
-    };
-    */
-
-    Ok(AllocatedImage::new_and_upload(
-        commands,
-        device,
-        allocator,
-        extent,
-        format,
-        vk::ImageUsageFlags::SAMPLED,
-        true,
-        &data[..],
-    ))
+        image::Source::Uri { uri, mime_type }
+            if mime_type == Some(KTX2_MIME) || uri.ends_with(".ktx2") =>
+        {
+            let bytes = fs::read(base.join(uri)).map_err(|e| format!("{uri} : {e}"))?;
+            texture_data::from_ktx2(&bytes, srgb, support)
+        }
+        source => image::Data::from_source(source, Some(base), buffers)
+            .map_err(|e| e.to_string())
+            .and_then(|data| texture_data::from_gltf(data, srgb, support)),
+    }
 }
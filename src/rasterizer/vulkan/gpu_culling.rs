@@ -0,0 +1,480 @@
+//! GPU-driven rendering.
+//!
+//! Every [`RenderObject`] is uploaded to the ring of the frame, then a compute shader culls them against
+//! the frustum and the depth pyramid of the previous frame. The visible ones are written as
+//! [`vk::DrawIndexedIndirectCommand`]s, one range per [`DrawBatch`], drawn with
+//! `cmd_draw_indexed_indirect_count`.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+use glam::{Mat4, Vec4};
+
+use super::{
+    FrustumCulling, VulkanSettings,
+    allocated::{AllocatedBuffer, AllocatedImage, MyMemoryUsage},
+    commands::image_subresource_range,
+    compute_shaders::compute_pipeline,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    frame_ring::RingSegment,
+    gfx_pipeline::GpuDrawPushConstants,
+    scene::{DrawContext, RenderObject},
+    shaders_loader::{ShaderName, ShadersLoader},
+    textures::MaterialInstance,
+};
+use crate::scene::bvh::Frustum;
+
+/// See `local_size_x` in `resources/cull_objects.comp.glsl`.
+pub const CULL_GROUP_SIZE: u32 = 64;
+/// See `local_size_x` and `local_size_y` in `resources/depth_reduce.comp.glsl`.
+pub const REDUCE_GROUP_SIZE: u32 = 16;
+
+/// A [`RenderObject`], see `DrawObject` in `resources/draw_objects.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct GpuObject {
+    world_mat: Mat4,
+    bounds_origin: Vec4,
+    bounds_extents: Vec4,
+    vertex_buffer: vk::DeviceAddress,
+    mesh_id: u32,
+    surface_id: u32,
+    lod: u32,
+    highlighted: u32,
+    /// Index of its [`DrawBatch`], and so of its draw count.
+    batch: u32,
+    /// First command slot of its [`DrawBatch`].
+    first_command: u32,
+    first_index: u32,
+    index_count: u32,
+    material_id: u32,
+}
+
+impl GpuObject {
+    /// `batch` and `first_command` are only read by the culling.
+    pub fn new(d: &RenderObject, batch: u32, first_command: u32) -> Self {
+        let push_constants = GpuDrawPushConstants::from(d);
+        let bounds = d.bounds();
+        GpuObject {
+            world_mat: push_constants.world_mat,
+            bounds_origin: bounds.origin.extend(1.),
+            bounds_extents: bounds.extents.extend(0.),
+            vertex_buffer: push_constants.vertex_buffer,
+            mesh_id: push_constants.mesh_id,
+            surface_id: push_constants.surface_id,
+            lod: push_constants.lod,
+            highlighted: push_constants.highlighted,
+            batch,
+            first_command,
+            first_index: d.first_index,
+            index_count: d.index_count,
+            material_id: push_constants.material_id,
+        }
+    }
+}
+
+/// See `CullData` in `resources/cull_objects.comp.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+struct GpuCullData {
+    view_proj: Mat4,
+    frustum_planes: [Vec4; 6],
+    pyramid_size: [u32; 2],
+    object_count: u32,
+    /// Booleans.
+    frustum: u32,
+    occlusion: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct CullPushConstants {
+    cull_data: vk::DeviceAddress,
+    objects: vk::DeviceAddress,
+    commands: vk::DeviceAddress,
+    counts: vk::DeviceAddress,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ReducePushConstants {
+    pub src_size: [u32; 2],
+    pub dst_size: [u32; 2],
+}
+
+/// Objects sharing their pipeline and index buffer, drawn with a single indirect draw.
+pub struct DrawBatch {
+    /// First material of the batch, for its pipeline.
+    pub material: Rc<MaterialInstance>,
+    pub index_buffer: vk::Buffer,
+    /// Range of the commands in [`IndirectDraws::commands`].
+    pub first_command: u32,
+    pub max_count: u32,
+}
+
+/// The indirect commands and counts of a frame in flight, kept between its renders and only
+/// allocated again when they are too small.
+pub struct IndirectBuffers {
+    allocator_copy: Arc<Mutex<vk_mem::Allocator>>,
+
+    /// With their size.
+    commands: Option<(u64, AllocatedBuffer)>,
+    counts: Option<(u64, AllocatedBuffer)>,
+}
+
+impl IndirectBuffers {
+    pub fn new(allocator: Arc<Mutex<vk_mem::Allocator>>) -> Self {
+        Self {
+            allocator_copy: allocator,
+            commands: None,
+            counts: None,
+        }
+    }
+}
+
+/// Grown to the next power of two, not to allocate again when a few objects are added.
+fn reserve<'a>(
+    buffer: &'a mut Option<(u64, AllocatedBuffer)>,
+    allocator: &Arc<Mutex<vk_mem::Allocator>>,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+) -> &'a AllocatedBuffer {
+    if buffer.as_ref().is_none_or(|(capacity, _)| *capacity < size) {
+        let capacity = size.next_power_of_two();
+        let allocated =
+            AllocatedBuffer::new(allocator.clone(), capacity, usage, MyMemoryUsage::GpuOnly);
+        *buffer = Some((capacity, allocated));
+    }
+    &buffer.as_ref().unwrap().1
+}
+
+/// Draws of a frame, in the [`IndirectBuffers`] of its frame in flight.
+///
+/// The objects and the culling data are in the ring of the frame, see `FrameData::ring`.
+pub struct IndirectDraws {
+    /// Opaque ones first, the transparent ones don't write to the depth buffer.
+    pub batches: Vec<DrawBatch>,
+    pub object_count: u32,
+    pub objects_address: vk::DeviceAddress,
+    pub push_constants: CullPushConstants,
+
+    pub commands: vk::Buffer,
+    /// One draw count per batch, cleared before culling.
+    pub counts: vk::Buffer,
+}
+
+impl IndirectDraws {
+    pub const COMMAND_STRIDE: u32 = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+    /// Bins the objects of `draw_ctx` and uploads them.
+    ///
+    /// The depth pyramid is only used with `occlusion_culling` if it was built by a previous frame.
+    pub fn new(
+        device: &Device,
+        buffers: &mut IndirectBuffers,
+        ring: &RingSegment,
+        gpu_culling: &GpuCulling,
+        draw_ctx: &DrawContext,
+        view_proj: &Mat4,
+        settings: &VulkanSettings,
+    ) -> Self {
+        let mut batches = Vec::new();
+        let mut objects = Vec::with_capacity(
+            draw_ctx.opaque_surfaces.len() + draw_ctx.transparent_surfaces.len(),
+        );
+        [&draw_ctx.opaque_surfaces, &draw_ctx.transparent_surfaces]
+            .into_iter()
+            .for_each(|surfaces| {
+                // Same order as `MeshSorting::Binding`, so a batch is contiguous.
+                let mut sorted: Vec<&RenderObject> = surfaces.iter().collect();
+                sorted.sort_by(|a, b| {
+                    a.material
+                        .pipeline_ptr()
+                        .cmp(&b.material.pipeline_ptr())
+                        .then(a.index_buffer.cmp(&b.index_buffer))
+                });
+                sorted
+                    .chunk_by(|a, b| {
+                        a.material.pipeline_ptr() == b.material.pipeline_ptr()
+                            && a.index_buffer == b.index_buffer
+                    })
+                    .for_each(|chunk| {
+                        let batch = batches.len() as u32;
+                        let first_command = objects.len() as u32;
+                        objects.extend(
+                            chunk
+                                .iter()
+                                .map(|d| GpuObject::new(d, batch, first_command)),
+                        );
+                        batches.push(DrawBatch {
+                            material: chunk[0].material.clone(),
+                            index_buffer: chunk[0].index_buffer,
+                            first_command,
+                            max_count: chunk.len() as u32,
+                        });
+                    });
+            });
+
+        let storage =
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        let object_count = objects.len() as u32;
+        let objects_address = ring.push_slice(&objects[..]).address;
+
+        // Nothing to read at the first frame.
+        let frustum = settings.frustum_culling != FrustumCulling::Off;
+        let occlusion = settings.occlusion_culling && gpu_culling.is_pyramid_built();
+        let cull_data = ring.push(&GpuCullData {
+            view_proj: *view_proj,
+            frustum_planes: *Frustum::from_view_proj(view_proj).planes(),
+            pyramid_size: [
+                gpu_culling.pyramid_extent.width,
+                gpu_culling.pyramid_extent.height,
+            ],
+            object_count,
+            frustum: frustum as u32,
+            occlusion: occlusion as u32,
+        });
+
+        let commands = reserve(
+            &mut buffers.commands,
+            &buffers.allocator_copy,
+            (Self::COMMAND_STRIDE as usize * objects.len().max(1)) as u64,
+            storage | vk::BufferUsageFlags::INDIRECT_BUFFER,
+        );
+        let commands_address = commands.device_address(device);
+        let commands = commands.buffer;
+        let counts = reserve(
+            &mut buffers.counts,
+            &buffers.allocator_copy,
+            (size_of::<u32>() * batches.len().max(1)) as u64,
+            storage | vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        );
+        let counts_address = counts.device_address(device);
+        let counts = counts.buffer;
+
+        let push_constants = CullPushConstants {
+            cull_data: cull_data.address,
+            objects: objects_address,
+            commands: commands_address,
+            counts: counts_address,
+        };
+
+        Self {
+            batches,
+            object_count,
+            objects_address,
+            push_constants,
+            commands,
+            counts,
+        }
+    }
+}
+
+/// Culling compute pipeline and the depth pyramid it reads.
+///
+/// The pyramid is built from the depth image at the end of a frame, each texel keeping the
+/// farthest depth of the ones it covers. Needs to be recreated with the depth image.
+pub struct GpuCulling {
+    device_copy: Rc<Device>,
+
+    _descriptor: DescriptorAllocator,
+    sampler: vk::Sampler,
+
+    pyramid: AllocatedImage,
+    pub pyramid_extent: vk::Extent2D,
+    /// One view per mip, the image view covers all of them.
+    pyramid_mips: Vec<vk::ImageView>,
+    /// Set once the pyramid is filled, it can be used by the next frame.
+    pyramid_built: RefCell<bool>,
+
+    reduce_desc_layout: vk::DescriptorSetLayout,
+    /// Reduces the mip `i - 1` (or the depth image) into the mip `i`.
+    pub reduce_descs: Vec<vk::DescriptorSet>,
+    pub reduce_layout: vk::PipelineLayout,
+    pub reduce_pipeline: vk::Pipeline,
+
+    cull_desc_layout: vk::DescriptorSetLayout,
+    pub cull_desc: vk::DescriptorSet,
+    pub cull_layout: vk::PipelineLayout,
+    pub cull_pipeline: vk::Pipeline,
+}
+
+impl GpuCulling {
+    pub fn new(
+        device: Rc<Device>,
+        shaders: &ShadersLoader,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        depth_img_view: vk::ImageView,
+        depth_extent: vk::Extent3D,
+    ) -> Self {
+        // Previous power of 2, so each level is exactly half of the previous one.
+        let pyramid_extent = vk::Extent2D {
+            width: 1 << depth_extent.width.max(1).ilog2(),
+            height: 1 << depth_extent.height.max(1).ilog2(),
+        };
+        let pyramid = AllocatedImage::new_depth_pyramid(device.clone(), allocator, pyramid_extent);
+        let mip_levels = pyramid_extent.width.max(pyramid_extent.height).ilog2() + 1;
+        let pyramid_mips: Vec<vk::ImageView> = (0..mip_levels)
+            .map(|level| {
+                let create_info = vk::ImageViewCreateInfo::default()
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(pyramid.format)
+                    .subresource_range(image_subresource_range(
+                        vk::ImageAspectFlags::COLOR,
+                        1,
+                        level,
+                    ))
+                    .image(pyramid.img);
+                unsafe { device.create_image_view(&create_info, None).unwrap() }
+            })
+            .collect();
+
+        // Only read with `texelFetch`.
+        let sampler = {
+            let create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(vk::LOD_CLAMP_NONE);
+            unsafe { device.create_sampler(&create_info, None).unwrap() }
+        };
+
+        let sizes = [
+            (vk::DescriptorType::STORAGE_IMAGE, 1.),
+            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1.),
+        ];
+        let descriptor = DescriptorAllocator::new(device.clone(), mip_levels + 1, &sizes[..]);
+
+        let reduce_desc_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::STORAGE_IMAGE)
+            .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .build(&device, vk::ShaderStageFlags::COMPUTE);
+        let reduce_descs = pyramid_mips
+            .iter()
+            .enumerate()
+            .map(|(level, mip)| {
+                let desc = descriptor.allocate(reduce_desc_layout);
+                let (src, src_layout) = if level == 0 {
+                    (depth_img_view, vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL)
+                } else {
+                    (pyramid_mips[level - 1], vk::ImageLayout::GENERAL)
+                };
+                let mut writer = DescriptorWriter::default();
+                writer.write_image(
+                    0,
+                    *mip,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::GENERAL,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                );
+                writer.write_image(
+                    1,
+                    src,
+                    sampler,
+                    src_layout,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                );
+                writer.update_set(&device, desc);
+                desc
+            })
+            .collect();
+
+        let cull_desc_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .build(&device, vk::ShaderStageFlags::COMPUTE);
+        let cull_desc = descriptor.allocate(cull_desc_layout);
+        {
+            let mut writer = DescriptorWriter::default();
+            writer.write_image(
+                0,
+                pyramid.img_view,
+                sampler,
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.update_set(&device, cull_desc);
+        }
+
+        let (reduce_layout, reduce_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::DepthReduce,
+            &[reduce_desc_layout],
+            size_of::<ReducePushConstants>(),
+        );
+        let (cull_layout, cull_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::CullObjects,
+            &[cull_desc_layout],
+            size_of::<CullPushConstants>(),
+        );
+
+        Self {
+            device_copy: device,
+            _descriptor: descriptor,
+            sampler,
+            pyramid,
+            pyramid_extent,
+            pyramid_mips,
+            pyramid_built: RefCell::new(false),
+            reduce_desc_layout,
+            reduce_descs,
+            reduce_layout,
+            reduce_pipeline,
+            cull_desc_layout,
+            cull_desc,
+            cull_layout,
+            cull_pipeline,
+        }
+    }
+
+    pub fn pyramid_img(&self) -> vk::Image {
+        self.pyramid.img
+    }
+
+    pub fn mip_extent(&self, level: usize) -> vk::Extent2D {
+        vk::Extent2D {
+            width: (self.pyramid_extent.width >> level).max(1),
+            height: (self.pyramid_extent.height >> level).max(1),
+        }
+    }
+
+    pub fn is_pyramid_built(&self) -> bool {
+        *self.pyramid_built.borrow()
+    }
+
+    pub fn set_pyramid_built(&self, built: bool) {
+        *self.pyramid_built.borrow_mut() = built;
+    }
+}
+
+impl Drop for GpuCulling {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop GpuCulling");
+        unsafe {
+            self.device_copy.destroy_pipeline(self.cull_pipeline, None);
+            self.device_copy
+                .destroy_pipeline_layout(self.cull_layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.cull_desc_layout, None);
+            self.device_copy
+                .destroy_pipeline(self.reduce_pipeline, None);
+            self.device_copy
+                .destroy_pipeline_layout(self.reduce_layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.reduce_desc_layout, None);
+            self.pyramid_mips
+                .drain(..)
+                .for_each(|v| self.device_copy.destroy_image_view(v, None));
+            self.device_copy.destroy_sampler(self.sampler, None);
+        }
+    }
+}
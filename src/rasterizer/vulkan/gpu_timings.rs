@@ -0,0 +1,374 @@
+//! GPU durations of the passes with timestamp queries, and shader invocations with pipeline
+//! statistics queries when the device supports them.
+//!
+//! Every `FrameData` has its own pools, read back when the frame is reused after its fence, so
+//! with `FRAME_OVERLAP` frames of latency.
+
+use std::{cell::Cell, collections::VecDeque, rc::Rc};
+
+use ash::{Device, vk};
+use egui::{Color32, Sense, Shape, Stroke, pos2, vec2};
+
+use super::base::VulkanBase;
+
+/// Frames kept for the graph.
+const HISTORY_LEN: usize = 240;
+const GRAPH_HEIGHT: f32 = 100.;
+const GRAPH_STROKE_WIDTH: f32 = 1.;
+
+/// Only the passes recorded in a frame get a duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPass {
+    Background,
+    Culling,
+    Geometry,
+    /// Of the deferred shading, with its blur.
+    AmbientOcclusion,
+    /// Of the deferred shading.
+    Lighting,
+    /// Drawn after the deferred lighting.
+    Transparent,
+    DepthPyramid,
+    PostProcess,
+    Gui,
+}
+
+impl GpuPass {
+    const ALL: [GpuPass; 9] = [
+        GpuPass::Background,
+        GpuPass::Culling,
+        GpuPass::Geometry,
+        GpuPass::AmbientOcclusion,
+        GpuPass::Lighting,
+        GpuPass::Transparent,
+        GpuPass::DepthPyramid,
+        GpuPass::PostProcess,
+        GpuPass::Gui,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            GpuPass::Background => "Background",
+            GpuPass::Culling => "Culling",
+            GpuPass::Geometry => "Geometry",
+            GpuPass::AmbientOcclusion => "Ambient occlusion",
+            GpuPass::Lighting => "Lighting",
+            GpuPass::Transparent => "Transparent",
+            GpuPass::DepthPyramid => "Depth pyramid",
+            GpuPass::PostProcess => "Post-processing",
+            GpuPass::Gui => "GUI",
+        }
+    }
+
+    fn color(self) -> Color32 {
+        match self {
+            GpuPass::Background => Color32::LIGHT_BLUE,
+            GpuPass::Culling => Color32::YELLOW,
+            GpuPass::Geometry => Color32::LIGHT_GREEN,
+            GpuPass::AmbientOcclusion => Color32::BROWN,
+            GpuPass::Lighting => Color32::GOLD,
+            GpuPass::Transparent => Color32::DARK_GREEN,
+            GpuPass::DepthPyramid => Color32::ORANGE,
+            GpuPass::PostProcess => Color32::LIGHT_RED,
+            GpuPass::Gui => Color32::GRAY,
+        }
+    }
+
+    /// Timestamps at the beginning and at the end.
+    fn queries(self) -> (u32, u32) {
+        let begin = self as u32 * 2;
+        (begin, begin + 1)
+    }
+}
+
+/// One frame, read with [`GpuTimings::read`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GpuFrameTimings {
+    millis: [Option<f32>; GpuPass::ALL.len()],
+    /// Of the geometry pass.
+    vertex_invocations: Option<u64>,
+    fragment_invocations: Option<u64>,
+}
+
+impl GpuFrameTimings {
+    fn total_millis(&self) -> f32 {
+        self.millis.iter().flatten().sum()
+    }
+}
+
+pub struct GpuTimings {
+    device_copy: Rc<Device>,
+
+    /// `None` if the queue doesn't support timestamps.
+    timestamps: Option<vk::QueryPool>,
+    /// `None` without the `pipeline_statistics_query` feature.
+    statistics: Option<vk::QueryPool>,
+    /// Nanoseconds per tick.
+    timestamp_period: f32,
+    timestamp_mask: u64,
+    /// The queries can only be read once reset and written by a submitted frame.
+    recorded: Cell<bool>,
+}
+
+impl GpuTimings {
+    pub fn new(base: &VulkanBase) -> Self {
+        let device = base.device.clone();
+        let limits = unsafe {
+            base.instance
+                .get_physical_device_properties(base.chosen_gpu)
+                .limits
+        };
+        let valid_bits = unsafe {
+            base.instance
+                .get_physical_device_queue_family_properties(base.chosen_gpu)
+                [base.queue_family_index as usize]
+                .timestamp_valid_bits
+        };
+
+        let timestamps = (valid_bits > 0).then(|| {
+            let create_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(GpuPass::ALL.len() as u32 * 2);
+            unsafe { device.create_query_pool(&create_info, None).unwrap() }
+        });
+        let statistics = base.pipeline_statistics.then(|| {
+            let create_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .query_count(1)
+                .pipeline_statistics(
+                    vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS
+                        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS,
+                );
+            unsafe { device.create_query_pool(&create_info, None).unwrap() }
+        });
+
+        Self {
+            device_copy: device,
+            timestamps,
+            statistics,
+            timestamp_period: limits.timestamp_period,
+            timestamp_mask: u64::MAX >> (64 - valid_bits.clamp(1, 64)),
+            recorded: Cell::new(false),
+        }
+    }
+
+    /// At the beginning of the command buffer, before any other query, see `FrameData`.
+    pub fn reset(&self, cmd_buf: vk::CommandBuffer) {
+        unsafe {
+            if let Some(pool) = self.timestamps {
+                self.device_copy.cmd_reset_query_pool(
+                    cmd_buf,
+                    pool,
+                    0,
+                    GpuPass::ALL.len() as u32 * 2,
+                );
+            }
+            if let Some(pool) = self.statistics {
+                self.device_copy.cmd_reset_query_pool(cmd_buf, pool, 0, 1);
+            }
+        }
+        self.recorded.set(true);
+    }
+
+    /// The pipeline statistics are only queried for the geometry pass, outside of the rendering.
+    pub fn begin(&self, cmd_buf: vk::CommandBuffer, pass: GpuPass) {
+        self.write_timestamp(cmd_buf, pass.queries().0);
+        if pass == GpuPass::Geometry
+            && let Some(pool) = self.statistics
+        {
+            unsafe {
+                self.device_copy
+                    .cmd_begin_query(cmd_buf, pool, 0, vk::QueryControlFlags::empty());
+            }
+        }
+    }
+
+    pub fn end(&self, cmd_buf: vk::CommandBuffer, pass: GpuPass) {
+        if pass == GpuPass::Geometry
+            && let Some(pool) = self.statistics
+        {
+            unsafe { self.device_copy.cmd_end_query(cmd_buf, pool, 0) };
+        }
+        self.write_timestamp(cmd_buf, pass.queries().1);
+    }
+
+    /// Waits for all the previous commands, so the passes don't overlap in the timings.
+    fn write_timestamp(&self, cmd_buf: vk::CommandBuffer, query: u32) {
+        if let Some(pool) = self.timestamps {
+            unsafe {
+                self.device_copy.cmd_write_timestamp2(
+                    cmd_buf,
+                    vk::PipelineStageFlags2::ALL_COMMANDS,
+                    pool,
+                    query,
+                );
+            }
+        }
+    }
+
+    /// After the fence of the frame, `None` before its first submit.
+    pub fn read(&self) -> Option<GpuFrameTimings> {
+        if !self.recorded.get() {
+            return None;
+        }
+
+        let mut timings = GpuFrameTimings::default();
+        let flags = vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY;
+
+        if let Some(pool) = self.timestamps {
+            // Value and availability, the passes not recorded are never available.
+            let mut results = [[0_u64; 2]; GpuPass::ALL.len() * 2];
+            query_results(&self.device_copy, pool, &mut results, flags);
+            for pass in GpuPass::ALL {
+                let (begin, end) = pass.queries();
+                let [begin, begin_available] = results[begin as usize];
+                let [end, end_available] = results[end as usize];
+                if begin_available != 0 && end_available != 0 {
+                    let ticks = end.wrapping_sub(begin) & self.timestamp_mask;
+                    timings.millis[pass as usize] =
+                        Some(ticks as f32 * self.timestamp_period / 1_000_000.);
+                }
+            }
+        }
+
+        if let Some(pool) = self.statistics {
+            // In the order of the flags bits, then the availability.
+            let mut results = [[0_u64; 3]];
+            query_results(&self.device_copy, pool, &mut results, flags);
+            let [vertex, fragment, available] = results[0];
+            if available != 0 {
+                timings.vertex_invocations = Some(vertex);
+                timings.fragment_invocations = Some(fragment);
+            }
+        }
+
+        Some(timings)
+    }
+}
+
+impl Drop for GpuTimings {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop GpuTimings");
+        unsafe {
+            self.timestamps
+                .into_iter()
+                .chain(self.statistics)
+                .for_each(|pool| self.device_copy.destroy_query_pool(pool, None));
+        }
+    }
+}
+
+/// Unavailable queries make it return `NOT_READY`, but the other results are still written.
+fn query_results<T>(
+    device: &Device,
+    pool: vk::QueryPool,
+    results: &mut [T],
+    flags: vk::QueryResultFlags,
+) {
+    match unsafe { device.get_query_pool_results(pool, 0, results, flags) } {
+        Ok(()) | Err(vk::Result::NOT_READY) => (),
+        Err(e) => panic!("Error reading queries : {e}"),
+    }
+}
+
+/// Last frames, for the Debug window.
+#[derive(Default)]
+pub struct GpuTimingsHistory {
+    frames: VecDeque<GpuFrameTimings>,
+}
+
+impl GpuTimingsHistory {
+    pub fn push(&mut self, timings: GpuFrameTimings) {
+        if self.frames.len() == HISTORY_LEN {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(timings);
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let Some(last) = self.frames.back() else {
+            ui.label("GPU timings : waiting for the first frames");
+            return;
+        };
+
+        ui.separator();
+        egui::Grid::new("gpu_timings")
+            .num_columns(3)
+            .show(ui, |ui| {
+                ui.label("GPU pass");
+                ui.label("ms");
+                ui.label("avg ms");
+                ui.end_row();
+                for pass in GpuPass::ALL {
+                    let recorded = self.frames.iter().filter_map(|f| f.millis[pass as usize]);
+                    let count = recorded.clone().count();
+                    ui.colored_label(pass.color(), pass.name());
+                    match last.millis[pass as usize] {
+                        Some(millis) => ui.label(format!("{millis:.3}")),
+                        None => ui.label("-"),
+                    };
+                    if count > 0 {
+                        ui.label(format!("{:.3}", recorded.sum::<f32>() / count as f32));
+                    } else {
+                        ui.label("-");
+                    }
+                    ui.end_row();
+                }
+                ui.label("Total");
+                ui.label(format!("{:.3}", last.total_millis()));
+                ui.end_row();
+            });
+
+        if let (Some(vertex), Some(fragment)) = (last.vertex_invocations, last.fragment_invocations)
+        {
+            ui.label(format!(
+                "Geometry invocations : {vertex} vertex, {fragment} fragment"
+            ));
+        }
+
+        self.graph(ui);
+    }
+
+    /// One line per pass, scaled to the slowest frame.
+    fn graph(&self, ui: &mut egui::Ui) {
+        let max_millis = self
+            .frames
+            .iter()
+            .flat_map(|f| f.millis.iter().flatten())
+            .fold(0.1_f32, |max, &m| max.max(m));
+
+        let width = ui.available_width().max(HISTORY_LEN as f32);
+        let (response, painter) = ui.allocate_painter(vec2(width, GRAPH_HEIGHT), Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 2., Color32::from_black_alpha(128));
+
+        let step = rect.width() / (HISTORY_LEN - 1) as f32;
+        for pass in GpuPass::ALL {
+            let points: Vec<_> = self
+                .frames
+                .iter()
+                .enumerate()
+                .filter_map(|(i, f)| {
+                    f.millis[pass as usize].map(|m| {
+                        pos2(
+                            rect.left() + i as f32 * step,
+                            rect.bottom() - m / max_millis * rect.height(),
+                        )
+                    })
+                })
+                .collect();
+            painter.add(Shape::line(
+                points,
+                Stroke::new(GRAPH_STROKE_WIDTH, pass.color()),
+            ));
+        }
+        painter.text(
+            rect.left_top(),
+            egui::Align2::LEFT_TOP,
+            format!("{max_millis:.2} ms"),
+            egui::FontId::monospace(10.),
+            Color32::WHITE,
+        );
+    }
+}
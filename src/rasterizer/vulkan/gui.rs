@@ -24,6 +24,8 @@ struct VulkanGuiMutable {
     state: egui_winit::State,
     info: egui::ViewportInfo,
     renderer: Renderer,
+    /// Of the swapchain the pipeline draws to.
+    format: vk::Format,
     textures_to_free: Option<Vec<TextureId>>,
 }
 
@@ -43,6 +45,22 @@ impl VulkanGui {
         inner.generate(ui)
     }
 
+    /// Rebuilds the pipeline when the swapchain format changed, the previous one must not be in
+    /// use anymore.
+    pub fn set_format(&mut self, format: vk::Format) {
+        let inner = self.inner.get_mut();
+        if inner.format != format {
+            inner
+                .renderer
+                .set_dynamic_rendering(DynamicRendering {
+                    color_attachment_format: format,
+                    depth_attachment_format: None,
+                })
+                .unwrap();
+            inner.format = format;
+        }
+    }
+
     pub fn draw(
         &self,
         queue: vk::Queue,
@@ -55,8 +73,9 @@ impl VulkanGui {
         inner.draw(queue, extent, cmd_pool, cmd_buf, generated_ui);
     }
 
-    pub fn on_window_event(&mut self, event: &WindowEvent) {
-        self.inner.get_mut().on_window_event(event);
+    /// Returns if egui used the event, so it shouldn't go to the scene.
+    pub fn on_window_event(&mut self, event: &WindowEvent) -> bool {
+        self.inner.get_mut().on_window_event(event)
     }
 
     pub fn on_mouse_motion(&mut self, delta: (f64, f64)) {
@@ -98,17 +117,18 @@ impl VulkanGuiMutable {
         let state = egui_winit::State::new(
             ctx,
             viewport_id,
-            &base.window,
-            Some(base.window.scale_factor() as f32),
+            base.window(),
+            Some(base.window().scale_factor() as f32),
             None,
             None,
         );
 
         Self {
-            window: base.window.clone(),
+            window: base.window().clone(),
             state,
             info,
             renderer,
+            format,
             textures_to_free: None,
         }
     }
@@ -173,9 +193,8 @@ impl VulkanGuiMutable {
             .unwrap();
     }
 
-    pub fn on_window_event(&mut self, event: &WindowEvent) {
-        // TODO: result ?
-        let _ = self.state.on_window_event(&self.window, event);
+    pub fn on_window_event(&mut self, event: &WindowEvent) -> bool {
+        self.state.on_window_event(&self.window, event).consumed
     }
 
     pub fn on_mouse_motion(&mut self, delta: (f64, f64)) {
@@ -0,0 +1,726 @@
+//! Image-based lighting from an HDR environment.
+//!
+//! The equirectangular environment is projected on a cube, then convolved into the irradiance cube
+//! for the diffuse and into the mips of the prefiltered cube for the specular, which is completed
+//! by the split-sum BRDF LUT. The environment cube is also drawn as the skybox, except for the
+//! atmosphere which the skybox computes again per pixel.
+
+use std::{
+    rc::Rc,
+    slice,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ash::{Device, vk};
+use glam::{Mat3, Mat4, Vec3, Vec4};
+
+use super::{
+    allocated::AllocatedImage,
+    commands::{VulkanCommands, as_u8_slice, image_subresource_range, transition_image},
+    compute_shaders::compute_pipeline,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    shaders_loader::{ShaderName, ShadersLoader},
+};
+use crate::scene::environment::{EnvironmentImage, EnvironmentSource};
+
+/// See `local_size_x` and `local_size_y` in `resources/{equirect_to_cube,irradiance,
+/// specular_prefilter,brdf_lut,skybox}.comp.glsl`.
+const IBL_GROUP_SIZE: u32 = 16;
+
+const ENVIRONMENT_SIZE: u32 = 512;
+/// Sampled by the convolutions according to their sample density.
+const ENVIRONMENT_MIPS: u32 = 6;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// One per roughness step, see `PREFILTERED_MIPS` in `resources/input_structures.glsl`.
+const PREFILTERED_MIPS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+const SAMPLE_COUNT: u32 = 1024;
+/// The atmosphere lighting is computed again once the sun stopped moving for that long, or moved
+/// by more than that many degrees.
+const SUN_SETTLE_TIME: Duration = Duration::from_millis(300);
+const SUN_REBAKE_ANGLE: f32 = 10.;
+
+const IRRADIANCE_BINDING: u32 = 0;
+const PREFILTERED_BINDING: u32 = 1;
+const BRDF_LUT_BINDING: u32 = 2;
+const ENVIRONMENT_BINDING: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IblSettings {
+    /// Lights the meshes, the flat ambient colour is used otherwise.
+    pub lighting: bool,
+    /// Of the lighting and the skybox.
+    pub intensity: f32,
+    /// Index in [`Ibl::environment_names`].
+    pub environment: usize,
+    /// Drawn instead of the background effect.
+    pub skybox: bool,
+    /// Roughness of the prefiltered cube shown instead, if not 0.
+    pub skybox_blur: f32,
+    /// Of the sun above the horizon in degrees, lights the scene and the atmosphere.
+    pub sun_elevation: f32,
+    /// Of the sun in degrees, from +X towards +Z.
+    pub sun_azimuth: f32,
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        Self {
+            lighting: true,
+            intensity: 1.,
+            environment: 0,
+            skybox: true,
+            skybox_blur: 0.,
+            sun_elevation: 63.4,
+            sun_azimuth: 90.,
+        }
+    }
+}
+
+impl IblSettings {
+    /// Normalised, towards the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        let (elevation, azimuth) = (
+            self.sun_elevation.to_radians(),
+            self.sun_azimuth.to_radians(),
+        );
+        Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        )
+    }
+
+    pub fn ui(&mut self, ctx: &egui::Context, environments: &[String]) {
+        egui::Window::new("Environment")
+            .default_open(false)
+            .show(ctx, |ui| {
+                egui::ComboBox::from_label("Environment map")
+                    .selected_text(&environments[self.environment])
+                    .show_ui(ui, |ui| {
+                        environments.iter().enumerate().for_each(|(i, n)| {
+                            ui.selectable_value(&mut self.environment, i, n);
+                        });
+                    });
+                ui.add(egui::Slider::new(&mut self.intensity, 0.0..=4.).text("Intensity"));
+                ui.checkbox(&mut self.lighting, "Image-based lighting");
+                ui.checkbox(&mut self.skybox, "Skybox");
+                ui.add_enabled(
+                    self.skybox,
+                    egui::Slider::new(&mut self.skybox_blur, 0.0..=1.).text("Skybox blur"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut self.sun_elevation, -10.0..=90.).text("Sun elevation"),
+                )
+                .on_hover_text("The lighting of the atmosphere follows once the sun settles");
+                ui.add(egui::Slider::new(&mut self.sun_azimuth, 0.0..=360.).text("Sun azimuth"));
+            });
+    }
+}
+
+/// Shared by the precomputations, see `resources/ibl_common.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+struct BakePushConstants {
+    /// Of the written face or image.
+    size: u32,
+    roughness: f32,
+    environment_size: u32,
+    sample_count: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SkyboxPushConstants {
+    /// Without the camera translation.
+    pub inv_view_proj: Mat4,
+    pub size: [u32; 2],
+    pub intensity: f32,
+    pub blur: f32,
+    /// Towards the sun, `w` is 1 to compute the atmosphere instead of sampling the environment.
+    pub sun_direction: Vec4,
+}
+
+impl SkyboxPushConstants {
+    pub fn new(
+        settings: &IblSettings,
+        atmosphere: bool,
+        view: &Mat4,
+        proj: &Mat4,
+        draw_extent: vk::Extent2D,
+    ) -> Self {
+        let rotation = Mat4::from_mat3(Mat3::from_mat4(*view));
+        Self {
+            inv_view_proj: (*proj * rotation).inverse(),
+            size: [draw_extent.width, draw_extent.height],
+            intensity: settings.intensity,
+            blur: settings.skybox_blur,
+            sun_direction: settings.sun_direction().extend(atmosphere as u32 as f32),
+        }
+    }
+}
+
+/// The selected environment, its precomputations and the skybox pipeline.
+pub struct Ibl {
+    device_copy: Rc<Device>,
+
+    environment: Environment,
+    precompute: Precompute,
+    environment_names: Vec<String>,
+    sources: Vec<EnvironmentSource>,
+    current: usize,
+    /// Lighting the current environment, if it is the atmosphere.
+    sun_direction: Vec3,
+    /// Of the settings when it differs from `sun_direction`, and since when it didn't change.
+    sun_moving: Option<(Vec3, Instant)>,
+
+    /// Set 0 of the skybox, the storage image of `draw_img`.
+    image_desc_layout: vk::DescriptorSetLayout,
+    pub skybox_layout: vk::PipelineLayout,
+    pub skybox_pipeline: vk::Pipeline,
+}
+
+impl Ibl {
+    pub fn new(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        shaders: &ShadersLoader,
+        commands: &VulkanCommands,
+        sun_direction: Vec3,
+    ) -> Self {
+        let precompute = Precompute::new(device.clone(), allocator, shaders, commands);
+
+        let sources = EnvironmentSource::list();
+        let environment_names = sources.iter().map(EnvironmentSource::name).collect();
+
+        // The atmosphere, which always loads.
+        let image = EnvironmentImage::load(&sources[0], sun_direction).unwrap();
+        let environment = precompute.environment(commands, &image);
+
+        let image_desc_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::STORAGE_IMAGE)
+            .build(&device, vk::ShaderStageFlags::COMPUTE);
+        let (skybox_layout, skybox_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::Skybox,
+            &[image_desc_layout, precompute.layout],
+            size_of::<SkyboxPushConstants>(),
+        );
+
+        Self {
+            device_copy: device,
+            environment,
+            precompute,
+            environment_names,
+            sources,
+            current: 0,
+            sun_direction,
+            sun_moving: None,
+            image_desc_layout,
+            skybox_layout,
+            skybox_pipeline,
+        }
+    }
+
+    pub fn environment_names(&self) -> &[String] {
+        &self.environment_names
+    }
+
+    /// Loads and precomputes the environment of the `settings` if it isn't the current one,
+    /// or the atmosphere again once the sun moved far enough or stopped moving. The skybox
+    /// computes the atmosphere with the current sun meanwhile.
+    ///
+    /// If the environment can't be loaded, the current one is selected back in the `settings`.
+    /// Waits for the device, the previous one may be in use.
+    pub fn select(&mut self, commands: &VulkanCommands, settings: &mut IblSettings) {
+        let (index, sun_direction) = (settings.environment, settings.sun_direction());
+        let source = &self.sources[index];
+        let mut update = index != self.current;
+        if *source == EnvironmentSource::Atmosphere && sun_direction != self.sun_direction {
+            let since = match self.sun_moving {
+                Some((direction, since)) if direction == sun_direction => since,
+                _ => Instant::now(),
+            };
+            self.sun_moving = Some((sun_direction, since));
+            update |= since.elapsed() >= SUN_SETTLE_TIME
+                || sun_direction.angle_between(self.sun_direction) >= SUN_REBAKE_ANGLE.to_radians();
+        }
+        if !update {
+            return;
+        }
+
+        let image = match EnvironmentImage::load(source, sun_direction) {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("Can't load the environment, keeping the current one : {e}");
+                settings.environment = self.current;
+                return;
+            }
+        };
+        unsafe { self.device_copy.device_wait_idle().unwrap() };
+        self.environment = self.precompute.environment(commands, &image);
+        self.current = index;
+        self.sun_direction = sun_direction;
+        self.sun_moving = None;
+    }
+
+    /// The current environment is the atmosphere, which the skybox computes per pixel.
+    pub fn atmosphere(&self) -> bool {
+        self.sources[self.current] == EnvironmentSource::Atmosphere
+    }
+
+    /// Layout of [`Self::set`].
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.precompute.layout
+    }
+
+    /// Set 2 of the mesh pipelines and 1 of the skybox, see `resources/input_structures.glsl`.
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.environment.set
+    }
+}
+
+impl Drop for Ibl {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop Ibl");
+        unsafe {
+            self.device_copy
+                .destroy_pipeline(self.skybox_pipeline, None);
+            self.device_copy
+                .destroy_pipeline_layout(self.skybox_layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.image_desc_layout, None);
+        }
+    }
+}
+
+/// Cubes of one environment, replaced when another one is selected.
+struct Environment {
+    _descriptor: DescriptorAllocator,
+    set: vk::DescriptorSet,
+
+    _cube: AllocatedImage,
+    _irradiance: AllocatedImage,
+    _prefiltered: AllocatedImage,
+}
+
+/// Pipelines computing an [`Environment`], and the BRDF LUT shared by all of them.
+struct Precompute {
+    device_copy: Rc<Device>,
+    allocator_copy: Arc<Mutex<vk_mem::Allocator>>,
+
+    sampler: vk::Sampler,
+    brdf_lut: AllocatedImage,
+
+    /// Irradiance, prefiltered, BRDF LUT and environment, all sampled.
+    layout: vk::DescriptorSetLayout,
+    /// Storage image and sampler.
+    bake_desc_layout: vk::DescriptorSetLayout,
+    equirect_layout: vk::PipelineLayout,
+    equirect_pipeline: vk::Pipeline,
+    irradiance_layout: vk::PipelineLayout,
+    irradiance_pipeline: vk::Pipeline,
+    prefilter_layout: vk::PipelineLayout,
+    prefilter_pipeline: vk::Pipeline,
+}
+
+impl Precompute {
+    fn new(
+        device: Rc<Device>,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        shaders: &ShadersLoader,
+        commands: &VulkanCommands,
+    ) -> Self {
+        let sampler = {
+            let create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(vk::LOD_CLAMP_NONE);
+            unsafe { device.create_sampler(&create_info, None).unwrap() }
+        };
+
+        let layout = DescriptorLayoutBuilder::default()
+            .add_binding(
+                IRRADIANCE_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            )
+            .add_binding(
+                PREFILTERED_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            )
+            .add_binding(BRDF_LUT_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .add_binding(
+                ENVIRONMENT_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            )
+            .build(
+                &device,
+                vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE,
+            );
+        let bake_desc_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::STORAGE_IMAGE)
+            .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .build(&device, vk::ShaderStageFlags::COMPUTE);
+
+        let bake_pipeline = |name| {
+            compute_pipeline(
+                &device,
+                shaders,
+                name,
+                &[bake_desc_layout],
+                size_of::<BakePushConstants>(),
+            )
+        };
+        let (equirect_layout, equirect_pipeline) = bake_pipeline(ShaderName::EquirectToCube);
+        let (irradiance_layout, irradiance_pipeline) = bake_pipeline(ShaderName::Irradiance);
+        let (prefilter_layout, prefilter_pipeline) = bake_pipeline(ShaderName::SpecularPrefilter);
+
+        let brdf_lut = AllocatedImage::new_lut(device.clone(), allocator.clone(), BRDF_LUT_SIZE);
+        {
+            // Only needed once.
+            let (brdf_lut_layout, brdf_lut_pipeline) = bake_pipeline(ShaderName::BrdfLut);
+            let sizes = [
+                (vk::DescriptorType::STORAGE_IMAGE, 1.),
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1.),
+            ];
+            let descriptor = DescriptorAllocator::new(device.clone(), 1, &sizes[..]);
+            let desc = descriptor.allocate(bake_desc_layout);
+            let mut writer = DescriptorWriter::default();
+            writer.write_image(
+                0,
+                brdf_lut.img_view,
+                vk::Sampler::null(),
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::STORAGE_IMAGE,
+            );
+            writer.update_set(&device, desc);
+
+            commands.immediate_submit(|device, cmd_buf| {
+                transition_image(
+                    device,
+                    cmd_buf,
+                    brdf_lut.img,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::GENERAL,
+                );
+                bake(
+                    device,
+                    cmd_buf,
+                    brdf_lut_layout,
+                    brdf_lut_pipeline,
+                    desc,
+                    &BakePushConstants {
+                        size: BRDF_LUT_SIZE,
+                        sample_count: SAMPLE_COUNT,
+                        ..Default::default()
+                    },
+                    1,
+                );
+                transition_image(
+                    device,
+                    cmd_buf,
+                    brdf_lut.img,
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                );
+            });
+
+            unsafe {
+                device.destroy_pipeline(brdf_lut_pipeline, None);
+                device.destroy_pipeline_layout(brdf_lut_layout, None);
+            }
+        }
+
+        Self {
+            device_copy: device,
+            allocator_copy: allocator,
+            sampler,
+            brdf_lut,
+            layout,
+            bake_desc_layout,
+            equirect_layout,
+            equirect_pipeline,
+            irradiance_layout,
+            irradiance_pipeline,
+            prefilter_layout,
+            prefilter_pipeline,
+        }
+    }
+
+    fn environment(&self, commands: &VulkanCommands, image: &EnvironmentImage) -> Environment {
+        let device = &self.device_copy;
+
+        let extent = vk::Extent3D {
+            width: image.width,
+            height: image.height,
+            depth: 1,
+        };
+        let pixels = image.rgba();
+        let equirect = AllocatedImage::new_and_upload(
+            commands,
+            device.clone(),
+            self.allocator_copy.clone(),
+            extent,
+            vk::Format::R32G32B32A32_SFLOAT,
+            vk::ImageUsageFlags::SAMPLED,
+            false,
+            unsafe {
+                slice::from_raw_parts(pixels.as_ptr() as *const u8, size_of_val(&pixels[..]))
+            },
+        );
+
+        let new_cube = |size, mips| {
+            AllocatedImage::new_cubemap(device.clone(), self.allocator_copy.clone(), size, mips)
+        };
+        let cube = new_cube(ENVIRONMENT_SIZE, ENVIRONMENT_MIPS);
+        let irradiance = new_cube(IRRADIANCE_SIZE, 1);
+        let prefiltered = new_cube(PREFILTERED_SIZE, PREFILTERED_MIPS);
+
+        // Written one mip at a time, as arrays of faces.
+        let mip_views = |cube: &AllocatedImage, mips| -> Vec<vk::ImageView> {
+            (0..mips)
+                .map(|level| {
+                    let create_info = vk::ImageViewCreateInfo::default()
+                        .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+                        .format(cube.format)
+                        .subresource_range(
+                            image_subresource_range(vk::ImageAspectFlags::COLOR, 1, level)
+                                .layer_count(6),
+                        )
+                        .image(cube.img);
+                    unsafe { device.create_image_view(&create_info, None).unwrap() }
+                })
+                .collect()
+        };
+        let cube_mips = mip_views(&cube, ENVIRONMENT_MIPS);
+        let irradiance_mips = mip_views(&irradiance, 1);
+        let prefiltered_mips = mip_views(&prefiltered, PREFILTERED_MIPS);
+
+        let set_count = ENVIRONMENT_MIPS + 1 + PREFILTERED_MIPS;
+        let sizes = [
+            (vk::DescriptorType::STORAGE_IMAGE, 1.),
+            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1.),
+        ];
+        let bake_descriptor = DescriptorAllocator::new(device.clone(), set_count, &sizes[..]);
+        let bake_desc = |dst: vk::ImageView, src: vk::ImageView, src_layout| {
+            let desc = bake_descriptor.allocate(self.bake_desc_layout);
+            let mut writer = DescriptorWriter::default();
+            writer.write_image(
+                0,
+                dst,
+                vk::Sampler::null(),
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::STORAGE_IMAGE,
+            );
+            writer.write_image(
+                1,
+                src,
+                self.sampler,
+                src_layout,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.update_set(device, desc);
+            desc
+        };
+        let read_only = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        let cube_descs: Vec<vk::DescriptorSet> = cube_mips
+            .iter()
+            .map(|mip| bake_desc(*mip, equirect.img_view, read_only))
+            .collect();
+        let irradiance_desc = bake_desc(irradiance_mips[0], cube.img_view, read_only);
+        let prefiltered_descs: Vec<vk::DescriptorSet> = prefiltered_mips
+            .iter()
+            .map(|mip| bake_desc(*mip, cube.img_view, read_only))
+            .collect();
+
+        commands.immediate_submit(|device, cmd_buf| {
+            [&cube, &irradiance, &prefiltered]
+                .into_iter()
+                .for_each(|c| {
+                    transition_image(
+                        device,
+                        cmd_buf,
+                        c.img,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::GENERAL,
+                    )
+                });
+
+            cube_descs.iter().enumerate().for_each(|(level, desc)| {
+                bake(
+                    device,
+                    cmd_buf,
+                    self.equirect_layout,
+                    self.equirect_pipeline,
+                    *desc,
+                    &BakePushConstants {
+                        size: (ENVIRONMENT_SIZE >> level).max(1),
+                        ..Default::default()
+                    },
+                    6,
+                );
+            });
+            transition_image(
+                device,
+                cmd_buf,
+                cube.img,
+                vk::ImageLayout::GENERAL,
+                read_only,
+            );
+
+            bake(
+                device,
+                cmd_buf,
+                self.irradiance_layout,
+                self.irradiance_pipeline,
+                irradiance_desc,
+                &BakePushConstants {
+                    size: IRRADIANCE_SIZE,
+                    environment_size: ENVIRONMENT_SIZE,
+                    sample_count: SAMPLE_COUNT,
+                    ..Default::default()
+                },
+                6,
+            );
+
+            prefiltered_descs
+                .iter()
+                .enumerate()
+                .for_each(|(level, desc)| {
+                    bake(
+                        device,
+                        cmd_buf,
+                        self.prefilter_layout,
+                        self.prefilter_pipeline,
+                        *desc,
+                        &BakePushConstants {
+                            size: (PREFILTERED_SIZE >> level).max(1),
+                            roughness: level as f32 / (PREFILTERED_MIPS - 1) as f32,
+                            environment_size: ENVIRONMENT_SIZE,
+                            sample_count: SAMPLE_COUNT,
+                        },
+                        6,
+                    );
+                });
+
+            [&irradiance, &prefiltered].into_iter().for_each(|c| {
+                transition_image(device, cmd_buf, c.img, vk::ImageLayout::GENERAL, read_only)
+            });
+        });
+
+        unsafe {
+            cube_mips
+                .into_iter()
+                .chain(irradiance_mips)
+                .chain(prefiltered_mips)
+                .for_each(|v| device.destroy_image_view(v, None));
+        }
+
+        let sizes = [(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4.)];
+        let descriptor = DescriptorAllocator::new(device.clone(), 1, &sizes[..]);
+        let set = descriptor.allocate(self.layout);
+        let mut writer = DescriptorWriter::default();
+        [
+            (IRRADIANCE_BINDING, irradiance.img_view),
+            (PREFILTERED_BINDING, prefiltered.img_view),
+            (BRDF_LUT_BINDING, self.brdf_lut.img_view),
+            (ENVIRONMENT_BINDING, cube.img_view),
+        ]
+        .into_iter()
+        .for_each(|(binding, view)| {
+            writer.write_image(
+                binding,
+                view,
+                self.sampler,
+                read_only,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            )
+        });
+        writer.update_set(device, set);
+
+        Environment {
+            _descriptor: descriptor,
+            set,
+            _cube: cube,
+            _irradiance: irradiance,
+            _prefiltered: prefiltered,
+        }
+    }
+}
+
+impl Drop for Precompute {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop Precompute");
+        unsafe {
+            [
+                self.equirect_pipeline,
+                self.irradiance_pipeline,
+                self.prefilter_pipeline,
+            ]
+            .into_iter()
+            .for_each(|p| self.device_copy.destroy_pipeline(p, None));
+            [
+                self.equirect_layout,
+                self.irradiance_layout,
+                self.prefilter_layout,
+            ]
+            .into_iter()
+            .for_each(|l| self.device_copy.destroy_pipeline_layout(l, None));
+            self.device_copy
+                .destroy_descriptor_set_layout(self.layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.bake_desc_layout, None);
+            self.device_copy.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+/// Dispatches `pipeline` over `push_constants.size` squared, times `layers`.
+fn bake(
+    device: &Device,
+    cmd_buf: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    desc: vk::DescriptorSet,
+    push_constants: &BakePushConstants,
+    layers: u32,
+) {
+    let groups = push_constants.size.div_ceil(IBL_GROUP_SIZE);
+    unsafe {
+        device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::COMPUTE, pipeline);
+        device.cmd_bind_descriptor_sets(
+            cmd_buf,
+            vk::PipelineBindPoint::COMPUTE,
+            layout,
+            0,
+            &[desc],
+            &[],
+        );
+        device.cmd_push_constants(
+            cmd_buf,
+            layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            as_u8_slice(push_constants),
+        );
+        device.cmd_dispatch(cmd_buf, groups, groups, layers);
+    }
+
+    // Each pass reads the previous one.
+    let barriers = [vk::MemoryBarrier2::default()
+        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ)];
+    let dep_info = vk::DependencyInfo::default().memory_barriers(&barriers);
+    unsafe { device.cmd_pipeline_barrier2(cmd_buf, &dep_info) };
+}
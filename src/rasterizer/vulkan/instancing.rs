@@ -0,0 +1,88 @@
+//! Instancing of the CPU draw path.
+//!
+//! The visible render objects drawing the same surface with the same pipeline are grouped in a
+//! single instanced draw. Their transforms and materials are uploaded to the ring of the frame as
+//! [`GpuObject`]s, read at `gl_InstanceIndex` by the vertex shader like with the GPU-driven path.
+
+use std::collections::{HashMap, hash_map::Entry};
+
+use super::{
+    frame_ring::RingSegment,
+    gfx_pipeline::GpuDrawPushConstants,
+    gpu_culling::GpuObject,
+    scene::{InstanceKey, RenderObject},
+};
+
+/// One draw of `FrameData::draw_mesh`.
+pub struct MeshDraw<'a> {
+    /// The first instance, for the pipeline, the index buffer and the indices.
+    pub object: &'a RenderObject,
+    pub push_constants: GpuDrawPushConstants,
+    pub first_instance: u32,
+    pub instance_count: u32,
+}
+
+impl<'a> From<&'a RenderObject> for MeshDraw<'a> {
+    /// Without instancing, everything is in the push constants.
+    fn from(object: &'a RenderObject) -> Self {
+        Self {
+            object,
+            push_constants: GpuDrawPushConstants::from(object),
+            first_instance: 0,
+            instance_count: 1,
+        }
+    }
+}
+
+/// Groups the sorted `opaque` and `transparent` objects, and uploads their instances.
+///
+/// An opaque group is drawn at the place of its first object. The transparent objects are
+/// blended in order, so only consecutive ones are grouped.
+pub fn instanced_draws<'a>(
+    ring: &RingSegment,
+    opaque: impl Iterator<Item = &'a RenderObject>,
+    transparent: impl Iterator<Item = &'a RenderObject>,
+) -> Vec<MeshDraw<'a>> {
+    let mut groups: Vec<Vec<&RenderObject>> = Vec::new();
+
+    let mut opaque_groups: HashMap<InstanceKey, usize> = HashMap::new();
+    opaque.for_each(|d| match opaque_groups.entry(d.instance_key()) {
+        Entry::Occupied(e) => groups[*e.get()].push(d),
+        Entry::Vacant(e) => {
+            e.insert(groups.len());
+            groups.push(vec![d]);
+        }
+    });
+
+    let first_transparent = groups.len();
+    transparent.for_each(|d| match groups[first_transparent..].last_mut() {
+        Some(last) if last[0].instance_key() == d.instance_key() => last.push(d),
+        _ => groups.push(vec![d]),
+    });
+
+    let objects: Vec<GpuObject> = groups
+        .iter()
+        .flatten()
+        .map(|d| GpuObject::new(d, 0, 0))
+        .collect();
+    let push_constants = GpuDrawPushConstants {
+        objects: ring.push_slice(&objects[..]).address,
+        draw_indirect: 1,
+        ..Default::default()
+    };
+
+    let mut first_instance = 0;
+    groups
+        .into_iter()
+        .map(|group| {
+            let draw = MeshDraw {
+                object: group[0],
+                push_constants,
+                first_instance,
+                instance_count: group.len() as u32,
+            };
+            first_instance += draw.instance_count;
+            draw
+        })
+        .collect()
+}
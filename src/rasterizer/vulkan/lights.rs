@@ -0,0 +1,104 @@
+//! Point lights, scattered in the bounds of the scene to light it from many places at once.
+//!
+//! They are shaded by every fragment of the forward pass, and only by the pixels of the tiles they
+//! touch with the deferred shading, see `resources/deferred_lighting.comp.glsl`.
+
+use glam::{Vec3, vec3};
+
+use crate::scene::bvh::Aabb;
+
+/// See `PointLight` in `resources/scene_data.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct GpuPointLight {
+    pub position: [f32; 3],
+    /// Distance at which it stops lighting.
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSettings {
+    pub count: u32,
+    pub intensity: f32,
+    /// Relative to the diagonal of the scene bounds.
+    pub radius: f32,
+}
+
+impl Default for LightSettings {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            intensity: 10.,
+            radius: 0.1,
+        }
+    }
+}
+
+impl LightSettings {
+    pub const MAX_COUNT: u32 = 4096;
+
+    pub fn ui(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Lights")
+            .default_open(false)
+            .show(ctx, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut self.count, 0..=Self::MAX_COUNT)
+                        .logarithmic(true)
+                        .text("Point lights"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut self.intensity, 0.0..=100.)
+                        .logarithmic(true)
+                        .text("Intensity"),
+                );
+                ui.add(egui::Slider::new(&mut self.radius, 0.01..=0.5).text("Radius"));
+            });
+    }
+
+    /// The same lights every frame for the same `bounds`, empty if they are.
+    pub fn lights(&self, bounds: &Aabb) -> Vec<GpuPointLight> {
+        if bounds.min.cmpgt(bounds.max).any() {
+            return Vec::new();
+        }
+        let size = bounds.max - bounds.min;
+        let radius = size.length() * self.radius;
+        (0..self.count)
+            .map(|i| {
+                let position = bounds.min + size * vec3(hash(i, 0), hash(i, 1), hash(i, 2));
+                GpuPointLight {
+                    position: position.to_array(),
+                    radius,
+                    color: hue(i as f32 * GOLDEN_RATIO_CONJUGATE).to_array(),
+                    intensity: self.intensity,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Spreads the hues of consecutive lights.
+const GOLDEN_RATIO_CONJUGATE: f32 = 0.618_034;
+
+/// In [0,1), a different sequence per `seed`.
+fn hash(i: u32, seed: u32) -> f32 {
+    let mut x = i.wrapping_mul(0x9e37_79b9) ^ seed.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Saturated colour of the hue `h`, wrapped in [0,1).
+fn hue(h: f32) -> Vec3 {
+    let h = h.fract() * 6.;
+    vec3(
+        (h - 3.).abs() - 1.,
+        2. - (h - 2.).abs(),
+        2. - (h - 4.).abs(),
+    )
+    .clamp(Vec3::ZERO, Vec3::ONE)
+}
@@ -2,36 +2,77 @@
 use std::time::Instant;
 use std::{
     collections::HashMap,
+    path::Path,
     rc::Rc,
     sync::{Arc, Mutex},
 };
 
 use ash::vk;
 use glam::Mat4;
-use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, MouseButton, WindowEvent},
+    window::Window,
+};
 
-use super::settings::Settings;
-use crate::{rasterizer::vulkan::gltf_loader::LoadedGLTF, scene::Camera, window::AppObserver};
+use super::settings::{AoSettings, Settings, ViewMode, Wireframe};
+use crate::{scene::Camera, window::AppObserver};
 
 mod base;
 use base::VulkanBase;
 mod swapchain;
-use swapchain::VulkanSwapchain;
+use swapchain::{OffscreenTarget, VulkanSwapchain};
 mod commands;
-use commands::VulkanCommands;
+use commands::{GeometryInputs, GeometryPass, VulkanCommands};
 mod compute_shaders;
 use compute_shaders::{ComputeEffect, ComputePushConstants};
 mod gui;
-use gui::VulkanGui;
+use gui::{GeneratedUi, VulkanGui};
 mod shaders_loader;
 use shaders_loader::ShadersLoader;
 mod allocated;
+mod ambient_occlusion;
+use ambient_occlusion::{AmbientOcclusion, AoPushConstants};
+mod bcn;
+mod bindless;
+mod deferred;
+use deferred::{DeferredLighting, GBufferView, LightingPushConstants, Shading, ShadingSettings};
 mod descriptors;
+mod effect_params;
+mod frame_ring;
 mod gfx_pipeline;
 mod gltf_loader;
+mod gpu_culling;
+use gpu_culling::IndirectDraws;
+#[cfg(feature = "vulkan_stats")]
+mod gpu_timings;
+#[cfg(feature = "vulkan_stats")]
+use gpu_timings::{GpuPass, GpuTimingsHistory};
+mod ibl;
+use ibl::{Ibl, IblSettings, SkyboxPushConstants};
+mod instancing;
+mod lights;
+use lights::LightSettings;
+mod msaa;
+use msaa::MsaaSettings;
+mod post_process;
+use post_process::PostSettings;
+mod pipeline_cache;
+mod present;
+use present::PresentSettings;
+mod render_graph;
+use render_graph::{BufferUsage, ImageUsage};
 mod scene;
+mod spirv_cache;
+mod texture_data;
+use texture_data::FormatSupport;
 mod textures;
+pub use scene::DEFAULT_SCENE;
+#[cfg(feature = "cpu")]
+pub use scene::FOV_Y;
 use scene::Scene;
+mod transform_editor;
+use transform_editor::TransformEditor;
 
 #[cfg(feature = "stats")]
 use super::Stats;
@@ -60,6 +101,7 @@ struct VulkanStatsStart {
     shaders_micros: u128,
     swapchain_micros: u128,
     commands_micros: u128,
+    ibl_micros: u128,
     scene_micros: u128,
     gui_micros: u128,
 }
@@ -69,7 +111,6 @@ struct VulkanStatsStart {
 pub struct VulkanStatsCounts {
     triangle_count: u32,
     drawcall_count: u32,
-    bound_mat: u32,
     bound_mat_pip: u32,
     bound_index_buf: u32,
 }
@@ -81,13 +122,48 @@ enum MeshSorting {
     Depth,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrustumCulling {
+    Off,
+    /// Testing every render object.
+    Linear,
+    /// Only walking the scene BVH, before filling the draw context.
+    Bvh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DrawPath {
+    /// Culling and sorting on the CPU, one draw per render object or per group of instances.
+    Cpu,
+    /// Culling in a compute shader, one indirect draw per pipeline and index buffer.
+    ///
+    /// Sorting settings are ignored, the frustum culling is also done on the GPU unless
+    /// [`FrustumCulling::Off`].
+    GpuDriven,
+}
+
 #[derive(Debug, Clone, Copy)]
 struct VulkanSettings {
     _validation_layers: bool,
     rebinding: bool,
     opaque_sorting: MeshSorting,
     transparent_sorting: MeshSorting,
-    frustum_culling: bool,
+    frustum_culling: FrustumCulling,
+    draw_path: DrawPath,
+    /// Of the render objects sharing a surface, only with [`DrawPath::Cpu`].
+    instancing: bool,
+    /// Against the depth of the previous frame, only with [`DrawPath::GpuDriven`].
+    occlusion_culling: bool,
+    /// Of the geometry pass, applied by [`VulkanEngine::apply_msaa`].
+    msaa: MsaaSettings,
+    /// Of the swapchain, applied by [`VulkanEngine::apply_present`].
+    present: PresentSettings,
+    /// Of the opaque surfaces, applied by [`VulkanEngine::apply_shading`].
+    shading: ShadingSettings,
+    lights: LightSettings,
+    /// Skipped by the debug views.
+    post: PostSettings,
+    ibl: IblSettings,
 }
 
 impl Default for VulkanSettings {
@@ -99,17 +175,56 @@ impl Default for VulkanSettings {
             opaque_sorting: MeshSorting::Binding,
             // Not enough transparent meshes to justify sorting
             transparent_sorting: MeshSorting::Off,
-            frustum_culling: true,
+            frustum_culling: FrustumCulling::Bvh,
+            draw_path: DrawPath::GpuDriven,
+            instancing: true,
+            occlusion_culling: true,
+            msaa: Default::default(),
+            present: Default::default(),
+            shading: Default::default(),
+            lights: Default::default(),
+            post: Default::default(),
+            ibl: Default::default(),
         }
     }
 }
 
+/// Renders one frame of the default scene without window nor surface, and saves it to
+/// `output` (PNG). Works with a software driver such as lavapipe, e.g. on CI.
+pub fn render_headless(size: PhysicalSize<u32>, output: &Path) {
+    let mut engine = VulkanEngine::new_headless(vk::Extent2D {
+        width: size.width,
+        height: size.height,
+    });
+    let pixels = engine.render_offscreen(&Settings::default(), &Camera::default());
+    ::image::RgbaImage::from_raw(size.width, size.height, pixels)
+        .unwrap()
+        .save(output)
+        .unwrap();
+}
+
+/// Where [`VulkanEngine::record_frame`] copies `draw_img`.
+enum FrameTarget {
+    /// Then draws the gui over it.
+    Swapchain {
+        image: vk::Image,
+        view: vk::ImageView,
+        generated_ui: GeneratedUi,
+    },
+    /// Then copies it to the readback buffer.
+    Offscreen,
+}
+
 /// Inspired from vkguide.dev and ash-examples/src/lib.rs since we don't have VkBootstrap
 pub struct VulkanEngine<'a> {
     // Elements are placed in the order they should be dropped, so inverse order of creation.
+    ambient_occlusion: AmbientOcclusion,
+    deferred_lighting: DeferredLighting,
     scene: Scene<'a>,
+    ibl: Ibl,
     swapchain: VulkanSwapchain,
-    gui: VulkanGui,
+    /// `None` when headless.
+    gui: Option<VulkanGui>,
     commands: VulkanCommands,
     shaders: ShadersLoader,
     allocator: Arc<Mutex<vk_mem::Allocator>>,
@@ -122,8 +237,15 @@ pub struct VulkanEngine<'a> {
     settings: VulkanSettings,
     #[cfg(feature = "vulkan_stats")]
     stats: VulkanStats,
+    #[cfg(feature = "vulkan_stats")]
+    gpu_timings: GpuTimingsHistory,
 
     selected_node: String,
+    transform_editor: TransformEditor,
+    /// Set on click, the picking is done at the next frame.
+    pick_requested: bool,
+    /// What the swapchain and the pipelines currently use.
+    applied_msaa: MsaaSettings,
 }
 
 impl Drop for VulkanEngine<'_> {
@@ -138,12 +260,22 @@ impl Drop for VulkanEngine<'_> {
 
 impl VulkanEngine<'_> {
     pub fn new(window: Rc<Window>) -> Self {
+        Self::init(|| VulkanBase::new(window), None)
+    }
+
+    /// Renders to an offscreen target of `extent` instead of a window, without gui, see
+    /// [`Self::render_offscreen`].
+    pub fn new_headless(extent: vk::Extent2D) -> Self {
+        Self::init(VulkanBase::new_headless, Some(extent))
+    }
+
+    fn init(create_base: impl FnOnce() -> VulkanBase, offscreen: Option<vk::Extent2D>) -> Self {
         #[cfg(feature = "vulkan_stats")]
         let mut stats = VulkanStats::default();
 
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
-        let base = VulkanBase::new(window);
+        let base = create_base();
         #[cfg(feature = "vulkan_stats")]
         {
             stats.start.base_micros = t.elapsed().as_micros();
@@ -159,14 +291,21 @@ impl VulkanEngine<'_> {
 
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
-        let shaders = ShadersLoader::new(base.device.clone());
+        let shaders = ShadersLoader::new(&base);
         #[cfg(feature = "vulkan_stats")]
         {
             stats.start.shaders_micros = t.elapsed().as_micros();
         }
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
-        let swapchain = VulkanSwapchain::new(&base, &shaders, allocator.clone(), None);
+        let swapchain = match offscreen {
+            Some(extent) => {
+                VulkanSwapchain::new_headless(&base, &shaders, allocator.clone(), extent)
+            }
+            None => {
+                VulkanSwapchain::new(&base, &shaders, allocator.clone(), None, Default::default())
+            }
+        };
         #[cfg(feature = "vulkan_stats")]
         {
             stats.start.swapchain_micros = t.elapsed().as_micros();
@@ -187,6 +326,20 @@ impl VulkanEngine<'_> {
             stats.start.commands_micros = t.elapsed().as_micros();
         }
 
+        #[cfg(feature = "vulkan_stats")]
+        let t = Instant::now();
+        let ibl = Ibl::new(
+            base.device.clone(),
+            allocator.clone(),
+            &shaders,
+            &commands,
+            IblSettings::default().sun_direction(),
+        );
+        #[cfg(feature = "vulkan_stats")]
+        {
+            stats.start.ibl_micros = t.elapsed().as_micros();
+        }
+
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
         let scene = Scene::new(
@@ -195,22 +348,38 @@ impl VulkanEngine<'_> {
             &shaders,
             base.device.clone(),
             allocator.clone(),
+            ibl.layout(),
+            FormatSupport::new(&base),
         );
         #[cfg(feature = "vulkan_stats")]
         {
             stats.start.scene_micros = t.elapsed().as_micros();
         }
 
+        let deferred_lighting = DeferredLighting::new(
+            base.device.clone(),
+            &shaders,
+            scene.data_descriptor_layout,
+            ibl.layout(),
+        );
+        let ambient_occlusion =
+            AmbientOcclusion::new(base.device.clone(), &shaders, scene.data_descriptor_layout);
+
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
-        let gui = VulkanGui::new(&base, allocator.clone(), swapchain.swapchain_img_format());
+        let gui = offscreen
+            .is_none()
+            .then(|| VulkanGui::new(&base, allocator.clone(), swapchain.swapchain_img_format()));
         #[cfg(feature = "vulkan_stats")]
         {
             stats.start.gui_micros = t.elapsed().as_micros();
         }
 
         Self {
+            ambient_occlusion,
+            deferred_lighting,
             scene,
+            ibl,
             gui,
             commands,
             swapchain,
@@ -220,59 +389,91 @@ impl VulkanEngine<'_> {
 
             current_bg_effect: 0,
             bg_effects_data,
-            current_scene: "basicmesh".into(),
+            current_scene: DEFAULT_SCENE.into(),
 
             settings: Default::default(),
             #[cfg(feature = "vulkan_stats")]
             stats,
+            #[cfg(feature = "vulkan_stats")]
+            gpu_timings: Default::default(),
 
             selected_node: Default::default(),
+            transform_editor: Default::default(),
+            pick_requested: false,
+            applied_msaa: Default::default(),
         }
     }
 
     pub fn window(&self) -> &Rc<Window> {
-        &self.base.window
+        self.base.window()
     }
 
     pub fn rasterize(
         &mut self,
-        _settings: &Settings,
+        settings: &mut Settings,
         camera: &Camera,
         app: &mut AppObserver,
         #[cfg(feature = "stats")] _stats: &mut Stats,
     ) {
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
-        self.swapchain.resize_if_necessary(
-            &self.base,
-            &self.shaders,
-            self.commands.allocator.clone(),
-        );
+        self.apply_present();
+        self.apply_shading();
+        self.apply_msaa();
         #[cfg(feature = "vulkan_stats")]
         {
             self.stats.resize_micros = t.elapsed().as_micros();
         }
 
+        self.scene
+            .update_loading(&self.commands, &self.current_scene, false);
+
+        if std::mem::take(&mut self.pick_requested)
+            && let Some(cursor) = app.cursor()
+        {
+            let size = self.base.window().inner_size();
+            // The projection flips y, so it goes down like the cursor.
+            let x = cursor.x as f32 / size.width as f32 * 2. - 1.;
+            let y = cursor.y as f32 / size.height as f32 * 2. - 1.;
+            self.selected_node = self
+                .scene
+                .pick(&self.current_scene, x, y)
+                .unwrap_or_default();
+        }
+
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
-        let generated_ui = self.gui.generate(|ctx| {
+        let view_proj = *self.scene.view_proj();
+        let mut unload = None;
+        let gui = self.gui.as_mut().unwrap();
+        let generated_ui = gui.generate(|ctx| {
             ui(
                 ctx,
-                format_debug(
-                    app,
-                    self.base.window.inner_size(),
-                    camera,
+                UiState {
+                    base: &self.base,
+                    debug: format_debug(
+                        app,
+                        self.base.window().inner_size(),
+                        camera,
+                        #[cfg(feature = "vulkan_stats")]
+                        self.stats,
+                    ),
+                    current_bg_effect: &mut self.current_bg_effect,
+                    render_scale: &mut self.swapchain.render_scale,
+                    bg_effects: &self.swapchain.effects.bg_effects[..],
+                    bg_effects_data: &mut self.bg_effects_data,
+                    current_scene: &mut self.current_scene,
+                    scene: &self.scene,
+                    unload: &mut unload,
+                    settings: &mut self.settings,
+                    global_settings: settings,
+                    selected_node: &mut self.selected_node,
+                    transform_editor: &mut self.transform_editor,
+                    view_proj: &view_proj,
+                    environments: self.ibl.environment_names(),
                     #[cfg(feature = "vulkan_stats")]
-                    self.stats,
-                ),
-                &mut self.current_bg_effect,
-                &mut self.swapchain.render_scale,
-                &self.swapchain.effects.bg_effects[..],
-                &mut self.bg_effects_data,
-                &mut self.current_scene,
-                &self.scene.loaded_scenes,
-                &mut self.settings,
-                &mut self.selected_node,
+                    gpu_timings: &self.gpu_timings,
+                },
             )
         });
         #[cfg(feature = "vulkan_stats")]
@@ -280,22 +481,30 @@ impl VulkanEngine<'_> {
             self.stats.ui_micros = t.elapsed().as_micros();
         }
 
+        if let Some(name) = unload {
+            self.scene.evict(&name);
+        }
+        self.scene
+            .highlight(&self.current_scene, &self.selected_node);
+        self.ibl.select(&self.commands, &mut self.settings.ibl);
+
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
-        self.update_scene(camera);
+        self.update_scene(camera, settings);
         #[cfg(feature = "vulkan_stats")]
         {
             self.stats.scene_update_micros = t.elapsed().as_micros();
         }
 
-        let image = self.swapchain.draw_img();
-
         #[cfg(feature = "vulkan_stats")]
         let t = Instant::now();
         self.commands.current_frame().wait_for_fences();
         #[cfg(feature = "vulkan_stats")]
         {
             self.stats.wait_fence_micros = t.elapsed().as_micros();
+            if let Some(timings) = self.commands.current_frame().read_timings() {
+                self.gpu_timings.push(timings);
+            }
         }
 
         let current_frame = self.commands.current_frame_mut();
@@ -307,8 +516,10 @@ impl VulkanEngine<'_> {
 
         let current_frame = self.commands.current_frame();
 
-        let Some((swapchain_img_index, swapchain_image, sem_render, swapchain_image_view)) =
-            self.swapchain.acquire_next_image(current_frame)
+        let Some((swapchain_img_index, swapchain_image, sem_render, swapchain_image_view)) = self
+            .swapchain
+            .acquire_next_image(current_frame)
+            .map(|(index, image, sem, view)| (index, *image, *sem, *view))
         else {
             return;
         };
@@ -316,136 +527,591 @@ impl VulkanEngine<'_> {
         current_frame.reset_fences();
         current_frame.begin_cmd_buf();
 
-        current_frame.transition_image(
-            *image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::GENERAL,
+        self.record_frame(
+            settings,
+            global_desc,
+            app.last_full_render_loop_micros() as f32 / 1_000_000.,
+            FrameTarget::Swapchain {
+                image: swapchain_image,
+                view: swapchain_image_view,
+                generated_ui,
+            },
         );
 
-        #[cfg(feature = "vulkan_stats")]
-        let t = Instant::now();
-        current_frame.draw_background(
-            &self.swapchain,
-            self.current_bg_effect,
-            &self.bg_effects_data[self.current_bg_effect],
+        let current_frame = self.commands.current_frame();
+        current_frame.end_cmd_buf();
+        current_frame.submit(Some(&sem_render), self.commands.queue);
+        self.swapchain
+            .present(swapchain_img_index, &sem_render, self.commands.queue);
+
+        self.commands.frame_number += 1;
+    }
+
+    /// Renders one frame to the offscreen target and waits for it, so the GPU is idle after.
+    ///
+    /// Needs [`Self::new_headless`]. Returns RGBA8 pixels, row by row. Auto-exposure fully
+    /// adapts each frame, so the result only depends on the scene and `camera`.
+    pub fn render_offscreen(&mut self, settings: &Settings, camera: &Camera) -> Vec<u8> {
+        self.swapchain.resize_if_necessary(
+            &self.base,
+            &self.shaders,
+            self.commands.allocator.clone(),
+        );
+        self.apply_shading();
+        self.apply_msaa();
+        self.scene
+            .update_loading(&self.commands, &self.current_scene, true);
+        self.ibl.select(&self.commands, &mut self.settings.ibl);
+        self.update_scene(camera, settings);
+
+        self.commands.current_frame().wait_for_fences();
+        let current_frame = self.commands.current_frame_mut();
+        current_frame.clear_descriptors();
+        current_frame.clear_buffers_in_use();
+        let global_desc = current_frame
+            .descriptors_mut()
+            .allocate(self.scene.data_descriptor_layout);
+
+        let current_frame = self.commands.current_frame();
+        current_frame.reset_fences();
+        current_frame.begin_cmd_buf();
+
+        self.record_frame(settings, global_desc, f32::INFINITY, FrameTarget::Offscreen);
+
+        let current_frame = self.commands.current_frame();
+        current_frame.end_cmd_buf();
+        current_frame.submit(None, self.commands.queue);
+        current_frame.wait_for_fences();
+
+        let pixels = self.swapchain.offscreen().read();
+
+        self.commands.frame_number += 1;
+
+        pixels
+    }
+
+    /// Resizes the swapchain if necessary, also recreating it when the present settings
+    /// changed. The gui follows the format of the new one.
+    fn apply_present(&mut self) {
+        self.swapchain.set_present(self.settings.present);
+        self.swapchain.resize_if_necessary(
+            &self.base,
+            &self.shaders,
+            self.commands.allocator.clone(),
         );
+        if let Some(gui) = &mut self.gui {
+            gui.set_format(self.swapchain.swapchain_img_format());
+        }
+    }
+
+    /// Creates or removes the G-buffer when the shading changed. The resizes keep it.
+    fn apply_shading(&mut self) {
+        let deferred = self.settings.shading.shading == Shading::Deferred;
+        if deferred == self.swapchain.gbuffer().is_some() {
+            return;
+        }
+        unsafe {
+            self.base.device.device_wait_idle().unwrap();
+        }
+        self.swapchain
+            .set_gbuffer(&self.base, self.allocator.clone(), deferred);
+    }
+
+    /// Of the settings, always off with the deferred shading.
+    fn msaa(&self) -> MsaaSettings {
+        match self.settings.shading.shading {
+            Shading::Forward => self.settings.msaa,
+            Shading::Deferred => Default::default(),
+        }
+    }
+
+    /// Recreates the multisampled targets and rebuilds the geometry pipelines when the MSAA
+    /// settings changed. The resizes keep the sample count of the swapchain.
+    fn apply_msaa(&mut self) {
+        let msaa = self.msaa();
+        if msaa == self.applied_msaa {
+            return;
+        }
+        unsafe {
+            self.base.device.device_wait_idle().unwrap();
+        }
+
+        let multisampling = msaa.pipeline_multisampling(&self.base);
+        if self.swapchain.samples() != multisampling.0 {
+            self.swapchain.set_samples(
+                &self.base,
+                &self.shaders,
+                self.allocator.clone(),
+                multisampling.0,
+            );
+        }
+        self.scene.set_multisampling(&self.shaders, multisampling);
+        self.applied_msaa = msaa;
+    }
+
+    /// Records the passes of the frame with a [`RenderGraph`] : the background, the culling, the
+    /// geometry, the ambient occlusion, deferred lighting and transparent surfaces if any, the
+    /// depth pyramid and the post-processing in `draw_img`, then its copy to the target.
+    fn record_frame(
+        &mut self,
+        settings: &Settings,
+        global_desc: vk::DescriptorSet,
+        delta_secs: f32,
+        target: FrameTarget,
+    ) {
+        let ring = self.commands.current_frame().ring();
+        self.scene.upload_data(&self.base.device, ring, global_desc);
+
+        let indirect_draws = (self.settings.draw_path == DrawPath::GpuDriven).then(|| {
+            IndirectDraws::new(
+                &self.base.device,
+                &mut self.commands.current_frame().indirect_buffers(),
+                ring,
+                &self.swapchain.gpu_culling,
+                &self.scene.main_draw_ctx,
+                self.scene.view_proj(),
+                &self.settings,
+            )
+        });
+        let build_pyramid = indirect_draws.is_some() && self.settings.occlusion_culling;
+        if !build_pyramid {
+            self.swapchain.gpu_culling.set_pyramid_built(false);
+        }
+        // The debug views replace the background and skip the post-processing.
+        let shaded =
+            settings.view_mode == ViewMode::Shaded && settings.wireframe != Wireframe::Only;
+        // Only the shaded view is deferred, the other ones are drawn like the forward pass.
+        let gbuffer = self.swapchain.gbuffer().filter(|_| shaded);
+        let gbuffer_view = self.settings.shading.gbuffer_view();
+        let ambient_occlusion = settings.ao.enabled;
+
+        #[cfg(feature = "vulkan_stats")]
+        let compute_shaders_micros = std::cell::Cell::new(0);
         #[cfg(feature = "vulkan_stats")]
         {
-            self.stats.compute_shaders_micros = t.elapsed().as_micros();
+            self.stats.counts = Default::default();
         }
 
-        current_frame.transition_image(
-            *image,
-            vk::ImageLayout::GENERAL,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        let current_frame = self.commands.current_frame();
+        let mut graph = current_frame.render_graph();
+        let draw_img = graph.import_image(
+            *self.swapchain.draw_img(),
+            vk::ImageAspectFlags::COLOR,
+            None,
         );
-        let depth = self.swapchain.depth_img();
-        current_frame.transition_image(
-            *depth,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+        let depth_img = graph.import_image(
+            *self.swapchain.depth_img(),
+            vk::ImageAspectFlags::DEPTH,
+            None,
         );
+        let msaa_imgs = self.swapchain.msaa().map(|msaa| {
+            (
+                graph.import_image(msaa.color.img, vk::ImageAspectFlags::COLOR, None),
+                graph.import_image(msaa.depth.img, vk::ImageAspectFlags::DEPTH, None),
+            )
+        });
+        let gbuffer_imgs = gbuffer.map(|gbuffer| {
+            gbuffer
+                .images()
+                .map(|image| graph.import_image(image.img, vk::ImageAspectFlags::COLOR, None))
+        });
+        let ao_imgs = gbuffer.map(|gbuffer| {
+            gbuffer
+                .ao
+                .images()
+                .map(|image| graph.import_image(image.img, vk::ImageAspectFlags::COLOR, None))
+        });
+        // Written by the previous frame if built, read by the culling even if not.
+        let pyramid_img = indirect_draws.is_some().then(|| {
+            let gpu_culling = &self.swapchain.gpu_culling;
+            if gpu_culling.is_pyramid_built() {
+                graph.import_kept_image(
+                    gpu_culling.pyramid_img(),
+                    vk::ImageAspectFlags::COLOR,
+                    ImageUsage::Storage,
+                )
+            } else {
+                graph.import_image(gpu_culling.pyramid_img(), vk::ImageAspectFlags::COLOR, None)
+            }
+        });
+        let indirect_buffers = indirect_draws.as_ref().map(|draws| {
+            (
+                graph.import_buffer(draws.commands, None),
+                graph.import_buffer(draws.counts, None),
+            )
+        });
+
+        graph
+            .add_pass()
+            .image(draw_img, ImageUsage::Storage)
+            .record(|| {
+                #[cfg(feature = "vulkan_stats")]
+                let t = Instant::now();
+                #[cfg(feature = "vulkan_stats")]
+                current_frame.begin_pass(GpuPass::Background);
+                // Also without any loaded effect.
+                if self.settings.ibl.skybox || self.swapchain.effects.bg_effects.is_empty() {
+                    current_frame.draw_skybox(
+                        &self.swapchain,
+                        &self.ibl,
+                        &SkyboxPushConstants::new(
+                            &self.settings.ibl,
+                            self.ibl.atmosphere(),
+                            self.scene.view(),
+                            self.scene.proj(),
+                            self.swapchain.draw_extent(),
+                        ),
+                    );
+                } else {
+                    current_frame.draw_background(
+                        &self.swapchain,
+                        self.current_bg_effect,
+                        &self.bg_effects_data[self.current_bg_effect],
+                    );
+                }
+                #[cfg(feature = "vulkan_stats")]
+                {
+                    current_frame.end_pass(GpuPass::Background);
+                    compute_shaders_micros.set(t.elapsed().as_micros());
+                }
+            });
 
-        let buffer_in_use =
-            self.scene
-                .upload_data(&self.base.device, self.allocator.clone(), global_desc);
+        if let (Some(draws), Some((commands, counts)), Some(pyramid_img)) =
+            (&indirect_draws, indirect_buffers, pyramid_img)
+        {
+            graph
+                .add_pass()
+                .image(pyramid_img, ImageUsage::Storage)
+                .buffer(commands, BufferUsage::StorageWrite)
+                .buffer(counts, BufferUsage::StorageWrite)
+                .record(|| {
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.begin_pass(GpuPass::Culling);
+                    current_frame.cull_objects(&self.swapchain, draws);
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.end_pass(GpuPass::Culling);
+                });
+        }
 
-        #[cfg(feature = "vulkan_stats")]
+        if let (Some(msaa), Some((msaa_color, _))) = (self.swapchain.msaa(), msaa_imgs)
+            && shaded
         {
-            self.stats.counts = Default::default();
+            graph
+                .add_pass()
+                .image(draw_img, ImageUsage::FragmentRead)
+                .image(msaa_color, ImageUsage::ColorAttachment)
+                .record(|| current_frame.draw_msaa_background(&self.swapchain, msaa));
         }
-        current_frame.draw_geometries(
-            &self.settings,
-            &self.swapchain,
-            &self.scene.view_proj(),
-            &self.scene.main_draw_ctx,
+
+        #[cfg(feature = "vulkan_stats")]
+        let stats = std::cell::RefCell::new(&mut self.stats);
+        let geometry_inputs = GeometryInputs {
+            settings: &self.settings,
+            view_mode: settings.view_mode,
+            wireframe: settings.wireframe,
+            materials: self.scene.metal_rough_material(),
+            swapchain: &self.swapchain,
+            view_proj: self.scene.view_proj(),
+            draw_ctx: &self.scene.main_draw_ctx,
+            indirect_draws: indirect_draws.as_ref(),
             global_desc,
+            ibl_desc: self.ibl.set(),
+        };
+        let draw_geometries = |pass| {
+            current_frame.draw_geometries(
+                pass,
+                &geometry_inputs,
+                #[cfg(feature = "vulkan_stats")]
+                &mut stats.borrow_mut(),
+            );
+        };
+
+        let mut geometry = graph.add_pass();
+        geometry = match (gbuffer_imgs, msaa_imgs) {
+            (Some(gbuffer_imgs), _) => gbuffer_imgs
+                .into_iter()
+                .fold(geometry, |pass, img| {
+                    pass.image(img, ImageUsage::ColorAttachment)
+                })
+                .image(depth_img, ImageUsage::DepthAttachment),
+            (None, Some((msaa_color, msaa_depth))) => geometry
+                .image(msaa_color, ImageUsage::ColorAttachment)
+                .image(msaa_depth, ImageUsage::DepthAttachment)
+                .image(draw_img, ImageUsage::ColorAttachment)
+                .image(depth_img, ImageUsage::DepthResolve),
+            (None, None) => geometry
+                .image(draw_img, ImageUsage::ColorAttachment)
+                .image(depth_img, ImageUsage::DepthAttachment),
+        };
+        if let Some((commands, counts)) = indirect_buffers {
+            geometry = geometry
+                .buffer(commands, BufferUsage::IndirectRead)
+                .buffer(counts, BufferUsage::IndirectRead);
+        }
+        geometry.record(|| {
             #[cfg(feature = "vulkan_stats")]
-            &mut self.stats,
-        );
+            current_frame.begin_pass(GpuPass::Geometry);
+            draw_geometries(match gbuffer {
+                Some(_) => GeometryPass::GBuffer,
+                None => GeometryPass::Forward,
+            });
+            #[cfg(feature = "vulkan_stats")]
+            current_frame.end_pass(GpuPass::Geometry);
+        });
 
-        current_frame.transition_image(
-            *image,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-        );
-        current_frame.transition_image(
-            *swapchain_image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        );
+        if let (Some(gbuffer), Some(gbuffer_imgs), Some([occlusion, blurred])) =
+            (gbuffer, gbuffer_imgs, ao_imgs)
+        {
+            if ambient_occlusion {
+                graph
+                    .add_pass()
+                    .image(gbuffer_imgs[1], ImageUsage::Storage)
+                    .image(depth_img, ImageUsage::DepthRead)
+                    .image(occlusion, ImageUsage::Storage)
+                    .image(blurred, ImageUsage::Storage)
+                    .record(|| {
+                        #[cfg(feature = "vulkan_stats")]
+                        current_frame.begin_pass(GpuPass::AmbientOcclusion);
+                        current_frame.ambient_occlusion(
+                            &self.ambient_occlusion,
+                            &gbuffer.ao,
+                            global_desc,
+                            &AoPushConstants::new(&settings.ao, self.swapchain.draw_extent()),
+                        );
+                        #[cfg(feature = "vulkan_stats")]
+                        current_frame.end_pass(GpuPass::AmbientOcclusion);
+                    });
+            }
 
-        current_frame.copy_img(
-            *image,
-            *swapchain_image,
-            self.swapchain.draw_extent(),
-            self.swapchain.swapchain_extent(),
-        );
+            graph
+                .add_pass()
+                .image(gbuffer_imgs[0], ImageUsage::Storage)
+                .image(gbuffer_imgs[1], ImageUsage::Storage)
+                .image(gbuffer_imgs[2], ImageUsage::Storage)
+                .image(depth_img, ImageUsage::DepthRead)
+                .image(occlusion, ImageUsage::Storage)
+                .image(draw_img, ImageUsage::Storage)
+                .record(|| {
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.begin_pass(GpuPass::Lighting);
+                    current_frame.deferred_lighting(
+                        &self.deferred_lighting,
+                        gbuffer,
+                        global_desc,
+                        self.ibl.set(),
+                        &LightingPushConstants::new(
+                            self.scene.proj(),
+                            self.swapchain.draw_extent(),
+                            gbuffer_view,
+                            ambient_occlusion,
+                        ),
+                    );
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.end_pass(GpuPass::Lighting);
+                });
 
-        current_frame.transition_image(
-            *swapchain_image,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        );
+            if gbuffer_view == GBufferView::Off {
+                let mut transparent = graph
+                    .add_pass()
+                    .image(draw_img, ImageUsage::ColorAttachment)
+                    .image(depth_img, ImageUsage::DepthAttachment);
+                if let Some((commands, counts)) = indirect_buffers {
+                    transparent = transparent
+                        .buffer(commands, BufferUsage::IndirectRead)
+                        .buffer(counts, BufferUsage::IndirectRead);
+                }
+                transparent.record(|| {
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.begin_pass(GpuPass::Transparent);
+                    draw_geometries(GeometryPass::Transparent);
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.end_pass(GpuPass::Transparent);
+                });
+            }
+        }
 
-        current_frame.draw_gui(
-            &self.swapchain,
-            &self.gui,
-            self.commands.queue,
-            *swapchain_image_view,
-            generated_ui,
-        );
+        if let (true, Some(pyramid_img)) = (build_pyramid, pyramid_img) {
+            graph
+                .add_pass()
+                .image(depth_img, ImageUsage::DepthRead)
+                .image(pyramid_img, ImageUsage::Storage)
+                .record(|| {
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.begin_pass(GpuPass::DepthPyramid);
+                    current_frame.build_depth_pyramid(&self.swapchain);
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.end_pass(GpuPass::DepthPyramid);
+                });
+        }
 
-        current_frame.transition_image(
-            *swapchain_image,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            vk::ImageLayout::PRESENT_SRC_KHR,
-        );
-        current_frame.end_cmd_buf();
-        current_frame.submit(sem_render, self.commands.queue);
-        self.swapchain
-            .present(swapchain_img_index, sem_render, self.commands.queue);
+        if shaded && gbuffer_view == GBufferView::Off {
+            // Bound by the tone mapping even without bloom.
+            let bloom_img = graph.import_image(
+                self.swapchain.post_process.bloom_img(),
+                vk::ImageAspectFlags::COLOR,
+                None,
+            );
+            graph
+                .add_pass()
+                .image(draw_img, ImageUsage::Storage)
+                .image(bloom_img, ImageUsage::Storage)
+                .record(|| {
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.begin_pass(GpuPass::PostProcess);
+                    current_frame.post_process(&self.swapchain, &self.settings.post, delta_secs);
+                    #[cfg(feature = "vulkan_stats")]
+                    current_frame.end_pass(GpuPass::PostProcess);
+                });
+        }
 
-        self.commands
-            .current_frame_mut()
-            .push_buffer_in_use(buffer_in_use);
-        self.commands.frame_number += 1;
+        let target_extent = self.swapchain.swapchain_extent();
+        let target_img = match &target {
+            FrameTarget::Swapchain { image, .. } => graph.import_image(
+                *image,
+                vk::ImageAspectFlags::COLOR,
+                Some(ImageUsage::Present),
+            ),
+            FrameTarget::Offscreen => graph.transient_image(
+                OffscreenTarget::FORMAT,
+                target_extent,
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC,
+            ),
+        };
+        // The values declared after the graph are moved in the passes.
+        let (draw_vk_img, target_vk_img) = (graph.image(draw_img), graph.image(target_img));
+        let draw_extent = self.swapchain.draw_extent();
+        graph
+            .add_pass()
+            .image(draw_img, ImageUsage::TransferSrc)
+            .image(target_img, ImageUsage::TransferDst)
+            .record(move || {
+                current_frame.copy_img(draw_vk_img, target_vk_img, draw_extent, target_extent);
+            });
+
+        match target {
+            FrameTarget::Swapchain {
+                view, generated_ui, ..
+            } => {
+                let (swapchain, gui) = (&self.swapchain, self.gui.as_ref().unwrap());
+                let queue = self.commands.queue;
+                graph
+                    .add_pass()
+                    .image(target_img, ImageUsage::ColorAttachment)
+                    .record(move || {
+                        #[cfg(feature = "vulkan_stats")]
+                        current_frame.begin_pass(GpuPass::Gui);
+                        current_frame.draw_gui(swapchain, gui, queue, view, generated_ui);
+                        #[cfg(feature = "vulkan_stats")]
+                        current_frame.end_pass(GpuPass::Gui);
+                    });
+            }
+            FrameTarget::Offscreen => {
+                let readback = self.swapchain.offscreen().readback.buffer;
+                let readback_id = graph.import_buffer(readback, Some(BufferUsage::HostRead));
+                graph
+                    .add_pass()
+                    .image(target_img, ImageUsage::TransferSrc)
+                    .buffer(readback_id, BufferUsage::TransferDst)
+                    .record(move || {
+                        current_frame.copy_img_to_buffer(target_vk_img, readback, target_extent);
+                    });
+            }
+        }
+
+        graph.execute();
+        #[cfg(feature = "vulkan_stats")]
+        {
+            self.stats.compute_shaders_micros = compute_shaders_micros.get();
+        }
+    }
+
+    /// The ambient occlusion only runs in the deferred lighting.
+    pub fn supports_ambient_occlusion(&self) -> bool {
+        self.settings.shading.shading == Shading::Deferred
     }
 
     pub fn on_window_event(&mut self, event: &WindowEvent) {
-        self.gui.on_window_event(event);
+        let consumed = self.gui.as_mut().unwrap().on_window_event(event);
+        if !consumed
+            && let WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } = event
+        {
+            self.pick_requested = true;
+        }
     }
 
     pub fn on_mouse_motion(&mut self, delta: (f64, f64), cursor_grabbed: bool) {
         if !cursor_grabbed {
-            self.gui.on_mouse_motion(delta);
+            self.gui.as_mut().unwrap().on_mouse_motion(delta);
         }
     }
 
-    fn update_scene(&mut self, camera: &Camera) {
+    fn update_scene(&mut self, camera: &Camera, settings: &Settings) {
+        self.scene
+            .update_lights(&self.current_scene, &self.settings.lights);
         self.scene.update_scene(
             self.swapchain.draw_extent(),
             camera.view_mat(),
             &self.current_scene,
+            settings,
+            self.settings.frustum_culling == FrustumCulling::Bvh,
+            &self.settings.ibl,
         );
     }
 }
 
-fn ui<'a>(
-    ctx: &egui::Context,
+/// What the engine lends to [`ui`].
+struct UiState<'u, 's> {
+    base: &'u VulkanBase,
     debug: String,
-    current_bg_effect: &mut usize,
-    render_scale: &mut f32,
-    bg_effects: &[ComputeEffect],
-    bg_effects_data: &mut [ComputePushConstants],
-    current_scene: &mut String,
-    scenes: &HashMap<String, LoadedGLTF>,
-    settings: &mut VulkanSettings,
-    selected_node: &mut String,
-) {
+    current_bg_effect: &'u mut usize,
+    render_scale: &'u mut f32,
+    bg_effects: &'u [ComputeEffect],
+    bg_effects_data: &'u mut [ComputePushConstants],
+    current_scene: &'u mut String,
+    scene: &'u Scene<'s>,
+    /// Set to the name of the scene to unload.
+    unload: &'u mut Option<String>,
+    settings: &'u mut VulkanSettings,
+    global_settings: &'u mut Settings,
+    selected_node: &'u mut String,
+    transform_editor: &'u mut TransformEditor,
+    view_proj: &'u Mat4,
+    environments: &'u [String],
+    #[cfg(feature = "vulkan_stats")]
+    gpu_timings: &'u GpuTimingsHistory,
+}
+
+fn ui(ctx: &egui::Context, state: UiState) {
+    let UiState {
+        base,
+        debug,
+        current_bg_effect,
+        render_scale,
+        bg_effects,
+        bg_effects_data,
+        current_scene,
+        scene,
+        unload,
+        settings,
+        global_settings,
+        selected_node,
+        transform_editor,
+        view_proj,
+        environments,
+        #[cfg(feature = "vulkan_stats")]
+        gpu_timings,
+    } = state;
     egui::Window::new("Debug")
         .default_open(false)
-        .show(ctx, |ui| ui.label(debug));
+        .show(ctx, |ui| {
+            ui.label(debug);
+            #[cfg(feature = "vulkan_stats")]
+            gpu_timings.ui(ui);
+        });
     egui::Window::new("Settings")
         .default_open(false)
         .show(ctx, |ui| {
@@ -457,8 +1123,93 @@ fn ui<'a>(
                 ),
             );
             ui.add(egui::Slider::new(render_scale, 0.3..=1.).text("Render scale"));
+            settings.shading.ui(ui);
+            ui.add_enabled_ui(settings.shading.shading == Shading::Forward, |ui| {
+                settings.msaa.ui(ui, base);
+            });
+            settings.present.ui(ui, base);
+            ui.label(format!("GPU : {}", base.gpus[base.gpu_index]))
+                .on_hover_text(format!(
+                    "Chosen on start with `--gpu <index|name>` or `GRAPHICS_GPU` :\n{}",
+                    base.gpus
+                        .iter()
+                        .enumerate()
+                        .map(|(i, name)| format!("{i} : {name}"))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                ));
             ui.checkbox(&mut settings.rebinding, "Rebinding");
-            ui.checkbox(&mut settings.frustum_culling, "Frustum culling");
+            {
+                ui.label("Draw path :");
+                ui.radio_value(&mut settings.draw_path, DrawPath::Cpu, "CPU lists");
+                ui.radio_value(&mut settings.draw_path, DrawPath::GpuDriven, "GPU driven");
+                ui.add_enabled(
+                    settings.draw_path == DrawPath::Cpu,
+                    egui::Checkbox::new(&mut settings.instancing, "Instancing"),
+                );
+                ui.add_enabled(
+                    settings.draw_path == DrawPath::GpuDriven,
+                    egui::Checkbox::new(&mut settings.occlusion_culling, "Occlusion culling"),
+                );
+            }
+            {
+                ui.label("Frustum culling :");
+                ui.radio_value(&mut settings.frustum_culling, FrustumCulling::Off, "off");
+                ui.radio_value(
+                    &mut settings.frustum_culling,
+                    FrustumCulling::Linear,
+                    "linear",
+                );
+                ui.radio_value(&mut settings.frustum_culling, FrustumCulling::Bvh, "BVH");
+            }
+            {
+                ui.checkbox(&mut global_settings.lod.enabled, "Levels of detail");
+                ui.add_enabled(
+                    global_settings.lod.enabled,
+                    egui::Slider::new(&mut global_settings.lod.pixel_error, 0.125..=64.)
+                        .logarithmic(true)
+                        .text("LOD pixel error"),
+                );
+            }
+            {
+                let ao = &mut global_settings.ao;
+                // The forward shading has no depth and normals to sample before the lighting.
+                ui.add_enabled_ui(settings.shading.shading == Shading::Deferred, |ui| {
+                    ui.checkbox(&mut ao.enabled, "Ambient occlusion")
+                        .on_disabled_hover_text("Only with the deferred shading");
+                    ui.add_enabled_ui(ao.enabled, |ui| {
+                        ui.add(
+                            egui::Slider::new(&mut ao.radius, 0.0625..=8.)
+                                .logarithmic(true)
+                                .text("AO radius"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ao.intensity, 0.25..=4.).text("AO intensity"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut ao.samples, 1..=AoSettings::MAX_SAMPLES)
+                                .text("AO samples"),
+                        );
+                    });
+                });
+            }
+            egui::ComboBox::from_label("View mode")
+                .selected_text(format!("{:?}", global_settings.view_mode))
+                .show_ui(ui, |ui| {
+                    ViewMode::ALL.iter().for_each(|m| {
+                        ui.selectable_value(&mut global_settings.view_mode, *m, format!("{m:?}"));
+                    });
+                });
+            {
+                ui.label("Wireframe :");
+                ui.radio_value(&mut global_settings.wireframe, Wireframe::Off, "off");
+                ui.radio_value(
+                    &mut global_settings.wireframe,
+                    Wireframe::Overlay,
+                    "overlay",
+                );
+                ui.radio_value(&mut global_settings.wireframe, Wireframe::Only, "only");
+            }
             {
                 ui.label("Opaque sorting :");
                 ui.radio_value(&mut settings.opaque_sorting, MeshSorting::Off, "off");
@@ -490,131 +1241,29 @@ fn ui<'a>(
             if !bg_effects.is_empty() {
                 ui.label("Selected effect :");
                 bg_effects.iter().enumerate().for_each(|(i, n)| {
-                    ui.radio_value(current_bg_effect, i, n.name.into_str());
+                    ui.radio_value(current_bg_effect, i, &n.name);
                 });
 
-                let current_bg_effect_data = &mut bg_effects_data[*current_bg_effect];
-                egui::Grid::new("data").num_columns(5).show(ui, |ui| {
-                    ui.label("Data 0");
-                    current_bg_effect_data.data0.iter_mut().for_each(|d| {
-                        ui.add(egui::DragValue::new(d).speed(0.01).range(0.0..=1.0));
-                    });
-                    ui.end_row();
-
-                    ui.label("Data 1");
-                    current_bg_effect_data.data1.iter_mut().for_each(|d| {
-                        ui.add(egui::DragValue::new(d).speed(0.01).range(0.0..=1.0));
-                    });
-                    ui.end_row();
-
-                    ui.label("Data 2");
-                    current_bg_effect_data.data2.iter_mut().for_each(|d| {
-                        ui.add(egui::DragValue::new(d).speed(0.01).range(0.0..=1.0));
-                    });
-                    ui.end_row();
-
-                    ui.label("Data 3");
-                    current_bg_effect_data.data3.iter_mut().for_each(|d| {
-                        ui.add(egui::DragValue::new(d).speed(0.01).range(0.0..=1.0));
-                    });
-                });
+                ui.separator();
+                bg_effects[*current_bg_effect].ui(ui, &mut bg_effects_data[*current_bg_effect]);
             }
         });
     egui::Window::new("Scene")
         .default_open(false)
         .show(ctx, |ui| {
-            ui.label("Selected scene :");
-            scenes.keys().for_each(|n| {
-                ui.radio_value(current_scene, n.clone(), n);
-            });
-        });
-    egui::Window::new("Scene nodes")
-        .default_open(false)
-        .show(ctx, |ui| {
-            let scene = scenes.get(current_scene).unwrap();
-            egui::ScrollArea::vertical()
-                .max_height(200.)
-                .auto_shrink(true)
-                .show(ui, |ui| {
-                    scene.nodes.iter().for_each(|(k, _)| {
-                        ui.radio_value(selected_node, k.clone(), k);
-                    });
-                });
-
-            // If the selected node exists (from this scene).
-            if let Some(node) = scene.nodes.get(selected_node) {
-                let mut node = node.borrow_mut();
-                let node_data = node.node_data_mut();
-                let local_tr_copy = node_data.local_transform;
-                egui::Grid::new("data").num_columns(4).show(ui, |ui| {
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.x_axis.x).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.y_axis.x).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.z_axis.x).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.w_axis.x).speed(0.01),
-                    );
-                    ui.end_row();
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.x_axis.y).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.y_axis.y).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.z_axis.y).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.w_axis.y).speed(0.01),
-                    );
-                    ui.end_row();
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.x_axis.z).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.y_axis.z).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.z_axis.z).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.w_axis.z).speed(0.01),
-                    );
-                    ui.end_row();
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.x_axis.w).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.y_axis.w).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.z_axis.w).speed(0.01),
-                    );
-                    ui.add(
-                        egui::DragValue::new(&mut node_data.local_transform.w_axis.w).speed(0.01),
-                    );
-                    ui.end_row();
-                });
-                if ui.button("rotate").clicked() {
-                    node_data.local_transform =
-                        Mat4::from_rotation_x(1.) * node_data.local_transform;
-                }
-                // If parameters were modified, update it (and its children) :
-                if node_data.local_transform != local_tr_copy {
-                    let parent_transform = if let Some(parent) = node_data.parent.upgrade() {
-                        parent.borrow().node_data().world_transform
-                    } else {
-                        Mat4::IDENTITY
-                    };
-                    node.refresh_transform(&parent_transform);
-                }
-            }
+            scene.ui(ui, current_scene, unload);
         });
+    settings.post.ui(ctx);
+    settings.ibl.ui(ctx, environments);
+    settings.lights.ui(ctx);
+    transform_editor.ui(
+        ctx,
+        scene
+            .loaded(current_scene)
+            .map_or(&HashMap::new(), |s| &s.nodes),
+        selected_node,
+        view_proj,
+    );
 }
 
 fn format_debug(
@@ -0,0 +1,230 @@
+//! Multisample anti-aliasing of the geometry pass.
+//!
+//! The geometry is drawn in multisampled colour and depth attachments, resolved into `draw_img`
+//! and `depth_img` at the end of the pass. The background is written in `draw_img` by a compute
+//! shader, so it is first copied to every sample by a fullscreen triangle.
+
+use std::{
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+
+use super::{
+    allocated::AllocatedImage,
+    base::VulkanBase,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    gfx_pipeline::PipelineBuilder,
+    shaders_loader::{ShaderName, ShadersLoader},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msaa {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Msaa {
+    pub const ALL: [Msaa; 4] = [Msaa::Off, Msaa::X2, Msaa::X4, Msaa::X8];
+
+    pub fn samples(self) -> vk::SampleCountFlags {
+        match self {
+            Msaa::Off => vk::SampleCountFlags::TYPE_1,
+            Msaa::X2 => vk::SampleCountFlags::TYPE_2,
+            Msaa::X4 => vk::SampleCountFlags::TYPE_4,
+            Msaa::X8 => vk::SampleCountFlags::TYPE_8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsaaSettings {
+    pub msaa: Msaa,
+    /// Shades every sample instead of every pixel, also smoothing the edges inside the
+    /// textures and the specular highlights.
+    pub sample_shading: bool,
+}
+
+impl Default for MsaaSettings {
+    fn default() -> Self {
+        Self {
+            msaa: Msaa::Off,
+            sample_shading: false,
+        }
+    }
+}
+
+impl MsaaSettings {
+    /// Only the sample counts supported by `base` can be selected.
+    pub fn ui(&mut self, ui: &mut egui::Ui, base: &VulkanBase) {
+        egui::ComboBox::from_label("MSAA")
+            .selected_text(format!("{:?}", self.msaa))
+            .show_ui(ui, |ui| {
+                Msaa::ALL
+                    .iter()
+                    .filter(|m| base.msaa_samples.contains(m.samples()))
+                    .for_each(|m| {
+                        ui.selectable_value(&mut self.msaa, *m, format!("{m:?}"));
+                    });
+            });
+        ui.add_enabled(
+            self.msaa != Msaa::Off && base.sample_rate_shading,
+            egui::Checkbox::new(&mut self.sample_shading, "Sample-rate shading"),
+        );
+    }
+
+    /// Of the geometry pipelines, sample-rate shading is ignored without the feature.
+    pub fn pipeline_multisampling(&self, base: &VulkanBase) -> (vk::SampleCountFlags, bool) {
+        let samples = self.msaa.samples();
+        let sample_shading =
+            self.sample_shading && base.sample_rate_shading && self.msaa != Msaa::Off;
+        (samples, sample_shading)
+    }
+}
+
+/// Multisampled attachments of the geometry pass, and the copy of the background into them.
+///
+/// Reads `draw_img` and has its size, so needs to be recreated with it, and when the sample
+/// count changes.
+pub struct MsaaTargets {
+    device_copy: Rc<Device>,
+
+    pub samples: vk::SampleCountFlags,
+    pub color: AllocatedImage,
+    pub depth: AllocatedImage,
+    /// Of the depth into `depth_img`, the farthest sample when supported so that the occlusion
+    /// culling stays conservative.
+    pub depth_resolve: vk::ResolveModeFlags,
+
+    _descriptor: DescriptorAllocator,
+    sampler: vk::Sampler,
+    desc_layout: vk::DescriptorSetLayout,
+    pub background_desc: vk::DescriptorSet,
+    pub background_layout: vk::PipelineLayout,
+    pub background_pipeline: vk::Pipeline,
+}
+
+impl MsaaTargets {
+    pub fn new(
+        base: &VulkanBase,
+        shaders: &ShadersLoader,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        draw_img: &AllocatedImage,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        let device = base.device.clone();
+        let color = AllocatedImage::new_multisampled(
+            device.clone(),
+            allocator.clone(),
+            draw_img.extent,
+            draw_img.format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            samples,
+        );
+        let depth = AllocatedImage::new_multisampled(
+            device.clone(),
+            allocator,
+            draw_img.extent,
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            samples,
+        );
+
+        let depth_resolve = {
+            let mut resolve_props = vk::PhysicalDeviceDepthStencilResolveProperties::default();
+            let mut props = vk::PhysicalDeviceProperties2::default().push_next(&mut resolve_props);
+            unsafe {
+                base.instance
+                    .get_physical_device_properties2(base.chosen_gpu, &mut props)
+            };
+            // Reversed depth, the farthest is the smallest. The first sample is always supported.
+            if resolve_props
+                .supported_depth_resolve_modes
+                .contains(vk::ResolveModeFlags::MIN)
+            {
+                vk::ResolveModeFlags::MIN
+            } else {
+                vk::ResolveModeFlags::SAMPLE_ZERO
+            }
+        };
+
+        let sampler = {
+            let create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST);
+            unsafe { device.create_sampler(&create_info, None).unwrap() }
+        };
+        let sizes = [(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1.)];
+        let descriptor = DescriptorAllocator::new(device.clone(), 1, &sizes[..]);
+        let desc_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .build(&device, vk::ShaderStageFlags::FRAGMENT);
+        let background_desc = descriptor.allocate(desc_layout);
+        {
+            let mut writer = DescriptorWriter::default();
+            writer.write_image(
+                0,
+                draw_img.img_view,
+                sampler,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.update_set(&device, background_desc);
+        }
+
+        let background_layout = {
+            let layouts = [desc_layout];
+            let create_info = vk::PipelineLayoutCreateInfo::default().set_layouts(&layouts[..]);
+            unsafe { device.create_pipeline_layout(&create_info, None).unwrap() }
+        };
+        let background_pipeline = {
+            let vert = shaders.get(ShaderName::FullscreenVert);
+            let frag = shaders.get(ShaderName::MsaaBackgroundFrag);
+            let mut builder = PipelineBuilder::new(background_layout);
+            builder.set_shaders(&vert, &frag);
+            builder.set_input_topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+            builder.set_polygon_mode(vk::PolygonMode::FILL);
+            builder.set_cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE);
+            // Each sample covered by the triangle gets the colour of its pixel.
+            builder.set_multisampling(samples, false);
+            builder.disable_blending();
+            let formats = [draw_img.format];
+            builder.set_color_attachment_format(&formats[..]);
+            builder.build(&device, shaders.pipeline_cache())
+        };
+
+        Self {
+            device_copy: device,
+            samples,
+            color,
+            depth,
+            depth_resolve,
+            _descriptor: descriptor,
+            sampler,
+            desc_layout,
+            background_desc,
+            background_layout,
+            background_pipeline,
+        }
+    }
+}
+
+impl Drop for MsaaTargets {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop MsaaTargets");
+        unsafe {
+            self.device_copy
+                .destroy_pipeline(self.background_pipeline, None);
+            self.device_copy
+                .destroy_pipeline_layout(self.background_layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.desc_layout, None);
+            self.device_copy.destroy_sampler(self.sampler, None);
+        }
+    }
+}
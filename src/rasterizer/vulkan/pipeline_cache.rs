@@ -0,0 +1,81 @@
+//! `VkPipelineCache` loaded at start and saved on drop, to speed up the pipelines creation.
+//!
+//! The saved file starts with the vendor, device, driver version and pipeline cache UUID of the
+//! GPU, and is ignored if any of them changed.
+
+use std::{fs, path::Path, rc::Rc};
+
+use ash::{Device, vk};
+
+use super::base::VulkanBase;
+
+const CACHE_FOLDER: &str = "./cache/";
+const PIPELINE_CACHE_FILE: &str = "pipeline_cache.bin";
+
+/// `vendor_id`, `device_id`, `driver_version` and `pipeline_cache_uuid`.
+const HEADER_LEN: usize = 3 * 4 + vk::UUID_SIZE;
+
+pub struct PipelineCache {
+    device_copy: Rc<Device>,
+    pub cache: vk::PipelineCache,
+    header: [u8; HEADER_LEN],
+}
+
+impl PipelineCache {
+    pub fn new(base: &VulkanBase) -> Self {
+        let properties = unsafe {
+            base.instance
+                .get_physical_device_properties(base.chosen_gpu)
+        };
+        let mut header = [0; HEADER_LEN];
+        header[0..4].copy_from_slice(&properties.vendor_id.to_le_bytes());
+        header[4..8].copy_from_slice(&properties.device_id.to_le_bytes());
+        header[8..12].copy_from_slice(&properties.driver_version.to_le_bytes());
+        header[12..].copy_from_slice(&properties.pipeline_cache_uuid);
+
+        let saved = fs::read(Path::new(CACHE_FOLDER).join(PIPELINE_CACHE_FILE)).unwrap_or_default();
+        let initial_data = match saved.split_at_checked(HEADER_LEN) {
+            Some((saved_header, data)) if saved_header == header => data,
+            _ => &[],
+        };
+
+        let create_info = vk::PipelineCacheCreateInfo::default().initial_data(initial_data);
+        let cache = unsafe {
+            base.device
+                .create_pipeline_cache(&create_info, None)
+                .unwrap()
+        };
+
+        Self {
+            device_copy: base.device.clone(),
+            cache,
+            header,
+        }
+    }
+
+    /// Failing to save only costs slower pipeline creations at the next start.
+    fn save(&self) {
+        let data = unsafe {
+            self.device_copy
+                .get_pipeline_cache_data(self.cache)
+                .unwrap()
+        };
+        let bytes = [&self.header[..], &data[..]].concat();
+        if let Err(e) = fs::create_dir_all(CACHE_FOLDER)
+            .and_then(|()| fs::write(Path::new(CACHE_FOLDER).join(PIPELINE_CACHE_FILE), bytes))
+        {
+            eprintln!("Couldn't save the pipeline cache : {e}");
+        }
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop PipelineCache");
+        self.save();
+        unsafe {
+            self.device_copy.destroy_pipeline_cache(self.cache, None);
+        }
+    }
+}
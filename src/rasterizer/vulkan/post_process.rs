@@ -0,0 +1,537 @@
+//! HDR post-processing, compute passes on `draw_img` before its copy to the swapchain.
+//!
+//! 1. A histogram of the log luminance, averaged and smoothed over time for the auto-exposure.
+//! 2. The bloom : the bright parts are downsampled in a mip chain, then upsampled back and
+//!    accumulated.
+//! 3. The tone mapping, applying the exposure, the bloom, the colour grading and the gamma in
+//!    place, so `draw_img` ends in `[0, 1]`, then encoding it for the swapchain, see
+//!    [`OutputTransfer`].
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+
+use super::{
+    allocated::{AllocatedBuffer, AllocatedImage, MyMemoryUsage},
+    commands::image_subresource_range,
+    compute_shaders::compute_pipeline,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    present::OutputTransfer,
+    shaders_loader::{ShaderName, ShadersLoader},
+};
+
+/// See `local_size_x` and `local_size_y` in the `resources/{luminance_histogram,bloom_*,tone_map}`
+/// shaders.
+pub const POST_GROUP_SIZE: u32 = 16;
+/// See `BINS` in `resources/luminance_{histogram,average}.comp.glsl`.
+const HISTOGRAM_BINS: usize = 256;
+/// Luminances out of the histogram range are clamped.
+const MIN_LOG_LUMINANCE: f32 = -10.;
+const LOG_LUMINANCE_RANGE: f32 = 22.;
+/// Levels of the bloom chain, the first being half the size of `draw_img`.
+const BLOOM_MIPS: u32 = 6;
+
+/// See `TONE_MAPPER_*` in `resources/tone_map.comp.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// Clamped.
+    Off,
+    Reinhard,
+    Aces,
+    AgX,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 4] = [Self::Off, Self::Reinhard, Self::Aces, Self::AgX];
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PostSettings {
+    pub bloom: bool,
+    /// Luminance from which the pixels bloom.
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    /// Of the upsampling filter, in texels.
+    pub bloom_radius: f32,
+    pub auto_exposure: bool,
+    /// Compensation in EV with `auto_exposure`, the exposure itself otherwise.
+    pub exposure_ev: f32,
+    /// Of the auto-exposure, per second.
+    pub adaptation_speed: f32,
+    pub tone_mapper: ToneMapper,
+    pub grading: bool,
+    pub contrast: f32,
+    pub saturation: f32,
+    /// Multiplies the colour.
+    pub tint: [f32; 3],
+    /// 1 when the swapchain format or the [`OutputTransfer`] already encodes it.
+    pub gamma: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            bloom: true,
+            bloom_threshold: 1.,
+            bloom_intensity: 0.04,
+            bloom_radius: 1.,
+            auto_exposure: true,
+            exposure_ev: 0.,
+            adaptation_speed: 1.5,
+            tone_mapper: ToneMapper::Aces,
+            grading: false,
+            contrast: 1.,
+            saturation: 1.,
+            tint: [1.; 3],
+            gamma: 1.,
+        }
+    }
+}
+
+impl PostSettings {
+    pub fn ui(&mut self, ctx: &egui::Context) {
+        const EV_RANGE: std::ops::RangeInclusive<f32> = -8.0..=8.0;
+        egui::Window::new("Post-processing")
+            .default_open(false)
+            .show(ctx, |ui| {
+                ui.checkbox(&mut self.auto_exposure, "Auto exposure");
+                ui.add(egui::Slider::new(&mut self.exposure_ev, EV_RANGE).text("Exposure (EV)"));
+                ui.add_enabled(
+                    self.auto_exposure,
+                    egui::Slider::new(&mut self.adaptation_speed, 0.1..=10.).text("Adaptation"),
+                );
+                ui.separator();
+
+                ui.checkbox(&mut self.bloom, "Bloom");
+                ui.add_enabled_ui(self.bloom, |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut self.bloom_threshold, 0.0..=10.).text("Threshold"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut self.bloom_intensity, 0.0..=1.).text("Intensity"),
+                    );
+                    ui.add(egui::Slider::new(&mut self.bloom_radius, 0.5..=4.).text("Radius"));
+                });
+                ui.separator();
+
+                egui::ComboBox::from_label("Tone mapping")
+                    .selected_text(format!("{:?}", self.tone_mapper))
+                    .show_ui(ui, |ui| {
+                        ToneMapper::ALL.iter().for_each(|m| {
+                            ui.selectable_value(&mut self.tone_mapper, *m, format!("{m:?}"));
+                        });
+                    });
+                ui.separator();
+
+                ui.checkbox(&mut self.grading, "Colour grading");
+                ui.add_enabled_ui(self.grading, |ui| {
+                    ui.add(egui::Slider::new(&mut self.contrast, 0.0..=2.).text("Contrast"));
+                    ui.add(egui::Slider::new(&mut self.saturation, 0.0..=2.).text("Saturation"));
+                    ui.horizontal(|ui| {
+                        ui.label("Tint");
+                        ui.color_edit_button_rgb(&mut self.tint);
+                    });
+                });
+                ui.add(egui::Slider::new(&mut self.gamma, 1.0..=3.).text("Gamma"));
+            });
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct HistogramPushConstants {
+    pub histogram: vk::DeviceAddress,
+    pub size: [u32; 2],
+    pub min_log_luminance: f32,
+    pub inv_log_luminance_range: f32,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct AveragePushConstants {
+    pub histogram: vk::DeviceAddress,
+    pub exposure: vk::DeviceAddress,
+    pub pixel_count: u32,
+    pub min_log_luminance: f32,
+    pub log_luminance_range: f32,
+    /// Part of the new luminance kept this frame, in `[0, 1]`.
+    pub adaptation: f32,
+    pub exposure_ev: f32,
+    /// Booleans.
+    pub auto_exposure: u32,
+    pub reset: u32,
+}
+
+/// Shared by the downsampling and the upsampling.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct BloomPushConstants {
+    /// Used part of the source.
+    pub src_size: [u32; 2],
+    pub dst_size: [u32; 2],
+    pub threshold: f32,
+    pub radius: f32,
+    /// Boolean, the source is `draw_img`.
+    pub first_pass: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ToneMapPushConstants {
+    pub exposure: vk::DeviceAddress,
+    pub size: [u32; 2],
+    pub tint: [f32; 4],
+    pub tone_mapper: u32,
+    /// Boolean.
+    pub bloom: u32,
+    pub bloom_intensity: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    pub gamma: f32,
+    pub output_transfer: u32,
+    /// In nits, for the HDR outputs.
+    pub paper_white: f32,
+}
+
+/// Post-processing pipelines, their bloom chain and exposure buffers.
+///
+/// Reads `draw_img`, so needs to be recreated with it.
+pub struct PostProcess {
+    device_copy: Rc<Device>,
+
+    _descriptor: DescriptorAllocator,
+    sampler: vk::Sampler,
+
+    bloom: AllocatedImage,
+    bloom_extent: vk::Extent2D,
+    /// One view per mip, the image view covers all of them.
+    bloom_mips: Vec<vk::ImageView>,
+
+    /// One counter per bin, cleared each frame.
+    pub histogram: AllocatedBuffer,
+    /// Adapted luminance and exposure, kept between frames.
+    _exposure: AllocatedBuffer,
+    pub histogram_address: vk::DeviceAddress,
+    pub exposure_address: vk::DeviceAddress,
+    /// Set once the exposure is written, the next frames adapt it.
+    exposure_initialized: RefCell<bool>,
+
+    image_desc_layout: vk::DescriptorSetLayout,
+    pub histogram_desc: vk::DescriptorSet,
+    pub histogram_layout: vk::PipelineLayout,
+    pub histogram_pipeline: vk::Pipeline,
+    pub average_layout: vk::PipelineLayout,
+    pub average_pipeline: vk::Pipeline,
+
+    /// Storage image and sampler, for the bloom and the tone mapping.
+    sampled_desc_layout: vk::DescriptorSetLayout,
+    /// Downsamples the mip `i - 1` (or `draw_img`) into the mip `i`.
+    pub downsample_descs: Vec<vk::DescriptorSet>,
+    pub downsample_layout: vk::PipelineLayout,
+    pub downsample_pipeline: vk::Pipeline,
+    /// Upsamples the mip `i + 1` and adds it to the mip `i`.
+    pub upsample_descs: Vec<vk::DescriptorSet>,
+    pub upsample_layout: vk::PipelineLayout,
+    pub upsample_pipeline: vk::Pipeline,
+    pub tone_map_desc: vk::DescriptorSet,
+    pub tone_map_layout: vk::PipelineLayout,
+    pub tone_map_pipeline: vk::Pipeline,
+}
+
+impl PostProcess {
+    pub fn new(
+        device: Rc<Device>,
+        shaders: &ShadersLoader,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        draw_img_view: vk::ImageView,
+        draw_extent: vk::Extent3D,
+    ) -> Self {
+        let bloom_extent = vk::Extent2D {
+            width: (draw_extent.width / 2).max(1),
+            height: (draw_extent.height / 2).max(1),
+        };
+        let bloom =
+            AllocatedImage::new_bloom_chain(device.clone(), allocator.clone(), bloom_extent);
+        let mip_levels = BLOOM_MIPS.min(bloom_extent.width.max(bloom_extent.height).ilog2() + 1);
+        let bloom_mips: Vec<vk::ImageView> = (0..mip_levels)
+            .map(|level| {
+                let create_info = vk::ImageViewCreateInfo::default()
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(bloom.format)
+                    .subresource_range(image_subresource_range(
+                        vk::ImageAspectFlags::COLOR,
+                        1,
+                        level,
+                    ))
+                    .image(bloom.img);
+                unsafe { device.create_image_view(&create_info, None).unwrap() }
+            })
+            .collect();
+
+        let sampler = {
+            let create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(vk::LOD_CLAMP_NONE);
+            unsafe { device.create_sampler(&create_info, None).unwrap() }
+        };
+
+        let storage =
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        let histogram = AllocatedBuffer::new(
+            allocator.clone(),
+            (size_of::<u32>() * HISTOGRAM_BINS) as u64,
+            storage | vk::BufferUsageFlags::TRANSFER_DST,
+            MyMemoryUsage::GpuOnly,
+        );
+        let exposure = AllocatedBuffer::new(
+            allocator,
+            (size_of::<f32>() * 2) as u64,
+            storage,
+            MyMemoryUsage::GpuOnly,
+        );
+
+        let sizes = [
+            (vk::DescriptorType::STORAGE_IMAGE, 1.),
+            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1.),
+        ];
+        let descriptor = DescriptorAllocator::new(device.clone(), 2 * mip_levels + 2, &sizes[..]);
+
+        let image_desc_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::STORAGE_IMAGE)
+            .build(&device, vk::ShaderStageFlags::COMPUTE);
+        let histogram_desc = descriptor.allocate(image_desc_layout);
+        {
+            let mut writer = DescriptorWriter::default();
+            writer.write_image(
+                0,
+                draw_img_view,
+                vk::Sampler::null(),
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::STORAGE_IMAGE,
+            );
+            writer.update_set(&device, histogram_desc);
+        }
+
+        let sampled_desc_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::STORAGE_IMAGE)
+            .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .build(&device, vk::ShaderStageFlags::COMPUTE);
+        let sampled_desc = |dst: vk::ImageView, src: vk::ImageView| {
+            let desc = descriptor.allocate(sampled_desc_layout);
+            let mut writer = DescriptorWriter::default();
+            writer.write_image(
+                0,
+                dst,
+                vk::Sampler::null(),
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::STORAGE_IMAGE,
+            );
+            writer.write_image(
+                1,
+                src,
+                sampler,
+                vk::ImageLayout::GENERAL,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            );
+            writer.update_set(&device, desc);
+            desc
+        };
+        let downsample_descs = bloom_mips
+            .iter()
+            .enumerate()
+            .map(|(level, mip)| {
+                let src = if level == 0 {
+                    draw_img_view
+                } else {
+                    bloom_mips[level - 1]
+                };
+                sampled_desc(*mip, src)
+            })
+            .collect();
+        let upsample_descs = bloom_mips
+            .windows(2)
+            .map(|mips| sampled_desc(mips[0], mips[1]))
+            .collect();
+        let tone_map_desc = sampled_desc(draw_img_view, bloom_mips[0]);
+
+        let (histogram_layout, histogram_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::LuminanceHistogram,
+            &[image_desc_layout],
+            size_of::<HistogramPushConstants>(),
+        );
+        let (average_layout, average_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::LuminanceAverage,
+            &[],
+            size_of::<AveragePushConstants>(),
+        );
+        let (downsample_layout, downsample_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::BloomDownsample,
+            &[sampled_desc_layout],
+            size_of::<BloomPushConstants>(),
+        );
+        let (upsample_layout, upsample_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::BloomUpsample,
+            &[sampled_desc_layout],
+            size_of::<BloomPushConstants>(),
+        );
+        let (tone_map_layout, tone_map_pipeline) = compute_pipeline(
+            &device,
+            shaders,
+            ShaderName::ToneMap,
+            &[sampled_desc_layout],
+            size_of::<ToneMapPushConstants>(),
+        );
+
+        Self {
+            histogram_address: histogram.device_address(&device),
+            exposure_address: exposure.device_address(&device),
+            device_copy: device,
+            _descriptor: descriptor,
+            sampler,
+            bloom,
+            bloom_extent,
+            bloom_mips,
+            histogram,
+            _exposure: exposure,
+            exposure_initialized: RefCell::new(false),
+            image_desc_layout,
+            histogram_desc,
+            histogram_layout,
+            histogram_pipeline,
+            average_layout,
+            average_pipeline,
+            sampled_desc_layout,
+            downsample_descs,
+            downsample_layout,
+            downsample_pipeline,
+            upsample_descs,
+            upsample_layout,
+            upsample_pipeline,
+            tone_map_desc,
+            tone_map_layout,
+            tone_map_pipeline,
+        }
+    }
+
+    pub fn bloom_img(&self) -> vk::Image {
+        self.bloom.img
+    }
+
+    /// Used part of the bloom mip `level`, when `draw_extent` of `draw_img` is rendered.
+    pub fn bloom_mip_extent(&self, draw_extent: vk::Extent2D, level: usize) -> vk::Extent2D {
+        vk::Extent2D {
+            width: (draw_extent.width >> (level + 1))
+                .clamp(1, (self.bloom_extent.width >> level).max(1)),
+            height: (draw_extent.height >> (level + 1))
+                .clamp(1, (self.bloom_extent.height >> level).max(1)),
+        }
+    }
+
+    pub fn histogram_push_constants(&self, draw_extent: vk::Extent2D) -> HistogramPushConstants {
+        HistogramPushConstants {
+            histogram: self.histogram_address,
+            size: [draw_extent.width, draw_extent.height],
+            min_log_luminance: MIN_LOG_LUMINANCE,
+            inv_log_luminance_range: 1. / LOG_LUMINANCE_RANGE,
+        }
+    }
+
+    /// Resets the exposure the first time.
+    pub fn average_push_constants(
+        &self,
+        settings: &PostSettings,
+        draw_extent: vk::Extent2D,
+        delta_secs: f32,
+    ) -> AveragePushConstants {
+        let reset = !self.exposure_initialized.replace(true);
+        AveragePushConstants {
+            histogram: self.histogram_address,
+            exposure: self.exposure_address,
+            pixel_count: draw_extent.width * draw_extent.height,
+            min_log_luminance: MIN_LOG_LUMINANCE,
+            log_luminance_range: LOG_LUMINANCE_RANGE,
+            adaptation: 1. - (-delta_secs * settings.adaptation_speed).exp(),
+            exposure_ev: settings.exposure_ev,
+            auto_exposure: settings.auto_exposure as u32,
+            reset: reset as u32,
+        }
+    }
+
+    pub fn tone_map_push_constants(
+        &self,
+        settings: &PostSettings,
+        draw_extent: vk::Extent2D,
+        output_transfer: OutputTransfer,
+        paper_white: f32,
+    ) -> ToneMapPushConstants {
+        let (tint, contrast, saturation) = if settings.grading {
+            (settings.tint, settings.contrast, settings.saturation)
+        } else {
+            ([1.; 3], 1., 1.)
+        };
+        ToneMapPushConstants {
+            exposure: self.exposure_address,
+            size: [draw_extent.width, draw_extent.height],
+            tint: [tint[0], tint[1], tint[2], 1.],
+            tone_mapper: settings.tone_mapper as u32,
+            bloom: settings.bloom as u32,
+            bloom_intensity: settings.bloom_intensity,
+            contrast,
+            saturation,
+            gamma: settings.gamma,
+            output_transfer: output_transfer as u32,
+            paper_white,
+        }
+    }
+}
+
+impl Drop for PostProcess {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop PostProcess");
+        unsafe {
+            [
+                self.histogram_pipeline,
+                self.average_pipeline,
+                self.downsample_pipeline,
+                self.upsample_pipeline,
+                self.tone_map_pipeline,
+            ]
+            .into_iter()
+            .for_each(|p| self.device_copy.destroy_pipeline(p, None));
+            [
+                self.histogram_layout,
+                self.average_layout,
+                self.downsample_layout,
+                self.upsample_layout,
+                self.tone_map_layout,
+            ]
+            .into_iter()
+            .for_each(|l| self.device_copy.destroy_pipeline_layout(l, None));
+            self.device_copy
+                .destroy_descriptor_set_layout(self.image_desc_layout, None);
+            self.device_copy
+                .destroy_descriptor_set_layout(self.sampled_desc_layout, None);
+            self.bloom_mips
+                .drain(..)
+                .for_each(|v| self.device_copy.destroy_image_view(v, None));
+            self.device_copy.destroy_sampler(self.sampler, None);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Presentation options of the swapchain : its present mode and its surface format.
+//!
+//! The choices are resolved against what the surface supports each time the swapchain is
+//! created, falling back to FIFO and to an SDR format.
+
+use ash::vk;
+
+use super::base::VulkanBase;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Hard V-Sync : no tearing, but waits for the next vertical blank, so the framerate is
+    /// capped by the screen and drops to the next step when slower (60 -> 30 fps).
+    ///
+    /// The only one required by the spec, so always there.
+    Fifo,
+    /// Like FIFO, but a late image is displayed immediately, so tearing is possible when slower
+    /// than the screen.
+    FifoRelaxed,
+    /// No tearing nor cap : the latest ready image is displayed at the vertical blank.
+    Mailbox,
+    /// No cap, but tearing.
+    Immediate,
+}
+
+impl PresentMode {
+    pub const ALL: [PresentMode; 4] = [
+        PresentMode::Fifo,
+        PresentMode::FifoRelaxed,
+        PresentMode::Mailbox,
+        PresentMode::Immediate,
+    ];
+
+    pub fn vk(self) -> vk::PresentModeKHR {
+        match self {
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+
+    /// The first of `self` and its fallbacks in `supported`, uncapped ones first for
+    /// [`PresentMode::Immediate`], FIFO otherwise.
+    pub fn choose(self, supported: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        let fallbacks: &[PresentMode] = match self {
+            PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+            _ => &[self],
+        };
+        fallbacks
+            .iter()
+            .map(|m| m.vk())
+            .find(|m| supported.contains(m))
+            .unwrap_or_else(|| {
+                eprintln!("Present mode {self:?} not supported, falling back to FIFO.");
+                vk::PresentModeKHR::FIFO
+            })
+    }
+}
+
+/// Preferred colour space of the swapchain, SDR when the preferred one isn't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// 8 bits sRGB.
+    Sdr,
+    /// 10 bits Rec.2020 with the PQ curve.
+    Hdr10,
+    /// 16 bits float, linear with the sRGB primaries, 1 being 80 nits.
+    ScRgb,
+}
+
+impl ColorSpace {
+    pub const ALL: [ColorSpace; 3] = [ColorSpace::Sdr, ColorSpace::Hdr10, ColorSpace::ScRgb];
+
+    /// By order of preference.
+    fn candidates(self) -> &'static [(vk::Format, vk::ColorSpaceKHR)] {
+        use vk::{ColorSpaceKHR as C, Format as F};
+        match self {
+            ColorSpace::Sdr => &[
+                (F::B8G8R8A8_SRGB, C::SRGB_NONLINEAR),
+                (F::R8G8B8A8_SRGB, C::SRGB_NONLINEAR),
+                (F::A8B8G8R8_SRGB_PACK32, C::SRGB_NONLINEAR),
+            ],
+            ColorSpace::Hdr10 => &[
+                (F::A2B10G10R10_UNORM_PACK32, C::HDR10_ST2084_EXT),
+                (F::A2R10G10B10_UNORM_PACK32, C::HDR10_ST2084_EXT),
+            ],
+            ColorSpace::ScRgb => &[(F::R16G16B16A16_SFLOAT, C::EXTENDED_SRGB_LINEAR_EXT)],
+        }
+    }
+
+    pub fn is_supported(self, formats: &[vk::SurfaceFormatKHR]) -> bool {
+        self.find(formats).is_some()
+    }
+
+    fn find(self, formats: &[vk::SurfaceFormatKHR]) -> Option<vk::SurfaceFormatKHR> {
+        self.candidates().iter().find_map(|(format, color_space)| {
+            formats
+                .iter()
+                .find(|f| f.format == *format && f.color_space == *color_space)
+                .copied()
+        })
+    }
+
+    /// Among the supported `formats`, with how the tone mapping must encode the colours for it.
+    ///
+    /// Falls back to the SDR candidates, then to any sRGB colour space encoded by the shader,
+    /// then to the first format.
+    pub fn choose(
+        self,
+        formats: &[vk::SurfaceFormatKHR],
+    ) -> (vk::SurfaceFormatKHR, OutputTransfer) {
+        if let Some(format) = self.find(formats) {
+            let transfer = match self {
+                ColorSpace::Sdr => OutputTransfer::Linear,
+                ColorSpace::Hdr10 => OutputTransfer::Pq,
+                ColorSpace::ScRgb => OutputTransfer::ScRgb,
+            };
+            return (format, transfer);
+        }
+        if self != ColorSpace::Sdr {
+            eprintln!("Colour space {self:?} not supported, falling back to SDR.");
+        }
+        if let Some(format) = ColorSpace::Sdr.find(formats) {
+            return (format, OutputTransfer::Linear);
+        }
+        formats
+            .iter()
+            .find(|f| f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
+            .map(|f| (*f, OutputTransfer::Srgb))
+            .unwrap_or((formats[0], OutputTransfer::Linear))
+    }
+}
+
+/// How `draw_img` is encoded at the end of the tone mapping, before its copy to the swapchain.
+///
+/// See `OUTPUT_*` in `resources/tone_map.comp.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputTransfer {
+    /// Left linear, an `_SRGB` swapchain format encodes it when copied.
+    Linear,
+    /// sRGB curve, for an `_UNORM` swapchain format in the sRGB colour space.
+    Srgb,
+    /// Rec.2020 primaries and PQ curve, for HDR10.
+    Pq,
+    /// Linear, scaled so that the paper white has its luminance.
+    ScRgb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PresentSettings {
+    pub present_mode: PresentMode,
+    pub color_space: ColorSpace,
+    /// Luminance of a white of 1 in HDR, in nits.
+    pub paper_white: f32,
+}
+
+impl Default for PresentSettings {
+    fn default() -> Self {
+        Self {
+            present_mode: PresentMode::Fifo,
+            color_space: ColorSpace::Sdr,
+            // ITU-R BT.2408 reference white.
+            paper_white: 203.,
+        }
+    }
+}
+
+impl PresentSettings {
+    /// Only the modes and the colour spaces supported by the surface of `base` can be selected.
+    pub fn ui(&mut self, ui: &mut egui::Ui, base: &VulkanBase) {
+        let presentation = base.presentation();
+        egui::ComboBox::from_label("Present mode")
+            .selected_text(format!("{:?}", self.present_mode))
+            .show_ui(ui, |ui| {
+                PresentMode::ALL
+                    .iter()
+                    .filter(|m| presentation.present_modes.contains(&m.vk()))
+                    .for_each(|m| {
+                        ui.selectable_value(&mut self.present_mode, *m, format!("{m:?}"));
+                    });
+            });
+        egui::ComboBox::from_label("Colour space")
+            .selected_text(format!("{:?}", self.color_space))
+            .show_ui(ui, |ui| {
+                ColorSpace::ALL
+                    .iter()
+                    .filter(|c| c.is_supported(&presentation.surface_formats))
+                    .for_each(|c| {
+                        ui.selectable_value(&mut self.color_space, *c, format!("{c:?}"));
+                    });
+            });
+        let (format, _) = self.color_space.choose(&presentation.surface_formats);
+        ui.label(format!(
+            "Surface format : {:?}, {:?}",
+            format.format, format.color_space
+        ));
+        ui.add_enabled(
+            self.color_space != ColorSpace::Sdr,
+            egui::Slider::new(&mut self.paper_white, 80.0..=500.).text("Paper white (nits)"),
+        );
+    }
+}
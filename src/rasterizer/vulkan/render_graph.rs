@@ -0,0 +1,561 @@
+//! Small render graph for one frame.
+//!
+//! Each pass declares the images and buffers it uses, and how. When the frame is executed, the
+//! passes whose results are never used are skipped, and before each remaining pass the graph
+//! records the synchronization2 barriers its usages need, with the layout transitions.
+//!
+//! The passes keep the order in which they are added, a pass only depends on the previous
+//! ones using the same resources. The barriers inside a pass (e.g. between two dispatches of
+//! the post-processing) are still up to the pass.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use ash::{Device, vk};
+
+use super::{allocated::AllocatedImage, commands::image_subresource_range_default};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageUsage {
+    /// Read and written by compute shaders, storage or sampled.
+    Storage,
+    ColorAttachment,
+    DepthAttachment,
+    /// Written by the resolve of a multisampled depth attachment, which happens with the colour
+    /// outputs.
+    DepthResolve,
+    /// Sampled by compute shaders.
+    DepthRead,
+    /// Sampled by fragment shaders.
+    FragmentRead,
+    TransferSrc,
+    TransferDst,
+    /// Only as the final usage of an imported image.
+    Present,
+}
+
+struct Usage {
+    layout: vk::ImageLayout,
+    stages: vk::PipelineStageFlags2,
+    read_access: vk::AccessFlags2,
+    write_access: vk::AccessFlags2,
+}
+
+impl ImageUsage {
+    fn usage(self) -> Usage {
+        let (layout, stages, read_access, write_access) = match self {
+            ImageUsage::Storage => (
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_SAMPLED_READ,
+                vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            ),
+            ImageUsage::ColorAttachment => (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_READ,
+                vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+            ImageUsage::DepthAttachment => (
+                vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            ImageUsage::DepthResolve => (
+                vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::NONE,
+                vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+            ImageUsage::DepthRead => (
+                vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_SAMPLED_READ,
+                vk::AccessFlags2::NONE,
+            ),
+            ImageUsage::FragmentRead => (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_SAMPLED_READ,
+                vk::AccessFlags2::NONE,
+            ),
+            ImageUsage::TransferSrc => (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags2::ALL_TRANSFER,
+                vk::AccessFlags2::TRANSFER_READ,
+                vk::AccessFlags2::NONE,
+            ),
+            ImageUsage::TransferDst => (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags2::ALL_TRANSFER,
+                vk::AccessFlags2::NONE,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            // The presentation waits on a semaphore, only the layout matters.
+            ImageUsage::Present => (
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                vk::PipelineStageFlags2::NONE,
+                vk::AccessFlags2::NONE,
+                vk::AccessFlags2::NONE,
+            ),
+        };
+        Usage {
+            layout,
+            stages,
+            read_access,
+            write_access,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferUsage {
+    /// Written by compute shaders.
+    StorageWrite,
+    /// Indirect draw commands and counts.
+    IndirectRead,
+    TransferDst,
+    /// Only as the final usage of an imported buffer, once the frame's fence is signaled.
+    HostRead,
+}
+
+impl BufferUsage {
+    fn usage(self) -> Usage {
+        let (stages, read_access, write_access) = match self {
+            BufferUsage::StorageWrite => (
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ,
+                vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            ),
+            BufferUsage::IndirectRead => (
+                vk::PipelineStageFlags2::DRAW_INDIRECT,
+                vk::AccessFlags2::INDIRECT_COMMAND_READ,
+                vk::AccessFlags2::NONE,
+            ),
+            BufferUsage::TransferDst => (
+                vk::PipelineStageFlags2::ALL_TRANSFER,
+                vk::AccessFlags2::NONE,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+            BufferUsage::HostRead => (
+                vk::PipelineStageFlags2::HOST,
+                vk::AccessFlags2::HOST_READ,
+                vk::AccessFlags2::NONE,
+            ),
+        };
+        Usage {
+            layout: vk::ImageLayout::UNDEFINED,
+            stages,
+            read_access,
+            write_access,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferId(usize);
+
+/// Where a resource is at, between the passes.
+#[derive(Clone, Copy)]
+struct State {
+    layout: vk::ImageLayout,
+    /// Of the last write and of the reads after it.
+    stages: vk::PipelineStageFlags2,
+    /// Of the last write, to be made visible to the next usages.
+    write_access: vk::AccessFlags2,
+    /// Stages the last write is already visible to.
+    visible_stages: vk::PipelineStageFlags2,
+}
+
+impl State {
+    /// Nothing to wait for but all previous commands, e.g. of the previous frame.
+    fn imported() -> Self {
+        Self {
+            layout: vk::ImageLayout::UNDEFINED,
+            stages: vk::PipelineStageFlags2::ALL_COMMANDS,
+            write_access: vk::AccessFlags2::NONE,
+            visible_stages: vk::PipelineStageFlags2::NONE,
+        }
+    }
+
+    /// Returns the source and destination stages and accesses of the barrier needed before
+    /// `usage`, if any.
+    fn transition(&mut self, usage: &Usage) -> Option<Barrier> {
+        let writes = !usage.write_access.is_empty();
+        let unseen_write =
+            !self.write_access.is_empty() && !self.visible_stages.contains(usage.stages);
+        let barrier = (self.layout != usage.layout || writes || unseen_write).then(|| Barrier {
+            old_layout: self.layout,
+            new_layout: usage.layout,
+            src_stages: self.stages,
+            src_access: self.write_access,
+            dst_stages: usage.stages,
+            dst_access: usage.read_access | usage.write_access,
+        });
+
+        if writes {
+            self.stages = usage.stages;
+            self.write_access = usage.write_access;
+            self.visible_stages = vk::PipelineStageFlags2::NONE;
+        } else {
+            self.stages |= usage.stages;
+            if barrier.is_some() {
+                self.visible_stages |= usage.stages;
+            }
+        }
+        self.layout = usage.layout;
+
+        barrier
+    }
+}
+
+struct Barrier {
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_stages: vk::PipelineStageFlags2,
+    src_access: vk::AccessFlags2,
+    dst_stages: vk::PipelineStageFlags2,
+    dst_access: vk::AccessFlags2,
+}
+
+struct ImageResource {
+    image: vk::Image,
+    aspect: vk::ImageAspectFlags,
+    state: State,
+    final_usage: Option<ImageUsage>,
+    /// Its content doesn't outlive the frame, so passes only writing it can be skipped.
+    transient: bool,
+}
+
+struct BufferResource {
+    buffer: vk::Buffer,
+    state: State,
+    final_usage: Option<BufferUsage>,
+}
+
+struct Pass<'a> {
+    images: Vec<(ImageId, ImageUsage)>,
+    buffers: Vec<(BufferId, BufferUsage)>,
+    record: Box<dyn FnOnce() + 'a>,
+}
+
+pub struct RenderGraph<'a> {
+    device: &'a Device,
+    cmd_buf: vk::CommandBuffer,
+    transients: &'a RefCell<TransientImages>,
+
+    images: Vec<ImageResource>,
+    buffers: Vec<BufferResource>,
+    passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new(
+        device: &'a Device,
+        cmd_buf: vk::CommandBuffer,
+        transients: &'a RefCell<TransientImages>,
+    ) -> Self {
+        transients.borrow_mut().begin_frame();
+        Self {
+            device,
+            cmd_buf,
+            transients,
+            images: Default::default(),
+            buffers: Default::default(),
+            passes: Default::default(),
+        }
+    }
+
+    /// An image living outside the graph. Its content at the start of the frame is discarded,
+    /// and it ends in `final_usage` if any.
+    pub fn import_image(
+        &mut self,
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        final_usage: Option<ImageUsage>,
+    ) -> ImageId {
+        self.images.push(ImageResource {
+            image,
+            aspect,
+            state: State::imported(),
+            final_usage,
+            transient: false,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// An image whose content is kept from the previous frames, which left it in `usage`.
+    pub fn import_kept_image(
+        &mut self,
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        usage: ImageUsage,
+    ) -> ImageId {
+        let usage = usage.usage();
+        self.images.push(ImageResource {
+            image,
+            aspect,
+            state: State {
+                layout: usage.layout,
+                stages: usage.stages,
+                write_access: usage.write_access,
+                visible_stages: vk::PipelineStageFlags2::NONE,
+            },
+            final_usage: None,
+            transient: false,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// A color image only used in this frame, allocated by the frame's [`TransientImages`].
+    pub fn transient_image(
+        &mut self,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        usages: vk::ImageUsageFlags,
+    ) -> ImageId {
+        let image = self.transients.borrow_mut().acquire(TransientDesc {
+            format,
+            extent,
+            usages,
+        });
+        self.images.push(ImageResource {
+            image,
+            aspect: vk::ImageAspectFlags::COLOR,
+            state: State::imported(),
+            final_usage: None,
+            transient: true,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn import_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        final_usage: Option<BufferUsage>,
+    ) -> BufferId {
+        self.buffers.push(BufferResource {
+            buffer,
+            state: State {
+                stages: vk::PipelineStageFlags2::NONE,
+                ..State::imported()
+            },
+            final_usage,
+        });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    pub fn image(&self, id: ImageId) -> vk::Image {
+        self.images[id.0].image
+    }
+
+    pub fn add_pass(&mut self) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            images: Default::default(),
+            buffers: Default::default(),
+        }
+    }
+
+    /// Records the passes and their barriers in the command buffer.
+    pub fn execute(mut self) {
+        let passes = std::mem::take(&mut self.passes);
+        let used = self.used_passes(&passes);
+
+        for (pass, _) in passes.into_iter().zip(used).filter(|(_, used)| *used) {
+            let image_barriers: Vec<_> = pass
+                .images
+                .iter()
+                .filter_map(|(id, usage)| self.image_barrier(*id, *usage))
+                .collect();
+            let buffer_barriers: Vec<_> = pass
+                .buffers
+                .iter()
+                .filter_map(|(id, usage)| self.buffer_barrier(*id, *usage))
+                .collect();
+            self.pipeline_barrier(&image_barriers, &buffer_barriers);
+
+            (pass.record)();
+        }
+
+        let image_barriers: Vec<_> = (0..self.images.len())
+            .filter_map(|i| {
+                let usage = self.images[i].final_usage?;
+                self.image_barrier(ImageId(i), usage)
+            })
+            .collect();
+        let buffer_barriers: Vec<_> = (0..self.buffers.len())
+            .filter_map(|i| {
+                let usage = self.buffers[i].final_usage?;
+                self.buffer_barrier(BufferId(i), usage)
+            })
+            .collect();
+        self.pipeline_barrier(&image_barriers, &buffer_barriers);
+    }
+
+    /// A pass is needed if it writes an imported resource, or a transient image read by a
+    /// needed pass after it.
+    fn used_passes(&self, passes: &[Pass]) -> Vec<bool> {
+        let mut read_later = vec![false; self.images.len()];
+        let mut used = vec![false; passes.len()];
+        for (i, pass) in passes.iter().enumerate().rev() {
+            used[i] = !pass.buffers.is_empty()
+                || pass.images.is_empty()
+                || pass.images.iter().any(|(id, usage)| {
+                    let writes = !usage.usage().write_access.is_empty();
+                    writes && (!self.images[id.0].transient || read_later[id.0])
+                });
+            if used[i] {
+                pass.images
+                    .iter()
+                    .for_each(|(id, _)| read_later[id.0] = true);
+            }
+        }
+        used
+    }
+
+    fn image_barrier(
+        &mut self,
+        id: ImageId,
+        usage: ImageUsage,
+    ) -> Option<vk::ImageMemoryBarrier2<'a>> {
+        let resource = &mut self.images[id.0];
+        let barrier = resource.state.transition(&usage.usage())?;
+        Some(
+            vk::ImageMemoryBarrier2::default()
+                .src_stage_mask(barrier.src_stages)
+                .src_access_mask(barrier.src_access)
+                .dst_stage_mask(barrier.dst_stages)
+                .dst_access_mask(barrier.dst_access)
+                .old_layout(barrier.old_layout)
+                .new_layout(barrier.new_layout)
+                .subresource_range(image_subresource_range_default(resource.aspect))
+                .image(resource.image),
+        )
+    }
+
+    fn buffer_barrier(
+        &mut self,
+        id: BufferId,
+        usage: BufferUsage,
+    ) -> Option<vk::BufferMemoryBarrier2<'a>> {
+        let resource = &mut self.buffers[id.0];
+        let barrier = resource.state.transition(&usage.usage())?;
+        // Only the first usage of a buffer has nothing to wait for.
+        (!barrier.src_stages.is_empty()).then(|| {
+            vk::BufferMemoryBarrier2::default()
+                .src_stage_mask(barrier.src_stages)
+                .src_access_mask(barrier.src_access)
+                .dst_stage_mask(barrier.dst_stages)
+                .dst_access_mask(barrier.dst_access)
+                .buffer(resource.buffer)
+                .size(vk::WHOLE_SIZE)
+        })
+    }
+
+    fn pipeline_barrier(
+        &self,
+        image_barriers: &[vk::ImageMemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+    ) {
+        if image_barriers.is_empty() && buffer_barriers.is_empty() {
+            return;
+        }
+        let dep_info = vk::DependencyInfo::default()
+            .image_memory_barriers(image_barriers)
+            .buffer_memory_barriers(buffer_barriers);
+        unsafe { self.device.cmd_pipeline_barrier2(self.cmd_buf, &dep_info) };
+    }
+}
+
+/// Declares the usages of a pass, then adds it with [`Self::record`].
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    images: Vec<(ImageId, ImageUsage)>,
+    buffers: Vec<(BufferId, BufferUsage)>,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn image(mut self, id: ImageId, usage: ImageUsage) -> Self {
+        self.images.push((id, usage));
+        self
+    }
+
+    pub fn buffer(mut self, id: BufferId, usage: BufferUsage) -> Self {
+        self.buffers.push((id, usage));
+        self
+    }
+
+    pub fn record(self, record: impl FnOnce() + 'a) {
+        self.graph.passes.push(Pass {
+            images: self.images,
+            buffers: self.buffers,
+            record: Box::new(record),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TransientDesc {
+    format: vk::Format,
+    extent: vk::Extent2D,
+    usages: vk::ImageUsageFlags,
+}
+
+/// Images of [`RenderGraph::transient_image`], one pool per `FrameData` so they are only
+/// reused once the frame is done.
+///
+/// The ones not used by the previous frame are freed, e.g. after a resize.
+pub struct TransientImages {
+    device_copy: Rc<Device>,
+    allocator_copy: Arc<Mutex<vk_mem::Allocator>>,
+
+    images: Vec<(TransientDesc, AllocatedImage, bool)>,
+}
+
+impl TransientImages {
+    pub fn new(device: Rc<Device>, allocator: Arc<Mutex<vk_mem::Allocator>>) -> Self {
+        Self {
+            device_copy: device,
+            allocator_copy: allocator,
+            images: Default::default(),
+        }
+    }
+
+    fn begin_frame(&mut self) {
+        self.images.retain(|(_, _, used)| *used);
+        self.images
+            .iter_mut()
+            .for_each(|(_, _, used)| *used = false);
+    }
+
+    fn acquire(&mut self, desc: TransientDesc) -> vk::Image {
+        if let Some((_, image, used)) = self
+            .images
+            .iter_mut()
+            .find(|(d, _, used)| *d == desc && !*used)
+        {
+            *used = true;
+            return image.img;
+        }
+
+        let image = AllocatedImage::new_transient(
+            self.device_copy.clone(),
+            self.allocator_copy.clone(),
+            desc.format,
+            desc.extent,
+            desc.usages,
+        );
+        let img = image.img;
+        self.images.push((desc, image, true));
+        img
+    }
+}
@@ -1,24 +1,36 @@
 use std::{
     cell::RefCell,
     collections::HashMap,
+    path::Path,
     rc::{Rc, Weak},
     sync::{Arc, Mutex},
 };
 
 use super::{
-    allocated::{AllocatedBuffer, MyMemoryUsage},
-    commands::VulkanCommands,
+    allocated::{AllocatedBuffer, MyMemoryUsage, UploadBatch},
+    commands::{VulkanCommands, as_u8_slice},
     descriptors::{DescriptorLayoutBuilder, DescriptorWriter},
+    frame_ring::RingSegment,
     gfx_pipeline::GpuDrawPushConstants,
-    gltf_loader::LoadedGLTF,
+    gltf_loader::{GltfLoading, LoadedGLTF},
+    ibl::IblSettings,
+    lights::{GpuPointLight, LightSettings},
     shaders_loader::ShadersLoader,
     swapchain::VulkanSwapchain,
-    textures::{MaterialInstance, MaterialPass, Textures},
+    texture_data::FormatSupport,
+    textures::{GltfMetallicRoughness, MaterialInstance, MaterialPass, MaterialPipeline, Textures},
+};
+use crate::{
+    rasterizer::settings::Settings,
+    scene::{
+        Bounds, Vertex,
+        bvh::{Frustum, Ray, TransformEpoch},
+        lod::{LodLevel, LodSelector},
+    },
 };
-use crate::scene::{Bounds, Vertex};
 
 use ash::{Device, vk};
-use glam::{Mat4, Vec4, vec4};
+use glam::{Mat4, Vec3, Vec4};
 
 // TODO: proper resource path mngmt and all
 const SCENES: &[(&str, &str)] = &[
@@ -28,15 +40,33 @@ const SCENES: &[(&str, &str)] = &[
     ("corridor", "./resources/Sponza/Sponza.gltf"),
     ("house2", "./resources/house2.glb"),
 ];
+/// Selected at the start.
+pub const DEFAULT_SCENE: &str = SCENES[0].0;
+/// Vertical field of view of the camera, in radians as given to `perspective_rh`.
+pub const FOV_Y: f32 = 70f32.to_radians();
+
+/// A scene of [`SCENES`], loaded the first time it's selected.
+pub enum SceneEntry {
+    Unloaded,
+    /// Read on worker threads, uploaded by [`Scene::update_loading`] once done.
+    Loading(GltfLoading),
+    Loaded(Box<LoadedGLTF>),
+    Failed(String),
+}
 
 pub struct Scene<'a> {
     device_copy: Rc<Device>,
+    allocator_copy: Arc<Mutex<vk_mem::Allocator>>,
+    /// Handed to the loading threads to pick the texture formats.
+    format_support: FormatSupport,
 
-    pub loaded_scenes: HashMap<String, LoadedGLTF>,
+    scenes: HashMap<String, SceneEntry>,
 
-    _textures: Textures<'a>,
+    textures: Textures<'a>,
 
     data: GpuSceneData,
+    /// Uploaded with `data`, see [`Self::update_lights`].
+    lights: Vec<GpuPointLight>,
     pub data_descriptor_layout: vk::DescriptorSetLayout,
     pub main_draw_ctx: DrawContext,
 }
@@ -48,116 +78,291 @@ impl Scene<'_> {
         shaders: &ShadersLoader,
         device: Rc<Device>,
         allocator: Arc<Mutex<vk_mem::Allocator>>,
+        ibl_layout: vk::DescriptorSetLayout,
+        format_support: FormatSupport,
     ) -> Self {
+        // Also read by the deferred lighting.
         let data_descriptor_layout = DescriptorLayoutBuilder::default()
             .add_binding(0, vk::DescriptorType::UNIFORM_BUFFER)
+            .add_binding(1, vk::DescriptorType::STORAGE_BUFFER)
             .build(
                 &device,
-                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                vk::ShaderStageFlags::VERTEX
+                    | vk::ShaderStageFlags::FRAGMENT
+                    | vk::ShaderStageFlags::COMPUTE,
             );
 
-        let mut textures = Textures::new(
+        let textures = Textures::new(
             swapchain,
             commands,
             shaders,
             device.clone(),
             allocator.clone(),
             data_descriptor_layout,
+            ibl_layout,
         );
 
-        let loaded_scenes = SCENES
+        let scenes = SCENES
             .iter()
-            .map(|(n, p)| {
-                (
-                    String::from(*n),
-                    LoadedGLTF::load(
-                        device.clone(),
-                        allocator.clone(),
-                        commands,
-                        &mut textures,
-                        p,
-                    ),
-                )
-            })
+            .map(|(n, _)| (String::from(*n), SceneEntry::Unloaded))
             .collect();
 
         Self {
             device_copy: device.clone(),
+            allocator_copy: allocator,
+            format_support,
 
-            loaded_scenes,
-            _textures: textures,
+            scenes,
+            textures,
 
             data: Default::default(),
+            lights: Default::default(),
             data_descriptor_layout,
             main_draw_ctx: Default::default(),
         }
     }
 
-    /// Returns an allocated buffer that should be kept until the end of the render.
-    pub fn upload_data(
-        &mut self,
-        device: &Device,
-        allocator: Arc<Mutex<vk_mem::Allocator>>,
-        global_desc: vk::DescriptorSet,
-    ) -> AllocatedBuffer {
-        // We will also dynamically allocate the uniform buffer itself as a way to
-        // showcase how you could do temporal per-frame data that is dynamically created.
-        // It would be better to hold the buffers cached in our FrameData structure,
-        // but we will be doing it this way to show how.
-        // There are cases with dynamic draws and passes where you might want to do it
-        // this way.
-        let gpu_scene_data_buffer = AllocatedBuffer::new(
-            allocator,
-            size_of::<GpuSceneData>() as u64,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            MyMemoryUsage::CpuToGpu,
-        );
-        let scene_data =
-            unsafe { &mut *gpu_scene_data_buffer.mapped_data().cast::<GpuSceneData>() };
-        *scene_data = self.data;
+    pub fn loaded(&self, name: &str) -> Option<&LoadedGLTF> {
+        match self.scenes.get(name)? {
+            SceneEntry::Loaded(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Starts loading the `current` scene if needed, and uploads the scenes done loading.
+    ///
+    /// With `wait`, blocks until the `current` scene is loaded, or failed.
+    pub fn update_loading(&mut self, commands: &VulkanCommands, current: &str, wait: bool) {
+        if let Some(entry @ SceneEntry::Unloaded) = self.scenes.get_mut(current) {
+            let (_, path) = SCENES.iter().find(|(n, _)| *n == current).unwrap();
+            *entry = SceneEntry::Loading(GltfLoading::start(
+                Path::new(path),
+                self.format_support.clone(),
+            ));
+        }
+
+        for (name, entry) in self.scenes.iter_mut() {
+            let done = match entry {
+                SceneEntry::Loading(loading) => loading.is_finished() || (wait && name == current),
+                _ => false,
+            };
+            if !done {
+                continue;
+            }
+            let SceneEntry::Loading(loading) = std::mem::replace(entry, SceneEntry::Unloaded)
+            else {
+                unreachable!()
+            };
+            *entry = match loading.finish() {
+                Ok(data) => SceneEntry::Loaded(Box::new(LoadedGLTF::upload(
+                    self.device_copy.clone(),
+                    self.allocator_copy.clone(),
+                    commands,
+                    &mut self.textures,
+                    data,
+                ))),
+                Err(err) => {
+                    eprintln!("Failed to load scene {name} : {err}");
+                    SceneEntry::Failed(err)
+                }
+            };
+        }
+    }
+
+    /// Frees the GPU resources of a loaded scene, or allows retrying a failed one.
+    ///
+    /// A scene still loading is left as is.
+    pub fn evict(&mut self, name: &str) {
+        let Some(entry) = self.scenes.get_mut(name) else {
+            return;
+        };
+        match std::mem::replace(entry, SceneEntry::Unloaded) {
+            SceneEntry::Loaded(scene) => {
+                // The frames in flight may still draw it.
+                unsafe { self.device_copy.device_wait_idle().unwrap() };
+                let (textures, materials) = scene.bindless_slots();
+                self.textures
+                    .metal_rough_material
+                    .bindless
+                    .free(textures, materials);
+            }
+            loading @ SceneEntry::Loading(_) => *entry = loading,
+            SceneEntry::Unloaded | SceneEntry::Failed(_) => (),
+        }
+    }
+
+    /// Selection of the scene, with the loading progress and errors.
+    ///
+    /// `unload` is set to the scene to [`Self::evict`], the current one can't be.
+    pub fn ui(&self, ui: &mut egui::Ui, current_scene: &mut String, unload: &mut Option<String>) {
+        ui.label("Selected scene :");
+        egui::Grid::new("scenes").num_columns(3).show(ui, |ui| {
+            for (name, _) in SCENES {
+                ui.radio_value(current_scene, String::from(*name), *name);
+                let not_current = current_scene != name;
+                match &self.scenes[*name] {
+                    SceneEntry::Unloaded => {
+                        ui.label("Not loaded");
+                    }
+                    SceneEntry::Loading(loading) => {
+                        let (progress, step) = loading.progress();
+                        ui.add(egui::ProgressBar::new(progress).text(step));
+                    }
+                    SceneEntry::Loaded(_) => {
+                        ui.label("Loaded");
+                        if ui
+                            .add_enabled(not_current, egui::Button::new("Unload"))
+                            .clicked()
+                        {
+                            *unload = Some(String::from(*name));
+                        }
+                    }
+                    SceneEntry::Failed(err) => {
+                        ui.colored_label(ui.visuals().error_fg_color, "Failed")
+                            .on_hover_text(err);
+                        if ui.button("Retry").clicked() {
+                            *unload = Some(String::from(*name));
+                        }
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    }
+
+    /// Writes the scene data and the lights in the `ring` of the frame, and binds them to
+    /// `global_desc`.
+    pub fn upload_data(&self, device: &Device, ring: &RingSegment, global_desc: vk::DescriptorSet) {
+        let scene_data = ring.push(&self.data);
+        let lights = ring.push_slice(&self.lights[..]);
 
         let mut writer = DescriptorWriter::default();
         writer.write_buffer(
             0,
-            gpu_scene_data_buffer.buffer,
-            size_of::<GpuSceneData>() as u64,
-            0,
+            scene_data.buffer,
+            scene_data.size,
+            scene_data.offset,
             vk::DescriptorType::UNIFORM_BUFFER,
         );
+        writer.write_buffer(
+            1,
+            lights.buffer,
+            lights.size,
+            lights.offset,
+            vk::DescriptorType::STORAGE_BUFFER,
+        );
         writer.update_set(device, global_desc);
-
-        gpu_scene_data_buffer
     }
 
-    /// Clears the `main_draw_ctx` and fills it with the meshes to render.
-    pub fn update_scene(&mut self, draw_extent: vk::Extent2D, view: Mat4, scene: &String) {
-        self.main_draw_ctx.clear();
-
-        if let Some(s) = self.loaded_scenes.get(scene) {
-            s.draw(&Mat4::IDENTITY, &mut self.main_draw_ctx);
-        }
-
+    /// Clears the `main_draw_ctx` and fills it with the meshes to render, at the levels of
+    /// detail of [`Settings::lod`].
+    ///
+    /// With `bvh_culling`, only the surfaces in the frustum are added.
+    pub fn update_scene(
+        &mut self,
+        draw_extent: vk::Extent2D,
+        view: Mat4,
+        scene: &String,
+        settings: &Settings,
+        bvh_culling: bool,
+        ibl: &IblSettings,
+    ) {
         // Camera projection
         let mut proj = Mat4::perspective_rh(
-            70.,
+            FOV_Y,
             draw_extent.width as f32 / draw_extent.height as f32,
             10_000.,
             0.1,
         );
         proj.y_axis[1] *= -1.;
+        let view_proj = proj * view;
+
+        self.main_draw_ctx.clear();
+        self.main_draw_ctx.lods = LodSelector::new(
+            settings.lod,
+            &view,
+            &proj,
+            draw_extent.width,
+            draw_extent.height,
+        );
+
+        if let Some(SceneEntry::Loaded(s)) = self.scenes.get_mut(scene) {
+            if bvh_culling {
+                s.draw_visible(
+                    &Frustum::from_view_proj(&view_proj),
+                    &mut self.main_draw_ctx,
+                );
+            } else {
+                s.draw(&Mat4::IDENTITY, &mut self.main_draw_ctx);
+            }
+        }
+
         self.data = GpuSceneData {
             view,
             proj,
-            view_proj: proj * view,
+            view_proj,
             ambient_color: Vec4::splat(1.),
-            sunlight_direction: vec4(0., 1., 0.5, 1.),
+            sunlight_direction: ibl.sun_direction().extend(1.),
             sunlight_color: Vec4::splat(1.),
+            camera_position: view.inverse().w_axis,
+            view_mode: settings.view_mode as u32,
+            ibl: ibl.lighting as u32,
+            ibl_intensity: ibl.intensity,
+            light_count: self.lights.len() as u32,
         };
     }
 
+    /// Scatters the point lights of `settings` in the bounds of the `scene`, none until it is
+    /// loaded. To be done before [`Self::update_scene`], which counts them.
+    pub fn update_lights(&mut self, scene: &String, settings: &LightSettings) {
+        self.lights = match self.scenes.get_mut(scene) {
+            Some(SceneEntry::Loaded(s)) => settings.lights(&s.bounds()),
+            _ => Vec::new(),
+        };
+    }
+
+    /// Name of the node under the cursor, given in [-1,1].
+    pub fn pick(&mut self, scene: &String, x: f32, y: f32) -> Option<String> {
+        // Reversed depth, the near plane is at 1.
+        let ray = Ray::from_clip_space(&self.data.view_proj.inverse(), x, y, 1., 0.);
+        match self.scenes.get_mut(scene)? {
+            SceneEntry::Loaded(s) => s.pick(&ray),
+            _ => None,
+        }
+    }
+
+    pub fn highlight(&mut self, scene: &String, node_name: &str) {
+        if let Some(SceneEntry::Loaded(s)) = self.scenes.get_mut(scene) {
+            s.highlight(node_name);
+        }
+    }
+
     pub fn view_proj(&self) -> &Mat4 {
         &self.data.view_proj
     }
+
+    pub fn view(&self) -> &Mat4 {
+        &self.data.view
+    }
+
+    pub fn proj(&self) -> &Mat4 {
+        &self.data.proj
+    }
+
+    pub fn metal_rough_material(&self) -> &GltfMetallicRoughness<'_> {
+        &self.textures.metal_rough_material
+    }
+
+    /// See [`GltfMetallicRoughness::set_multisampling`].
+    pub fn set_multisampling(
+        &mut self,
+        shaders: &ShadersLoader,
+        multisampling: (vk::SampleCountFlags, bool),
+    ) {
+        self.textures
+            .metal_rough_material
+            .set_multisampling(shaders, multisampling);
+    }
 }
 
 impl Drop for Scene<'_> {
@@ -173,8 +378,6 @@ impl Drop for Scene<'_> {
 
 #[repr(C)]
 #[derive(Default, Debug, Clone, Copy)]
-// TODO Dynamic alignment on VkPhysicalDeviceLimits::minUniformBufferOffsetAlignment, see textures.MaterialConstants
-#[repr(align(256))]
 pub struct GpuSceneData {
     pub view: Mat4,
     pub proj: Mat4,
@@ -182,6 +385,12 @@ pub struct GpuSceneData {
     pub ambient_color: Vec4,
     pub sunlight_direction: Vec4,
     pub sunlight_color: Vec4,
+    pub camera_position: Vec4,
+    pub view_mode: u32,
+    /// Boolean, the image-based lighting replaces `ambient_color`.
+    pub ibl: u32,
+    pub ibl_intensity: f32,
+    pub light_count: u32,
 }
 
 pub struct GpuMeshBuffers {
@@ -191,9 +400,10 @@ pub struct GpuMeshBuffers {
 }
 
 impl GpuMeshBuffers {
+    /// The buffers are filled once the `batch` is submitted.
     pub fn new(
         device: &Device,
-        commands: &VulkanCommands,
+        batch: &mut UploadBatch,
         indices: &[u32],
         vertices: &[Vertex],
     ) -> Self {
@@ -201,7 +411,7 @@ impl GpuMeshBuffers {
         let index_buffer_size = size_of_val(indices) as u64;
 
         let vertex_buffer = AllocatedBuffer::new(
-            commands.allocator.clone(),
+            batch.allocator(),
             vertex_buffer_size,
             vk::BufferUsageFlags::STORAGE_BUFFER
                 | vk::BufferUsageFlags::TRANSFER_DST
@@ -215,7 +425,7 @@ impl GpuMeshBuffers {
             unsafe { device.get_buffer_device_address(&device_address_info) };
 
         let index_buffer = AllocatedBuffer::new(
-            commands.allocator.clone(),
+            batch.allocator(),
             index_buffer_size,
             vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
             MyMemoryUsage::GpuOnly,
@@ -223,55 +433,8 @@ impl GpuMeshBuffers {
 
         // TODO: check https://gpuopen-librariesandsdks.github.io/VulkanMemoryAllocator/html/usage_patterns.html
         // esp Advanced data uploading for APU without staging and stuff...
-
-        let staging = AllocatedBuffer::new(
-            commands.allocator.clone(),
-            vertex_buffer_size + index_buffer_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            MyMemoryUsage::StagingUpload,
-        );
-
-        let data = staging.mapped_data();
-        // TODO: alignment ?
-        {
-            let vertices_dst: &mut [Vertex] =
-                unsafe { std::slice::from_raw_parts_mut(data as *mut Vertex, vertices.len()) };
-            vertices_dst.copy_from_slice(vertices);
-        }
-        // TODO: can alignment break sizes ?
-        {
-            let indices_dst: &mut [u32] = unsafe {
-                std::slice::from_raw_parts_mut(
-                    data.add(vertex_buffer_size as usize) as *mut u32,
-                    indices.len(),
-                )
-            };
-            indices_dst.copy_from_slice(indices);
-        }
-
-        // TODO: can be sent to background thread to avoid blocking
-        commands.immediate_submit(|device, cmd| {
-            let vertex_copies = [vk::BufferCopy::default()
-                .dst_offset(0)
-                .src_offset(0)
-                .size(vertex_buffer_size)];
-            unsafe {
-                device.cmd_copy_buffer(
-                    cmd,
-                    staging.buffer,
-                    vertex_buffer.buffer,
-                    &vertex_copies[..],
-                );
-            }
-
-            let index_copies = [vk::BufferCopy::default()
-                .dst_offset(0)
-                .src_offset(vertex_buffer_size)
-                .size(index_buffer_size)];
-            unsafe {
-                device.cmd_copy_buffer(cmd, staging.buffer, index_buffer.buffer, &index_copies[..]);
-            }
-        });
+        batch.buffer(vertex_buffer.buffer, as_u8_slice(vertices));
+        batch.buffer(index_buffer.buffer, as_u8_slice(indices));
 
         Self {
             index_buffer,
@@ -298,6 +461,21 @@ pub struct GeoSurface {
     pub material: Rc<MaterialInstance>,
 
     pub bounds: Bounds,
+    /// Simplified versions, see [`crate::scene::lod::generate_lods`].
+    pub lods: Vec<LodLevel>,
+}
+
+impl GeoSurface {
+    /// First index and count of the `level` of detail, 0 being the surface itself.
+    pub fn level(&self, level: usize) -> (u32, u32) {
+        match level {
+            0 => (self.start_index, self.count),
+            l => (
+                self.lods[l - 1].start_index as u32,
+                self.lods[l - 1].count as u32,
+            ),
+        }
+    }
 }
 
 pub struct MeshAsset {
@@ -305,6 +483,10 @@ pub struct MeshAsset {
     pub _name: Option<String>,
     pub surfaces: Vec<GeoSurface>,
     mesh_buffers: GpuMeshBuffers,
+
+    /// CPU copy for picking.
+    positions: Vec<Vec3>,
+    indices: Vec<u32>,
 }
 
 impl MeshAsset {
@@ -312,14 +494,33 @@ impl MeshAsset {
         name: Option<String>,
         surfaces: Vec<GeoSurface>,
         mesh_buffers: GpuMeshBuffers,
+        positions: Vec<Vec3>,
+        indices: Vec<u32>,
     ) -> Self {
         Self {
             _name: name,
             surfaces,
             mesh_buffers,
+            positions,
+            indices,
         }
     }
 
+    /// Distance to the closest triangle of the surface hit by the local space `ray`.
+    pub fn intersect_surface(&self, surface: &GeoSurface, ray: &Ray) -> Option<f32> {
+        let start = surface.start_index as usize;
+        self.indices[start..start + surface.count as usize]
+            .chunks_exact(3)
+            .filter_map(|is| {
+                ray.intersect_triangle(
+                    self.positions[is[0] as usize],
+                    self.positions[is[1] as usize],
+                    self.positions[is[2] as usize],
+                )
+            })
+            .reduce(f32::min)
+    }
+
     pub fn index_buffer(&self) -> &vk::Buffer {
         &self.mesh_buffers.index_buffer.buffer
     }
@@ -340,13 +541,25 @@ pub struct RenderObject {
 
     transform: Mat4,
     vertex_buffer_addr: vk::DeviceAddress,
+    highlighted: bool,
+    /// Of the original surface, the same at every level of detail.
+    surface_first_index: u32,
+    lod: u32,
 }
 
 impl From<&RenderObject> for GpuDrawPushConstants {
     fn from(value: &RenderObject) -> Self {
+        // Vertex buffers are at least 16 bytes aligned.
+        let mesh_id = (value.vertex_buffer_addr >> 4) as u32;
         GpuDrawPushConstants {
             world_mat: value.transform,
             vertex_buffer: value.vertex_buffer_addr,
+            mesh_id,
+            surface_id: mesh_id ^ value.surface_first_index.wrapping_mul(0x9e3779b9),
+            lod: value.lod,
+            highlighted: value.highlighted as u32,
+            material_id: value.material.material_id,
+            ..Default::default()
         }
     }
 }
@@ -360,12 +573,38 @@ impl RenderObject {
         self.bounds
             .clip_space_origin_depth(view_proj, &self.transform)
     }
+
+    pub fn bounds(&self) -> &Bounds {
+        &self.bounds
+    }
+
+    /// Objects with the same key draw the same surface with the same pipeline, so can be drawn
+    /// by a single instanced draw. Their materials can differ, they are read per instance.
+    pub fn instance_key(&self) -> InstanceKey {
+        (
+            self.index_buffer,
+            self.first_index,
+            self.index_count,
+            self.vertex_buffer_addr,
+            self.material.pipeline_ptr(),
+        )
+    }
 }
 
+pub type InstanceKey = (
+    vk::Buffer,
+    u32,
+    u32,
+    vk::DeviceAddress,
+    *const MaterialPipeline,
+);
+
 #[derive(Default)]
 pub struct DrawContext {
     pub opaque_surfaces: Vec<RenderObject>,
     pub transparent_surfaces: Vec<RenderObject>,
+    /// Of the surfaces pushed, for the camera of the frame.
+    pub lods: LodSelector,
 }
 
 impl DrawContext {
@@ -373,6 +612,37 @@ impl DrawContext {
         self.opaque_surfaces.clear();
         self.transparent_surfaces.clear();
     }
+
+    pub fn push_surface(
+        &mut self,
+        mesh: &MeshAsset,
+        surface: &GeoSurface,
+        transform: Mat4,
+        highlighted: bool,
+    ) {
+        let level = self.lods.level(&surface.lods, &surface.bounds, &transform);
+        let (first_index, index_count) = surface.level(level);
+        let def = RenderObject {
+            index_count,
+            first_index,
+            index_buffer: *mesh.index_buffer(),
+            material: surface.material.clone(),
+
+            bounds: surface.bounds,
+
+            transform,
+            vertex_buffer_addr: mesh.vertex_buffer_address(),
+            highlighted,
+            surface_first_index: surface.start_index,
+            lod: level as u32,
+        };
+
+        if let MaterialPass::Transparent = surface.material.pass_type() {
+            self.transparent_surfaces.push(def);
+        } else {
+            self.opaque_surfaces.push(def);
+        }
+    }
 }
 
 pub trait Renderable {
@@ -384,6 +654,10 @@ pub trait Node: Renderable {
 
     fn node_data(&self) -> &NodeData;
     fn node_data_mut(&mut self) -> &mut NodeData;
+
+    fn mesh(&self) -> Option<&Rc<MeshAsset>> {
+        None
+    }
 }
 
 struct EmptyNode;
@@ -411,6 +685,19 @@ pub struct NodeData {
     pub children: Vec<Rc<RefCell<dyn Node>>>,
     pub local_transform: Mat4,
     pub world_transform: Mat4,
+    /// Drawn tinted, when the node or one of its parents is selected.
+    pub highlighted: bool,
+    /// The one of the scene, once the node is in it.
+    pub transform_epoch: TransformEpoch,
+}
+
+impl NodeData {
+    pub fn set_highlighted(&mut self, highlighted: bool) {
+        self.highlighted = highlighted;
+        self.children
+            .iter()
+            .for_each(|c| c.borrow_mut().node_data_mut().set_highlighted(highlighted));
+    }
 }
 
 impl Default for NodeData {
@@ -421,6 +708,8 @@ impl Default for NodeData {
             children: Default::default(),
             local_transform: Default::default(),
             world_transform: Default::default(),
+            highlighted: false,
+            transform_epoch: Default::default(),
         }
     }
 }
@@ -436,6 +725,7 @@ impl Renderable for NodeData {
 impl Node for NodeData {
     fn refresh_transform(&mut self, parent_mat: &Mat4) {
         self.world_transform = parent_mat * self.local_transform;
+        self.transform_epoch.bump();
         self.children
             .iter()
             .for_each(|c| c.borrow_mut().refresh_transform(&self.world_transform));
@@ -482,25 +772,10 @@ impl Renderable for MeshNode {
     fn draw(&self, top_mat: &Mat4, ctx: &mut DrawContext) {
         let node_mat = top_mat * self.node.world_transform;
 
-        self.mesh.surfaces.iter().for_each(|s| {
-            let def = RenderObject {
-                index_count: s.count,
-                first_index: s.start_index,
-                index_buffer: *self.mesh.index_buffer(),
-                material: s.material.clone(),
-
-                bounds: s.bounds,
-
-                transform: node_mat,
-                vertex_buffer_addr: self.mesh.vertex_buffer_address(),
-            };
-
-            if let MaterialPass::Transparent = s.material.pass_type() {
-                ctx.transparent_surfaces.push(def);
-            } else {
-                ctx.opaque_surfaces.push(def);
-            }
-        });
+        self.mesh
+            .surfaces
+            .iter()
+            .for_each(|s| ctx.push_surface(&self.mesh, s, node_mat, self.node.highlighted));
 
         self.node.draw(top_mat, ctx);
     }
@@ -516,4 +791,7 @@ impl Node for MeshNode {
     fn node_data_mut(&mut self) -> &mut NodeData {
         &mut self.node
     }
+    fn mesh(&self) -> Option<&Rc<MeshAsset>> {
+        Some(&self.mesh)
+    }
 }
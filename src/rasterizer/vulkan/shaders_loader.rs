@@ -1,305 +1,271 @@
+use ash::{Device, vk};
 #[cfg(feature = "vulkan_shaderc")]
-pub use cond::*;
-#[cfg(not(feature = "vulkan_shaderc"))]
-pub use cond::*;
-
+use shaderc::{CompileOptions, Compiler, ShaderKind};
 #[cfg(feature = "vulkan_shaderc")]
-mod cond {
-    use ash::{Device, vk};
-    use shaderc::{CompilationArtifact, CompileOptions, Compiler, ShaderKind};
-    use std::{fs::File, io::Read, path::PathBuf, rc::Rc};
-
-    const SHADER_FOLDER: &str = "./resources/";
-    const SHADER_EXT: &str = "glsl";
-
-    /// Making it easy to load shaders from the [`SHADER_FOLDER`].
-    ///
-    /// Known shaders are listed in the [`ShaderName`] enum.
-    /// Their corresponding name and kind are configured in the corresponding `From` impl for `&str` and
-    /// `ShaderKind`.
-    ///
-    /// The file name should be `{name}.{kind}.glsl` : `colored_triangle_mesh.vert.glsl`
-    ///
-    /// This struct stores a [`shaderc::Compiler`], which is costly to create.
-    pub struct ShadersLoader {
-        device_copy: Rc<Device>,
-        compiler: Compiler,
-        // shaders: RefCell<HashMap<ShaderName, vk::ShaderModule>>,
-    }
-
-    impl ShadersLoader {
-        pub fn new(device: Rc<Device>) -> Self {
-            Self {
-                device_copy: device,
-                compiler: Compiler::new().unwrap(),
-                // shaders: Default::default(),
-            }
-        }
-
-        pub fn get(&self, name: ShaderName) -> ShaderModule {
-            // let mut shaders = self.shaders.borrow_mut();
-            // if shaders.contains_key(&name) {
-            //     shaders.get(&name).unwrap()
-            // } else {
-            //     let module = self.load_shader_module(name);
-            //     shaders.entry(name).or_insert(module)
-            // }
-            ShaderModule::load(self.device_copy.clone(), &self.compiler, name)
-        }
-    }
-
-    // impl Drop for ShadersLoader {
-    //     fn drop(&mut self) {
-    //         #[cfg(feature = "vulkan_dbg_mem")]
-    //         println!("drop VulkanShaders");
-    //         // println!("drop VulkanShaders : {} shaders", shaders.len());
-    //         // unsafe {
-    //         //     shaders
-    //         //         .drain()
-    //         //         .for_each(|(_, module)| self.device_copy.destroy_shader_module(module, None));
-    //         // }
-    //     }
-    // }
-
-    // TODO: stop using this and directly call with name + type ?
-    #[allow(dead_code)]
-    #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
-    pub enum ShaderName {
-        Gradient,
-        ParametrableGradient,
-        Sky,
-        ColoredTriangleVert,
-        ColoredTriangleFrag,
-        ColoredTriangleMeshVert,
-        TexImage,
-        MeshFrag,
-        MeshVert,
-    }
-
-    impl From<ShaderName> for &str {
-        fn from(value: ShaderName) -> &'static str {
-            use ShaderName::*;
-
-            match value {
-                Gradient => "gradient",
-                ParametrableGradient => "parametrable_gradient",
-                Sky => "sky",
-                ColoredTriangleVert | ColoredTriangleFrag => "colored_triangle",
-                ColoredTriangleMeshVert => "colored_triangle_mesh",
-                TexImage => "tex_image",
-                MeshFrag | MeshVert => "mesh",
-            }
-        }
-    }
-
+use std::{fs::File, io::Read};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{base::VulkanBase, pipeline_cache::PipelineCache, spirv_cache};
+
+const SHADER_FOLDER: &str = "./resources/";
+const SHADER_EXT: &str = "glsl";
+
+/// Making it easy to load shaders from the [`SHADER_FOLDER`].
+///
+/// Known shaders are listed in the [`ShaderName`] enum.
+/// Their corresponding name and stage are configured in the corresponding `From` impl for `&str`
+/// and [`ShaderName::stage`].
+///
+/// The file name should be `{name}.{stage}.glsl` : `colored_triangle_mesh.vert.glsl`
+///
+/// The SPIR-V is cached on disk, see [`spirv_cache`]. With `vulkan_shaderc`, a shader whose
+/// sources changed is compiled again. Without it, only the cached SPIR-V can be loaded.
+///
+/// This struct stores a [`shaderc::Compiler`], which is costly to create, and the
+/// [`PipelineCache`] used by every pipeline.
+pub struct ShadersLoader {
+    device_copy: Rc<Device>,
     #[cfg(feature = "vulkan_shaderc")]
-    impl From<ShaderName> for ShaderKind {
-        fn from(value: ShaderName) -> ShaderKind {
-            use ShaderKind::*;
-            use ShaderName::*;
+    compiler: Compiler,
+    pipeline_cache: PipelineCache,
+}
 
-            match value {
-                Gradient | ParametrableGradient | Sky => Compute,
-                ColoredTriangleVert | ColoredTriangleMeshVert | MeshVert => Vertex,
-                ColoredTriangleFrag | TexImage | MeshFrag => Fragment,
-            }
+impl ShadersLoader {
+    pub fn new(base: &VulkanBase) -> Self {
+        Self {
+            device_copy: base.device.clone(),
+            #[cfg(feature = "vulkan_shaderc")]
+            compiler: Compiler::new().unwrap(),
+            pipeline_cache: PipelineCache::new(base),
         }
     }
 
-    impl From<ShaderName> for PathBuf {
-        fn from(value: ShaderName) -> Self {
-            let name: &str = value.into();
-            let stage = match value.into() {
-                ShaderKind::Compute => "comp",
-                ShaderKind::Vertex => "vert",
-                ShaderKind::Fragment => "frag",
-                _ => unimplemented!(),
-            };
-            let mut path = PathBuf::from(SHADER_FOLDER);
-            path.push(format!("{name}.{stage}.{SHADER_EXT}"));
-            path
-        }
+    pub fn get(&self, name: ShaderName) -> ShaderModule {
+        self.load(&PathBuf::from(name), name.stage())
     }
 
-    impl ShaderName {
-        pub fn into_str(self) -> &'static str {
-            self.into()
-        }
+    /// For the compute shaders not listed in [`ShaderName`], like the background effects.
+    pub fn get_compute(&self, path: &Path) -> ShaderModule {
+        self.load(path, "comp")
     }
 
-    /// Wrapper around a [`vk::ShaderModule`].
-    ///
-    /// It will take care of destroying it on drop, so keep it around long enough.
-    pub struct ShaderModule {
-        device_copy: Rc<Device>,
-        #[cfg(feature = "vulkan_dbg_mem")]
-        name: ShaderName,
-        module: vk::ShaderModule,
+    /// To create every pipeline with.
+    pub fn pipeline_cache(&self) -> vk::PipelineCache {
+        self.pipeline_cache.cache
     }
 
-    impl ShaderModule {
-        /// Loads the corresponding `glsl` file and compiles it using `shaderc`.
-        ///
-        /// Global includes will be searched in `SHADER_FOLDER`.
-        pub fn load(device: Rc<Device>, compiler: &Compiler, name: ShaderName) -> Self {
-            let path: PathBuf = name.into();
-
-            let mut glsl = String::new();
-            File::open(path).unwrap().read_to_string(&mut glsl).unwrap();
-
-            let spirv = Self::compile_glsl_to_spirv(compiler, name, &glsl);
-            let create_info = vk::ShaderModuleCreateInfo::default().code(spirv.as_binary());
-
-            let module = unsafe { device.create_shader_module(&create_info, None).unwrap() };
-
-            Self {
-                device_copy: device,
-                #[cfg(feature = "vulkan_dbg_mem")]
-                name,
-                module,
-            }
-        }
-
-        /// `ShaderModule` takes care of destroying the `vk::ShaderModule` on drop.
-        /// So it **must** outlive this object, even if you copy it.
-        pub fn module_copy(&self) -> vk::ShaderModule {
-            self.module
-        }
-
-        #[cfg(not(feature = "vulkan_shaderc"))]
-        fn compile_glsl_to_spirv(
-            compiler: &Compiler,
-            name: ShaderName,
-            glsl: &str,
-        ) -> CompilationArtifact {
-            unimplemented!();
-        }
-
+    fn load(&self, path: &Path, stage: &str) -> ShaderModule {
         #[cfg(feature = "vulkan_shaderc")]
-        fn compile_glsl_to_spirv(
-            compiler: &Compiler,
-            name: ShaderName,
-            glsl: &str,
-        ) -> CompilationArtifact {
-            let path: PathBuf = name.into();
-            let mut options = CompileOptions::new().unwrap();
-            options.set_include_callback(|name, include_type, _src_name, _| {
-                let resolved_path = match include_type {
-                    shaderc::IncludeType::Relative => path.with_file_name(name),
-                    shaderc::IncludeType::Standard => {
-                        let mut res = PathBuf::from(SHADER_FOLDER);
-                        res.push(name);
-                        res
+        let spirv = {
+            // Without a hash, the compiler reports the unreadable file.
+            let hash = spirv_cache::source_hash(path, SHADER_FOLDER);
+            match (spirv_cache::load(path, SHADER_FOLDER), hash) {
+                (Some((cached_hash, spirv)), Ok(hash)) if cached_hash == hash => spirv,
+                (_, hash) => {
+                    let spirv = compile_glsl_to_spirv(&self.compiler, path, stage);
+                    if let Ok(hash) = hash {
+                        spirv_cache::store(path, SHADER_FOLDER, hash, &spirv);
                     }
-                };
-
-                let mut content = String::new();
-                File::open(resolved_path)
-                    .unwrap()
-                    .read_to_string(&mut content)
-                    .unwrap();
-
-                Ok(shaderc::ResolvedInclude {
-                    resolved_name: name.into(),
-                    content,
-                })
-            });
-            let res = compiler.compile_into_spirv(
-                glsl,
-                name.into(),
-                name.into_str(),
-                "main",
-                Some(&options),
-            );
-            match res {
-                Ok(res) => res,
-                Err(shaderc::Error::CompilationError(nb, msg)) => {
-                    panic!(
-                        "{nb} errors compiling shader `{}` :{}",
-                        path.to_string_lossy(),
-                        msg.lines()
-                            .map(|s| format!("\n    - {s}"))
-                            .collect::<String>()
-                    );
+                    spirv
                 }
-                Err(e) => panic!("{e:?}"),
             }
-        }
-    }
+        };
+        #[cfg(not(feature = "vulkan_shaderc"))]
+        let spirv = spirv_cache::load(path, SHADER_FOLDER)
+            .map(|(_, spirv)| spirv)
+            .unwrap_or_else(|| {
+                panic!(
+                    "No cached SPIR-V for {} ({stage}), build with `vulkan_shaderc` to compile it",
+                    path.to_string_lossy()
+                )
+            });
 
-    impl Drop for ShaderModule {
-        fn drop(&mut self) {
-            #[cfg(feature = "vulkan_dbg_mem")]
-            {
-                let kind: ShaderKind = self.name.into();
-                println!("drop ShaderModule {:?}, kind {:?}", self.name, kind);
-            }
-            unsafe {
-                self.device_copy.destroy_shader_module(self.module, None);
-            }
-        }
+        ShaderModule::new(self.device_copy.clone(), path, &spirv)
     }
 }
 
-#[cfg(not(feature = "vulkan_shaderc"))]
-mod cond {
-    use std::rc::Rc;
-
-    use ash::{Device, vk};
-
-    pub struct ShadersLoader {}
+// TODO: stop using this and directly call with name + type ?
+#[allow(dead_code)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ShaderName {
+    ColoredTriangleVert,
+    ColoredTriangleFrag,
+    ColoredTriangleMeshVert,
+    TexImage,
+    MeshFrag,
+    MeshVert,
+    MeshWireframeFrag,
+    CullObjects,
+    DepthReduce,
+    LuminanceHistogram,
+    LuminanceAverage,
+    BloomDownsample,
+    BloomUpsample,
+    ToneMap,
+    EquirectToCube,
+    Irradiance,
+    SpecularPrefilter,
+    BrdfLut,
+    Skybox,
+    FullscreenVert,
+    MsaaBackgroundFrag,
+    GBufferFrag,
+    DeferredLighting,
+    Ssao,
+    SsaoBlur,
+}
 
-    impl ShadersLoader {
-        pub fn new(_device: Rc<Device>) -> Self {
-            Self {}
+impl From<ShaderName> for &str {
+    fn from(value: ShaderName) -> &'static str {
+        use ShaderName::*;
+
+        match value {
+            ColoredTriangleVert | ColoredTriangleFrag => "colored_triangle",
+            ColoredTriangleMeshVert => "colored_triangle_mesh",
+            TexImage => "tex_image",
+            MeshFrag | MeshVert => "mesh",
+            MeshWireframeFrag => "mesh_wireframe",
+            CullObjects => "cull_objects",
+            DepthReduce => "depth_reduce",
+            LuminanceHistogram => "luminance_histogram",
+            LuminanceAverage => "luminance_average",
+            BloomDownsample => "bloom_downsample",
+            BloomUpsample => "bloom_upsample",
+            ToneMap => "tone_map",
+            EquirectToCube => "equirect_to_cube",
+            Irradiance => "irradiance",
+            SpecularPrefilter => "specular_prefilter",
+            BrdfLut => "brdf_lut",
+            Skybox => "skybox",
+            FullscreenVert => "fullscreen",
+            MsaaBackgroundFrag => "msaa_background",
+            GBufferFrag => "gbuffer",
+            DeferredLighting => "deferred_lighting",
+            Ssao => "ssao",
+            SsaoBlur => "ssao_blur",
         }
+    }
+}
 
-        pub fn get(&self, _name: ShaderName) -> ShaderModule {
-            unimplemented!();
+impl ShaderName {
+    /// `comp`, `vert` or `frag`, as in the file name.
+    fn stage(self) -> &'static str {
+        use ShaderName::*;
+
+        match self {
+            CullObjects | DepthReduce | LuminanceHistogram | LuminanceAverage | BloomDownsample
+            | BloomUpsample | ToneMap | EquirectToCube | Irradiance | SpecularPrefilter
+            | BrdfLut | Skybox | DeferredLighting | Ssao | SsaoBlur => "comp",
+            ColoredTriangleVert | ColoredTriangleMeshVert | MeshVert | FullscreenVert => "vert",
+            ColoredTriangleFrag | TexImage | MeshFrag | MeshWireframeFrag | MsaaBackgroundFrag
+            | GBufferFrag => "frag",
         }
     }
+}
 
-    #[allow(dead_code)]
-    #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
-    pub enum ShaderName {
-        Gradient,
-        ParametrableGradient,
-        Sky,
-        ColoredTriangleVert,
-        ColoredTriangleFrag,
-        ColoredTriangleMeshVert,
-        TexImage,
-        MeshFrag,
-        MeshVert,
+impl From<ShaderName> for PathBuf {
+    fn from(value: ShaderName) -> Self {
+        let name: &str = value.into();
+        let stage = value.stage();
+        let mut path = PathBuf::from(SHADER_FOLDER);
+        path.push(format!("{name}.{stage}.{SHADER_EXT}"));
+        path
     }
+}
 
-    impl From<ShaderName> for &str {
-        fn from(value: ShaderName) -> &'static str {
-            use ShaderName::*;
-
-            match value {
-                Gradient => "gradient",
-                ParametrableGradient => "parametrable_gradient",
-                Sky => "sky",
-                ColoredTriangleVert | ColoredTriangleFrag => "colored_triangle",
-                ColoredTriangleMeshVert => "colored_triangle_mesh",
-                TexImage => "tex_image",
-                MeshFrag | MeshVert => "mesh",
+/// Compiles the `glsl` file using `shaderc`.
+///
+/// Global includes will be searched in `SHADER_FOLDER`.
+#[cfg(feature = "vulkan_shaderc")]
+fn compile_glsl_to_spirv(compiler: &Compiler, path: &Path, stage: &str) -> Vec<u32> {
+    let kind = match stage {
+        "comp" => ShaderKind::Compute,
+        "vert" => ShaderKind::Vertex,
+        "frag" => ShaderKind::Fragment,
+        _ => unimplemented!(),
+    };
+
+    let mut glsl = String::new();
+    File::open(path).unwrap().read_to_string(&mut glsl).unwrap();
+
+    let mut options = CompileOptions::new().unwrap();
+    options.set_include_callback(|name, include_type, _src_name, _| {
+        let resolved_path = match include_type {
+            shaderc::IncludeType::Relative => path.with_file_name(name),
+            shaderc::IncludeType::Standard => {
+                let mut res = PathBuf::from(SHADER_FOLDER);
+                res.push(name);
+                res
             }
+        };
+
+        let mut content = String::new();
+        File::open(resolved_path)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: name.into(),
+            content,
+        })
+    });
+    let res =
+        compiler.compile_into_spirv(&glsl, kind, &path.to_string_lossy(), "main", Some(&options));
+    match res {
+        Ok(res) => res.as_binary().to_vec(),
+        Err(shaderc::Error::CompilationError(nb, msg)) => {
+            panic!(
+                "{nb} errors compiling shader `{}` :{}",
+                path.to_string_lossy(),
+                msg.lines()
+                    .map(|s| format!("\n    - {s}"))
+                    .collect::<String>()
+            );
         }
+        Err(e) => panic!("{e:?}"),
     }
+}
+
+/// Wrapper around a [`vk::ShaderModule`].
+///
+/// It will take care of destroying it on drop, so keep it around long enough.
+pub struct ShaderModule {
+    device_copy: Rc<Device>,
+    #[cfg(feature = "vulkan_dbg_mem")]
+    path: PathBuf,
+    module: vk::ShaderModule,
+}
+
+impl ShaderModule {
+    fn new(device: Rc<Device>, #[allow(unused)] path: &Path, spirv: &[u32]) -> Self {
+        let create_info = vk::ShaderModuleCreateInfo::default().code(spirv);
+        let module = unsafe { device.create_shader_module(&create_info, None).unwrap() };
 
-    impl ShaderName {
-        pub fn into_str(self) -> &'static str {
-            self.into()
+        Self {
+            device_copy: device,
+            #[cfg(feature = "vulkan_dbg_mem")]
+            path: path.to_owned(),
+            module,
         }
     }
 
-    pub struct ShaderModule {}
+    /// `ShaderModule` takes care of destroying the `vk::ShaderModule` on drop.
+    /// So it **must** outlive this object, even if you copy it.
+    pub fn module_copy(&self) -> vk::ShaderModule {
+        self.module
+    }
+}
 
-    impl ShaderModule {
-        pub fn module_copy(&self) -> vk::ShaderModule {
-            unimplemented!();
+impl Drop for ShaderModule {
+    fn drop(&mut self) {
+        #[cfg(feature = "vulkan_dbg_mem")]
+        println!("drop ShaderModule {}", self.path.to_string_lossy());
+        unsafe {
+            self.device_copy.destroy_shader_module(self.module, None);
         }
     }
 }
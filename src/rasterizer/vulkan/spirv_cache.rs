@@ -0,0 +1,190 @@
+//! Compiled SPIR-V kept on disk, so the shaders are only compiled again when their source changes.
+//!
+//! A cached file is the 64 bits hash of the sources, then the SPIR-V words, all little endian.
+//! The hash covers the shader and every file it includes, recursively. It is only checked with
+//! `vulkan_shaderc`, without a compiler the cached SPIR-V is used as is.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const SPIRV_FOLDER: &str = "./cache/spirv/";
+const SPIRV_EXT: &str = "spv";
+
+#[cfg(any(feature = "vulkan_shaderc", test))]
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+#[cfg(any(feature = "vulkan_shaderc", test))]
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a of the shader and its includes, stable across builds unlike `DefaultHasher`.
+///
+/// `#include "..."` is relative to the including file and `#include <...>` to `shader_folder`,
+/// like the include callback of the compiler.
+#[cfg(any(feature = "vulkan_shaderc", test))]
+pub fn source_hash(path: &Path, shader_folder: &str) -> Result<u64, String> {
+    let mut hash = FNV_OFFSET;
+    hash_file(path, shader_folder, &mut hash, &mut Vec::new())?;
+    Ok(hash)
+}
+
+/// `visited` stops include cycles, the compiler reports them anyway.
+#[cfg(any(feature = "vulkan_shaderc", test))]
+fn hash_file(
+    path: &Path,
+    shader_folder: &str,
+    hash: &mut u64,
+    visited: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    if visited.iter().any(|p| p == path) {
+        return Ok(());
+    }
+    visited.push(path.to_owned());
+
+    let source =
+        fs::read(path).map_err(|e| format!("Can't read {} : {e}", path.to_string_lossy()))?;
+    for byte in &source {
+        *hash = (*hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
+    }
+
+    for line in String::from_utf8_lossy(&source).lines() {
+        let Some(include) = line.trim().strip_prefix("#include") else {
+            continue;
+        };
+        let include = include.trim();
+        let resolved = if let Some(name) = include.strip_prefix('"') {
+            path.with_file_name(name.trim_end_matches('"'))
+        } else if let Some(name) = include.strip_prefix('<') {
+            Path::new(shader_folder).join(name.trim_end_matches('>'))
+        } else {
+            continue;
+        };
+        hash_file(&resolved, shader_folder, hash, visited)?;
+    }
+    Ok(())
+}
+
+/// `./resources/backgrounds/gradient.comp.glsl` is cached as
+/// `./cache/spirv/backgrounds.gradient.comp.spv`.
+fn cache_path(path: &Path, shader_folder: &str) -> PathBuf {
+    let relative = path.strip_prefix(shader_folder).unwrap_or(path);
+    let name = relative
+        .with_extension(SPIRV_EXT)
+        .iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(".");
+    Path::new(SPIRV_FOLDER).join(name)
+}
+
+/// The cached SPIR-V of the shader at `path`, and the hash of the sources it was compiled from.
+pub fn load(path: &Path, shader_folder: &str) -> Option<(u64, Vec<u32>)> {
+    let bytes = fs::read(cache_path(path, shader_folder)).ok()?;
+    if bytes.len() < 8 || bytes.len() % 4 != 0 {
+        return None;
+    }
+    let (hash, spirv) = bytes.split_at(8);
+    let hash = u64::from_le_bytes(hash.try_into().unwrap());
+    let spirv = spirv
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+        .collect();
+    Some((hash, spirv))
+}
+
+/// Failing to write the cache only costs a compilation at the next start.
+#[cfg(feature = "vulkan_shaderc")]
+pub fn store(path: &Path, shader_folder: &str, hash: u64, spirv: &[u32]) {
+    let bytes: Vec<u8> = hash
+        .to_le_bytes()
+        .into_iter()
+        .chain(spirv.iter().flat_map(|w| w.to_le_bytes()))
+        .collect();
+    let cache_path = cache_path(path, shader_folder);
+    if let Err(e) = fs::create_dir_all(SPIRV_FOLDER).and_then(|()| fs::write(&cache_path, bytes)) {
+        eprintln!(
+            "Couldn't write the SPIR-V cache {} : {e}",
+            cache_path.to_string_lossy()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh folder of shaders, as `(relative path, source)`.
+    fn shader_folder(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("graphics_spirv_cache_{name}"));
+        let _ = fs::remove_dir_all(&folder);
+        files.iter().for_each(|(path, source)| {
+            let path = folder.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        });
+        folder
+    }
+
+    fn hash(folder: &Path, path: &str) -> Result<u64, String> {
+        source_hash(&folder.join(path), &folder.to_string_lossy())
+    }
+
+    #[test]
+    fn hash_is_fnv_1a() {
+        let folder = shader_folder("fnv", &[("empty.glsl", ""), ("a.glsl", "a")]);
+        assert_eq!(hash(&folder, "empty.glsl"), Ok(FNV_OFFSET));
+        assert_eq!(hash(&folder, "a.glsl"), Ok(0xaf63dc4c8601ec8c));
+    }
+
+    #[test]
+    fn hash_covers_the_includes() {
+        let main = "#include \"common.glsl\"\n#include <scene.glsl>\nvoid main() {}";
+        let folder = shader_folder(
+            "includes",
+            &[
+                ("sub/main.glsl", main),
+                ("sub/common.glsl", "#define A 1"),
+                ("scene.glsl", "#define B 2"),
+            ],
+        );
+        let before = hash(&folder, "sub/main.glsl").unwrap();
+        assert_eq!(hash(&folder, "sub/main.glsl"), Ok(before));
+
+        fs::write(folder.join("sub/common.glsl"), "#define A 2").unwrap();
+        let relative_changed = hash(&folder, "sub/main.glsl").unwrap();
+        assert_ne!(relative_changed, before);
+
+        fs::write(folder.join("scene.glsl"), "#define B 3").unwrap();
+        assert_ne!(hash(&folder, "sub/main.glsl").unwrap(), relative_changed);
+    }
+
+    #[test]
+    fn hash_stops_include_cycles() {
+        let folder = shader_folder(
+            "cycle",
+            &[
+                ("a.glsl", "#include \"b.glsl\""),
+                ("b.glsl", "#include \"a.glsl\""),
+            ],
+        );
+        assert!(hash(&folder, "a.glsl").is_ok());
+    }
+
+    #[test]
+    fn hash_fails_on_missing_include() {
+        let folder = shader_folder("missing", &[("a.glsl", "#include \"gone.glsl\"")]);
+        let error = hash(&folder, "a.glsl").unwrap_err();
+        assert!(error.contains("gone.glsl"), "{error}");
+    }
+
+    #[test]
+    fn cache_path_flattens_the_shader_folder() {
+        assert_eq!(
+            cache_path(
+                Path::new("./resources/backgrounds/gradient.comp.glsl"),
+                "./resources/"
+            ),
+            Path::new(SPIRV_FOLDER).join("backgrounds.gradient.comp.spv")
+        );
+    }
+}
@@ -8,16 +8,23 @@ use ash::{Device, khr::swapchain, vk};
 use winit::dpi::PhysicalSize;
 
 use super::{
-    allocated::{AllocatedImage, image_view_create_info},
+    allocated::{AllocatedBuffer, AllocatedImage, MyMemoryUsage, image_view_create_info},
     base::VulkanBase,
     commands::FrameData,
     compute_shaders::Effects,
+    deferred::GBuffer,
+    gpu_culling::GpuCulling,
+    msaa::MsaaTargets,
+    post_process::PostProcess,
+    present::{OutputTransfer, PresentSettings},
     shaders_loader::ShadersLoader,
 };
 
 /// Creation of the swapchain and images based on the window.
+///
+/// When headless, an [`OffscreenTarget`] of fixed size replaces the swapchain.
 pub struct VulkanSwapchain {
-    inner: SwapchainData,
+    target: Target,
 
     draw_extent: vk::Extent2D,
     pub render_scale: f32,
@@ -27,6 +34,38 @@ pub struct VulkanSwapchain {
 
     // TODO: need to recreate on resize along swapchain because we use draw_img ?
     pub effects: Effects,
+    /// Reads `depth_img`, recreated with it.
+    pub gpu_culling: GpuCulling,
+    /// Reads and writes `draw_img`, recreated with it.
+    pub post_process: PostProcess,
+    /// `None` without MSAA, see [`Self::set_samples`].
+    msaa: Option<MsaaTargets>,
+    /// `None` with the forward shading, see [`Self::set_gbuffer`].
+    gbuffer: Option<GBuffer>,
+    /// Applied when the swapchain is recreated, see [`Self::set_present`].
+    present: PresentSettings,
+}
+
+enum Target {
+    Window(SwapchainData),
+    Offscreen(OffscreenTarget),
+}
+
+/// What is presented when headless : `draw_img` is blitted to a transient image of
+/// [`OffscreenTarget::FORMAT`], then copied to `readback` to be read on the host.
+pub struct OffscreenTarget {
+    pub readback: AllocatedBuffer,
+    pub extent: vk::Extent2D,
+}
+
+impl OffscreenTarget {
+    pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+    /// RGBA8 pixels, row by row, once the copy to `readback` is done.
+    pub fn read(&self) -> Vec<u8> {
+        self.readback
+            .read(self.extent.width as usize * self.extent.height as usize * 4)
+    }
 }
 
 impl VulkanSwapchain {
@@ -37,9 +76,10 @@ impl VulkanSwapchain {
         shaders: &ShadersLoader,
         allocator: Arc<Mutex<vk_mem::Allocator>>,
         min_extent: Option<vk::Extent3D>,
+        present: PresentSettings,
     ) -> Self {
-        let swapchain_data = SwapchainData::new(base);
-        let window_size = base.window.inner_size();
+        let swapchain_data = SwapchainData::new(base, &present);
+        let window_size = base.window().inner_size();
         let max_size = min_extent
             .map(|e| PhysicalSize {
                 width: u32::max(window_size.width, e.width),
@@ -47,11 +87,68 @@ impl VulkanSwapchain {
             })
             .unwrap_or(window_size);
 
+        let mut swapchain = Self::with_target(
+            base,
+            shaders,
+            allocator,
+            Target::Window(swapchain_data),
+            max_size,
+        );
+        swapchain.present = present;
+        swapchain
+    }
+
+    /// Without window, renders to an [`OffscreenTarget`] of `extent`.
+    pub fn new_headless(
+        base: &VulkanBase,
+        shaders: &ShadersLoader,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        extent: vk::Extent2D,
+    ) -> Self {
+        let readback = AllocatedBuffer::new(
+            allocator.clone(),
+            extent.width as u64 * extent.height as u64 * 4,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MyMemoryUsage::Readback,
+        );
+        let target = Target::Offscreen(OffscreenTarget { readback, extent });
+
+        Self::with_target(
+            base,
+            shaders,
+            allocator,
+            target,
+            PhysicalSize::new(extent.width, extent.height),
+        )
+    }
+
+    fn with_target(
+        base: &VulkanBase,
+        shaders: &ShadersLoader,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        target: Target,
+        max_size: PhysicalSize<u32>,
+    ) -> Self {
         let draw_img =
             AllocatedImage::new_draw_img(base.device.clone(), allocator.clone(), max_size);
-        let depth_img = AllocatedImage::new_draw_depth(base.device.clone(), allocator, max_size);
+        let depth_img =
+            AllocatedImage::new_draw_depth(base.device.clone(), allocator.clone(), max_size);
 
         let effects = Effects::new(base.device.clone(), shaders, draw_img.img_view);
+        let gpu_culling = GpuCulling::new(
+            base.device.clone(),
+            shaders,
+            allocator.clone(),
+            depth_img.img_view,
+            depth_img.extent,
+        );
+        let post_process = PostProcess::new(
+            base.device.clone(),
+            shaders,
+            allocator,
+            draw_img.img_view,
+            draw_img.extent,
+        );
 
         let draw_extent = vk::Extent2D {
             width: draw_img.extent.width,
@@ -59,23 +156,55 @@ impl VulkanSwapchain {
         };
 
         Self {
-            inner: swapchain_data,
+            target,
             draw_extent,
             render_scale: 1.,
             draw_img,
             depth_img,
             effects,
+            gpu_culling,
+            post_process,
+            msaa: None,
+            gbuffer: None,
+            present: Default::default(),
+        }
+    }
+
+    /// Panics when headless.
+    fn inner(&self) -> &SwapchainData {
+        match &self.target {
+            Target::Window(inner) => inner,
+            Target::Offscreen(_) => panic!("No swapchain when headless"),
+        }
+    }
+
+    /// Panics with a window.
+    pub fn offscreen(&self) -> &OffscreenTarget {
+        match &self.target {
+            Target::Offscreen(offscreen) => offscreen,
+            Target::Window(_) => panic!("No offscreen target with a window"),
         }
     }
 
     fn set_suboptimal(&self) {
         println!("Suboptimal swapchain, needs resizing.");
-        *self.inner.is_suboptimal.borrow_mut() = true;
+        *self.inner().is_suboptimal.borrow_mut() = true;
+    }
+
+    /// Recreates the swapchain at the next [`Self::resize_if_necessary`] if the present mode or
+    /// the colour space changed, the paper white only changes the tone mapping.
+    pub fn set_present(&mut self, present: PresentSettings) {
+        let recreate = (present.present_mode, present.color_space)
+            != (self.present.present_mode, self.present.color_space);
+        self.present = present;
+        if let (true, Target::Window(inner)) = (recreate, &self.target) {
+            *inner.is_suboptimal.borrow_mut() = true;
+        }
     }
 
     fn set_out_of_date_khr(&self) {
         println!("Error out of date khr, needs, resizing.");
-        *self.inner.is_suboptimal.borrow_mut() = true;
+        *self.inner().is_suboptimal.borrow_mut() = true;
     }
 
     /// If window is resized, we need to recreate the swapchain.
@@ -83,33 +212,48 @@ impl VulkanSwapchain {
     /// If the new size is smaller, we only recreate the [`SwapchainData`],
     /// but if it's bigger we recreate the [`VulkanSwapchain`] and re-allocate the images at least
     /// as big.
+    ///
+    /// When headless, the size never changes, only the render scale.
     pub fn resize_if_necessary(
         &mut self,
         base: &VulkanBase,
         shaders: &ShadersLoader,
         allocator: Arc<Mutex<vk_mem::Allocator>>,
     ) {
-        let window_size = base.window.inner_size();
+        if let Target::Window(inner) = &mut self.target {
+            let window_size = base.window().inner_size();
 
-        if *self.inner.is_suboptimal.borrow()
-            || *self.inner.is_out_of_date_khr.borrow()
-            || self.inner.window_size != window_size
-        {
-            // If the draw_img is bigger, we avoid re-allocating it,
-            // and just use smaller extent (updated from [`update_draw_extent`]) :
-            if self.draw_img.extent.height >= window_size.height
-                && self.draw_img.extent.width >= window_size.width
+            if *inner.is_suboptimal.borrow()
+                || *inner.is_out_of_date_khr.borrow()
+                || inner.window_size != window_size
             {
-                println!("--- Resize swapchain only ---");
-                self.inner.drop_early();
-                self.inner = SwapchainData::new(base);
-            } else {
-                println!("--- Resize swapchain and draw image ---");
-                self.inner.drop_early();
-                *self = VulkanSwapchain::new(base, shaders, allocator, Some(self.draw_img.extent));
-            }
+                // If the draw_img is bigger, we avoid re-allocating it,
+                // and just use smaller extent (updated from [`update_draw_extent`]) :
+                if self.draw_img.extent.height >= window_size.height
+                    && self.draw_img.extent.width >= window_size.width
+                {
+                    println!("--- Resize swapchain only ---");
+                    inner.drop_early();
+                    *inner = SwapchainData::new(base, &self.present);
+                } else {
+                    println!("--- Resize swapchain and draw image ---");
+                    inner.drop_early();
+                    let (render_scale, samples, deferred) =
+                        (self.render_scale, self.samples(), self.gbuffer.is_some());
+                    *self = VulkanSwapchain::new(
+                        base,
+                        shaders,
+                        allocator.clone(),
+                        Some(self.draw_img.extent),
+                        self.present,
+                    );
+                    self.render_scale = render_scale;
+                    self.set_samples(base, shaders, allocator.clone(), samples);
+                    self.set_gbuffer(base, allocator, deferred);
+                }
 
-            println!("--- End of resize ---");
+                println!("--- End of resize ---");
+            }
         }
 
         self.update_draw_extent();
@@ -119,9 +263,10 @@ impl VulkanSwapchain {
         &self,
         current_frame: &FrameData,
     ) -> Option<(u32, &vk::Image, &vk::Semaphore, &vk::ImageView)> {
+        let inner = self.inner();
         let res = unsafe {
-            self.inner.swapchain_loader.acquire_next_image(
-                self.inner.swapchain,
+            inner.swapchain_loader.acquire_next_image(
+                inner.swapchain,
                 1_000_000_000,
                 current_frame.sem_swapchain,
                 vk::Fence::null(),
@@ -134,7 +279,7 @@ impl VulkanSwapchain {
                     self.set_suboptimal();
                 }
 
-                let (i, v, s) = &self.inner.swapchain_images[swapchain_img_index as usize];
+                let (i, v, s) = &inner.swapchain_images[swapchain_img_index as usize];
 
                 Some((swapchain_img_index, i, s, v))
             }
@@ -147,18 +292,15 @@ impl VulkanSwapchain {
     }
 
     pub fn present(&self, swapchain_img_index: u32, sem_render: &vk::Semaphore, queue: vk::Queue) {
-        let swapchains = [self.inner.swapchain];
+        let inner = self.inner();
+        let swapchains = [inner.swapchain];
         let wait_semaphores = [*sem_render];
         let images_indices = [swapchain_img_index];
         let present_info = vk::PresentInfoKHR::default()
             .swapchains(&swapchains)
             .wait_semaphores(&wait_semaphores)
             .image_indices(&images_indices);
-        let res = unsafe {
-            self.inner
-                .swapchain_loader
-                .queue_present(queue, &present_info)
-        };
+        let res = unsafe { inner.swapchain_loader.queue_present(queue, &present_info) };
 
         match res {
             Ok(false) => (),
@@ -169,15 +311,12 @@ impl VulkanSwapchain {
     }
 
     fn update_draw_extent(&mut self) {
-        self.draw_extent.height = (u32::min(
-            self.inner.swapchain_extent.height,
-            self.draw_img.extent.height,
-        ) as f32
+        let swapchain_extent = self.swapchain_extent();
+        self.draw_extent.height = (u32::min(swapchain_extent.height, self.draw_img.extent.height)
+            as f32
             * self.render_scale) as u32;
-        self.draw_extent.width = (u32::min(
-            self.inner.swapchain_extent.width,
-            self.draw_img.extent.width,
-        ) as f32
+        self.draw_extent.width = (u32::min(swapchain_extent.width, self.draw_img.extent.width)
+            as f32
             * self.render_scale) as u32;
     }
 
@@ -186,11 +325,29 @@ impl VulkanSwapchain {
     }
 
     pub fn swapchain_extent(&self) -> vk::Extent2D {
-        self.inner.swapchain_extent
+        match &self.target {
+            Target::Window(inner) => inner.swapchain_extent,
+            Target::Offscreen(offscreen) => offscreen.extent,
+        }
     }
 
     pub fn swapchain_img_format(&self) -> vk::Format {
-        self.inner.swapchain_img_format
+        match &self.target {
+            Target::Window(inner) => inner.swapchain_img_format,
+            Target::Offscreen(_) => OffscreenTarget::FORMAT,
+        }
+    }
+
+    /// Headless, the `_UNORM` offscreen target is encoded like an SDR window.
+    pub fn output_transfer(&self) -> OutputTransfer {
+        match &self.target {
+            Target::Window(inner) => inner.output_transfer,
+            Target::Offscreen(_) => OutputTransfer::Srgb,
+        }
+    }
+
+    pub fn paper_white(&self) -> f32 {
+        self.present.paper_white
     }
 
     pub fn draw_img(&self) -> &vk::Image {
@@ -216,6 +373,63 @@ impl VulkanSwapchain {
     pub fn depth_format(&self) -> &vk::Format {
         &self.depth_img.format
     }
+
+    /// Of the geometry pass, `TYPE_1` without MSAA.
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.msaa
+            .as_ref()
+            .map_or(vk::SampleCountFlags::TYPE_1, |msaa| msaa.samples)
+    }
+
+    pub fn msaa(&self) -> Option<&MsaaTargets> {
+        self.msaa.as_ref()
+    }
+
+    /// Recreates the multisampled targets of the geometry pass, `TYPE_1` removes them.
+    ///
+    /// The previous ones must not be in use anymore.
+    pub fn set_samples(
+        &mut self,
+        base: &VulkanBase,
+        shaders: &ShadersLoader,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        samples: vk::SampleCountFlags,
+    ) {
+        self.msaa = None;
+        if samples != vk::SampleCountFlags::TYPE_1 {
+            self.msaa = Some(MsaaTargets::new(
+                base,
+                shaders,
+                allocator,
+                &self.draw_img,
+                self.depth_img.format,
+                samples,
+            ));
+        }
+    }
+
+    pub fn gbuffer(&self) -> Option<&GBuffer> {
+        self.gbuffer.as_ref()
+    }
+
+    /// Creates the G-buffer of the deferred shading, or removes it.
+    ///
+    /// The previous one must not be in use anymore.
+    pub fn set_gbuffer(
+        &mut self,
+        base: &VulkanBase,
+        allocator: Arc<Mutex<vk_mem::Allocator>>,
+        deferred: bool,
+    ) {
+        self.gbuffer = deferred.then(|| {
+            GBuffer::new(
+                base.device.clone(),
+                allocator,
+                &self.draw_img,
+                &self.depth_img,
+            )
+        });
+    }
 }
 
 /// Part that needs to be recreated on resize
@@ -231,49 +445,24 @@ struct SwapchainData {
     swapchain_images: Vec<(vk::Image, vk::ImageView, vk::Semaphore)>,
     pub swapchain_img_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
+    output_transfer: OutputTransfer,
 
     destroyed: bool,
 }
 
 impl SwapchainData {
-    pub fn new(base: &VulkanBase) -> Self {
-        let present_mode = vk::PresentModeKHR::FIFO;
-        // FIFO = HARD V-Sync
-        //     - No tearing
-        //     - Forces to wait for next frame : limits framerate to screen
-        //     - Only one that is required by spec, so always here.
-        //     - If app is slower, goes to next step : 60 -> 30fps
-        // MAILBOX = OFF
-        //     - No tearing as well
-        //     - No cap on FPS
-        //     - When ready, displays the latest
-        //     - Only other one supported by my AMD Radeon RX 7800XT
-        // FIFO_RELAXED = Like FIFO, but if slower, displays immediately when available
-        //     - Tearing possible if slower than screen
-        //     - On 7800XT, uncaps FPS in practice -> MAILBOX ?
-        // FIFO_LATEST_READY = new ?
-        // TODO: when implemented : MAILBOX : https://vkguide.dev/docs/new_chapter_1/vulkan_init_flow/
-        // let present_mode = present_modes
-        //     .iter()
-        //     .cloned()
-        //     .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-        //     .unwrap_or(vk::PresentModeKHR::FIFO);
-
-        let surface_format = unsafe {
-            // base.surface_loader
-            //     .get_physical_device_surface_formats(base.chosen_gpu, base.surface)
-            //     .unwrap()
-            //     .iter()
-            //     .for_each(|f| eprintln!("Supported device surface format : {f:?}."));
-            base.surface_loader
-                .get_physical_device_surface_formats(base.chosen_gpu, base.surface)
-                .unwrap()[0]
-        };
+    pub fn new(base: &VulkanBase, present: &PresentSettings) -> Self {
+        let presentation = base.presentation();
+        let present_mode = present.present_mode.choose(&presentation.present_modes);
+        let (surface_format, output_transfer) =
+            present.color_space.choose(&presentation.surface_formats);
+        println!("Swapchain : {present_mode:?}, {surface_format:?}");
         let swapchain_img_format = surface_format.format;
 
         let surface_capabilities = unsafe {
-            base.surface_loader
-                .get_physical_device_surface_capabilities(base.chosen_gpu, base.surface)
+            presentation
+                .surface_loader
+                .get_physical_device_surface_capabilities(base.chosen_gpu, presentation.surface)
                 .unwrap()
         };
         let mut desired_image_count = surface_capabilities.min_image_count + 1;
@@ -282,7 +471,7 @@ impl SwapchainData {
         {
             desired_image_count = surface_capabilities.max_image_count;
         }
-        let window_size = base.window.inner_size();
+        let window_size = presentation.window.inner_size();
         let swapchain_extent = match surface_capabilities.current_extent.width {
             u32::MAX => vk::Extent2D {
                 width: window_size.width,
@@ -301,9 +490,9 @@ impl SwapchainData {
 
         let swapchain_loader = swapchain::Device::new(&base.instance, &base.device);
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
-            .surface(base.surface)
+            .surface(presentation.surface)
             .image_format(swapchain_img_format)
-            .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
+            .image_color_space(surface_format.color_space)
             .present_mode(present_mode)
             .image_extent(swapchain_extent)
             .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
@@ -354,6 +543,7 @@ impl SwapchainData {
             swapchain_images,
             swapchain_img_format,
             swapchain_extent,
+            output_transfer,
 
             destroyed: false,
         }
@@ -0,0 +1,331 @@
+//! Texels of the glTF images, decoded on the loading threads in the format they're sampled in.
+//!
+//! PNG and JPEG images keep their channel count and bit depth when the GPU samples them, grey
+//! images being swizzled to `rrr1` or `rrrg`. Colour textures use `_SRGB` formats, except 16 bits
+//! ones which are made linear on the CPU.
+//!
+//! KTX2 images, from `KHR_texture_basisu`, are uploaded with their mip chain. Their BCn blocks
+//! are decompressed by [`bcn`] when the GPU doesn't support them.
+
+use ash::vk;
+use gltf::image;
+
+use super::{base::VulkanBase, bcn};
+
+/// The formats decoded glTF images can end up in.
+const DECODED_FORMATS: &[vk::Format] = &[
+    vk::Format::R8_UNORM,
+    vk::Format::R8_SRGB,
+    vk::Format::R8G8_UNORM,
+    vk::Format::R8G8_SRGB,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::R16_UNORM,
+    vk::Format::R16G16_UNORM,
+    vk::Format::R16G16B16A16_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+];
+
+/// What the GPU can do with the texture formats, queried once to be sent to the loading threads.
+#[derive(Debug, Clone)]
+pub struct FormatSupport {
+    /// `textureCompressionBC`, enabled on the device when available.
+    bc: bool,
+    /// Optimal tiling features of the [`DECODED_FORMATS`].
+    features: Vec<(vk::Format, vk::FormatFeatureFlags)>,
+}
+
+impl FormatSupport {
+    pub fn new(base: &VulkanBase) -> Self {
+        let features = DECODED_FORMATS
+            .iter()
+            .map(|&format| {
+                let properties = unsafe {
+                    base.instance
+                        .get_physical_device_format_properties(base.chosen_gpu, format)
+                };
+                (format, properties.optimal_tiling_features)
+            })
+            .collect();
+
+        Self {
+            bc: base.texture_compression_bc,
+            features,
+        }
+    }
+
+    fn features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        if bcn::is_bcn(format) && self.bc {
+            return vk::FormatFeatureFlags::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        }
+        self.features
+            .iter()
+            .find(|(f, _)| *f == format)
+            .map_or(vk::FormatFeatureFlags::empty(), |(_, features)| *features)
+    }
+
+    fn samples(&self, format: vk::Format) -> bool {
+        self.features(format)
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    }
+
+    /// With linear blits, like `generate_mipmaps` does.
+    fn can_generate_mips(&self, format: vk::Format) -> bool {
+        self.features(format).contains(
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+}
+
+/// Ready for `UploadBatch::texture`.
+pub struct TextureData {
+    pub extent: vk::Extent3D,
+    pub format: vk::Format,
+    pub components: vk::ComponentMapping,
+    /// Tightly packed, from the full size one.
+    pub levels: Vec<Vec<u8>>,
+    /// From the only level given, by blits.
+    pub generate_mips: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Depth {
+    U8,
+    U16,
+    F32,
+}
+
+impl Depth {
+    fn size(self) -> usize {
+        match self {
+            Depth::U8 => 1,
+            Depth::U16 => 2,
+            Depth::F32 => 4,
+        }
+    }
+
+    /// Of an opaque alpha.
+    fn one(self) -> Vec<u8> {
+        match self {
+            Depth::U8 => vec![u8::MAX],
+            Depth::U16 => u16::MAX.to_ne_bytes().to_vec(),
+            Depth::F32 => 1f32.to_ne_bytes().to_vec(),
+        }
+    }
+}
+
+/// RGB formats are rarely sampled, they always get an alpha channel.
+fn format_of(depth: Depth, channels: usize, srgb: bool) -> vk::Format {
+    use vk::Format as F;
+
+    match (depth, channels, srgb) {
+        (Depth::U8, 1, false) => F::R8_UNORM,
+        (Depth::U8, 1, true) => F::R8_SRGB,
+        (Depth::U8, 2, false) => F::R8G8_UNORM,
+        (Depth::U8, 2, true) => F::R8G8_SRGB,
+        (Depth::U8, _, false) => F::R8G8B8A8_UNORM,
+        (Depth::U8, _, true) => F::R8G8B8A8_SRGB,
+        // There's no 16 bits sRGB format, see `linearize_u16`.
+        (Depth::U16, 1, _) => F::R16_UNORM,
+        (Depth::U16, 2, _) => F::R16G16_UNORM,
+        (Depth::U16, _, _) => F::R16G16B16A16_UNORM,
+        (Depth::F32, _, _) => F::R32G32B32A32_SFLOAT,
+    }
+}
+
+/// A decoded PNG or JPEG, `srgb` for colour textures.
+pub fn from_gltf(
+    image: image::Data,
+    srgb: bool,
+    support: &FormatSupport,
+) -> Result<TextureData, String> {
+    use image::Format::*;
+
+    let (mut depth, mut channels) = match image.format {
+        R8 => (Depth::U8, 1),
+        R8G8 => (Depth::U8, 2),
+        R8G8B8 => (Depth::U8, 3),
+        R8G8B8A8 => (Depth::U8, 4),
+        R16 => (Depth::U16, 1),
+        R16G16 => (Depth::U16, 2),
+        R16G16B16 => (Depth::U16, 3),
+        R16G16B16A16 => (Depth::U16, 4),
+        R32G32B32FLOAT => (Depth::F32, 3),
+        R32G32B32A32FLOAT => (Depth::F32, 4),
+    };
+    let mut pixels = image.pixels;
+
+    if depth == Depth::U16 && !support.samples(format_of(depth, channels, srgb)) {
+        pixels = narrow_u16(&pixels);
+        depth = Depth::U8;
+    }
+    if depth == Depth::U16 && srgb {
+        linearize_u16(&mut pixels, channels);
+    }
+    // Float images are already linear.
+    let srgb = srgb && depth == Depth::U8;
+    if channels == 3 || !support.samples(format_of(depth, channels, srgb)) {
+        pixels = to_rgba(&pixels, depth, channels);
+        channels = 4;
+    }
+
+    let format = format_of(depth, channels, srgb);
+    Ok(TextureData {
+        extent: vk::Extent3D {
+            width: image.width,
+            height: image.height,
+            depth: 1,
+        },
+        format,
+        components: grey_swizzle(channels),
+        levels: vec![pixels],
+        generate_mips: support.can_generate_mips(format),
+    })
+}
+
+fn grey_swizzle(channels: usize) -> vk::ComponentMapping {
+    use vk::ComponentSwizzle as S;
+
+    match channels {
+        1 => vk::ComponentMapping {
+            r: S::R,
+            g: S::R,
+            b: S::R,
+            a: S::ONE,
+        },
+        2 => vk::ComponentMapping {
+            r: S::R,
+            g: S::R,
+            b: S::R,
+            a: S::G,
+        },
+        _ => vk::ComponentMapping::default(),
+    }
+}
+
+/// Keeps the most significant byte.
+fn narrow_u16(pixels: &[u8]) -> Vec<u8> {
+    pixels
+        .chunks_exact(2)
+        .map(|c| (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8)
+        .collect()
+}
+
+/// The colour channels, not the alpha, from sRGB to linear.
+fn linearize_u16(pixels: &mut [u8], channels: usize) {
+    let colors = if channels >= 3 { 3 } else { 1 };
+    for pixel in pixels.chunks_exact_mut(2 * channels) {
+        for c in pixel[..2 * colors].chunks_exact_mut(2) {
+            let v = u16::from_ne_bytes([c[0], c[1]]) as f32 / u16::MAX as f32;
+            let linear = if v <= 0.04045 {
+                v / 12.92
+            } else {
+                ((v + 0.055) / 1.055).powf(2.4)
+            };
+            c.copy_from_slice(&((linear * u16::MAX as f32).round() as u16).to_ne_bytes());
+        }
+    }
+}
+
+/// Grey is copied to the RGB channels, alpha is opaque if missing.
+fn to_rgba(pixels: &[u8], depth: Depth, channels: usize) -> Vec<u8> {
+    let size = depth.size();
+    let one = depth.one();
+    let mut rgba = Vec::with_capacity(pixels.len() / channels * 4);
+    for pixel in pixels.chunks_exact(size * channels) {
+        let channel = |i: usize| &pixel[i * size..(i + 1) * size];
+        let [r, g, b, a] = match channels {
+            1 => [channel(0), channel(0), channel(0), &one],
+            2 => [channel(0), channel(0), channel(0), channel(1)],
+            3 => [channel(0), channel(1), channel(2), &one],
+            _ => [channel(0), channel(1), channel(2), channel(3)],
+        };
+        [r, g, b, a].iter().for_each(|c| rgba.extend_from_slice(c));
+    }
+    rgba
+}
+
+/// The `_SRGB` variant of a `_UNORM` format, for colour textures.
+fn srgb_format(format: vk::Format) -> vk::Format {
+    use vk::Format as F;
+
+    match format {
+        F::R8_UNORM => F::R8_SRGB,
+        F::R8G8_UNORM => F::R8G8_SRGB,
+        F::R8G8B8A8_UNORM => F::R8G8B8A8_SRGB,
+        F::BC1_RGB_UNORM_BLOCK => F::BC1_RGB_SRGB_BLOCK,
+        F::BC1_RGBA_UNORM_BLOCK => F::BC1_RGBA_SRGB_BLOCK,
+        F::BC2_UNORM_BLOCK => F::BC2_SRGB_BLOCK,
+        F::BC3_UNORM_BLOCK => F::BC3_SRGB_BLOCK,
+        F::BC7_UNORM_BLOCK => F::BC7_SRGB_BLOCK,
+        _ => format,
+    }
+}
+
+/// A KTX2 container of a 2D texture, with its mip chain, `srgb` for colour textures.
+///
+/// Basis Universal payloads aren't transcoded, only Zstandard supercompression is supported.
+pub fn from_ktx2(bytes: &[u8], srgb: bool, support: &FormatSupport) -> Result<TextureData, String> {
+    let reader = ktx2::Reader::new(bytes).map_err(|e| format!("Invalid KTX2 : {e}"))?;
+    let header = reader.header();
+    let Some(format) = header.format else {
+        return Err("Basis Universal KTX2 isn't supported, it needs a BCn format".into());
+    };
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        return Err("Only 2D KTX2 textures are supported".into());
+    }
+
+    let mut format = vk::Format::from_raw(format.value() as i32);
+    if srgb {
+        format = srgb_format(format);
+    }
+
+    let mut levels = reader
+        .levels()
+        .map(|level| match header.supercompression_scheme {
+            None => Ok(level.data.to_vec()),
+            Some(ktx2::SupercompressionScheme::Zstandard) => {
+                let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+                ruzstd::decoding::StreamingDecoder::new(level.data)
+                    .map_err(|e| e.to_string())
+                    .and_then(|mut decoder| {
+                        std::io::Read::read_to_end(&mut decoder, &mut data)
+                            .map_err(|e| e.to_string())
+                    })
+                    .map_err(|e| format!("Invalid Zstandard level : {e}"))?;
+                Ok(data)
+            }
+            Some(scheme) => Err(format!("Unsupported KTX2 supercompression : {scheme:?}")),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let (width, height) = (header.pixel_width, header.pixel_height.max(1));
+    if bcn::is_bcn(format) && !support.bc {
+        let mut decoded_format = format;
+        for (mip, level) in levels.iter_mut().enumerate() {
+            let (f, texels) =
+                bcn::decode(format, (width >> mip).max(1), (height >> mip).max(1), level)?;
+            decoded_format = f;
+            *level = texels;
+        }
+        format = decoded_format;
+    } else if !bcn::is_bcn(format) && !support.samples(format) {
+        return Err(format!("Unsupported KTX2 format : {format:?}"));
+    }
+
+    Ok(TextureData {
+        extent: vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        },
+        format,
+        components: vk::ComponentMapping::default(),
+        generate_mips: levels.len() == 1 && support.can_generate_mips(format),
+        levels,
+    })
+}
@@ -1,4 +1,5 @@
 use std::{
+    cell::Cell,
     ops::Deref,
     rc::{Rc, Weak},
     sync::{Arc, Mutex},
@@ -9,9 +10,10 @@ use glam::Vec4;
 use vk_mem::Allocator;
 
 use super::{
-    allocated::{AllocatedBuffer, AllocatedImage, MyMemoryUsage},
+    allocated::AllocatedImage,
+    bindless::BindlessTable,
     commands::VulkanCommands,
-    descriptors::{DescriptorAllocatorGrowable, DescriptorLayoutBuilder, DescriptorWriter},
+    deferred::GBUFFER_FORMATS,
     gfx_pipeline::{GpuDrawPushConstants, PipelineBuilder},
     shaders_loader::{ShaderName, ShadersLoader},
     swapchain::VulkanSwapchain,
@@ -27,11 +29,11 @@ pub struct Textures<'a> {
 
     pub default_sampler_linear: vk::Sampler,
     pub default_sampler_nearest: vk::Sampler,
+    /// Index of `white` in the bindless table.
+    pub white_texture: u32,
 
     pub default_material: Rc<MaterialInstance>,
-    _material_constants: AllocatedBuffer,
     pub metal_rough_material: GltfMetallicRoughness<'a>,
-    _global_desc_alloc: DescriptorAllocatorGrowable,
 }
 
 impl Textures<'_> {
@@ -42,6 +44,7 @@ impl Textures<'_> {
         device: Rc<Device>,
         allocator: Arc<Mutex<Allocator>>,
         scene_data_descriptor_layout: vk::DescriptorSetLayout,
+        ibl_layout: vk::DescriptorSetLayout,
     ) -> Self {
         let extent = vk::Extent3D {
             width: 1,
@@ -139,29 +142,19 @@ impl Textures<'_> {
 
         let mut metal_rough_material = GltfMetallicRoughness::new(
             device.clone(),
+            allocator,
             shaders,
             *swapchain.draw_format(),
             *swapchain.depth_format(),
             scene_data_descriptor_layout,
+            ibl_layout,
         );
 
-        // See `resources/input_structures.glsl` :
-        // - UNIFORM_BUFFER for GpuSceneData
-        // - ''             for GLTFMaterialData
-        // - img sampler    for colorTex
-        // - ''             for metalRoughTex
-        let sizes = vec![
-            (vk::DescriptorType::UNIFORM_BUFFER, 2.),
-            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2.),
-        ];
-        let mut global_desc_alloc = DescriptorAllocatorGrowable::new(device.clone(), 10, sizes);
-
-        let (material_constants, default_material) = metal_rough_material.create_material(
-            allocator.clone(),
-            &mut global_desc_alloc,
-            &error_checkerboard,
-            default_sampler_nearest,
-        );
+        let white_texture = metal_rough_material
+            .bindless
+            .add_texture(&white, default_sampler_linear);
+        let default_material =
+            metal_rough_material.create_material(&error_checkerboard, default_sampler_nearest);
 
         Self {
             device_copy: device,
@@ -172,11 +165,10 @@ impl Textures<'_> {
             error_checkerboard: Rc::new(error_checkerboard),
             default_sampler_linear,
             default_sampler_nearest,
+            white_texture,
 
             default_material: Rc::new(default_material),
-            _material_constants: material_constants,
             metal_rough_material,
-            _global_desc_alloc: global_desc_alloc,
         }
     }
 }
@@ -202,9 +194,18 @@ pub struct GltfMetallicRoughness<'a> {
     pipeline_opaque: Rc<MaterialPipeline>,
     pipeline_transparent: Rc<MaterialPipeline>,
 
-    material_layout: vk::DescriptorSetLayout,
-
-    writer: DescriptorWriter<'a>,
+    // Debug views, see `ViewMode` and `Wireframe`, they share the layout of `pipeline_opaque`.
+    pub pipeline_overdraw: MaterialPipeline,
+    pub pipeline_wireframe: MaterialPipeline,
+    pub pipeline_wireframe_overlay: MaterialPipeline,
+    /// Draws the opaque surfaces in the `GBuffer`, never multisampled.
+    pub pipeline_gbuffer: MaterialPipeline,
+    /// Of the attachments, to rebuild the pipelines with.
+    draw_img_format: vk::Format,
+    depth_img_format: vk::Format,
+
+    /// Set 1 of the pipelines, every material is in it.
+    pub bindless: BindlessTable<'a>,
 }
 
 impl Drop for GltfMetallicRoughness<'_> {
@@ -212,9 +213,6 @@ impl Drop for GltfMetallicRoughness<'_> {
         #[cfg(feature = "vulkan_dbg_mem")]
         println!("drop GltfMetallicRoughness");
         unsafe {
-            self.device_copy
-                .destroy_descriptor_set_layout(self.material_layout, None);
-
             self.device_copy
                 .destroy_pipeline_layout(self.pipeline_opaque.layout, None);
             if self.pipeline_opaque.layout != self.pipeline_transparent.layout {
@@ -223,9 +221,17 @@ impl Drop for GltfMetallicRoughness<'_> {
             }
 
             self.device_copy
-                .destroy_pipeline(self.pipeline_opaque.pipeline, None);
+                .destroy_pipeline(self.pipeline_opaque.pipeline.get(), None);
+            self.device_copy
+                .destroy_pipeline(self.pipeline_transparent.pipeline.get(), None);
+            self.device_copy
+                .destroy_pipeline(self.pipeline_overdraw.pipeline.get(), None);
             self.device_copy
-                .destroy_pipeline(self.pipeline_transparent.pipeline, None);
+                .destroy_pipeline(self.pipeline_wireframe.pipeline.get(), None);
+            self.device_copy
+                .destroy_pipeline(self.pipeline_wireframe_overlay.pipeline.get(), None);
+            self.device_copy
+                .destroy_pipeline(self.pipeline_gbuffer.pipeline.get(), None);
         }
     }
 }
@@ -233,29 +239,21 @@ impl Drop for GltfMetallicRoughness<'_> {
 impl GltfMetallicRoughness<'_> {
     pub fn new(
         device: Rc<Device>,
+        allocator: Arc<Mutex<Allocator>>,
         shaders: &ShadersLoader,
         draw_img_format: vk::Format,
         depth_img_format: vk::Format,
         scene_data_descriptor_layout: vk::DescriptorSetLayout,
+        ibl_layout: vk::DescriptorSetLayout,
     ) -> Self {
-        let mesh_frag = shaders.get(ShaderName::MeshFrag);
-        let mesh_vert = shaders.get(ShaderName::MeshVert);
-
         let matrix_range = vk::PushConstantRange::default()
             .offset(0)
             .size(size_of::<GpuDrawPushConstants>() as u32)
             .stage_flags(vk::ShaderStageFlags::VERTEX);
         let push_constant_ranges = [matrix_range];
 
-        let material_layout = DescriptorLayoutBuilder::default()
-            .add_binding(0, vk::DescriptorType::UNIFORM_BUFFER)
-            .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .add_binding(2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .build(
-                &device,
-                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-            );
-        let layouts = [scene_data_descriptor_layout, material_layout];
+        let bindless = BindlessTable::new(device.clone(), allocator);
+        let layouts = [scene_data_descriptor_layout, bindless.layout, ibl_layout];
 
         let mesh_layout_info = vk::PipelineLayoutCreateInfo::default()
             .set_layouts(&layouts[..])
@@ -267,132 +265,208 @@ impl GltfMetallicRoughness<'_> {
                 .unwrap()
         };
 
-        let mut pipeline_builder = PipelineBuilder::new(new_layout);
-        pipeline_builder.set_shaders(&mesh_vert, &mesh_frag);
-        pipeline_builder.set_input_topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-        pipeline_builder.set_polygon_mode(vk::PolygonMode::FILL);
-        pipeline_builder.set_cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE);
-        pipeline_builder.set_multisampling_none();
-        pipeline_builder.disable_blending();
-        pipeline_builder.enable_depthtest(true, vk::CompareOp::GREATER_OR_EQUAL);
-        // render format
-        let formats = [draw_img_format];
-        pipeline_builder.set_color_attachment_format(&formats[..]);
-        pipeline_builder.set_depth_format(depth_img_format);
-
-        let pipeline_opaque = pipeline_builder.build(&device);
-
-        pipeline_builder.enable_blending_additive();
-        pipeline_builder.enable_depthtest(false, vk::CompareOp::GREATER_OR_EQUAL);
-        let pipeline_transparent = pipeline_builder.build(&device);
+        let pipelines = GeometryPipelines::build(
+            &device,
+            shaders,
+            new_layout,
+            draw_img_format,
+            depth_img_format,
+            (vk::SampleCountFlags::TYPE_1, false),
+        );
 
         Self {
             device_copy: device,
 
-            pipeline_opaque: Rc::new(MaterialPipeline {
-                pipeline: pipeline_opaque,
-                layout: new_layout,
-            }),
-            pipeline_transparent: Rc::new(MaterialPipeline {
-                pipeline: pipeline_transparent,
-                layout: new_layout,
-            }),
-            material_layout,
-            writer: Default::default(),
+            pipeline_opaque: Rc::new(MaterialPipeline::new(pipelines.opaque, new_layout)),
+            pipeline_transparent: Rc::new(MaterialPipeline::new(pipelines.transparent, new_layout)),
+            pipeline_overdraw: MaterialPipeline::new(pipelines.overdraw, new_layout),
+            pipeline_wireframe: MaterialPipeline::new(pipelines.wireframe, new_layout),
+            pipeline_wireframe_overlay: MaterialPipeline::new(
+                pipelines.wireframe_overlay,
+                new_layout,
+            ),
+            pipeline_gbuffer: MaterialPipeline::new(pipelines.gbuffer, new_layout),
+            draw_img_format,
+            depth_img_format,
+            bindless,
         }
     }
 
+    /// Rebuilds the pipelines for the sample count of the attachments, and whether they are
+    /// shaded per sample. The previous pipelines must not be in use anymore.
+    ///
+    /// The materials keep pointing to the same [`MaterialPipeline`]s.
+    pub fn set_multisampling(
+        &mut self,
+        shaders: &ShadersLoader,
+        multisampling: (vk::SampleCountFlags, bool),
+    ) {
+        let pipelines = GeometryPipelines::build(
+            &self.device_copy,
+            shaders,
+            self.pipeline_opaque.layout,
+            self.draw_img_format,
+            self.depth_img_format,
+            multisampling,
+        );
+        [
+            (&*self.pipeline_opaque, pipelines.opaque),
+            (&*self.pipeline_transparent, pipelines.transparent),
+            (&self.pipeline_overdraw, pipelines.overdraw),
+            (&self.pipeline_wireframe, pipelines.wireframe),
+            (
+                &self.pipeline_wireframe_overlay,
+                pipelines.wireframe_overlay,
+            ),
+            (&self.pipeline_gbuffer, pipelines.gbuffer),
+        ]
+        .into_iter()
+        .for_each(|(material_pipeline, pipeline)| unsafe {
+            self.device_copy
+                .destroy_pipeline(material_pipeline.pipeline.replace(pipeline), None);
+        });
+    }
+
     pub fn write_material(
         &mut self,
         pass: MaterialPass,
-        resources: &MaterialResources,
-        desc_alloc: &mut DescriptorAllocatorGrowable,
+        constants: MaterialConstants,
     ) -> MaterialInstance {
-        let mat_data = MaterialInstance {
+        MaterialInstance {
             pipeline: Rc::downgrade(if let MaterialPass::Transparent = pass {
                 &self.pipeline_transparent
             } else {
                 &self.pipeline_opaque
             }),
-            material_set: desc_alloc.allocate(self.material_layout),
+            material_id: self.bindless.add_material(constants),
             pass_type: pass,
-        };
-
-        self.writer.clear();
-        self.writer.write_buffer(
-            0,
-            resources.data_buffer,
-            size_of::<MaterialConstants>() as u64,
-            resources.data_buffer_offset as u64,
-            vk::DescriptorType::UNIFORM_BUFFER,
-        );
-        self.writer.write_image(
-            1,
-            resources.color_img.img_view,
-            resources.color_sampler,
-            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-        );
-        self.writer.write_image(
-            2,
-            resources.metal_rough_img.img_view,
-            resources.metal_rough_sampler,
-            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-        );
-
-        self.writer
-            .update_set(&self.device_copy, mat_data.material_set);
-
-        mat_data
+        }
     }
 
     pub fn create_material(
         &mut self,
-        allocator: Arc<Mutex<Allocator>>,
-        global_desc_alloc: &mut DescriptorAllocatorGrowable,
         texture: &AllocatedImage,
         sampler: vk::Sampler,
-    ) -> (AllocatedBuffer, MaterialInstance) {
-        let material_constants = AllocatedBuffer::new(
-            allocator,
-            size_of::<MaterialConstants>() as u64,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            MyMemoryUsage::CpuToGpu,
-        );
-
-        let scene_uniform_data =
-            unsafe { &mut *material_constants.mapped_data().cast::<MaterialConstants>() };
-        *scene_uniform_data = MaterialConstants {
-            color_factors: Vec4::splat(1.),
-            metal_rough_factors: glam::vec4(1., 0.5, 0., 0.),
-        };
-
-        let material_resources = MaterialResources {
-            color_img: texture,
-            color_sampler: sampler,
-            metal_rough_img: texture,
-            metal_rough_sampler: sampler,
-            data_buffer: material_constants.buffer,
-            data_buffer_offset: 0,
-        };
+    ) -> MaterialInstance {
+        let texture = self.bindless.add_texture(texture, sampler);
 
-        let instance = self.write_material(
+        self.write_material(
             MaterialPass::MainColor,
-            &material_resources,
-            global_desc_alloc,
-        );
-
-        (material_constants, instance)
+            MaterialConstants {
+                color_factors: Vec4::splat(1.),
+                metal_rough_factors: glam::vec4(1., 0.5, 0., 0.),
+                color_tex: texture,
+                metal_rough_tex: texture,
+            },
+        )
     }
 }
 
 /// The fields are supposed to be destroyed by the parent class GltfMetallicRoughness
 pub struct MaterialPipeline {
-    pub pipeline: vk::Pipeline,
+    /// Replaced when the MSAA changes, see [`GltfMetallicRoughness::set_multisampling`].
+    pub pipeline: Cell<vk::Pipeline>,
     pub layout: vk::PipelineLayout,
 }
 
+impl MaterialPipeline {
+    fn new(pipeline: vk::Pipeline, layout: vk::PipelineLayout) -> Self {
+        Self {
+            pipeline: Cell::new(pipeline),
+            layout,
+        }
+    }
+}
+
+/// Every pipeline of [`GltfMetallicRoughness`], sharing its layout.
+struct GeometryPipelines {
+    opaque: vk::Pipeline,
+    transparent: vk::Pipeline,
+    overdraw: vk::Pipeline,
+    wireframe: vk::Pipeline,
+    wireframe_overlay: vk::Pipeline,
+    gbuffer: vk::Pipeline,
+}
+
+impl GeometryPipelines {
+    fn build(
+        device: &Device,
+        shaders: &ShadersLoader,
+        layout: vk::PipelineLayout,
+        draw_img_format: vk::Format,
+        depth_img_format: vk::Format,
+        (samples, sample_shading): (vk::SampleCountFlags, bool),
+    ) -> Self {
+        let mesh_frag = shaders.get(ShaderName::MeshFrag);
+        let mesh_vert = shaders.get(ShaderName::MeshVert);
+        let mesh_wireframe_frag = shaders.get(ShaderName::MeshWireframeFrag);
+
+        let mut pipeline_builder = PipelineBuilder::new(layout);
+        pipeline_builder.set_shaders(&mesh_vert, &mesh_frag);
+        pipeline_builder.set_input_topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        pipeline_builder.set_polygon_mode(vk::PolygonMode::FILL);
+        pipeline_builder.set_cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE);
+        pipeline_builder.set_multisampling(samples, sample_shading);
+        pipeline_builder.disable_blending();
+        pipeline_builder.enable_depthtest(true, vk::CompareOp::GREATER_OR_EQUAL);
+        // render format
+        let formats = [draw_img_format];
+        pipeline_builder.set_color_attachment_format(&formats[..]);
+        pipeline_builder.set_depth_format(depth_img_format);
+
+        let opaque = pipeline_builder.build(device, shaders.pipeline_cache());
+
+        pipeline_builder.enable_blending_additive();
+        pipeline_builder.enable_depthtest(false, vk::CompareOp::GREATER_OR_EQUAL);
+        let transparent = pipeline_builder.build(device, shaders.pipeline_cache());
+
+        // Every fragment is accumulated, hidden or not.
+        pipeline_builder.enable_depthtest(false, vk::CompareOp::ALWAYS);
+        let overdraw = pipeline_builder.build(device, shaders.pipeline_cache());
+
+        let mut pipeline_builder = PipelineBuilder::new(layout);
+        pipeline_builder.set_shaders(&mesh_vert, &mesh_wireframe_frag);
+        pipeline_builder.set_input_topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        pipeline_builder.set_polygon_mode(vk::PolygonMode::LINE);
+        pipeline_builder.set_cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE);
+        // The lines don't need to be shaded per sample.
+        pipeline_builder.set_multisampling(samples, false);
+        pipeline_builder.disable_blending();
+        pipeline_builder.set_color_attachment_format(&formats[..]);
+        pipeline_builder.set_depth_format(depth_img_format);
+
+        pipeline_builder.enable_depthtest(false, vk::CompareOp::ALWAYS);
+        let wireframe = pipeline_builder.build(device, shaders.pipeline_cache());
+
+        // Edges hidden by the already drawn surfaces are discarded.
+        pipeline_builder.enable_depthtest(false, vk::CompareOp::GREATER_OR_EQUAL);
+        let wireframe_overlay = pipeline_builder.build(device, shaders.pipeline_cache());
+
+        // The deferred shading disables the MSAA.
+        let gbuffer_frag = shaders.get(ShaderName::GBufferFrag);
+        let mut pipeline_builder = PipelineBuilder::new(layout);
+        pipeline_builder.set_shaders(&mesh_vert, &gbuffer_frag);
+        pipeline_builder.set_input_topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        pipeline_builder.set_polygon_mode(vk::PolygonMode::FILL);
+        pipeline_builder.set_cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE);
+        pipeline_builder.set_multisampling_none();
+        pipeline_builder.disable_blending();
+        pipeline_builder.enable_depthtest(true, vk::CompareOp::GREATER_OR_EQUAL);
+        pipeline_builder.set_color_attachment_format(&GBUFFER_FORMATS[..]);
+        pipeline_builder.set_depth_format(depth_img_format);
+        let gbuffer = pipeline_builder.build(device, shaders.pipeline_cache());
+
+        Self {
+            opaque,
+            transparent,
+            overdraw,
+            wireframe,
+            wireframe_overlay,
+            gbuffer,
+        }
+    }
+}
+
 pub enum MaterialPass {
     MainColor,
     Transparent,
@@ -401,7 +475,8 @@ pub enum MaterialPass {
 
 pub struct MaterialInstance {
     pipeline: Weak<MaterialPipeline>,
-    pub material_set: vk::DescriptorSet,
+    /// Index in the bindless materials.
+    pub material_id: u32,
     pass_type: MaterialPass,
 }
 
@@ -410,31 +485,23 @@ impl MaterialInstance {
         self.pipeline.upgrade().unwrap()
     }
 
+    /// To sort and batch by pipeline without upgrading it.
+    pub fn pipeline_ptr(&self) -> *const MaterialPipeline {
+        self.pipeline.as_ptr()
+    }
+
     pub fn pass_type(&self) -> &MaterialPass {
         &self.pass_type
     }
 }
 
+/// See `MaterialData` in `resources/input_structures.glsl`.
 #[derive(Clone, Copy)]
-#[repr(C)]
-// If descriptorType is VK_DESCRIPTOR_TYPE_UNIFORM_BUFFER or VK_DESCRIPTOR_TYPE_UNIFORM_BUFFER_DYNAMIC, the offset member of each element of pBufferInfo must be a multiple of VkPhysicalDeviceLimits::minUniformBufferOffsetAlignment (https://www.khronos.org/registry/vulkan/specs/1.3-extensions/html/vkspec.html#VUID-VkWriteDescriptorSet-descriptorType-00327)
-//
-// 256 is always safe in the meantime, but may waste memory
-// minUniformBufferOffsetAlignment :
-// - On 7800XT : 16 (like computer, so no warning reported...)
-// - On work Intel(R) UHD Graphics 620 (WHL GT2) : 64 (needs annotation)
-// TODO: dynamic, see : https://vkguide.dev/docs/chapter-4/descriptors_code_more/
-#[repr(align(256))]
+#[repr(C, align(16))]
 pub struct MaterialConstants {
     pub color_factors: Vec4,
     pub metal_rough_factors: Vec4,
-}
-
-pub struct MaterialResources<'a> {
-    pub color_img: &'a AllocatedImage,
-    pub color_sampler: vk::Sampler,
-    pub metal_rough_img: &'a AllocatedImage,
-    pub metal_rough_sampler: vk::Sampler,
-    pub data_buffer: vk::Buffer,
-    pub data_buffer_offset: u32,
+    /// Indices in the bindless textures.
+    pub color_tex: u32,
+    pub metal_rough_tex: u32,
 }
@@ -0,0 +1,420 @@
+//! Edition of the selected node local transform : decomposed fields, a gizmo in the viewport
+//! and an undo history.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use egui::{Color32, Id, Key, KeyboardShortcut, LayerId, Modifiers, Order, Sense, Stroke, pos2};
+use glam::{EulerRot, Mat4, Quat, Vec3, Vec4Swizzles, vec3};
+
+use super::scene::Node;
+
+const TRANSLATION_SNAP: f32 = 0.25;
+const ROTATION_SNAP_DEG: f32 = 15.;
+const SCALE_SNAP: f32 = 0.1;
+
+/// In points.
+const GIZMO_AXIS_LENGTH: f32 = 80.;
+const GIZMO_HANDLE_RADIUS: f32 = 7.;
+const GIZMO_STROKE_WIDTH: f32 = 2.;
+/// Degrees per point dragged.
+const GIZMO_ROTATION_SPEED: f32 = 0.5;
+const GIZMO_COLORS: [Color32; 3] = [Color32::RED, Color32::GREEN, Color32::BLUE];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// Affine transform split in editable values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Decomposed {
+    translation: Vec3,
+    /// Euler angles in degrees, XYZ order.
+    rotation: Vec3,
+    scale: Vec3,
+}
+
+impl Decomposed {
+    fn new(transform: &Mat4) -> Self {
+        let (scale, rotation, translation) = transform.to_scale_rotation_translation();
+        let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+        Self {
+            translation,
+            rotation: vec3(x.to_degrees(), y.to_degrees(), z.to_degrees()),
+            scale,
+        }
+    }
+
+    fn to_mat(self) -> Mat4 {
+        let rotation = Quat::from_euler(
+            EulerRot::XYZ,
+            self.rotation.x.to_radians(),
+            self.rotation.y.to_radians(),
+            self.rotation.z.to_radians(),
+        );
+        Mat4::from_scale_rotation_translation(self.scale, rotation, self.translation)
+    }
+}
+
+struct Edit {
+    node: Rc<RefCell<dyn Node>>,
+    before: Mat4,
+    after: Mat4,
+}
+
+#[derive(Default)]
+struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    /// With `merge`, a change of the same node as the last edit is folded into it (one drag is
+    /// one edit).
+    fn record(&mut self, node: &Rc<RefCell<dyn Node>>, before: Mat4, after: Mat4, merge: bool) {
+        self.redo.clear();
+        if merge
+            && let Some(last) = self.undo.last_mut()
+            && Rc::ptr_eq(&last.node, node)
+        {
+            last.after = after;
+        } else {
+            self.undo.push(Edit {
+                node: node.clone(),
+                before,
+                after,
+            });
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(edit) = self.undo.pop() {
+            set_local_transform(&edit.node, edit.before);
+            self.redo.push(edit);
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(edit) = self.redo.pop() {
+            set_local_transform(&edit.node, edit.after);
+            self.undo.push(edit);
+        }
+    }
+}
+
+fn parent_world_transform(node: &dyn Node) -> Mat4 {
+    if let Some(parent) = node.node_data().parent.upgrade() {
+        parent.borrow().node_data().world_transform
+    } else {
+        Mat4::IDENTITY
+    }
+}
+
+/// Updates the node and its children world transforms.
+fn set_local_transform(node: &Rc<RefCell<dyn Node>>, local_transform: Mat4) {
+    let mut node = node.borrow_mut();
+    node.node_data_mut().local_transform = local_transform;
+    let parent_transform = parent_world_transform(&*node);
+    node.refresh_transform(&parent_transform);
+}
+
+#[derive(Clone, Copy)]
+struct GizmoDrag {
+    axis: usize,
+    start: Mat4,
+    /// Along the axis on screen, in points.
+    dragged: f32,
+}
+
+#[derive(Default)]
+pub struct TransformEditor {
+    history: History,
+    mode: GizmoMode,
+    snap: bool,
+    drag: Option<GizmoDrag>,
+    /// An edit was recorded and the pointer is still down : next changes are part of it.
+    editing: bool,
+    /// Values of the fields and the local transform they give. Kept while the transform doesn't
+    /// change elsewhere, since decomposing it again could give other Euler angles (near gimbal
+    /// lock) and make the fields jump.
+    fields: Option<(Mat4, Decomposed)>,
+}
+
+impl TransformEditor {
+    pub fn ui(
+        &mut self,
+        ctx: &egui::Context,
+        nodes: &HashMap<String, Rc<RefCell<dyn Node>>>,
+        selected_node: &mut String,
+        view_proj: &Mat4,
+    ) {
+        // A focused text field handles its own undo.
+        let (undo, redo) = if ctx.wants_keyboard_input() {
+            (false, false)
+        } else {
+            ctx.input_mut(|i| {
+                (
+                    i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::Z)),
+                    i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::Y)),
+                )
+            })
+        };
+
+        egui::Window::new("Scene nodes")
+            .default_open(false)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(200.)
+                    .auto_shrink(true)
+                    .show(ui, |ui| {
+                        nodes.iter().for_each(|(k, _)| {
+                            ui.radio_value(selected_node, k.clone(), k);
+                        });
+                    });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!self.history.undo.is_empty(), egui::Button::new("Undo"))
+                        .clicked()
+                    {
+                        self.history.undo();
+                    }
+                    if ui
+                        .add_enabled(!self.history.redo.is_empty(), egui::Button::new("Redo"))
+                        .clicked()
+                    {
+                        self.history.redo();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Gizmo :");
+                    ui.radio_value(&mut self.mode, GizmoMode::Translate, "translate");
+                    ui.radio_value(&mut self.mode, GizmoMode::Rotate, "rotate");
+                    ui.radio_value(&mut self.mode, GizmoMode::Scale, "scale");
+                    ui.checkbox(&mut self.snap, "snap");
+                });
+
+                // If the selected node exists (from this scene).
+                if let Some(node) = nodes.get(selected_node) {
+                    let before = node.borrow().node_data().local_transform;
+                    let mut decomposed = match self.fields {
+                        Some((transform, fields)) if transform == before => fields,
+                        _ => Decomposed::new(&before),
+                    };
+                    let mut changed = false;
+                    egui::Grid::new("transform").num_columns(4).show(ui, |ui| {
+                        let mut row = |ui: &mut egui::Ui, name: &str, v: &mut Vec3, speed: f64| {
+                            ui.label(name);
+                            changed |= ui
+                                .add(egui::DragValue::new(&mut v.x).speed(speed))
+                                .changed();
+                            changed |= ui
+                                .add(egui::DragValue::new(&mut v.y).speed(speed))
+                                .changed();
+                            changed |= ui
+                                .add(egui::DragValue::new(&mut v.z).speed(speed))
+                                .changed();
+                            ui.end_row();
+                        };
+                        row(ui, "translation", &mut decomposed.translation, 0.01);
+                        row(ui, "rotation", &mut decomposed.rotation, 0.5);
+                        row(ui, "scale", &mut decomposed.scale, 0.01);
+                    });
+
+                    if changed {
+                        let after = decomposed.to_mat();
+                        self.fields = Some((after, decomposed));
+                        set_local_transform(node, after);
+                        self.history.record(node, before, after, self.editing);
+                        self.editing = true;
+                    }
+                }
+            });
+
+        if let Some(node) = nodes.get(selected_node) {
+            self.gizmo(ctx, node, view_proj);
+        } else {
+            self.drag = None;
+        }
+
+        if undo {
+            self.history.undo();
+        }
+        if redo {
+            self.history.redo();
+        }
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.editing = false;
+        }
+    }
+
+    /// Axis handles around the node origin, dragging one constrains the edit to its axis.
+    fn gizmo(&mut self, ctx: &egui::Context, node: &Rc<RefCell<dyn Node>>, view_proj: &Mat4) {
+        let screen = ctx.content_rect();
+        // Projection flips y, so clip space y already goes down.
+        let to_screen = |p: Vec3| {
+            let clip = view_proj * p.extend(1.);
+            (clip.w > 0.).then(|| {
+                let ndc = clip.xyz() / clip.w;
+                pos2(
+                    screen.left() + (ndc.x + 1.) / 2. * screen.width(),
+                    screen.top() + (ndc.y + 1.) / 2. * screen.height(),
+                )
+            })
+        };
+
+        let (local_transform, world_transform) = {
+            let n = node.borrow();
+            (n.node_data().local_transform, n.node_data().world_transform)
+        };
+        let origin = world_transform.w_axis.xyz();
+        let Some(origin_screen) = to_screen(origin) else {
+            return;
+        };
+        // Small enough step to measure the projected axes.
+        let step = (view_proj * origin.extend(1.)).w.abs().max(0.01) * 0.01;
+
+        let painter = ctx.layer_painter(LayerId::new(Order::Background, Id::new("gizmo")));
+
+        (0..3).for_each(|axis| {
+            let world_axis = match self.mode {
+                // Scaling is done along the node own axes.
+                GizmoMode::Scale => world_transform.col(axis).xyz().normalize_or_zero(),
+                GizmoMode::Translate | GizmoMode::Rotate => Vec3::AXES[axis],
+            };
+            let Some(step_screen) = to_screen(origin + world_axis * step) else {
+                return;
+            };
+            let points_per_unit = (step_screen - origin_screen).length() / step;
+            let dir = (step_screen - origin_screen).normalized();
+            if !dir.x.is_finite() || !dir.y.is_finite() {
+                return;
+            }
+            let handle = origin_screen + dir * GIZMO_AXIS_LENGTH;
+
+            let color = GIZMO_COLORS[axis];
+            painter.line_segment(
+                [origin_screen, handle],
+                Stroke::new(GIZMO_STROKE_WIDTH, color),
+            );
+            match self.mode {
+                GizmoMode::Translate => {
+                    painter.circle_filled(handle, GIZMO_HANDLE_RADIUS, color);
+                }
+                GizmoMode::Rotate => {
+                    painter.circle_stroke(
+                        handle,
+                        GIZMO_HANDLE_RADIUS,
+                        Stroke::new(GIZMO_STROKE_WIDTH, color),
+                    );
+                }
+                GizmoMode::Scale => {
+                    painter.rect_filled(
+                        egui::Rect::from_center_size(
+                            handle,
+                            egui::Vec2::splat(GIZMO_HANDLE_RADIUS * 2.),
+                        ),
+                        0.,
+                        color,
+                    );
+                }
+            }
+
+            let response = egui::Area::new(Id::new("gizmo_handle").with(axis))
+                .fixed_pos(handle - egui::Vec2::splat(GIZMO_HANDLE_RADIUS))
+                .show(ctx, |ui| {
+                    ui.allocate_exact_size(
+                        egui::Vec2::splat(GIZMO_HANDLE_RADIUS * 2.),
+                        Sense::drag(),
+                    )
+                    .1
+                })
+                .inner;
+
+            if response.drag_started() {
+                self.drag = Some(GizmoDrag {
+                    axis,
+                    start: local_transform,
+                    dragged: 0.,
+                });
+            }
+            if response.dragged()
+                && let Some(drag) = self.drag.as_mut().filter(|d| d.axis == axis)
+            {
+                // Rotations follow the pointer around the handle, across the axis.
+                let drag_dir = match self.mode {
+                    GizmoMode::Rotate => dir.rot90(),
+                    GizmoMode::Translate | GizmoMode::Scale => dir,
+                };
+                drag.dragged += response.drag_delta().dot(drag_dir);
+                let drag = *drag;
+
+                let after = self.dragged_transform(
+                    &drag,
+                    &parent_world_transform(&*node.borrow()),
+                    world_axis,
+                    points_per_unit,
+                );
+                if after != local_transform {
+                    set_local_transform(node, after);
+                    self.history
+                        .record(node, local_transform, after, self.editing);
+                    self.editing = true;
+                }
+            }
+            if response.drag_stopped() {
+                self.drag = None;
+            }
+        });
+    }
+
+    /// New local transform from the one at the start of the drag.
+    fn dragged_transform(
+        &self,
+        drag: &GizmoDrag,
+        parent_transform: &Mat4,
+        world_axis: Vec3,
+        points_per_unit: f32,
+    ) -> Mat4 {
+        let snap = |v: f32, step: f32| {
+            if self.snap {
+                (v / step).round() * step
+            } else {
+                v
+            }
+        };
+        let world_start = parent_transform * drag.start;
+
+        match self.mode {
+            GizmoMode::Translate => {
+                let distance = snap(
+                    drag.dragged / points_per_unit.max(f32::EPSILON),
+                    TRANSLATION_SNAP,
+                );
+                parent_transform.inverse()
+                    * Mat4::from_translation(world_axis * distance)
+                    * world_start
+            }
+            GizmoMode::Rotate => {
+                let angle = snap(drag.dragged * GIZMO_ROTATION_SPEED, ROTATION_SNAP_DEG);
+                let origin = world_start.w_axis.xyz();
+                parent_transform.inverse()
+                    * Mat4::from_translation(origin)
+                    * Mat4::from_axis_angle(world_axis, angle.to_radians())
+                    * Mat4::from_translation(-origin)
+                    * world_start
+            }
+            GizmoMode::Scale => {
+                let factor =
+                    snap(1. + drag.dragged / GIZMO_AXIS_LENGTH, SCALE_SNAP).max(SCALE_SNAP);
+                let mut scale = Vec3::ONE;
+                scale[drag.axis] = factor;
+                drag.start * Mat4::from_scale(scale)
+            }
+        }
+    }
+}
@@ -0,0 +1,654 @@
+//! Bounding volume hierarchy over world space boxes, built with binned SAH.
+//!
+//! Items are referenced by their index in the slice given at build time, the owner keeps the
+//! mapping to its nodes and surfaces.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use glam::{Mat4, Vec3, Vec4, vec3};
+
+use super::Bounds;
+
+/// Bumped each time a world transform of a scene changes, so its BVH knows it needs a refit.
+///
+/// Shared by the scene and its nodes, the clones count together.
+#[derive(Debug, Default, Clone)]
+pub struct TransformEpoch(Arc<AtomicU64>);
+
+impl TransformEpoch {
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 2;
+/// Refitting degrades the tree, it is rebuilt when its cost grew more than that.
+const REBUILD_COST_RATIO: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    /// World space box containing the local `bounds` (Arvo's method).
+    pub fn from_bounds(bounds: &Bounds, transform: &Mat4) -> Self {
+        let center = transform.transform_point3(bounds.origin);
+        let half = transform.x_axis.truncate().abs() * bounds.extents.x
+            + transform.y_axis.truncate().abs() * bounds.extents.y
+            + transform.z_axis.truncate().abs() * bounds.extents.z;
+        Self {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn grow(&self, p: Vec3) -> Self {
+        Self {
+            min: self.min.min(p),
+            max: self.max.max(p),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.min.cmpgt(self.max).any() {
+            return 0.;
+        }
+        let d = self.max - self.min;
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Distance along the ray where it enters the box, if before `t_max`.
+    pub fn intersect_ray(&self, ray: &Ray, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - ray.origin) * ray.inv_direction;
+        let t1 = (self.max - ray.origin) * ray.inv_direction;
+        let t_near = t0.min(t1).max_element().max(0.);
+        let t_far = t0.max(t1).min_element().min(t_max);
+        (t_near <= t_far).then_some(t_near)
+    }
+}
+
+/// The direction isn't normalized, distances are in its unit.
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    inv_direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction,
+            inv_direction: direction.recip(),
+        }
+    }
+
+    /// Ray through the clip space point (`x`, `y`), going from depth `z_from` to `z_to`.
+    pub fn from_clip_space(inv_view_proj: &Mat4, x: f32, y: f32, z_from: f32, z_to: f32) -> Self {
+        let from = inv_view_proj.project_point3(vec3(x, y, z_from));
+        let to = inv_view_proj.project_point3(vec3(x, y, z_to));
+        Self::new(from, (to - from).normalize())
+    }
+
+    /// Same ray through `transform`, distances along it are kept.
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        Self::new(
+            transform.transform_point3(self.origin),
+            transform.transform_vector3(self.direction),
+        )
+    }
+
+    /// Möller–Trumbore, both faces are hit.
+    pub fn intersect_triangle(&self, p0: Vec3, p1: Vec3, p2: Vec3) -> Option<f32> {
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let p = self.direction.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let inv_det = 1. / det;
+
+        let s = self.origin - p0;
+        let u = s.dot(p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+
+        let t = e2.dot(q) * inv_det;
+        (t > 0.).then_some(t)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// Planes pointing inward, not normalized.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Gribb & Hartmann extraction, for a clip space where `-w <= x,y <= w` and `0 <= z <= w`.
+    pub fn from_view_proj(m: &Mat4) -> Self {
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        Self {
+            planes: [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2],
+        }
+    }
+
+    /// `(normal, distance)`, not normalized, positive inside.
+    #[cfg(feature = "vulkan")]
+    pub fn planes(&self) -> &[Vec4; 6] {
+        &self.planes
+    }
+
+    fn classify(&self, aabb: &Aabb) -> Containment {
+        let mut containment = Containment::Inside;
+        for plane in self.planes.iter() {
+            let normal = plane.truncate();
+            // Corners furthest along and against the normal.
+            let positive = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            let negative = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.min, aabb.max);
+            if normal.dot(positive) + plane.w < 0. {
+                return Containment::Outside;
+            }
+            if normal.dot(negative) + plane.w < 0. {
+                containment = Containment::Intersecting;
+            }
+        }
+        containment
+    }
+
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.classify(aabb) != Containment::Outside
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    /// Left child index if `count == 0` (the right one follows), else first slot in `indices`.
+    first: usize,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Split {
+    axis: usize,
+    min: f32,
+    scale: f32,
+    /// First bin going to the right child.
+    bin: usize,
+}
+
+impl Split {
+    fn bin_of(axis: usize, min: f32, scale: f32, centroid: Vec3) -> usize {
+        (((centroid[axis] - min) * scale) as usize).min(BINS - 1)
+    }
+
+    fn is_left(&self, centroid: Vec3) -> bool {
+        Self::bin_of(self.axis, self.min, self.scale, centroid) < self.bin
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    item_bounds: Vec<Aabb>,
+    /// Cost right after the build, to detect when refits degraded the tree too much.
+    built_cost: f32,
+}
+
+impl Bvh {
+    pub fn build(item_bounds: Vec<Aabb>) -> Self {
+        let len = item_bounds.len();
+        let mut nodes = Vec::with_capacity(len.max(1) * 2 - 1);
+        let mut indices: Vec<usize> = (0..len).collect();
+        let centroids: Vec<Vec3> = item_bounds.iter().map(Aabb::centroid).collect();
+
+        let mut stack = Vec::new();
+        if len > 0 {
+            nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first: 0,
+                count: len,
+            });
+            stack.push(0);
+        }
+
+        while let Some(node_i) = stack.pop() {
+            let BvhNode { first, count, .. } = nodes[node_i];
+            let node_items = &mut indices[first..first + count];
+
+            let (bounds, centroid_bounds) = node_items.iter().fold(
+                (Aabb::EMPTY, Aabb::EMPTY),
+                |(bounds, centroid_bounds), i| {
+                    (
+                        bounds.union(&item_bounds[*i]),
+                        centroid_bounds.grow(centroids[*i]),
+                    )
+                },
+            );
+            nodes[node_i].bounds = bounds;
+
+            if count <= MAX_LEAF_SIZE {
+                continue;
+            }
+            let Some(split) = find_split(
+                node_items,
+                &item_bounds,
+                &centroids,
+                &bounds,
+                &centroid_bounds,
+            ) else {
+                continue;
+            };
+
+            let mut mid = 0;
+            for j in 0..count {
+                if split.is_left(centroids[node_items[j]]) {
+                    node_items.swap(j, mid);
+                    mid += 1;
+                }
+            }
+            if mid == 0 || mid == count {
+                continue;
+            }
+
+            let left = nodes.len();
+            nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first,
+                count: mid,
+            });
+            nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first: first + mid,
+                count: count - mid,
+            });
+            nodes[node_i].first = left;
+            nodes[node_i].count = 0;
+            stack.push(left);
+            stack.push(left + 1);
+        }
+
+        let mut bvh = Self {
+            nodes,
+            indices,
+            item_bounds,
+            built_cost: 0.,
+        };
+        bvh.built_cost = bvh.cost();
+        bvh
+    }
+
+    /// Updates the bounds keeping the topology, rebuilding if the tree degraded too much.
+    pub fn refit(&mut self, item_bounds: Vec<Aabb>) {
+        if item_bounds.len() != self.item_bounds.len() {
+            *self = Self::build(item_bounds);
+            return;
+        }
+        self.item_bounds = item_bounds;
+
+        // Children are always after their parent.
+        for i in (0..self.nodes.len()).rev() {
+            let BvhNode { first, count, .. } = self.nodes[i];
+            self.nodes[i].bounds = if count > 0 {
+                self.indices[first..first + count]
+                    .iter()
+                    .fold(Aabb::EMPTY, |b, item| b.union(&self.item_bounds[*item]))
+            } else {
+                self.nodes[first]
+                    .bounds
+                    .union(&self.nodes[first + 1].bounds)
+            };
+        }
+
+        if self.cost() > self.built_cost * REBUILD_COST_RATIO {
+            *self = Self::build(std::mem::take(&mut self.item_bounds));
+        }
+    }
+
+    /// Of every item, empty without any.
+    #[cfg(feature = "vulkan")]
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |root| root.bounds)
+    }
+
+    /// SAH cost relative to the root area, so moving the whole scene doesn't change it.
+    fn cost(&self) -> f32 {
+        let Some(root) = self.nodes.first() else {
+            return 0.;
+        };
+        let root_area = root.bounds.surface_area().max(f32::EPSILON);
+        self.nodes
+            .iter()
+            .map(|n| n.bounds.surface_area() * n.count.max(1) as f32)
+            .sum::<f32>()
+            / root_area
+    }
+
+    /// Calls `f` with the index of every item whose box is at least partially in the frustum.
+    pub fn query_frustum(&self, frustum: &Frustum, mut f: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        // Once a node is fully inside, its subtree doesn't need testing anymore.
+        let mut stack = vec![(0, false)];
+        while let Some((node_i, parent_inside)) = stack.pop() {
+            let node = &self.nodes[node_i];
+            let inside = parent_inside
+                || match frustum.classify(&node.bounds) {
+                    Containment::Outside => continue,
+                    Containment::Intersecting => false,
+                    Containment::Inside => true,
+                };
+
+            if node.count > 0 {
+                self.indices[node.first..node.first + node.count]
+                    .iter()
+                    .filter(|i| inside || frustum.intersects(&self.item_bounds[**i]))
+                    .for_each(|i| f(*i));
+            } else {
+                stack.push((node.first, inside));
+                stack.push((node.first + 1, inside));
+            }
+        }
+    }
+
+    /// Closest hit along the ray before `t_max`.
+    ///
+    /// `hit` is called with the items whose box is crossed and the current closest distance,
+    /// and returns the distance of its own hit if any (like a triangle test).
+    pub fn query_ray(
+        &self,
+        ray: &Ray,
+        t_max: f32,
+        mut hit: impl FnMut(usize, f32) -> Option<f32>,
+    ) -> Option<(usize, f32)> {
+        let mut closest: Option<(usize, f32)> = None;
+        let closest_t = |closest: &Option<(usize, f32)>| closest.map_or(t_max, |(_, t)| t);
+
+        let mut stack = Vec::new();
+        if let Some(t) = self
+            .nodes
+            .first()
+            .and_then(|n| n.bounds.intersect_ray(ray, t_max))
+        {
+            stack.push((0, t));
+        }
+
+        while let Some((node_i, t_enter)) = stack.pop() {
+            if t_enter > closest_t(&closest) {
+                continue;
+            }
+            let node = &self.nodes[node_i];
+
+            if node.count > 0 {
+                self.indices[node.first..node.first + node.count]
+                    .iter()
+                    .for_each(|i| {
+                        let t_max = closest_t(&closest);
+                        if self.item_bounds[*i].intersect_ray(ray, t_max).is_some()
+                            && let Some(t) = hit(*i, t_max).filter(|t| *t < t_max)
+                        {
+                            closest = Some((*i, t));
+                        }
+                    });
+            } else {
+                let t_max = closest_t(&closest);
+                let left = self.nodes[node.first].bounds.intersect_ray(ray, t_max);
+                let right = self.nodes[node.first + 1].bounds.intersect_ray(ray, t_max);
+                // The nearest child is pushed last to be visited first.
+                match (left, right) {
+                    (Some(l), Some(r)) if l <= r => {
+                        stack.push((node.first + 1, r));
+                        stack.push((node.first, l));
+                    }
+                    (Some(l), Some(r)) => {
+                        stack.push((node.first, l));
+                        stack.push((node.first + 1, r));
+                    }
+                    (Some(l), None) => stack.push((node.first, l)),
+                    (None, Some(r)) => stack.push((node.first + 1, r)),
+                    (None, None) => (),
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+fn find_split(
+    node_items: &[usize],
+    item_bounds: &[Aabb],
+    centroids: &[Vec3],
+    bounds: &Aabb,
+    centroid_bounds: &Aabb,
+) -> Option<Split> {
+    let mut best_cost = node_items.len() as f32 * bounds.surface_area();
+    let mut best = None;
+
+    for axis in 0..3 {
+        let (min, max) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+        if max <= min {
+            continue;
+        }
+        let scale = BINS as f32 / (max - min);
+
+        let mut bins = [(Aabb::EMPTY, 0); BINS];
+        node_items.iter().for_each(|i| {
+            let bin = &mut bins[Split::bin_of(axis, min, scale, centroids[*i])];
+            bin.0 = bin.0.union(&item_bounds[*i]);
+            bin.1 += 1;
+        });
+
+        // Sweep from the left, then from the right evaluating each plane between bins.
+        let mut left_area = [0.; BINS - 1];
+        let mut left_count = [0; BINS - 1];
+        let (mut acc, mut count) = (Aabb::EMPTY, 0);
+        for b in 0..BINS - 1 {
+            acc = acc.union(&bins[b].0);
+            count += bins[b].1;
+            left_area[b] = acc.surface_area();
+            left_count[b] = count;
+        }
+
+        let (mut acc, mut count) = (Aabb::EMPTY, 0);
+        for b in (1..BINS).rev() {
+            acc = acc.union(&bins[b].0);
+            count += bins[b].1;
+            let cost =
+                left_count[b - 1] as f32 * left_area[b - 1] + count as f32 * acc.surface_area();
+            if left_count[b - 1] > 0 && count > 0 && cost < best_cost {
+                best_cost = cost;
+                best = Some(Split {
+                    axis,
+                    min,
+                    scale,
+                    bin: b,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Mat4;
+
+    use super::*;
+
+    /// Unit boxes on a 3D grid, each `spacing` apart.
+    fn grid(n: usize, spacing: f32) -> Vec<Aabb> {
+        (0..n * n * n)
+            .map(|i| {
+                let p = vec3((i % n) as f32, (i / n % n) as f32, (i / n / n) as f32) * spacing;
+                Aabb {
+                    min: p,
+                    max: p + Vec3::ONE,
+                }
+            })
+            .collect()
+    }
+
+    fn frustum_query(bvh: &Bvh, frustum: &Frustum) -> Vec<usize> {
+        let mut found = Vec::new();
+        bvh.query_frustum(frustum, |i| found.push(i));
+        found.sort();
+        found
+    }
+
+    fn brute_force(items: &[Aabb], frustum: &Frustum) -> Vec<usize> {
+        (0..items.len())
+            .filter(|i| frustum.intersects(&items[*i]))
+            .collect()
+    }
+
+    /// Orthographic box between `min` and `max`, in the `[0,1]` depth range of the engines.
+    fn box_frustum(min: Vec3, max: Vec3) -> Frustum {
+        Frustum::from_view_proj(&Mat4::orthographic_rh(
+            min.x, max.x, min.y, max.y, -max.z, -min.z,
+        ))
+    }
+
+    #[test]
+    fn build_keeps_every_item_once() {
+        let items = grid(5, 3.);
+        let bvh = Bvh::build(items.clone());
+        let mut indices = bvh.indices.clone();
+        indices.sort();
+        assert_eq!(indices, (0..items.len()).collect::<Vec<_>>());
+        assert!(bvh.nodes.iter().all(|n| n.count <= MAX_LEAF_SIZE));
+    }
+
+    #[test]
+    fn frustum_query_matches_brute_force() {
+        let items = grid(6, 2.5);
+        let bvh = Bvh::build(items.clone());
+        for (min, max) in [
+            (Vec3::splat(-1.), Vec3::splat(4.)),
+            (vec3(2., 0., 5.), vec3(9., 3., 7.)),
+            (Vec3::splat(100.), Vec3::splat(101.)),
+        ] {
+            let frustum = box_frustum(min, max);
+            assert_eq!(frustum_query(&bvh, &frustum), brute_force(&items, &frustum));
+        }
+    }
+
+    #[test]
+    fn refit_follows_moved_items() {
+        let mut items = grid(4, 3.);
+        let mut bvh = Bvh::build(items.clone());
+        let frustum = box_frustum(vec3(-50., -50., 49.), vec3(-40., -40., 51.));
+        assert!(frustum_query(&bvh, &frustum).is_empty());
+
+        items[7] = Aabb {
+            min: vec3(-45., -45., 50.),
+            max: vec3(-44., -44., 51.),
+        };
+        bvh.refit(items.clone());
+        assert_eq!(frustum_query(&bvh, &frustum), vec![7]);
+        assert_eq!(
+            frustum_query(&bvh, &box_frustum(Vec3::splat(-100.), Vec3::splat(100.))),
+            (0..items.len()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn refit_rebuilds_with_another_item_count() {
+        let mut bvh = Bvh::build(grid(2, 3.));
+        bvh.refit(grid(3, 3.));
+        assert_eq!(bvh.item_bounds.len(), 27);
+        assert_eq!(bvh.indices.len(), 27);
+    }
+
+    #[test]
+    fn ray_finds_the_closest_item() {
+        let items = grid(4, 3.);
+        let bvh = Bvh::build(items.clone());
+        // Along +X through the row of boxes at y = z = 0.
+        let ray = Ray::new(vec3(-5., 0.5, 0.5), Vec3::X);
+        let hit = bvh.query_ray(&ray, f32::INFINITY, |i, _| {
+            items[i].intersect_ray(&ray, f32::INFINITY)
+        });
+        assert_eq!(hit, Some((0, 5.)));
+
+        // The hit callback rejecting the first box.
+        let hit = bvh.query_ray(&ray, f32::INFINITY, |i, _| {
+            (i != 0).then(|| items[i].intersect_ray(&ray, f32::INFINITY))?
+        });
+        assert_eq!(hit, Some((1, 8.)));
+
+        let miss = Ray::new(vec3(-5., 100., 0.5), Vec3::X);
+        assert_eq!(bvh.query_ray(&miss, f32::INFINITY, |_, _| Some(0.)), None);
+    }
+
+    #[test]
+    fn triangle_hit_distance() {
+        let ray = Ray::new(vec3(0.2, 0.2, 5.), -Vec3::Z);
+        let t = ray.intersect_triangle(Vec3::ZERO, Vec3::X, Vec3::Y);
+        assert_eq!(t, Some(5.));
+        assert_eq!(
+            ray.intersect_triangle(Vec3::X, Vec3::X * 2., Vec3::ONE),
+            None
+        );
+    }
+
+    #[test]
+    fn transform_epoch_is_per_scene() {
+        let (a, b) = (TransformEpoch::default(), TransformEpoch::default());
+        let a_node = a.clone();
+        a_node.bump();
+        assert_eq!(a.get(), 1);
+        assert_eq!(b.get(), 0);
+    }
+}
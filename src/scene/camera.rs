@@ -1,9 +1,19 @@
+#[cfg(feature = "cpu")]
+use glam::vec4;
 use glam::{Mat4, Quat, Vec3, Vec4, Vec4Swizzles, vec3};
+#[cfg(feature = "cpu")]
+use winit::dpi::PhysicalSize;
 use winit::{
     event::{ElementState, KeyEvent, MouseButton, WindowEvent},
     keyboard::{KeyCode, PhysicalKey},
 };
 
+#[cfg(feature = "cpu")]
+use crate::scene::{
+    bvh::Frustum,
+    lod::{LodSelector, LodSettings},
+};
+
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
     pub z_near: f32,
@@ -47,6 +57,45 @@ impl Camera {
         (tr * rot).inverse()
     }
 
+    /// Same projection as [`local_to_clipspace`](super::local_to_clipspace) as a matrix, `w` is
+    /// the depth and `z` the distance past the near plane, there is no far plane.
+    #[cfg(feature = "cpu")]
+    pub fn projection(&self, size: PhysicalSize<u32>, ratio_w_h: f32) -> Mat4 {
+        let scale = self.z_near / self.canvas_side;
+        let (scale_x, scale_y) = if size.width > size.height {
+            (scale / ratio_w_h, scale)
+        } else {
+            (scale, scale * ratio_w_h)
+        };
+        Mat4::from_cols(
+            vec4(scale_x, 0., 0., 0.),
+            vec4(0., scale_y, 0., 0.),
+            vec4(0., 0., -1., -1.),
+            vec4(0., 0., -self.z_near, 0.),
+        )
+    }
+
+    #[cfg(feature = "cpu")]
+    pub fn frustum(&self, size: PhysicalSize<u32>, ratio_w_h: f32) -> Frustum {
+        Frustum::from_view_proj(&(self.projection(size, ratio_w_h) * self.view_mat()))
+    }
+
+    #[cfg(feature = "cpu")]
+    pub fn lod_selector(
+        &self,
+        settings: LodSettings,
+        size: PhysicalSize<u32>,
+        ratio_w_h: f32,
+    ) -> LodSelector {
+        LodSelector::new(
+            settings,
+            &self.view_mat(),
+            &self.projection(size, ratio_w_h),
+            size.width,
+            size.height,
+        )
+    }
+
     pub fn rot_mat(&self) -> Mat4 {
         // fairly typical FPS style camera. we join the pitch and yaw rotations into
         // the final rotation matrix
@@ -0,0 +1,377 @@
+//! Backgrounds of the engines where nothing is drawn, looked up by view direction.
+//!
+//! Either an atmosphere lit by the sun, or an image of [`ENVIRONMENTS_FOLDER`] : an
+//! equirectangular `.hdr`, or a folder with the six faces of a cube. All of them end up
+//! equirectangular, which is the only mapping the engines sample.
+
+use std::{
+    f32::consts::{PI, TAU},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glam::{Vec3, vec3};
+use image::DynamicImage;
+
+/// Folder of the `.hdr` environments and of the cube folders, listed after the atmosphere.
+pub const ENVIRONMENTS_FOLDER: &str = "./resources/environments/";
+#[cfg(feature = "vulkan")]
+const ATMOSPHERE_NAME: &str = "atmosphere";
+/// File stems of the faces of a cube folder, in the Vulkan order : +X, -X, +Y, -Y, +Z, -Z.
+const CUBE_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+/// Of the faces, the `.png` ones are sRGB.
+const FACE_EXTENSIONS: [&str; 2] = ["hdr", "png"];
+
+/// Size of the equirectangular atmosphere.
+const ATMOSPHERE_WIDTH: u32 = 512;
+const ATMOSPHERE_HEIGHT: u32 = 256;
+
+// See `resources/atmosphere.glsl`, which has to give the same colours.
+/// Optical depth of the air at the zenith, for red, green and blue.
+const RAYLEIGH: Vec3 = vec3(0.05, 0.12, 0.29);
+/// Optical depth of the aerosols at the zenith, grey.
+const MIE: f32 = 0.02;
+/// Forward scattering of the aerosols.
+const MIE_G: f32 = 0.76;
+/// Of the sun, out of the atmosphere.
+const SUN_ILLUMINANCE: f32 = 20.;
+const SUN_RADIANCE: f32 = 100.;
+/// Cosine of the angular radius of the sun disc.
+const SUN_COS_RADIUS: f32 = 0.99995;
+const GROUND: Vec3 = vec3(0.25, 0.22, 0.2);
+/// Keeps the nights from being black.
+const NIGHT: Vec3 = vec3(0.002, 0.003, 0.006);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvironmentSource {
+    /// Computed from the sun direction, always available.
+    Atmosphere,
+    /// Equirectangular `.hdr`.
+    Equirect(PathBuf),
+    /// Folder with a face named after each of [`CUBE_FACES`].
+    Cube(PathBuf),
+}
+
+impl EnvironmentSource {
+    /// The atmosphere, then the environments of [`ENVIRONMENTS_FOLDER`] sorted by path.
+    pub fn list() -> Vec<Self> {
+        let mut sources: Vec<Self> = fs::read_dir(ENVIRONMENTS_FOLDER)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter_map(|p| {
+                        if p.is_dir() {
+                            cube_faces(&p).is_some().then_some(Self::Cube(p))
+                        } else {
+                            p.extension()
+                                .is_some_and(|e| e == "hdr")
+                                .then_some(Self::Equirect(p))
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        sources.sort_by(|a, b| a.path().cmp(&b.path()));
+        sources.insert(0, Self::Atmosphere);
+        sources
+    }
+
+    #[cfg(feature = "vulkan")]
+    pub fn name(&self) -> String {
+        match self.path() {
+            Some(path) => path.file_stem().unwrap().to_string_lossy().into_owned(),
+            None => ATMOSPHERE_NAME.into(),
+        }
+    }
+
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Atmosphere => None,
+            Self::Equirect(path) | Self::Cube(path) => Some(path),
+        }
+    }
+}
+
+/// Path of each face in `folder`, if none is missing.
+fn cube_faces(folder: &Path) -> Option<[PathBuf; 6]> {
+    let faces = CUBE_FACES.map(|stem| {
+        FACE_EXTENSIONS
+            .iter()
+            .map(|ext| folder.join(stem).with_extension(ext))
+            .find(|p| p.is_file())
+    });
+    faces
+        .iter()
+        .all(Option::is_some)
+        .then(|| faces.map(Option::unwrap))
+}
+
+/// Equirectangular, in linear RGB.
+///
+/// The mapping is the one of `resources/equirect_to_cube.comp.glsl` : the top row is up, the
+/// middle column looks along +X.
+#[derive(Clone)]
+pub struct EnvironmentImage {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Vec3>,
+}
+
+impl EnvironmentImage {
+    /// Loads the image of `source`, or computes the atmosphere lit by the sun in the
+    /// `sun_direction`, pointing towards the sun.
+    ///
+    /// The cubes are resampled at four times the width of their faces. Errors if an image
+    /// can't be read, the atmosphere never does.
+    pub fn load(source: &EnvironmentSource, sun_direction: Vec3) -> Result<Self, String> {
+        match source {
+            EnvironmentSource::Atmosphere => {
+                let sun = sun_direction.normalize();
+                Ok(Self::from_fn(ATMOSPHERE_WIDTH, ATMOSPHERE_HEIGHT, |dir| {
+                    atmosphere(dir, sun)
+                }))
+            }
+            EnvironmentSource::Equirect(path) => {
+                println!("Loading environment : {}", path.to_string_lossy());
+                let (width, height, pixels) = open_linear(path)?;
+                Ok(Self {
+                    width,
+                    height,
+                    pixels,
+                })
+            }
+            EnvironmentSource::Cube(folder) => {
+                println!("Loading environment : {}", folder.to_string_lossy());
+                let paths = cube_faces(folder)
+                    .ok_or_else(|| format!("{} misses faces", folder.to_string_lossy()))?;
+                let mut faces = Vec::with_capacity(paths.len());
+                for path in paths {
+                    let (width, height, pixels) = open_linear(&path)?;
+                    if width != height || faces.first().is_some_and(|f: &Self| f.width != width) {
+                        return Err(format!(
+                            "{} isn't square or of the size of the other faces",
+                            path.to_string_lossy()
+                        ));
+                    }
+                    faces.push(Self {
+                        width,
+                        height,
+                        pixels,
+                    });
+                }
+                let faces: [Self; 6] = faces.try_into().ok().unwrap();
+                let size = faces[0].width;
+                Ok(Self::from_fn(size * 4, size * 2, |dir| {
+                    sample_cube(&faces, dir)
+                }))
+            }
+        }
+    }
+
+    fn from_fn(width: u32, height: u32, f: impl Fn(Vec3) -> Vec3) -> Self {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let theta = (y as f32 + 0.5) / height as f32 * PI;
+                let phi = ((x as f32 + 0.5) / width as f32 - 0.5) * TAU;
+                f(vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                ))
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[cfg(feature = "vulkan")]
+    /// RGBA, to upload as `R32G32B32A32_SFLOAT`.
+    pub fn rgba(&self) -> Vec<f32> {
+        self.pixels
+            .iter()
+            .flat_map(|p| p.extend(1.).to_array())
+            .collect()
+    }
+
+    /// Bilinear, wrapping around horizontally.
+    #[cfg(feature = "cpu")]
+    pub fn sample(&self, dir: Vec3) -> Vec3 {
+        let dir = dir.normalize();
+        let u = dir.z.atan2(dir.x) / TAU + 0.5;
+        let v = dir.y.clamp(-1., 1.).acos() / PI;
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0., (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |x: i64, y: i64| {
+            let x = x.rem_euclid(self.width as i64) as usize;
+            let y = y.min(self.height as i64 - 1) as usize;
+            self.pixels[x + y * self.width as usize]
+        };
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = texel(x0, y0).lerp(texel(x0 + 1, y0), fx);
+        let bottom = texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+/// Radiance of the sky in the direction `dir`, with the sun in the normalised direction `sun`.
+///
+/// Single scattering in a flat atmosphere : the sunlight reaching the air is filtered by the air
+/// mass along the sun direction, the light scattered towards the camera by the one along `dir`.
+/// Rayleigh scattering turns the sky blue and the low sun red, Mie scattering adds the haze
+/// around the sun.
+pub fn atmosphere(dir: Vec3, sun: Vec3) -> Vec3 {
+    let dir = dir.normalize();
+    let extinction = RAYLEIGH + Vec3::splat(MIE);
+    let sunlight =
+        SUN_ILLUMINANCE * (-extinction * air_mass(sun.y)).exp() * smoothstep(-0.1, 0., sun.y);
+
+    // Under the horizon, the air is the one at the horizon.
+    let cos_theta = dir.dot(sun);
+    let rayleigh_phase = 3. / (16. * PI) * (1. + cos_theta * cos_theta);
+    let mie_phase = henyey_greenstein(cos_theta);
+    let view_extinction = (-extinction * air_mass(dir.y)).exp();
+    let mut color = sunlight * (RAYLEIGH * rayleigh_phase + Vec3::splat(MIE * mie_phase))
+        / extinction
+        * (Vec3::ONE - view_extinction);
+
+    if dir.y < 0. {
+        // Lambertian, fading into the haze of the horizon.
+        let ground = GROUND * sunlight * sun.y.max(0.) / PI;
+        color = color.lerp(ground, smoothstep(0., -0.1, dir.y));
+    } else if cos_theta > SUN_COS_RADIUS {
+        color += SUN_RADIANCE * view_extinction * smoothstep(-0.1, 0., sun.y);
+    }
+    color + NIGHT
+}
+
+/// Relative to the zenith, from Kasten and Young, for the cosine of the zenith angle.
+fn air_mass(cos_zenith: f32) -> f32 {
+    let cos_zenith = cos_zenith.clamp(0., 1.);
+    let zenith_degrees = cos_zenith.acos().to_degrees();
+    1. / (cos_zenith + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364))
+}
+
+fn henyey_greenstein(cos_theta: f32) -> f32 {
+    let g2 = MIE_G * MIE_G;
+    (1. - g2) / (4. * PI * (1. + g2 - 2. * MIE_G * cos_theta).powf(1.5))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// Face and position in it, inverse of `cubeDirection` in `resources/ibl_common.glsl`.
+fn sample_cube(faces: &[EnvironmentImage; 6], dir: Vec3) -> Vec3 {
+    let a = dir.abs();
+    let (face, u, v, major) = if a.x >= a.y && a.x >= a.z {
+        if dir.x > 0. {
+            (0, -dir.z, -dir.y, a.x)
+        } else {
+            (1, dir.z, -dir.y, a.x)
+        }
+    } else if a.y >= a.z {
+        if dir.y > 0. {
+            (2, dir.x, dir.z, a.y)
+        } else {
+            (3, dir.x, -dir.z, a.y)
+        }
+    } else if dir.z > 0. {
+        (4, dir.x, -dir.y, a.z)
+    } else {
+        (5, -dir.x, -dir.y, a.z)
+    };
+    let face = &faces[face];
+    let size = face.width as f32;
+    let to_texel = |c: f32| (((c / major + 1.) / 2. * size) as usize).min(face.width as usize - 1);
+    face.pixels[to_texel(u) + to_texel(v) * face.width as usize]
+}
+
+fn open_linear(path: &Path) -> Result<(u32, u32, Vec<Vec3>), String> {
+    let img = image::open(path).map_err(|e| format!("{} : {e}", path.to_string_lossy()))?;
+    let srgb = !matches!(
+        img,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    );
+    let img = img.into_rgb32f();
+    let pixels = img
+        .pixels()
+        .map(|p| {
+            let c = Vec3::from_array(p.0);
+            if srgb { srgb_to_linear(c) } else { c }
+        })
+        .collect();
+    Ok((img.width(), img.height(), pixels))
+}
+
+fn srgb_to_linear(c: Vec3) -> Vec3 {
+    c.map(|c| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUN: Vec3 = vec3(0., 1., 1.);
+
+    #[test]
+    fn shipped_environments_load() {
+        let sources = EnvironmentSource::list();
+        assert_eq!(sources[0], EnvironmentSource::Atmosphere);
+        assert!(sources.len() > 2, "no environment in {ENVIRONMENTS_FOLDER}");
+        sources.iter().for_each(|source| {
+            let image = EnvironmentImage::load(source, SUN).unwrap();
+            assert_eq!(image.width, image.height * 2);
+            assert_eq!(image.pixels.len(), (image.width * image.height) as usize);
+            assert!(
+                image
+                    .pixels
+                    .iter()
+                    .all(|p| p.is_finite() && p.min_element() >= 0.)
+            );
+        });
+    }
+
+    #[test]
+    fn cube_faces_are_mapped() {
+        let folder = Path::new(ENVIRONMENTS_FOLDER).join("grid_cube");
+        let image = EnvironmentImage::load(&EnvironmentSource::Cube(folder), SUN).unwrap();
+        // At the centre of the faces, away from the grid lines : red on +X, green on +Y.
+        let center = |x: u32, y: u32| image.pixels[(x + y * image.width) as usize];
+        let px = center(image.width / 2 + 3, image.height / 2 + 3);
+        let py = center(3, 3);
+        assert!(px.x > px.y && px.x > px.z, "{px}");
+        assert!(py.y > py.x && py.y > py.z, "{py}");
+    }
+
+    #[test]
+    fn missing_images_are_errors() {
+        let missing = Path::new(ENVIRONMENTS_FOLDER).join("missing.hdr");
+        assert!(EnvironmentImage::load(&EnvironmentSource::Equirect(missing), SUN).is_err());
+        let not_a_cube = Path::new(ENVIRONMENTS_FOLDER).to_path_buf();
+        assert!(EnvironmentImage::load(&EnvironmentSource::Cube(not_a_cube), SUN).is_err());
+    }
+
+    #[test]
+    fn atmosphere_is_blue_with_a_high_sun() {
+        let sun = Vec3::Y;
+        let zenith = atmosphere(Vec3::Y, vec3(0.5, 1., 0.).normalize());
+        assert!(zenith.z > zenith.x, "{zenith}");
+        let towards_sun = atmosphere(sun, sun);
+        assert!(towards_sun.min_element() > zenith.max_element());
+        let night = atmosphere(Vec3::Y, -Vec3::Y);
+        assert_eq!(night, NIGHT);
+    }
+}
@@ -1,5 +1,7 @@
 use std::{
     collections::HashMap,
+    fs::File,
+    io::BufReader,
     iter::zip,
     path::Path,
     sync::{Arc, RwLock},
@@ -8,15 +10,152 @@ use std::{
 
 use crate::{
     maths::ColorF32,
-    scene::{GeoSurface, MeshAsset, Node, Scene, Texture, Vertex},
+    scene::{GeoSurface, MeshAsset, Node, Scene, Texture, Vertex, mesh_processing},
 };
-use glam::{Mat4, Vec3, Vec4};
-use gltf::{Document, buffer};
+use glam::{Mat4, Quat, Vec3, Vec4};
+use gltf::{
+    Document, Gltf,
+    accessor::{self, DataType},
+    animation::util::Rotations,
+    buffer,
+};
+
+/// Instances of the mesh of a node, each with its own translation, rotation and scale.
+pub const GPU_INSTANCING_EXTENSION: &str = "EXT_mesh_gpu_instancing";
+
+/// Like [`Gltf::open`], but also accepts the files requiring [`GPU_INSTANCING_EXTENSION`].
+pub fn open_gltf<P: AsRef<Path>>(path: P) -> gltf::Result<Gltf> {
+    let reader = BufReader::new(File::open(path)?);
+    let Gltf { document, blob } = Gltf::from_reader_without_validation(reader)?;
+    let mut json = document.into_json();
+    json.extensions_required
+        .retain(|e| e != GPU_INSTANCING_EXTENSION);
+    let document = Document::from_json(json)?;
+    Ok(Gltf { document, blob })
+}
+
+/// Vertices and indices of a triangle list `primitive`.
+///
+/// Like the glTF spec requires, the normals are flat when missing. The missing tangents are
+/// generated from the normals and the UVs. The triangles and the vertices are then reordered for
+/// the caches of the GPU, like the OBJ meshes.
+pub fn read_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[buffer::Data],
+) -> (Vec<Vertex>, Vec<u32>) {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let mut indices: Vec<u32> = reader
+        .read_indices()
+        .map(|iter| iter.into_u32().collect())
+        .unwrap_or_default();
+
+    let mut vertices: Vec<Vertex> = reader
+        .read_positions()
+        .map(|iter| {
+            iter.map(|p| Vertex {
+                position: Vec3::from_array(p),
+                ..Default::default()
+            })
+            .collect()
+        })
+        .unwrap_or_default();
+
+    let has_normals = reader.read_normals().is_some_and(|iter| {
+        zip(&mut vertices, iter).for_each(|(v, n)| v.normal = Vec3::from_array(n));
+        true
+    });
+
+    let has_uvs = reader.read_tex_coords(0).is_some_and(|iter| {
+        zip(&mut vertices, iter.into_f32()).for_each(|(v, c)| {
+            v.uv_x = c[0];
+            v.uv_y = c[1];
+        });
+        true
+    });
+
+    if let Some(iter) = reader.read_colors(0) {
+        zip(&mut vertices, iter.into_rgba_f32()).for_each(|(v, c)| v.color = Vec4::from_array(c));
+    }
+
+    let has_tangents = reader.read_tangents().is_some_and(|iter| {
+        zip(&mut vertices, iter).for_each(|(v, t)| v.tangent = Vec4::from_array(t));
+        true
+    });
+
+    if !has_normals {
+        mesh_processing::flat_normals(&mut vertices, &mut indices[..]);
+        // Back to shared vertices where the faces are coplanar.
+        mesh_processing::weld(&mut vertices, &mut indices[..]);
+    }
+    if !has_tangents && has_uvs {
+        mesh_processing::tangents(&mut vertices, &mut indices[..]);
+    }
+
+    mesh_processing::optimize_vertex_cache(&mut indices[..], vertices.len());
+    mesh_processing::optimize_overdraw(
+        &vertices[..],
+        &mut indices[..],
+        mesh_processing::OVERDRAW_THRESHOLD,
+    );
+    mesh_processing::optimize_vertex_fetch(&mut vertices, &mut indices[..]);
+
+    (vertices, indices)
+}
+
+/// Transforms of the instances of the mesh of `node`, relative to it. `None` without
+/// [`GPU_INSTANCING_EXTENSION`].
+pub fn instance_transforms(
+    document: &Document,
+    buffers: &[buffer::Data],
+    node: &gltf::Node,
+) -> Option<Vec<Mat4>> {
+    let attributes = node
+        .extension_value(GPU_INSTANCING_EXTENSION)?
+        .get("attributes")?;
+    let get_buffer_data = |buffer: buffer::Buffer| buffers.get(buffer.index()).map(|d| &d[..]);
+    let accessor = |name: &str| {
+        let index = attributes.get(name)?.as_u64()?;
+        document.accessors().nth(index as usize)
+    };
+    let vec3s = |name: &str| -> Vec<Vec3> {
+        accessor(name)
+            .and_then(|a| accessor::Iter::<[f32; 3]>::new(a, get_buffer_data))
+            .map_or_else(Vec::new, |iter| iter.map(Vec3::from_array).collect())
+    };
+
+    let translations = vec3s("TRANSLATION");
+    let scales = vec3s("SCALE");
+    // Also allowed as normalized integers.
+    let rotations: Vec<Quat> = accessor("ROTATION")
+        .and_then(|a| match a.data_type() {
+            DataType::F32 => accessor::Iter::new(a, get_buffer_data).map(Rotations::F32),
+            DataType::I8 => accessor::Iter::new(a, get_buffer_data).map(Rotations::I8),
+            DataType::U8 => accessor::Iter::new(a, get_buffer_data).map(Rotations::U8),
+            DataType::I16 => accessor::Iter::new(a, get_buffer_data).map(Rotations::I16),
+            DataType::U16 => accessor::Iter::new(a, get_buffer_data).map(Rotations::U16),
+            _ => None,
+        })
+        .map_or_else(Vec::new, |r| r.into_f32().map(Quat::from_array).collect());
+
+    let count = translations.len().max(scales.len()).max(rotations.len());
+    Some(
+        (0..count)
+            .map(|i| {
+                Mat4::from_scale_rotation_translation(
+                    scales.get(i).copied().unwrap_or(Vec3::ONE),
+                    rotations.get(i).copied().unwrap_or(Quat::IDENTITY),
+                    translations.get(i).copied().unwrap_or(Vec3::ZERO),
+                )
+            })
+            .collect(),
+    )
+}
 
 // TODO: better error handling
 pub fn import_mesh_and_diffuse<P: AsRef<Path>>(path: P) -> Scene {
     let t0 = Instant::now();
-    let gltf::Gltf { document, blob } = gltf::Gltf::open(&path).unwrap();
+    let Gltf { document, blob } = open_gltf(&path).unwrap();
 
     let t1 = Instant::now();
     let buffers =
@@ -28,10 +167,10 @@ pub fn import_mesh_and_diffuse<P: AsRef<Path>>(path: P) -> Scene {
     let materials_vec = load_materials(&document);
 
     let t3 = Instant::now();
-    let meshes_vec = load_meshes(&document, buffers, materials_vec);
+    let meshes_vec = load_meshes(&document, &buffers, materials_vec);
 
     let t4 = Instant::now();
-    let (top_nodes, nodes) = load_nodes(&document, &meshes_vec[..]);
+    let (top_nodes, nodes) = load_nodes(&document, &buffers, &meshes_vec[..]);
 
     let t5 = Instant::now();
     let scene = Scene::new(nodes, top_nodes);
@@ -76,7 +215,7 @@ fn load_materials(document: &Document) -> Vec<Texture> {
 
 fn load_meshes(
     document: &Document,
-    buffers: Vec<buffer::Data>,
+    buffers: &[buffer::Data],
     materials_vec: Vec<Texture>,
 ) -> Vec<Arc<MeshAsset>> {
     let mut meshes_vec = Vec::with_capacity(document.meshes().count());
@@ -89,44 +228,13 @@ fn load_meshes(
             .filter_map(|p| p.indices().map(|i| (p, i)))
             .map(|(primitive, index_accessor)| {
                 let start_index = indices.len();
-                let count = index_accessor.count();
+                indices.reserve(index_accessor.count());
 
                 let initial_vtx = vertices.len();
-                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-                indices.reserve(count);
-                if let Some(iter) = reader.read_indices() {
-                    indices.extend(iter.into_u32().map(|i| i as usize + initial_vtx));
-                }
-
-                if let Some(pos_accessor) = primitive.get(&gltf::Semantic::Positions) {
-                    vertices.reserve(pos_accessor.count());
-                    if let Some(iter) = reader.read_positions() {
-                        vertices.extend(iter.map(|v| Vertex {
-                            position: Vec3::from_array(v),
-                            ..Default::default()
-                        }));
-                    }
-                }
-
-                if let Some(iter) = reader.read_normals() {
-                    iter.enumerate()
-                        .for_each(|(i, n)| vertices[initial_vtx + i].normal = Vec3::from_array(n));
-                }
-
-                if let Some(iter) = reader.read_tex_coords(0) {
-                    iter.into_f32().enumerate().for_each(|(i, c)| {
-                        let v = &mut vertices[initial_vtx + i];
-                        v.uv_x = c[0];
-                        v.uv_y = c[1];
-                    });
-                }
-
-                if let Some(iter) = reader.read_colors(0) {
-                    iter.into_rgba_f32()
-                        .enumerate()
-                        .for_each(|(i, c)| vertices[initial_vtx + i].color = Vec4::from_array(c));
-                }
+                let (primitive_vertices, primitive_indices) = read_primitive(&primitive, buffers);
+                vertices.extend(primitive_vertices);
+                indices.extend(primitive_indices.iter().map(|i| *i as usize + initial_vtx));
+                let count = indices.len() - start_index;
 
                 GeoSurface::new(
                     &vertices[..],
@@ -152,6 +260,7 @@ fn load_meshes(
 
 fn load_nodes(
     document: &Document,
+    buffers: &[buffer::Data],
     meshes_vec: &[Arc<MeshAsset>],
 ) -> (Vec<Arc<RwLock<Node>>>, HashMap<String, Arc<RwLock<Node>>>) {
     let mut nodes = HashMap::new();
@@ -160,12 +269,25 @@ fn load_nodes(
         let local_transform = Mat4::from_cols_array_2d(&node.transform().matrix());
         let mut new_node = Node::new(local_transform);
 
-        if let Some(mesh) = node.mesh() {
+        let instances = instance_transforms(document, buffers, &node);
+        if let Some(mesh) = node.mesh()
+            && instances.is_none()
+        {
             new_node.mesh = Some(meshes_vec[mesh.index()].clone());
         };
 
         let new_node = Arc::new(RwLock::new(new_node));
 
+        // Children sharing the mesh, grouped again when drawn.
+        if let (Some(mesh), Some(instances)) = (node.mesh(), instances) {
+            let children = instances.into_iter().map(|transform| {
+                let mut instance = Node::new_mesh(meshes_vec[mesh.index()].clone(), transform);
+                instance.parent = Arc::downgrade(&new_node);
+                Arc::new(RwLock::new(instance))
+            });
+            new_node.write().unwrap().children.extend(children);
+        }
+
         if let Some(name) = node.name() {
             nodes.insert(name.into(), new_node.clone());
         }
@@ -0,0 +1,133 @@
+//! Levels of detail of the surfaces, simplified once and selected per frame.
+//!
+//! The Vulkan loader simplifies the surfaces while loading, the CPU engines on another thread
+//! the first time the levels are enabled. The indices of the levels are appended after the
+//! original ones of the mesh. A level is
+//! selected when the distance between it and the original surface, projected on the screen,
+//! stays under [`LodSettings::pixel_error`].
+
+use glam::{Mat4, Vec3, Vec4Swizzles};
+
+use super::{Bounds, Vertex, mesh_processing};
+
+/// Simplified levels per surface, after the original one.
+pub const MAX_LOD_LEVELS: usize = 4;
+/// Of the triangles of the previous level.
+const LOD_REDUCTION: f32 = 0.5;
+/// A level with fewer triangles than that isn't worth it.
+const LOD_MIN_TRIANGLES: usize = 32;
+/// A level is dropped if the locked vertices prevent it from getting much smaller.
+const LOD_MIN_GAIN: f32 = 0.85;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LodLevel {
+    pub start_index: usize,
+    pub count: usize,
+    /// Distance between the level and the original surface, in the space of the mesh.
+    pub error: f32,
+}
+
+/// Simplifies the surface of `count` indices at `start`, appending each level to `indices`.
+pub fn generate_lods<I: mesh_processing::MeshIndex>(
+    vertices: &[Vertex],
+    indices: &mut Vec<I>,
+    start: usize,
+    count: usize,
+) -> Vec<LodLevel> {
+    let mut lods = Vec::new();
+    let mut source = indices[start..start + count].to_vec();
+    let mut error = 0.;
+    while lods.len() < MAX_LOD_LEVELS {
+        let target = (source.len() as f32 * LOD_REDUCTION) as usize / 3 * 3;
+        if target < LOD_MIN_TRIANGLES * 3 {
+            break;
+        }
+        let (simplified, level_error) =
+            mesh_processing::simplify(vertices, &source[..], target, f32::INFINITY);
+        if simplified.len() as f32 > source.len() as f32 * LOD_MIN_GAIN {
+            break;
+        }
+        // Each level is simplified from the previous one.
+        error += level_error;
+        lods.push(LodLevel {
+            start_index: indices.len(),
+            count: simplified.len(),
+            error,
+        });
+        indices.extend_from_slice(&simplified[..]);
+        source = simplified;
+    }
+    lods
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LodSettings {
+    pub enabled: bool,
+    /// Largest error on screen of the selected levels, in pixels.
+    pub pixel_error: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            pixel_error: 1.,
+        }
+    }
+}
+
+impl LodSettings {
+    pub fn coarser(&mut self) {
+        self.pixel_error = (self.pixel_error * 2.).min(64.);
+    }
+
+    pub fn finer(&mut self) {
+        self.pixel_error = (self.pixel_error / 2.).max(0.125);
+    }
+}
+
+/// Selects the levels of the frame, for a camera.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LodSelector {
+    settings: LodSettings,
+    camera_position: Vec3,
+    /// Pixels covered by a unit at a distance of one.
+    pixel_scale: f32,
+}
+
+impl LodSelector {
+    /// `proj` maps the view space to [-1,1] over the `width` and `height` in pixels.
+    pub fn new(settings: LodSettings, view: &Mat4, proj: &Mat4, width: u32, height: u32) -> Self {
+        let pixel_scale =
+            (proj.x_axis.x.abs() * width as f32).max(proj.y_axis.y.abs() * height as f32) / 2.;
+        Self {
+            settings,
+            camera_position: view.inverse().w_axis.xyz(),
+            pixel_scale,
+        }
+    }
+
+    /// The coarsest of the `lods` of a surface staying under the pixel error, 0 for the surface
+    /// itself.
+    pub fn level(&self, lods: &[LodLevel], bounds: &Bounds, transform: &Mat4) -> usize {
+        if !self.settings.enabled || lods.is_empty() {
+            return 0;
+        }
+        let scale = transform
+            .x_axis
+            .xyz()
+            .length()
+            .max(transform.y_axis.xyz().length())
+            .max(transform.z_axis.xyz().length());
+        let center = transform.transform_point3(bounds.origin);
+        // To the closest point of the bounding sphere, 0 inside of it.
+        let distance = center.distance(self.camera_position) - bounds.extents.length() * scale;
+        if distance <= 0. {
+            return 0;
+        }
+        let max_error = self.settings.pixel_error * distance / (self.pixel_scale * scale);
+        lods.iter()
+            .rposition(|l| l.error <= max_error)
+            .map_or(0, |i| i + 1)
+    }
+}
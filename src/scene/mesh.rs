@@ -1,4 +1,9 @@
-use std::sync::{Arc, RwLock, Weak};
+#[cfg(feature = "cpu")]
+use std::sync::OnceLock;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, RwLock, Weak},
+};
 
 #[cfg(feature = "vulkan")]
 use glam::Vec4Swizzles;
@@ -6,7 +11,9 @@ use glam::{Mat4, Vec3, Vec4, vec3, vec4};
 
 use winit::dpi::PhysicalSize;
 
-use crate::scene::{Camera, local_to_clipspace};
+#[cfg(feature = "cpu")]
+use crate::scene::lod::{self, LodLevel};
+use crate::scene::{Camera, bvh::TransformEpoch, local_to_clipspace};
 
 use super::Texture;
 
@@ -15,6 +22,20 @@ pub struct MeshAsset {
     pub indices: Vec<usize>,
     pub surfaces: Vec<GeoSurface<Texture>>,
     pub bounds: Bounds,
+    /// Hash of the geometry, the same between runs, for the false colours of the debug views.
+    pub id: u64,
+    /// Generated on demand, see [`Self::generate_lods`].
+    #[cfg(feature = "cpu")]
+    lods: OnceLock<MeshLods>,
+}
+
+/// The levels of detail of the surfaces of a [`MeshAsset`].
+#[cfg(feature = "cpu")]
+struct MeshLods {
+    /// Of every level, after the ones of `MeshAsset::indices`.
+    indices: Vec<usize>,
+    /// Per surface, starting in `indices`.
+    surfaces: Vec<Vec<LodLevel>>,
 }
 
 impl MeshAsset {
@@ -24,11 +45,66 @@ impl MeshAsset {
         surfaces: Vec<GeoSurface<Texture>>,
     ) -> Self {
         let bounds = Bounds::new(&vertices, &indices, 0, indices.len());
+        let id = {
+            let mut hasher = DefaultHasher::new();
+            vertices
+                .iter()
+                .for_each(|v| v.position.to_array().map(f32::to_bits).hash(&mut hasher));
+            indices.hash(&mut hasher);
+            hasher.finish()
+        };
         Self {
             vertices,
             indices,
             surfaces,
             bounds,
+            id,
+            #[cfg(feature = "cpu")]
+            lods: OnceLock::new(),
+        }
+    }
+
+    /// Simplifies the surfaces the first time, see [`lod::generate_lods`].
+    ///
+    /// Slow, done off the render thread by [`Scene::request_lods`](super::Scene::request_lods).
+    #[cfg(feature = "cpu")]
+    pub fn generate_lods(&self) {
+        self.lods.get_or_init(|| {
+            let mut indices = self.indices.clone();
+            let mut surfaces: Vec<Vec<LodLevel>> = self
+                .surfaces
+                .iter()
+                .map(|s| {
+                    lod::generate_lods(&self.vertices[..], &mut indices, s.start_index, s.count)
+                })
+                .collect();
+            let indices = indices.split_off(self.indices.len());
+            surfaces
+                .iter_mut()
+                .flatten()
+                .for_each(|l| l.start_index -= self.indices.len());
+            MeshLods { indices, surfaces }
+        });
+    }
+
+    /// Of the surface `s_index`, none until generated.
+    #[cfg(feature = "cpu")]
+    pub fn lods(&self, s_index: usize) -> &[LodLevel] {
+        self.lods
+            .get()
+            .map_or(&[], |lods| &lods.surfaces[s_index][..])
+    }
+
+    /// Of the `level` of detail of the surface `s_index`, 0 being the surface itself.
+    #[cfg(feature = "cpu")]
+    pub fn level_indices(&self, s_index: usize, level: usize) -> &[usize] {
+        let s = &self.surfaces[s_index];
+        match (level, self.lods.get()) {
+            (0, _) | (_, None) => &self.indices[s.start_index..s.start_index + s.count],
+            (l, Some(lods)) => {
+                let lod = lods.surfaces[s_index][l - 1];
+                &lods.indices[lod.start_index..lod.start_index + lod.count]
+            }
         }
     }
 }
@@ -41,6 +117,10 @@ pub struct Vertex {
     pub normal: Vec3,
     pub uv_y: f32,
     pub color: Vec4,
+    /// Along the UVs u axis, `w` is the sign of the bitangent, see [`mesh_processing::tangents`].
+    ///
+    /// [`mesh_processing::tangents`]: super::mesh_processing::tangents
+    pub tangent: Vec4,
 }
 
 impl Default for Vertex {
@@ -51,6 +131,7 @@ impl Default for Vertex {
             normal: vec3(1., 0., 0.),
             uv_y: Default::default(),
             color: vec4(1., 1., 1., 1.),
+            tangent: vec4(0., 1., 0., 1.),
         }
     }
 }
@@ -288,6 +369,11 @@ pub struct Node {
 
     /// Actual mesh if any at this node
     pub mesh: Option<Arc<MeshAsset>>,
+
+    /// Drawn tinted, when the node or one of its parents is selected.
+    pub highlighted: bool,
+    /// The one of the scene, once the node is in it.
+    pub transform_epoch: TransformEpoch,
 }
 
 impl Node {
@@ -300,6 +386,8 @@ impl Node {
             world_transform: Default::default(),
 
             mesh: None,
+            highlighted: false,
+            transform_epoch: Default::default(),
         }
     }
 
@@ -323,6 +411,8 @@ impl Node {
                 world_transform: Default::default(),
 
                 mesh: None,
+                highlighted: false,
+                transform_epoch: Default::default(),
             };
             RwLock::new(node)
         })
@@ -330,11 +420,27 @@ impl Node {
 
     pub fn refresh_transform(&mut self, parent_mat: &Mat4) {
         self.world_transform = parent_mat * self.local_transform;
+        self.transform_epoch.bump();
         self.children
             .iter()
             .for_each(|c| c.write().unwrap().refresh_transform(&self.world_transform));
     }
 
+    /// Of the node and its children.
+    pub fn set_transform_epoch(&mut self, epoch: &TransformEpoch) {
+        self.transform_epoch = epoch.clone();
+        self.children
+            .iter()
+            .for_each(|c| c.write().unwrap().set_transform_epoch(epoch));
+    }
+
+    pub fn set_highlighted(&mut self, highlighted: bool) {
+        self.highlighted = highlighted;
+        self.children
+            .iter()
+            .for_each(|c| c.write().unwrap().set_highlighted(highlighted));
+    }
+
     pub fn transform(&mut self, tr: &Mat4) {
         // We split to rotate in place.
         let (tr_scale, tr_rot, tr_pos) = tr.to_scale_rotation_translation();
@@ -356,6 +462,8 @@ impl From<MeshAsset> for Node {
             world_transform: Default::default(),
 
             mesh: Some(Arc::new(value)),
+            highlighted: false,
+            transform_epoch: Default::default(),
         }
     }
 }
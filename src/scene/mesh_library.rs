@@ -29,14 +29,11 @@ pub fn load_scene_index_looping(index: usize) -> SceneStandIn {
     SceneStandIn::new(n, p, f)
 }
 
-/*
-pub fn load_scene_by_name(name: &str) -> Option<SceneStandIn> {
-    SCENES
-        .iter()
-        .find(|(n, _, _)| *n == name)
-        .map(|(n, p, f)| SceneStandIn::new(n, p, f))
+/// Index in [`SCENES`].
+#[cfg(all(feature = "cpu", feature = "vulkan"))]
+pub fn scene_index(name: &str) -> Option<usize> {
+    SCENES.iter().position(|(n, _, _)| *n == name)
 }
-*/
 
 fn base_scene(_: String) -> Scene {
     let suzanne: Node = obj_file::import_mesh_and_diffuse(obj_file::SUZANNE_OBJ_PATH).into();
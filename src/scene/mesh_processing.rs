@@ -0,0 +1,901 @@
+//! Processing of the imported meshes : normals, tangents, welding, reordering and simplification.
+//!
+//! Every function works on an indexed triangle list, with `u32` or `usize` indices.
+
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use super::Vertex;
+
+/// Of the vertex cache simulated by [`optimize_vertex_cache`].
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+/// The vertices of the last triangle are penalized, to avoid strips going back and forth.
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Of the hardware cache simulated by [`optimize_overdraw`].
+const OVERDRAW_CACHE_SIZE: usize = 16;
+/// Of [`optimize_overdraw`] for the imported meshes, a few more cache misses for less overdraw.
+pub const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+pub trait MeshIndex: Copy {
+    fn get(self) -> usize;
+    fn new(index: usize) -> Self;
+}
+
+impl MeshIndex for u32 {
+    fn get(self) -> usize {
+        self as usize
+    }
+    fn new(index: usize) -> Self {
+        index as u32
+    }
+}
+
+impl MeshIndex for usize {
+    fn get(self) -> usize {
+        self
+    }
+    fn new(index: usize) -> Self {
+        index
+    }
+}
+
+/// Counter-clockwise is the front, zero if degenerate.
+fn face_normal(p: [Vec3; 3]) -> Vec3 {
+    (p[1] - p[0]).cross(p[2] - p[0]).normalize_or_zero()
+}
+
+/// Angle of the triangle `p` at its corner `k`.
+fn corner_angle(p: [Vec3; 3], k: usize) -> f32 {
+    let e0 = (p[(k + 1) % 3] - p[k]).normalize_or_zero();
+    let e1 = (p[(k + 2) % 3] - p[k]).normalize_or_zero();
+    e0.dot(e1).clamp(-1., 1.).acos()
+}
+
+fn triangle_positions<I: MeshIndex>(vertices: &[Vertex], is: &[I]) -> [Vec3; 3] {
+    [0, 1, 2].map(|k| vertices[is[k].get()].position)
+}
+
+/// Adding zero turns `-0.` into `0.`, so they are the same position.
+fn position_key(p: Vec3) -> [u32; 3] {
+    (p + Vec3::ZERO).to_array().map(f32::to_bits)
+}
+
+/// Gives each triangle its own vertices, with the normal of its face.
+pub fn flat_normals<I: MeshIndex>(vertices: &mut Vec<Vertex>, indices: &mut [I]) {
+    let mut flat = Vec::with_capacity(indices.len());
+    indices.chunks_exact_mut(3).for_each(|is| {
+        let normal = face_normal(triangle_positions(vertices, is));
+        is.iter_mut().for_each(|i| {
+            flat.push(Vertex {
+                normal,
+                ..vertices[i.get()]
+            });
+            *i = I::new(flat.len() - 1);
+        });
+    });
+    *vertices = flat;
+}
+
+/// Average of the normals of the faces around each position, weighted by their angle there, for
+/// the vertices missing a normal, marked by a NaN one.
+///
+/// The vertices at the same position share their normal, even if split by a UV seam. The unused
+/// vertices and the ones with a normal are left as they are.
+pub fn smooth_normals<I: MeshIndex>(vertices: &mut [Vertex], indices: &[I]) {
+    let mut normals: HashMap<[u32; 3], Vec3> = HashMap::new();
+    indices.chunks_exact(3).for_each(|is| {
+        let p = triangle_positions(vertices, is);
+        let normal = face_normal(p);
+        (0..3).for_each(|k| {
+            *normals.entry(position_key(p[k])).or_default() += normal * corner_angle(p, k);
+        });
+    });
+
+    vertices
+        .iter_mut()
+        .filter(|v| v.normal.is_nan())
+        .for_each(|v| {
+            if let Some(normal) = normals.get(&position_key(v.position)) {
+                v.normal = normal.normalize_or_zero();
+            }
+        });
+}
+
+/// Tangents following the UVs, with the handedness in `w` : the bitangent is
+/// `w * normal.cross(tangent)`, the convention of MikkTSpace and glTF.
+///
+/// Like MikkTSpace, the tangents of the faces are weighted by their angle at the vertex and
+/// orthogonalized against its normal, and a vertex shared by faces of both handedness (e.g. on
+/// the seam of mirrored UVs) is split in two.
+pub fn tangents<I: MeshIndex>(vertices: &mut Vec<Vertex>, indices: &mut [I]) {
+    let faces: Vec<Option<(Vec3, Vec3)>> = indices
+        .chunks_exact(3)
+        .map(|is| {
+            let v = [0, 1, 2].map(|k| &vertices[is[k].get()]);
+            let p = [0, 1, 2].map(|k| v[k].position);
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (du1, dv1) = (v[1].uv_x - v[0].uv_x, v[1].uv_y - v[0].uv_y);
+            let (du2, dv2) = (v[2].uv_x - v[0].uv_x, v[2].uv_y - v[0].uv_y);
+            let det = du1 * dv2 - du2 * dv1;
+            (det != 0.).then(|| {
+                (
+                    ((e1 * dv2 - e2 * dv1) / det).normalize_or_zero(),
+                    ((e2 * du1 - e1 * du2) / det).normalize_or_zero(),
+                )
+            })
+        })
+        .collect();
+
+    // The handedness of the first face of each vertex, and its copy for the other one.
+    let mut handedness: Vec<Option<bool>> = vec![None; vertices.len()];
+    let mut mirrored: Vec<Option<usize>> = vec![None; vertices.len()];
+    indices
+        .chunks_exact_mut(3)
+        .zip(&faces)
+        .filter_map(|(is, face)| face.map(|f| (is, f)))
+        .for_each(|(is, (tangent, bitangent))| {
+            is.iter_mut().for_each(|i| {
+                let v = i.get();
+                let negative = vertices[v].normal.cross(tangent).dot(bitangent) < 0.;
+                if *handedness[v].get_or_insert(negative) != negative {
+                    let copy = *mirrored[v].get_or_insert_with(|| {
+                        vertices.push(vertices[v]);
+                        vertices.len() - 1
+                    });
+                    *i = I::new(copy);
+                }
+            });
+        });
+
+    let mut tangents = vec![Vec3::ZERO; vertices.len()];
+    let mut bitangents = vec![Vec3::ZERO; vertices.len()];
+    indices
+        .chunks_exact(3)
+        .zip(&faces)
+        .filter_map(|(is, face)| face.map(|f| (is, f)))
+        .for_each(|(is, (tangent, bitangent))| {
+            let p = triangle_positions(vertices, is);
+            (0..3).for_each(|k| {
+                let angle = corner_angle(p, k);
+                tangents[is[k].get()] += tangent * angle;
+                bitangents[is[k].get()] += bitangent * angle;
+            });
+        });
+
+    vertices
+        .iter_mut()
+        .zip(tangents.into_iter().zip(bitangents))
+        .for_each(|(v, (tangent, bitangent))| {
+            let n = v.normal;
+            let tangent = (tangent - n * n.dot(tangent)).normalize_or(n.any_orthonormal_vector());
+            let w = if n.cross(tangent).dot(bitangent) < 0. {
+                -1.
+            } else {
+                1.
+            };
+            v.tangent = tangent.extend(w);
+        });
+}
+
+fn vertex_key(v: &Vertex) -> [u32; 16] {
+    let (p, n, c, t) = (v.position, v.normal, v.color, v.tangent);
+    [
+        p.x, p.y, p.z, v.uv_x, v.uv_y, n.x, n.y, n.z, c.x, c.y, c.z, c.w, t.x, t.y, t.z, t.w,
+    ]
+    .map(f32::to_bits)
+}
+
+/// Merges the identical vertices and drops the unused ones.
+///
+/// The vertices are kept in the order of their first use, like with [`optimize_vertex_fetch`].
+pub fn weld<I: MeshIndex>(vertices: &mut Vec<Vertex>, indices: &mut [I]) {
+    let mut unique: HashMap<[u32; 16], usize> = HashMap::new();
+    let mut remap: Vec<Option<usize>> = vec![None; vertices.len()];
+    let mut welded = Vec::with_capacity(vertices.len());
+    indices.iter_mut().for_each(|i| {
+        let new = *remap[i.get()].get_or_insert_with(|| {
+            let v = vertices[i.get()];
+            *unique.entry(vertex_key(&v)).or_insert_with(|| {
+                welded.push(v);
+                welded.len() - 1
+            })
+        });
+        *i = I::new(new);
+    });
+    *vertices = welded;
+}
+
+/// Orders the vertices by their first use, so they are read sequentially. Drops the unused ones.
+pub fn optimize_vertex_fetch<I: MeshIndex>(vertices: &mut Vec<Vertex>, indices: &mut [I]) {
+    let mut remap: Vec<Option<usize>> = vec![None; vertices.len()];
+    let mut fetched = Vec::with_capacity(vertices.len());
+    indices.iter_mut().for_each(|i| {
+        let new = *remap[i.get()].get_or_insert_with(|| {
+            fetched.push(vertices[i.get()]);
+            fetched.len() - 1
+        });
+        *i = I::new(new);
+    });
+    *vertices = fetched;
+}
+
+/// Of a vertex for [`optimize_vertex_cache`], -1 once all its triangles are emitted.
+fn vertex_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.;
+    }
+    let cache = match cache_position {
+        Some(p) if p < 3 => LAST_TRIANGLE_SCORE,
+        Some(p) => (1. - (p - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(CACHE_DECAY_POWER),
+        None => 0.,
+    };
+    cache + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Reorders the triangles so their vertices are still in the post-transform cache when reused,
+/// with Tom Forsyth's linear-speed vertex cache optimisation.
+pub fn optimize_vertex_cache<I: MeshIndex>(indices: &mut [I], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // Triangles around each vertex, the emitted ones are moved after the `remaining` ones.
+    let mut offsets = vec![0; vertex_count + 1];
+    indices.iter().for_each(|i| offsets[i.get() + 1] += 1);
+    (0..vertex_count).for_each(|v| offsets[v + 1] += offsets[v]);
+    let mut remaining = vec![0; vertex_count];
+    let mut adjacency = vec![0; indices.len()];
+    indices.chunks_exact(3).enumerate().for_each(|(t, is)| {
+        is.iter().for_each(|i| {
+            let v = i.get();
+            adjacency[offsets[v] + remaining[v]] = t;
+            remaining[v] += 1;
+        })
+    });
+
+    let mut cache_positions: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = remaining.iter().map(|r| vertex_score(None, *r)).collect();
+    let mut triangle_scores: Vec<f32> = indices
+        .chunks_exact(3)
+        .map(|is| is.iter().map(|i| vertex_scores[i.get()]).sum())
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let mut output = Vec::with_capacity(indices.len());
+    let mut cache: Vec<usize> = Vec::with_capacity(CACHE_SIZE + 3);
+    // For the dead ends, when no triangle in the cache is left.
+    let mut next_unemitted = 0;
+
+    let mut best =
+        (0..triangle_count).max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]));
+    while let Some(t) = best {
+        emitted[t] = true;
+        let triangle = [0, 1, 2].map(|k| indices[t * 3 + k].get());
+        output.extend(triangle);
+
+        triangle.iter().for_each(|v| {
+            let live = &mut adjacency[offsets[*v]..offsets[*v] + remaining[*v]];
+            let position = live.iter().position(|a| *a == t).unwrap();
+            live.swap(position, remaining[*v] - 1);
+            remaining[*v] -= 1;
+        });
+
+        let mut new_cache = triangle.to_vec();
+        new_cache.extend(cache.iter().filter(|v| !triangle.contains(v)));
+        let evicted = new_cache.split_off(new_cache.len().min(CACHE_SIZE));
+        cache = new_cache;
+        evicted.iter().for_each(|v| cache_positions[*v] = None);
+        cache
+            .iter()
+            .enumerate()
+            .for_each(|(p, v)| cache_positions[*v] = Some(p));
+
+        cache.iter().chain(evicted.iter()).for_each(|v| {
+            let score = vertex_score(cache_positions[*v], remaining[*v]);
+            let delta = score - vertex_scores[*v];
+            vertex_scores[*v] = score;
+            adjacency[offsets[*v]..offsets[*v] + remaining[*v]]
+                .iter()
+                .for_each(|a| triangle_scores[*a] += delta);
+        });
+
+        best = cache
+            .iter()
+            .flat_map(|v| &adjacency[offsets[*v]..offsets[*v] + remaining[*v]])
+            .copied()
+            .max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]));
+        if best.is_none() {
+            while next_unemitted < triangle_count && emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            best = (next_unemitted < triangle_count).then_some(next_unemitted);
+        }
+    }
+
+    indices
+        .iter_mut()
+        .zip(output)
+        .for_each(|(i, v)| *i = I::new(v));
+}
+
+/// Vertex cache misses of each triangle, with a FIFO cache.
+fn cache_misses<I: MeshIndex>(indices: &[I], vertex_count: usize) -> Vec<u32> {
+    let mut timestamps = vec![0usize; vertex_count];
+    let mut time = OVERDRAW_CACHE_SIZE + 1;
+    indices
+        .chunks_exact(3)
+        .map(|is| {
+            is.iter()
+                .filter(|i| {
+                    let miss = time - timestamps[i.get()] > OVERDRAW_CACHE_SIZE;
+                    if miss {
+                        timestamps[i.get()] = time;
+                        time += 1;
+                    }
+                    miss
+                })
+                .count() as u32
+        })
+        .collect()
+}
+
+/// Reorders the clusters of triangles of indices optimized by [`optimize_vertex_cache`], drawing
+/// first the ones facing outwards so that they hide the others.
+///
+/// The clusters are split where all the vertices of a triangle miss the cache, and where the
+/// cache misses per triangle so far are below the ones of the whole cluster times `threshold`.
+/// The higher it is, the smaller the clusters and the lower the cache efficiency.
+pub fn optimize_overdraw<I: MeshIndex>(vertices: &[Vertex], indices: &mut [I], threshold: f32) {
+    let misses = cache_misses(indices, vertices.len());
+    let triangle_count = misses.len();
+
+    let mut clusters = Vec::new();
+    let mut start = 0;
+    while start < triangle_count {
+        let mut end = start + 1;
+        while end < triangle_count && misses[end] < 3 {
+            end += 1;
+        }
+        let cluster_misses: u32 = misses[start..end].iter().sum();
+        let cluster_ratio = cluster_misses as f32 / (end - start) as f32;
+
+        // Soft boundaries, where the triangles so far are efficient enough on their own.
+        let mut soft_start = start;
+        let mut soft_misses = 0;
+        (start..end).for_each(|t| {
+            // The cache is reset at the start of a soft cluster.
+            soft_misses += if t == soft_start { 3 } else { misses[t] };
+            let ratio = soft_misses as f32 / (t + 1 - soft_start) as f32;
+            if t + 1 < end && t > soft_start && ratio <= cluster_ratio * threshold {
+                clusters.push(soft_start..t + 1);
+                soft_start = t + 1;
+                soft_misses = 0;
+            }
+        });
+        clusters.push(soft_start..end);
+        start = end;
+    }
+
+    let mesh_centroid = vertices.iter().map(|v| v.position).sum::<Vec3>() / vertices.len() as f32;
+    let mut sorted: Vec<(f32, std::ops::Range<usize>)> = clusters
+        .into_iter()
+        .map(|cluster| {
+            // Area weighted.
+            let (centroid, normal, area) = indices[cluster.start * 3..cluster.end * 3]
+                .chunks_exact(3)
+                .map(|is| triangle_positions(vertices, is))
+                .fold((Vec3::ZERO, Vec3::ZERO, 0.), |(c, n, a), p| {
+                    let cross = (p[1] - p[0]).cross(p[2] - p[0]);
+                    let area = cross.length();
+                    (c + (p[0] + p[1] + p[2]) / 3. * area, n + cross, a + area)
+                });
+            let centroid = if area > 0. {
+                centroid / area
+            } else {
+                mesh_centroid
+            };
+            let score = (centroid - mesh_centroid).dot(normal.normalize_or_zero());
+            (score, cluster)
+        })
+        .collect();
+    sorted.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    let reordered: Vec<I> = sorted
+        .iter()
+        .flat_map(|(_, cluster)| &indices[cluster.start * 3..cluster.end * 3])
+        .copied()
+        .collect();
+    indices.copy_from_slice(&reordered[..]);
+}
+
+/// Symmetric 4x4 matrix of the squared distance to planes, weighted by their area.
+#[derive(Default, Clone, Copy)]
+struct Quadric {
+    /// xx, xy, xz, xw, yy, yz, yw, zz, zw, ww
+    m: [f32; 10],
+    weight: f32,
+}
+
+impl Quadric {
+    fn from_plane(normal: Vec3, d: f32, weight: f32) -> Self {
+        let [a, b, c] = normal.to_array();
+        let m = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ]
+        .map(|v| v * weight);
+        Self { m, weight }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.m.iter_mut().zip(other.m).for_each(|(a, b)| *a += b);
+        self.weight += other.weight;
+    }
+
+    /// Mean squared distance of `p` to the planes.
+    fn error(&self, p: Vec3) -> f32 {
+        let [xx, xy, xz, xw, yy, yz, yw, zz, zw, ww] = self.m;
+        let Vec3 { x, y, z } = p;
+        let e = xx * x * x
+            + yy * y * y
+            + zz * z * z
+            + ww
+            + 2. * (xy * x * y + xz * x * z + yz * y * z + xw * x + yw * y + zw * z);
+        (e / self.weight.max(f32::EPSILON)).max(0.)
+    }
+}
+
+/// Simplifies the triangles `indices` down to `target_count` indices, by collapsing the edges
+/// moving the surface the least, measured with quadric error metrics.
+///
+/// The vertices on a border, a UV or normal seam or a non-manifold edge are locked, so that the
+/// simplified surface doesn't open or slide its attributes. No collapse moves the surface further
+/// than `max_error`. Returns the new indices, still in `vertices`, and the distance the surface
+/// moved, in the units of the positions.
+pub fn simplify<I: MeshIndex>(
+    vertices: &[Vertex],
+    indices: &[I],
+    target_count: usize,
+    max_error: f32,
+) -> (Vec<I>, f32) {
+    // Local ids of the used vertices, so that the work is proportional to the surface.
+    let mut local: HashMap<usize, usize> = HashMap::new();
+    let mut global = Vec::new();
+    let mut result: Vec<usize> = indices
+        .iter()
+        .map(|i| {
+            *local.entry(i.get()).or_insert_with(|| {
+                global.push(i.get());
+                global.len() - 1
+            })
+        })
+        .collect();
+    let vertex_count = global.len();
+    if result.len() <= target_count || vertex_count == 0 {
+        return (indices.to_vec(), 0.);
+    }
+
+    // Normalized in a unit cube for the precision of the quadrics.
+    let (min, max) = global.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), v| {
+            (
+                min.min(vertices[*v].position),
+                max.max(vertices[*v].position),
+            )
+        },
+    );
+    let scale = (max - min).max_element().max(f32::EPSILON);
+    let positions: Vec<Vec3> = global
+        .iter()
+        .map(|v| (vertices[*v].position - min) / scale)
+        .collect();
+
+    let mut locked = vec![false; vertex_count];
+    let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+    result.chunks_exact(3).for_each(|t| {
+        (0..3).for_each(|k| {
+            let (a, b) = (t[k], t[(k + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        })
+    });
+    edges
+        .iter()
+        .filter(|(_, count)| **count != 2)
+        .for_each(|((a, b), _)| {
+            locked[*a] = true;
+            locked[*b] = true;
+        });
+    // A seam can also be a single vertex, like at the tip of a cone.
+    let mut seams: HashMap<[u32; 3], usize> = HashMap::new();
+    positions.iter().enumerate().for_each(|(v, p)| {
+        if let Some(other) = seams.insert(position_key(*p), v) {
+            locked[v] = true;
+            locked[other] = true;
+        }
+    });
+
+    let mut quadrics = vec![Quadric::default(); vertex_count];
+    result.chunks_exact(3).for_each(|t| {
+        let p = [0, 1, 2].map(|k| positions[t[k]]);
+        let cross = (p[1] - p[0]).cross(p[2] - p[0]);
+        let area = cross.length() / 2.;
+        if area > 0. {
+            let normal = cross.normalize();
+            let quadric = Quadric::from_plane(normal, -normal.dot(p[0]), area);
+            t.iter().for_each(|v| quadrics[*v].add(&quadric));
+        }
+    });
+
+    let max_error = (max_error / scale).powi(2);
+    let target_triangles = target_count / 3;
+    let mut error: f32 = 0.;
+    loop {
+        let triangle_count = result.len() / 3;
+        if triangle_count <= target_triangles {
+            break;
+        }
+
+        // Triangles around each vertex.
+        let mut offsets = vec![0; vertex_count + 1];
+        result.iter().for_each(|v| offsets[v + 1] += 1);
+        (0..vertex_count).for_each(|v| offsets[v + 1] += offsets[v]);
+        let mut filled = offsets.clone();
+        let mut adjacency = vec![0; result.len()];
+        result.chunks_exact(3).enumerate().for_each(|(t, vs)| {
+            vs.iter().for_each(|v| {
+                adjacency[filled[*v]] = t;
+                filled[*v] += 1;
+            })
+        });
+
+        // Each interior edge is seen once in each direction, by each of its triangles.
+        let mut collapses: Vec<(f32, usize, usize)> = result
+            .chunks_exact(3)
+            .flat_map(|t| (0..3).map(move |k| (t[k], t[(k + 1) % 3])))
+            .filter(|(from, _)| !locked[*from])
+            .map(|(from, to)| {
+                let mut q = quadrics[from];
+                q.add(&quadrics[to]);
+                (q.error(positions[to]), from, to)
+            })
+            .filter(|(cost, _, _)| *cost <= max_error)
+            .collect();
+        collapses.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        // A collapse removes two triangles, at most one per vertex per pass.
+        let wanted = (triangle_count - target_triangles).div_ceil(2);
+        let mut touched = vec![false; vertex_count];
+        let mut remap: Vec<usize> = (0..vertex_count).collect();
+        let mut collapsed = 0;
+        for (cost, from, to) in collapses {
+            if collapsed >= wanted {
+                break;
+            }
+            if touched[from] || touched[to] {
+                continue;
+            }
+            let flips = adjacency[offsets[from]..offsets[from + 1]]
+                .iter()
+                .map(|t| &result[t * 3..t * 3 + 3])
+                .filter(|t| !t.contains(&to))
+                .any(|t| {
+                    let p = |v: usize| positions[v];
+                    let k = t.iter().position(|v| *v == from).unwrap();
+                    let (b, c) = (p(t[(k + 1) % 3]), p(t[(k + 2) % 3]));
+                    let before = (b - p(from)).cross(c - p(from));
+                    let after = (b - p(to)).cross(c - p(to));
+                    before.dot(after) <= 0.25 * before.length() * after.length()
+                });
+            if flips {
+                continue;
+            }
+            remap[from] = to;
+            let q = quadrics[from];
+            quadrics[to].add(&q);
+            touched[from] = true;
+            touched[to] = true;
+            error = error.max(cost);
+            collapsed += 1;
+        }
+        if collapsed == 0 {
+            break;
+        }
+
+        result.iter_mut().for_each(|v| *v = remap[*v]);
+        let mut kept = 0;
+        (0..result.len() / 3).for_each(|t| {
+            let [a, b, c] = [0, 1, 2].map(|k| result[t * 3 + k]);
+            if a != b && b != c && c != a {
+                result.copy_within(t * 3..t * 3 + 3, kept * 3);
+                kept += 1;
+            }
+        });
+        result.truncate(kept * 3);
+    }
+
+    let simplified = result.iter().map(|v| I::new(global[*v])).collect();
+    (simplified, error.sqrt() * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec4, vec3};
+
+    use super::*;
+
+    /// `n`×`n` quads in the XY plane facing +Z, with the UVs following X and Y.
+    fn grid(n: usize) -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = (0..(n + 1) * (n + 1))
+            .map(|i| {
+                let (x, y) = ((i % (n + 1)) as f32, (i / (n + 1)) as f32);
+                Vertex {
+                    position: vec3(x, y, 0.),
+                    normal: Vec3::Z,
+                    uv_x: x / n as f32,
+                    uv_y: y / n as f32,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let indices = (0..n * n)
+            .flat_map(|q| {
+                let i = (q / n * (n + 1) + q % n) as u32;
+                let j = i + n as u32 + 1;
+                [i, i + 1, j + 1, i, j + 1, j]
+            })
+            .collect();
+        (vertices, indices)
+    }
+
+    /// The triangles by position, starting at their smallest corner to keep their winding.
+    fn triangles(vertices: &[Vertex], indices: &[u32]) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<_> = indices
+            .chunks_exact(3)
+            .map(|is| {
+                let mut t = triangle_positions(vertices, is).map(position_key);
+                let first = (0..3).min_by_key(|k| t[*k]).unwrap();
+                t.rotate_left(first);
+                t
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    /// Average cache misses per triangle.
+    fn acmr(indices: &[u32], vertex_count: usize) -> f32 {
+        let misses: u32 = cache_misses(indices, vertex_count).iter().sum();
+        misses as f32 / (indices.len() / 3) as f32
+    }
+
+    #[test]
+    fn optimize_vertex_fetch_follows_the_first_uses() {
+        let vertices: Vec<Vertex> = (0..4)
+            .map(|i| Vertex {
+                position: Vec3::splat(i as f32),
+                ..Default::default()
+            })
+            .collect();
+        let mut fetched = vertices.clone();
+        let mut indices = vec![2u32, 0, 1, 1, 0, 2];
+        optimize_vertex_fetch(&mut fetched, &mut indices);
+
+        assert_eq!(indices, [0, 1, 2, 2, 1, 0]);
+        // The unused vertex 3 is dropped.
+        let positions: Vec<Vec3> = fetched.iter().map(|v| v.position).collect();
+        assert_eq!(
+            positions,
+            [vertices[2], vertices[0], vertices[1]].map(|v| v.position)
+        );
+    }
+
+    #[test]
+    fn optimize_vertex_cache_reduces_the_misses() {
+        let (vertices, ordered) = grid(16);
+        // Shuffled triangles, 7919 being prime with their count.
+        let triangle_count = ordered.len() / 3;
+        let mut indices: Vec<u32> = (0..triangle_count)
+            .flat_map(|t| {
+                let t = t * 7919 % triangle_count;
+                ordered[t * 3..t * 3 + 3].to_vec()
+            })
+            .collect();
+        let shuffled_acmr = acmr(&indices, vertices.len());
+
+        optimize_vertex_cache(&mut indices, vertices.len());
+
+        assert_eq!(
+            triangles(&vertices, &indices),
+            triangles(&vertices, &ordered)
+        );
+        let optimized_acmr = acmr(&indices, vertices.len());
+        assert!(
+            optimized_acmr < 0.8 && optimized_acmr < shuffled_acmr / 2.,
+            "{shuffled_acmr} -> {optimized_acmr}"
+        );
+    }
+
+    #[test]
+    fn optimize_overdraw_draws_the_outer_clusters_first() {
+        // Two quads facing +Z, the inner one first.
+        let (quad, quad_indices) = grid(1);
+        let outer = quad.iter().map(|v| Vertex {
+            position: v.position + Vec3::Z,
+            ..*v
+        });
+        let vertices: Vec<Vertex> = quad.iter().copied().chain(outer).collect();
+        let mut indices: Vec<u32> = quad_indices
+            .iter()
+            .copied()
+            .chain(quad_indices.iter().map(|i| i + 4))
+            .collect();
+        let before = triangles(&vertices, &indices);
+
+        optimize_overdraw(&vertices, &mut indices, OVERDRAW_THRESHOLD);
+
+        assert_eq!(triangles(&vertices, &indices), before);
+        assert!(indices[..6].iter().all(|i| *i >= 4), "{indices:?}");
+    }
+
+    #[test]
+    fn optimize_overdraw_keeps_the_cache_efficiency() {
+        let (vertices, mut indices) = grid(16);
+        optimize_vertex_cache(&mut indices, vertices.len());
+        let optimized_acmr = acmr(&indices, vertices.len());
+        let before = triangles(&vertices, &indices);
+
+        optimize_overdraw(&vertices, &mut indices, OVERDRAW_THRESHOLD);
+
+        assert_eq!(triangles(&vertices, &indices), before);
+        let acmr = acmr(&indices, vertices.len());
+        assert!(acmr <= optimized_acmr * 1.25, "{optimized_acmr} -> {acmr}");
+    }
+
+    #[test]
+    fn tangents_follow_the_uvs() {
+        let (mut vertices, mut indices) = grid(2);
+        let vertex_count = vertices.len();
+        tangents(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), vertex_count);
+        assert!(
+            vertices
+                .iter()
+                .all(|v| v.tangent.abs_diff_eq(Vec3::X.extend(1.), 1e-6))
+        );
+    }
+
+    #[test]
+    fn tangents_split_the_mirrored_uvs() {
+        // The right quad mirrors the UVs of the left one, along their shared edge.
+        let (mut vertices, mut indices) = grid(1);
+        let mirrored = vertices.iter().map(|v| Vertex {
+            position: vec3(2. - v.position.x, v.position.y, 0.),
+            ..*v
+        });
+        vertices = vertices.iter().copied().chain(mirrored).collect();
+        // The right quad is mirrored too, its winding is flipped back to face +Z.
+        let right: Vec<u32> = indices
+            .chunks_exact(3)
+            .flat_map(|t| [t[0] + 4, t[2] + 4, t[1] + 4])
+            .collect();
+        indices.extend(right);
+        weld(&mut vertices, &mut indices);
+        assert_eq!(vertices.len(), 6);
+
+        tangents(&mut vertices, &mut indices);
+
+        // The 2 vertices of the shared edge are split.
+        assert_eq!(vertices.len(), 8);
+        let (left, right) = indices.split_at(6);
+        let tangent = |is: &[u32]| -> Vec<Vec4> {
+            is.iter().map(|i| vertices[*i as usize].tangent).collect()
+        };
+        assert!(
+            tangent(left)
+                .iter()
+                .all(|t| t.abs_diff_eq(Vec3::X.extend(1.), 1e-6))
+        );
+        assert!(
+            tangent(right)
+                .iter()
+                .all(|t| t.abs_diff_eq(Vec3::NEG_X.extend(-1.), 1e-6))
+        );
+    }
+
+    /// Total area of the triangles.
+    fn area(vertices: &[Vertex], indices: &[u32]) -> f32 {
+        indices
+            .chunks_exact(3)
+            .map(|is| {
+                let [a, b, c] = triangle_positions(vertices, is);
+                (b - a).cross(c - a).length() / 2.
+            })
+            .sum()
+    }
+
+    #[test]
+    fn simplify_keeps_the_meshes_under_the_target() {
+        let (vertices, indices) = grid(2);
+
+        let (simplified, error) = simplify(&vertices, &indices, indices.len(), f32::INFINITY);
+
+        assert_eq!(simplified, indices);
+        assert_eq!(error, 0.);
+    }
+
+    #[test]
+    fn simplify_collapses_the_flat_interior() {
+        let (vertices, indices) = grid(8);
+
+        let (simplified, error) = simplify(&vertices, &indices, indices.len() / 4, f32::INFINITY);
+
+        assert!(
+            simplified.len() <= indices.len() / 4,
+            "{}",
+            simplified.len()
+        );
+        assert!(error < 1e-3, "{error}");
+        assert!((area(&vertices, &simplified) - 64.).abs() < 1e-3);
+        // The border is locked, so that the surface doesn't shrink.
+        let border = (0..vertices.len() as u32).filter(|i| {
+            let p = vertices[*i as usize].position;
+            p.x == 0. || p.y == 0. || p.x == 8. || p.y == 8.
+        });
+        border.for_each(|i| assert!(simplified.contains(&i), "{i}"));
+    }
+
+    #[test]
+    fn simplify_respects_the_max_error() {
+        // A curved grid, which can't be simplified without moving the surface.
+        let (mut vertices, indices) = grid(8);
+        vertices
+            .iter_mut()
+            .for_each(|v| v.position.z = (v.position - vec3(4., 4., 0.)).length_squared() / 8.);
+
+        let (unbounded, unbounded_error) = simplify(&vertices, &indices, 0, f32::INFINITY);
+        let (bounded, bounded_error) = simplify(&vertices, &indices, 0, 0.01);
+
+        assert!(unbounded.len() < indices.len());
+        assert!(unbounded_error > 0.01, "{unbounded_error}");
+        assert!(bounded_error <= 0.01, "{bounded_error}");
+        assert!(bounded.len() > unbounded.len());
+    }
+
+    #[test]
+    fn simplify_locks_the_uv_seams() {
+        // Two grids side by side, with their own UVs along the shared edge.
+        let (left, left_indices) = grid(4);
+        let right = left.iter().map(|v| Vertex {
+            position: v.position + vec3(4., 0., 0.),
+            ..*v
+        });
+        let vertices: Vec<Vertex> = left.iter().copied().chain(right).collect();
+        let indices: Vec<u32> = left_indices
+            .iter()
+            .copied()
+            .chain(left_indices.iter().map(|i| i + left.len() as u32))
+            .collect();
+
+        let (simplified, _) = simplify(&vertices, &indices, 0, f32::INFINITY);
+
+        assert!(simplified.len() < indices.len());
+        // The seam keeps its vertices on both sides.
+        let seam = (0..vertices.len() as u32).filter(|i| vertices[*i as usize].position.x == 4.);
+        seam.for_each(|i| assert!(simplified.contains(&i), "{i}"));
+    }
+}
@@ -1,6 +1,9 @@
 /// Describing the world
 mod camera;
-use std::time::Instant;
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
 
 mod scene;
 pub use camera::Camera;
@@ -8,12 +11,20 @@ pub use scene::Scene;
 mod mesh;
 use glam::{Mat4, Vec3, Vec4Swizzles, vec3};
 pub use mesh::*;
+#[cfg(feature = "cpu")]
+use winit::dpi::PhysicalPosition;
 use winit::dpi::PhysicalSize;
 
+#[cfg(feature = "cpu")]
+use crate::scene::bvh::Ray;
 use crate::scene::{mesh_library::load_scene_index_looping, scene::SceneStandIn};
 
+pub mod bvh;
+pub mod environment;
 pub mod gltf_file;
+pub mod lod;
 mod mesh_library;
+pub mod mesh_processing;
 pub mod obj_file;
 
 pub const DEFAULT_BACKGROUND_COLOR: u32 = 0xff181818;
@@ -24,6 +35,8 @@ pub struct World {
     // TODO: copy vulkan world info
     pub camera: Camera,
     pub sun_direction: Vec3,
+    /// Picked with the mouse, highlighted with its children.
+    pub selected_node: Option<Arc<RwLock<Node>>>,
 }
 
 impl Default for World {
@@ -34,6 +47,7 @@ impl Default for World {
             scene_index: 0,
             camera: Default::default(),
             sun_direction: vec3(-1., -1., -1.).normalize(),
+            selected_node: None,
         };
         println!("World loaded in : {}μs", t.elapsed().as_micros());
         w
@@ -41,9 +55,49 @@ impl Default for World {
 }
 
 impl World {
+    /// With the scene `scene_name` of the library, once it is loaded.
+    #[cfg(all(feature = "cpu", feature = "vulkan"))]
+    pub fn new_loaded(scene_name: &str, camera: Camera) -> Self {
+        let scene_index = mesh_library::scene_index(scene_name)
+            .unwrap_or_else(|| panic!("No scene `{scene_name}`"));
+        let scene = load_scene_index_looping(scene_index);
+        scene.wait();
+        World {
+            scene,
+            scene_index,
+            camera,
+            sun_direction: vec3(-1., -1., -1.).normalize(),
+            selected_node: None,
+        }
+    }
+
     pub fn load_next_scene(&mut self) {
         self.scene_index += 1;
         self.scene = load_scene_index_looping(self.scene_index);
+        self.selected_node = None;
+    }
+
+    /// Selects the node under the `cursor`, or none if there is nothing.
+    #[cfg(feature = "cpu")]
+    pub fn pick(&mut self, cursor: PhysicalPosition<f64>, size: PhysicalSize<u32>) {
+        let ratio_w_h = size.width as f32 / size.height as f32;
+        let inv_view_proj =
+            (self.camera.projection(size, ratio_w_h) * self.camera.view_mat()).inverse();
+        // Raster space to [-1,1], see `to_raster`.
+        let x = cursor.x as f32 / size.width as f32 * 2. - 1.;
+        let y = 1. - cursor.y as f32 / size.height as f32 * 2.;
+        // 0 is the near plane, 0.5 twice further.
+        let ray = Ray::from_clip_space(&inv_view_proj, x, y, 0., 0.5);
+
+        let picked = self.scene.if_present(|s| s.pick(&ray)).flatten();
+
+        if let Some(previous) = self.selected_node.take() {
+            previous.write().unwrap().set_highlighted(false);
+        }
+        if let Some(node) = picked.as_ref() {
+            node.write().unwrap().set_highlighted(true);
+        }
+        self.selected_node = picked;
     }
 }
 
@@ -2,15 +2,19 @@ pub const SUZANNE_OBJ_PATH: &str = "resources/Suzanne.obj";
 
 use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
 
-use glam::vec3;
+use glam::{Vec3, vec3};
 use obj::raw::{material::MtlColor, object::Polygon, parse_mtl, parse_obj};
 
-use super::{GeoSurface, MeshAsset, Texture, Vertex};
+use super::{GeoSurface, MeshAsset, Texture, Vertex, mesh_processing};
 
 // TODO: better error handling
 pub fn import_mesh_and_diffuse<P: AsRef<Path>>(obj_path: P) -> MeshAsset {
-    let obj = parse_obj(BufReader::new(File::open(&obj_path).unwrap_or_else(|_| panic!("Couldn't load path : {}",
-        obj_path.as_ref().to_string_lossy()))))
+    let obj = parse_obj(BufReader::new(File::open(&obj_path).unwrap_or_else(|_| {
+        panic!(
+            "Couldn't load path : {}",
+            obj_path.as_ref().to_string_lossy()
+        )
+    })))
     .expect("Couldn't load .obj");
 
     println!(
@@ -23,39 +27,81 @@ pub fn import_mesh_and_diffuse<P: AsRef<Path>>(obj_path: P) -> MeshAsset {
 
     let mtls = load_materials_diffuse_rgb(obj_path, &obj.material_libraries[..]);
 
-    let mut vertices = Vec::with_capacity(obj.positions.len());
-    vertices.extend(obj.positions.iter().map(|(x, y, z, _)| Vertex {
-        position: vec3(*x, *y, *z),
-        ..Default::default()
-    }));
-
-    let mut indices: Vec<usize> = Vec::with_capacity(obj.polygons.len() * 3);
+    // A vertex per corner, welded back once all their attributes are known. The missing normals
+    // are NaN until generated.
+    let corner = |p: usize, t: Option<usize>, n: Option<usize>| {
+        let (x, y, z, _) = obj.positions[p];
+        let mut v = Vertex {
+            position: vec3(x, y, z),
+            normal: Vec3::NAN,
+            ..Default::default()
+        };
+        if let Some((u, w, _)) = t.map(|t| obj.tex_coords[t]) {
+            // Origin at the top left like glTF.
+            v.uv_x = u;
+            v.uv_y = 1. - w;
+        }
+        if let Some((x, y, z)) = n.map(|n| obj.normals[n]) {
+            v.normal = vec3(x, y, z);
+        }
+        v
+    };
+    let mut vertices = Vec::with_capacity(obj.polygons.len() * 3);
     for poly in obj.polygons.iter() {
         match poly {
-            Polygon::P(vec) if vec.len() == 3 => indices.extend(vec.iter()),
-            Polygon::PT(vec) | Polygon::PN(vec) if vec.len() == 3 => {
-                indices.extend(vec.iter().map(|(p, _)| p))
+            Polygon::P(vec) if vec.len() == 3 => {
+                vertices.extend(vec.iter().map(|p| corner(*p, None, None)))
+            }
+            Polygon::PT(vec) if vec.len() == 3 => {
+                vertices.extend(vec.iter().map(|(p, t)| corner(*p, Some(*t), None)))
+            }
+            Polygon::PN(vec) if vec.len() == 3 => {
+                vertices.extend(vec.iter().map(|(p, n)| corner(*p, None, Some(*n))))
+            }
+            Polygon::PTN(vec) if vec.len() == 3 => {
+                vertices.extend(vec.iter().map(|(p, t, n)| corner(*p, Some(*t), Some(*n))))
             }
-            Polygon::PTN(vec) if vec.len() == 3 => indices.extend(vec.iter().map(|(p, _, _)| p)),
             _ => panic!("Model should be triangulated first to be loaded properly"),
         }
     }
-    // TODO PTN save normal
 
-    let surfaces: Vec<_> = obj
+    let mut indices: Vec<usize> = (0..vertices.len()).collect();
+    mesh_processing::weld(&mut vertices, &mut indices[..]);
+    if vertices.iter().any(|v| v.normal.is_nan()) {
+        mesh_processing::smooth_normals(&mut vertices[..], &indices[..]);
+    }
+
+    let ranges: Vec<_> = obj
         .meshes
         .iter()
         .flat_map(|(material_name, group)| {
-            group.polygons.iter().map(|r| {
-                let range = (r.start * 3)..(r.end * 3);
-                GeoSurface::new(
-                    &vertices[..],
-                    &indices[..],
-                    r.start * 3,
-                    range.len(),
-                    mtls[material_name],
-                )
-            })
+            group
+                .polygons
+                .iter()
+                .map(move |r| ((r.start * 3)..(r.end * 3), material_name))
+        })
+        .collect();
+    ranges.iter().for_each(|(range, _)| {
+        let surface_indices = &mut indices[range.clone()];
+        mesh_processing::optimize_vertex_cache(surface_indices, vertices.len());
+        mesh_processing::optimize_overdraw(
+            &vertices[..],
+            surface_indices,
+            mesh_processing::OVERDRAW_THRESHOLD,
+        );
+    });
+    mesh_processing::optimize_vertex_fetch(&mut vertices, &mut indices[..]);
+
+    let surfaces: Vec<_> = ranges
+        .into_iter()
+        .map(|(range, material_name)| {
+            GeoSurface::new(
+                &vertices[..],
+                &indices[..],
+                range.start,
+                range.len(),
+                mtls[material_name],
+            )
         })
         .collect();
 
@@ -112,3 +158,46 @@ fn load_materials_diffuse_rgb<P: AsRef<Path>>(
 
     mtls
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn normals_are_only_generated_for_the_corners_missing_them() {
+        // A quad bent along its diagonal, the `P` triangle facing +Z and the `PN` one with a
+        // normal of its own.
+        let folder = std::env::temp_dir().join("graphics_obj_mixed_normals");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("mixed.mtl"), "newmtl grey\nKd 0.5 0.5 0.5\n").unwrap();
+        let path = folder.join("mixed.obj");
+        fs::write(
+            &path,
+            "mtllib mixed.mtl\nusemtl grey\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 1\nvn 0 1 0\n\
+             f 1 2 3\nf 1//1 3//1 4//1\n",
+        )
+        .unwrap();
+
+        let mesh = import_mesh_and_diffuse(&path);
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert!(mesh.vertices.iter().all(|v| v.normal.is_finite()));
+        let normal = |position: Vec3, generated: bool| {
+            mesh.vertices
+                .iter()
+                .find(|v| v.position == position && (v.normal == Vec3::Y) != generated)
+                .unwrap()
+                .normal
+        };
+        // The given normals are kept.
+        assert_eq!(normal(Vec3::ZERO, false), Vec3::Y);
+        assert_eq!(normal(vec3(1., 1., 0.), false), Vec3::Y);
+        // The corner only in the `P` triangle faces its plane.
+        assert!(normal(Vec3::X, true).abs_diff_eq(Vec3::Z, 1e-6));
+        // The shared corners average both faces.
+        let shared = normal(Vec3::ZERO, true);
+        assert!(shared.z > 0. && shared.y < 0., "{shared}");
+    }
+}
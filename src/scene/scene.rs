@@ -1,3 +1,8 @@
+#[cfg(feature = "cpu")]
+use std::sync::{
+    RwLockReadGuard,
+    atomic::{AtomicBool, Ordering},
+};
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
@@ -7,7 +12,12 @@ use std::{
 
 use glam::Mat4;
 
-use crate::scene::Node;
+#[cfg(feature = "cpu")]
+use crate::scene::{
+    MeshAsset,
+    bvh::{Aabb, Bvh, Frustum, Ray},
+};
+use crate::scene::{Node, bvh::TransformEpoch};
 
 enum WaitingOrReady {
     Waiting(Option<JoinHandle<Scene>>),
@@ -71,6 +81,17 @@ impl SceneStandIn {
         }
     }
 
+    /// Blocks until the scene is loaded.
+    #[cfg(all(feature = "cpu", feature = "vulkan"))]
+    pub fn wait(&self) {
+        let mut state = self.state.write().unwrap();
+        if let WaitingOrReady::Waiting(ref mut handle) = *state
+            && let Some(handle) = handle.take()
+        {
+            *state = WaitingOrReady::Ready(handle.join().unwrap());
+        }
+    }
+
     pub fn if_present<T>(&self, closure: impl FnOnce(&Scene) -> T) -> Option<T> {
         self.set_if_ready();
 
@@ -88,6 +109,70 @@ pub struct Scene {
     named_nodes: HashMap<String, Arc<RwLock<Node>>>,
 
     top_nodes: Vec<Arc<RwLock<Node>>>,
+
+    /// Behind a lock since the scene is shared, but it is refitted when transforms change.
+    #[cfg(feature = "cpu")]
+    surfaces_bvh: RwLock<SurfacesBvh>,
+    /// Set by the first [`Scene::request_lods`].
+    #[cfg(feature = "cpu")]
+    lods_requested: AtomicBool,
+}
+
+/// BVH over the world bounds of every surface of the scene.
+#[cfg(feature = "cpu")]
+#[derive(Default)]
+struct SurfacesBvh {
+    bvh: Bvh,
+    /// Node and surface index of each BVH item.
+    surfaces: Vec<(Arc<RwLock<Node>>, usize)>,
+    /// Bumped by the nodes of the scene.
+    transform_epoch: TransformEpoch,
+    /// Transforms epoch of the last build or refit.
+    epoch: u64,
+}
+
+#[cfg(feature = "cpu")]
+impl SurfacesBvh {
+    fn new(top_nodes: &[Arc<RwLock<Node>>], transform_epoch: TransformEpoch) -> Self {
+        fn collect(node: &Arc<RwLock<Node>>, surfaces: &mut Vec<(Arc<RwLock<Node>>, usize)>) {
+            let n = node.read().unwrap();
+            if let Some(mesh) = n.mesh.as_ref() {
+                surfaces.extend((0..mesh.surfaces.len()).map(|i| (node.clone(), i)));
+            }
+            n.children.iter().for_each(|c| collect(c, surfaces));
+        }
+
+        let mut surfaces = Vec::new();
+        top_nodes.iter().for_each(|n| collect(n, &mut surfaces));
+
+        let epoch = transform_epoch.get();
+        let bvh = Bvh::build(Self::bounds(&surfaces));
+        Self {
+            bvh,
+            surfaces,
+            transform_epoch,
+            epoch,
+        }
+    }
+
+    fn bounds(surfaces: &[(Arc<RwLock<Node>>, usize)]) -> Vec<Aabb> {
+        surfaces
+            .iter()
+            .map(|(node, s_index)| {
+                let node = node.read().unwrap();
+                let mesh = node.mesh.as_ref().unwrap();
+                Aabb::from_bounds(&mesh.surfaces[*s_index].bounds, &node.world_transform)
+            })
+            .collect()
+    }
+
+    fn refit_if_needed(&mut self) {
+        let epoch = self.transform_epoch.get();
+        if epoch != self.epoch {
+            self.epoch = epoch;
+            self.bvh.refit(Self::bounds(&self.surfaces));
+        }
+    }
 }
 
 impl Scene {
@@ -96,16 +181,99 @@ impl Scene {
         top_nodes: Vec<Arc<RwLock<Node>>>,
     ) -> Self {
         // Update world transform infos to all nodes.
-        top_nodes
-            .iter()
-            .for_each(|n| n.write().unwrap().refresh_transform(&Mat4::IDENTITY));
+        let transform_epoch = TransformEpoch::default();
+        top_nodes.iter().for_each(|n| {
+            let mut n = n.write().unwrap();
+            n.set_transform_epoch(&transform_epoch);
+            n.refresh_transform(&Mat4::IDENTITY);
+        });
+
+        #[cfg(feature = "cpu")]
+        let surfaces_bvh = RwLock::new(SurfacesBvh::new(&top_nodes, transform_epoch));
 
         Scene {
             named_nodes,
             top_nodes,
+            #[cfg(feature = "cpu")]
+            surfaces_bvh,
+            #[cfg(feature = "cpu")]
+            lods_requested: AtomicBool::new(false),
         }
     }
 
+    /// Generates the levels of detail of every mesh on another thread, the first time. Until
+    /// then, the surfaces are drawn without them.
+    #[cfg(feature = "cpu")]
+    pub fn request_lods(&self) {
+        fn collect(node: &Arc<RwLock<Node>>, meshes: &mut Vec<Arc<MeshAsset>>) {
+            let n = node.read().unwrap();
+            if let Some(mesh) = n.mesh.as_ref()
+                && !meshes.iter().any(|m| Arc::ptr_eq(m, mesh))
+            {
+                meshes.push(mesh.clone());
+            }
+            n.children.iter().for_each(|c| collect(c, meshes));
+        }
+
+        if self.lods_requested.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let mut meshes = Vec::new();
+        self.top_nodes.iter().for_each(|n| collect(n, &mut meshes));
+        thread::spawn(move || {
+            let t = Instant::now();
+            meshes.iter().for_each(|m| m.generate_lods());
+            println!(
+                "Levels of detail of {} meshes generated in {}μs",
+                meshes.len(),
+                t.elapsed().as_micros()
+            );
+        });
+    }
+
+    #[cfg(feature = "cpu")]
+    fn surfaces_bvh(&self) -> RwLockReadGuard<'_, SurfacesBvh> {
+        self.surfaces_bvh.write().unwrap().refit_if_needed();
+        self.surfaces_bvh.read().unwrap()
+    }
+
+    /// Calls `f` with the node and surface index of every surface in the `frustum`.
+    #[cfg(feature = "cpu")]
+    pub fn for_each_visible_surface(&self, frustum: &Frustum, mut f: impl FnMut(&Node, usize)) {
+        let surfaces_bvh = self.surfaces_bvh();
+        surfaces_bvh.bvh.query_frustum(frustum, |i| {
+            let (node, s_index) = &surfaces_bvh.surfaces[i];
+            f(&node.read().unwrap(), *s_index)
+        });
+    }
+
+    /// Node with the closest triangle hit by the `ray`.
+    #[cfg(feature = "cpu")]
+    pub fn pick(&self, ray: &Ray) -> Option<Arc<RwLock<Node>>> {
+        let surfaces_bvh = self.surfaces_bvh();
+        surfaces_bvh
+            .bvh
+            .query_ray(ray, f32::INFINITY, |i, _| {
+                let (node, s_index) = &surfaces_bvh.surfaces[i];
+                let node = node.read().unwrap();
+                let mesh = node.mesh.as_ref().unwrap();
+                let s = &mesh.surfaces[*s_index];
+                let local_ray = ray.transformed(&node.world_transform.inverse());
+
+                mesh.indices[s.start_index..s.start_index + s.count]
+                    .chunks_exact(3)
+                    .filter_map(|is| {
+                        local_ray.intersect_triangle(
+                            mesh.vertices[is[0]].position,
+                            mesh.vertices[is[1]].position,
+                            mesh.vertices[is[2]].position,
+                        )
+                    })
+                    .reduce(f32::min)
+            })
+            .map(|(i, _)| surfaces_bvh.surfaces[i].0.clone())
+    }
+
     pub fn top_nodes(&self) -> &[Arc<RwLock<Node>>] {
         &self.top_nodes
     }
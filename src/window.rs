@@ -13,6 +13,8 @@ use winit::{
 
 #[cfg(feature = "cpu")]
 use glam::Mat4;
+#[cfg(feature = "cpu")]
+use winit::event::MouseButton;
 
 #[cfg(target_os = "linux")]
 use winit::platform::x11::ActiveEventLoopExtX11;
@@ -27,6 +29,7 @@ use crate::rasterizer::{Engine, Settings};
 use crate::scene::Camera;
 #[cfg(feature = "cpu")]
 use crate::scene::World;
+use crate::scene::environment::EnvironmentSource;
 
 const BLENDING_RATIO: f32 = 0.01;
 
@@ -44,7 +47,6 @@ pub struct AppObserver {
 }
 
 impl AppObserver {
-    #[cfg(feature = "cpu")]
     pub fn cursor(&self) -> &Option<PhysicalPosition<f64>> {
         &self.cursor
     }
@@ -100,12 +102,14 @@ pub struct InitializedWindow<'a> {
 impl InitializedWindow<'_> {
     pub fn new(window: Rc<Window>) -> Self {
         let engine = Engine::new(window.clone());
+        let mut settings = Settings {
+            engine_type: engine.as_engine_type(),
+            ..Default::default()
+        };
+        settings.restrict_to_engine();
         Self {
             window,
-            settings: Settings {
-                engine_type: engine.as_engine_type(),
-                ..Default::default()
-            },
+            settings,
             engine,
         }
     }
@@ -118,7 +122,7 @@ impl InitializedWindow<'_> {
         #[cfg(feature = "stats")] stats: &mut Stats,
     ) {
         self.engine.rasterize(
-            &self.settings,
+            &mut self.settings,
             #[cfg(feature = "cpu")]
             world,
             #[cfg(all(not(feature = "cpu"), feature = "vulkan"))]
@@ -132,6 +136,7 @@ impl InitializedWindow<'_> {
     pub fn set_next_engine(&mut self) {
         self.engine.set_next();
         self.settings.engine_type = self.engine.as_engine_type();
+        self.settings.restrict_to_engine();
     }
 }
 
@@ -397,6 +402,26 @@ impl ApplicationHandler for App<'_> {
                         });
                     }
                     KeyCode::Backquote => w.settings.show_vertices = !w.settings.show_vertices,
+                    KeyCode::KeyV if w.settings.engine_type.supports_debug_views() => {
+                        w.settings.view_mode.next()
+                    }
+                    KeyCode::KeyF if w.settings.engine_type.supports_debug_views() => {
+                        w.settings.wireframe.next()
+                    }
+                    KeyCode::KeyB => w.settings.culling_bvh = !w.settings.culling_bvh,
+                    KeyCode::KeyO => w.settings.culling_occlusion = !w.settings.culling_occlusion,
+                    KeyCode::KeyL => w.settings.lod.enabled = !w.settings.lod.enabled,
+                    KeyCode::BracketLeft => w.settings.lod.finer(),
+                    KeyCode::BracketRight => w.settings.lod.coarser(),
+                    KeyCode::KeyK if w.engine.supports_ambient_occlusion() => {
+                        w.settings.ao.enabled = !w.settings.ao.enabled
+                    }
+                    KeyCode::KeyJ => w.settings.ao.next_samples(),
+                    KeyCode::Minus => w.settings.ao.smaller(),
+                    KeyCode::Equal => w.settings.ao.larger(),
+                    KeyCode::KeyE if w.settings.engine_type.supports_background() => {
+                        w.settings.background.next(EnvironmentSource::list().len())
+                    }
                     KeyCode::Digit1 => w.set_next_engine(),
                     // KeyCode::Digit2 => w.settings.sort_triangles.next(),
                     KeyCode::Digit2 => w.settings.parallel_text = !w.settings.parallel_text,
@@ -422,6 +447,21 @@ impl ApplicationHandler for App<'_> {
             WindowEvent::CursorMoved { position, .. } => {
                 self.cursor = Some(position);
             }
+            #[cfg(feature = "cpu")]
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let w = self.window.as_ref().unwrap();
+                // Vulkan picks on its own scenes.
+                if !self.cursor_grabbed
+                    && matches!(w.engine, Engine::Cpu(..))
+                    && let Some(cursor) = self.cursor
+                {
+                    self.world.pick(cursor, w.window.inner_size());
+                }
+            }
             WindowEvent::RedrawRequested => {
                 self.update_last_frame_micros();
                 // TODO: forward update and events to world to manage itself ?